@@ -0,0 +1,809 @@
+// Copyright 2024 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Stable library API.
+//!
+//! The other modules in `libcoreinst` exist to implement the command-line
+//! tools and may change incompatibly in any release.  The items in this
+//! module follow semver: they will only change incompatibly in a release
+//! that increments the major version (or, before 1.0, the minor version).
+//!
+//! Modify the embedded Ignition config of a live ISO:
+//!
+//! ```no_run
+//! use libcoreinst::api::IsoConfig;
+//! use std::fs::OpenOptions;
+//!
+//! let mut file = OpenOptions::new().read(true).write(true).open("live.iso")?;
+//! let mut config = IsoConfig::for_file(&mut file)?;
+//! config.initrd_mut().add("config.ign", br#"{"ignition": {"version": "3.4.0"}}"#.to_vec());
+//! config.write(&mut file)?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! Install to a disk:
+//!
+//! ```no_run
+//! use libcoreinst::api::{Installer, Source};
+//!
+//! Installer::new()
+//!     .source(Source::Stream("stable".into()))
+//!     .dest("/dev/sda")
+//!     .ignition_file("config.ign")
+//!     .install()?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use anyhow::{Error, Result};
+use reqwest::Url;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::num::NonZeroU32;
+use std::str::FromStr;
+
+use crate::cmdline::{self, ByteSize, DefaultedString, InstallConfig};
+use crate::install::install;
+use crate::io as imp_io;
+use crate::live as imp_live;
+
+// The types below wrap internal types that change freely, so that only
+// the methods listed here are covered by the semver guarantee.
+
+/// The customizable areas of a CoreOS live ISO image: the embedded
+/// Ignition config and network settings, the kernel arguments, extra
+/// files in the ISO 9660 filesystem, and the volume ID.
+pub struct IsoConfig(imp_live::IsoConfig);
+
+impl IsoConfig {
+    /// Reads the customizable areas of an ISO image file.
+    pub fn for_file(file: &mut File) -> Result<Self> {
+        imp_live::IsoConfig::for_file(file).map(Self)
+    }
+
+    /// Whether an Ignition config is embedded.
+    pub fn have_ignition(&self) -> bool {
+        self.0.have_ignition()
+    }
+
+    /// Whether network settings are embedded.
+    pub fn have_network(&self) -> bool {
+        self.0.have_network()
+    }
+
+    /// Removes the embedded network settings.
+    pub fn remove_network(&mut self) {
+        self.0.remove_network()
+    }
+
+    /// Returns the embedded initrd.
+    pub fn initrd(&self) -> &Initrd {
+        Initrd::from_ref(self.0.initrd())
+    }
+
+    /// Returns the embedded initrd for modification.
+    pub fn initrd_mut(&mut self) -> &mut Initrd {
+        Initrd::from_mut(self.0.initrd_mut())
+    }
+
+    /// Whether the image supports modifying kernel arguments.
+    pub fn kargs_supported(&self) -> bool {
+        self.0.kargs_supported()
+    }
+
+    /// Returns the current kernel arguments.
+    pub fn kargs(&self) -> Result<&str> {
+        self.0.kargs()
+    }
+
+    /// Returns the kernel arguments the image was built with.
+    pub fn kargs_default(&self) -> Result<&str> {
+        self.0.kargs_default()
+    }
+
+    /// Replaces the kernel arguments.
+    pub fn set_kargs(&mut self, kargs: &str) -> Result<()> {
+        self.0.set_kargs(kargs)
+    }
+
+    /// Adds a file to the ISO 9660 filesystem.
+    pub fn add_file(&mut self, path: &str, contents: Vec<u8>) -> Result<()> {
+        self.0.add_file(path, contents)
+    }
+
+    /// Whether files were previously added to the ISO 9660 filesystem.
+    pub fn have_added_files(&self) -> bool {
+        self.0.have_added_files()
+    }
+
+    /// Removes previously added files from the ISO 9660 filesystem.
+    pub fn remove_added_files(&mut self) -> Result<()> {
+        self.0.remove_added_files()
+    }
+
+    /// Returns the volume ID.
+    pub fn volume_id(&self) -> &str {
+        self.0.volume_id()
+    }
+
+    /// Changes the volume ID.
+    pub fn set_volume_id(&mut self, volume_id: &str) -> Result<()> {
+        self.0.set_volume_id(volume_id)
+    }
+
+    /// Writes the changes into the ISO image file they were read from.
+    pub fn write(&self, file: &mut File) -> Result<()> {
+        self.0.write(file)
+    }
+
+    /// Writes a copy of the ISO image file with the changes applied.
+    pub fn stream(&self, input: &mut File, writer: &mut (impl Write + ?Sized)) -> Result<()> {
+        self.0.stream(input, writer)
+    }
+}
+
+/// An initrd in newc CPIO format.
+#[derive(Debug, Default)]
+#[repr(transparent)]
+pub struct Initrd(imp_io::Initrd);
+
+impl Initrd {
+    fn from_ref(initrd: &imp_io::Initrd) -> &Self {
+        // SAFETY: Initrd is repr(transparent) over imp_io::Initrd
+        unsafe { &*(initrd as *const imp_io::Initrd as *const Self) }
+    }
+
+    fn from_mut(initrd: &mut imp_io::Initrd) -> &mut Self {
+        // SAFETY: Initrd is repr(transparent) over imp_io::Initrd
+        unsafe { &mut *(initrd as *mut imp_io::Initrd as *mut Self) }
+    }
+
+    /// Parses an initrd, which may be compressed or a concatenation of
+    /// archives.
+    pub fn from_reader<R: Read>(source: R) -> Result<Self> {
+        imp_io::Initrd::from_reader(source).map(Self)
+    }
+
+    /// Parses an initrd, keeping only the members matching `filter`.
+    pub fn from_reader_filtered<R: Read>(source: R, filter: &GlobMatcher) -> Result<Self> {
+        imp_io::Initrd::from_reader_filtered(source, &filter.0).map(Self)
+    }
+
+    /// Generates the compressed archive.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        self.0.to_bytes()
+    }
+
+    /// Sets the compression used by [`Initrd::to_bytes`].
+    pub fn set_compression(&mut self, compression: InitrdCompression) {
+        self.0.set_compression(compression.into())
+    }
+
+    /// Returns the contents of a file.
+    pub fn get(&self, path: &str) -> Option<&[u8]> {
+        self.0.get(path)
+    }
+
+    /// Returns the contents of the files matching `filter`.
+    pub fn find(&self, filter: &GlobMatcher) -> BTreeMap<&str, &[u8]> {
+        self.0.find(&filter.0)
+    }
+
+    /// Adds a file with mode 0600.
+    pub fn add(&mut self, path: &str, contents: Vec<u8>) {
+        self.0.add(path, contents)
+    }
+
+    /// Adds a file with the specified permission bits.
+    pub fn add_with_mode(&mut self, path: &str, contents: Vec<u8>, mode: u32) {
+        self.0.add_with_mode(path, contents, mode)
+    }
+
+    /// Adds a directory with the specified permission bits.
+    pub fn add_dir(&mut self, path: &str, mode: u32) {
+        self.0.add_dir(path, mode)
+    }
+
+    /// Adds a symbolic link pointing to the specified target.
+    pub fn add_symlink(&mut self, path: &str, target: &str) {
+        self.0.add_symlink(path, target)
+    }
+
+    /// Sets the owner of an existing member.
+    pub fn set_owner(&mut self, path: &str, uid: u32, gid: u32) {
+        self.0.set_owner(path, uid, gid)
+    }
+
+    /// Returns the metadata of a member, if present.
+    pub fn metadata(&self, path: &str) -> Option<InitrdMetadata> {
+        self.0.metadata(path).map(Into::into)
+    }
+
+    /// Returns all members with their metadata.
+    pub fn entries(&self) -> BTreeMap<&str, InitrdMetadata> {
+        self.0
+            .entries()
+            .into_iter()
+            .map(|(path, metadata)| (path, metadata.into()))
+            .collect()
+    }
+
+    /// Removes a member.
+    pub fn remove(&mut self, path: &str) {
+        self.0.remove(path)
+    }
+
+    /// Whether the initrd has no files or symlinks.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Compression format and level for a generated initrd.  Parses from
+/// "gzip", "xz", or "zstd", optionally followed by ":LEVEL".
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum InitrdCompression {
+    /// Level 0-9
+    Gzip(u32),
+    /// Level 0-9
+    Xz(u32),
+    /// Level 1-19.  Requires kernel 5.9 or later.
+    Zstd(i32),
+}
+
+impl Default for InitrdCompression {
+    fn default() -> Self {
+        imp_io::InitrdCompression::default().into()
+    }
+}
+
+impl FromStr for InitrdCompression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        imp_io::InitrdCompression::from_str(s).map(Into::into)
+    }
+}
+
+impl fmt::Display for InitrdCompression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        imp_io::InitrdCompression::from(*self).fmt(f)
+    }
+}
+
+impl From<InitrdCompression> for imp_io::InitrdCompression {
+    fn from(compression: InitrdCompression) -> Self {
+        match compression {
+            InitrdCompression::Gzip(level) => Self::Gzip(level),
+            InitrdCompression::Xz(level) => Self::Xz(level),
+            InitrdCompression::Zstd(level) => Self::Zstd(level),
+        }
+    }
+}
+
+impl From<imp_io::InitrdCompression> for InitrdCompression {
+    fn from(compression: imp_io::InitrdCompression) -> Self {
+        match compression {
+            imp_io::InitrdCompression::Gzip(level) => Self::Gzip(level),
+            imp_io::InitrdCompression::Xz(level) => Self::Xz(level),
+            imp_io::InitrdCompression::Zstd(level) => Self::Zstd(level),
+        }
+    }
+}
+
+/// Type of an initrd member.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum InitrdEntryKind {
+    File,
+    Directory,
+    /// Symbolic link to the specified target
+    Symlink(String),
+}
+
+/// Metadata of an initrd member.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct InitrdMetadata {
+    pub kind: InitrdEntryKind,
+    /// Permission bits
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl From<imp_io::InitrdMetadata> for InitrdMetadata {
+    fn from(metadata: imp_io::InitrdMetadata) -> Self {
+        Self {
+            kind: match metadata.kind {
+                imp_io::InitrdEntryKind::File => InitrdEntryKind::File,
+                imp_io::InitrdEntryKind::Directory => InitrdEntryKind::Directory,
+                imp_io::InitrdEntryKind::Symlink(target) => InitrdEntryKind::Symlink(target),
+            },
+            mode: metadata.mode,
+            uid: metadata.uid,
+            gid: metadata.gid,
+        }
+    }
+}
+
+/// A set of shell-style globs for selecting initrd members.
+pub struct GlobMatcher(imp_io::GlobMatcher);
+
+impl GlobMatcher {
+    pub fn new(globs: &[&str]) -> Result<Self> {
+        imp_io::GlobMatcher::new(globs).map(Self)
+    }
+}
+
+/// Low-level access to the ISO 9660 filesystem of an ISO image, such as a
+/// CoreOS live ISO.  Enabled by the `iso9660` feature.
+///
+/// Images can be read from a file or from any other `Read + Seek` stream.
+/// Only the ISO 9660 names are visible; Joliet and Rock Ridge names are
+/// not parsed.
+///
+/// ```no_run
+/// use libcoreinst::api::iso9660::IsoFs;
+/// use std::io::Read;
+///
+/// let mut iso = IsoFs::from_file(std::fs::File::open("live.iso")?)?;
+/// for (path, file) in iso.files()? {
+///     let mut contents = Vec::new();
+///     iso.read_file(&file)?.read_to_end(&mut contents)?;
+///     println!("{path}: {} bytes", contents.len());
+/// }
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[cfg(feature = "iso9660")]
+pub mod iso9660 {
+    // These wrap the types in crate::iso9660, which also implement
+    // in-place modification for the CLI and change freely.
+    use anyhow::{anyhow, Result};
+    use std::collections::BTreeMap;
+    use std::io::{Read, Seek};
+
+    use crate::iso9660 as imp;
+
+    pub use crate::iso9660::NotFound;
+
+    /// An ISO 9660 filesystem read from a file or any other seekable
+    /// stream.
+    #[derive(Debug)]
+    pub struct IsoFs<R = std::fs::File>(imp::IsoFs<R>);
+
+    impl IsoFs<std::fs::File> {
+        /// Parses the volume descriptors of an ISO image file.
+        pub fn from_file(file: std::fs::File) -> Result<Self> {
+            imp::IsoFs::from_file(file).map(Self)
+        }
+    }
+
+    impl<R: Read + Seek> IsoFs<R> {
+        /// Parses the volume descriptors of an ISO image, such as one in
+        /// a `std::io::Cursor` over an in-memory copy.
+        pub fn new(file: R) -> Result<Self> {
+            imp::IsoFs::new(file).map(Self)
+        }
+
+        /// Consumes the filesystem and returns the underlying stream.
+        pub fn into_inner(self) -> R {
+            self.0.into_inner()
+        }
+
+        /// Returns the volume ID from the primary volume descriptor.
+        pub fn volume_id(&self) -> Result<&str> {
+            self.0.get_volume_id()
+        }
+
+        /// Returns the root directory.
+        pub fn root_directory(&self) -> Result<Directory> {
+            self.0.get_root_directory().map(Directory)
+        }
+
+        /// Returns the record for a specific path.  Fails with
+        /// [`NotFound`] if the path doesn't exist.
+        pub fn get_path(&mut self, path: &str) -> Result<DirectoryRecord> {
+            self.0.get_path(path).map(wrap_record)
+        }
+
+        /// Returns an iterator over the records of a directory.
+        pub fn list_dir(&mut self, dir: &Directory) -> Result<IsoFsIterator> {
+            self.0.list_dir(&dir.0).map(IsoFsIterator)
+        }
+
+        /// Returns an iterator over every record in the filesystem, depth
+        /// first, along with its path.
+        pub fn walk(&mut self) -> Result<IsoFsWalkIterator<'_, R>> {
+            self.0.walk().map(IsoFsWalkIterator)
+        }
+
+        /// Returns all the files in the filesystem, by path.
+        pub fn files(&mut self) -> Result<BTreeMap<String, File>> {
+            Ok(self
+                .0
+                .files()?
+                .into_iter()
+                .map(|(path, file)| (path, File(file)))
+                .collect())
+        }
+
+        /// Returns a reader for the contents of a file.
+        pub fn read_file(&mut self, file: &File) -> Result<impl Read + '_> {
+            self.0.read_file(&file.0)
+        }
+    }
+
+    /// A directory in the filesystem.
+    #[derive(Debug, Clone)]
+    pub struct Directory(imp::Directory);
+
+    impl Directory {
+        pub fn name(&self) -> &str {
+            &self.0.name
+        }
+    }
+
+    /// A file in the filesystem.
+    #[derive(Debug, Clone)]
+    pub struct File(imp::File);
+
+    impl File {
+        pub fn name(&self) -> &str {
+            &self.0.name
+        }
+
+        /// Size of the file in bytes.
+        pub fn size(&self) -> u64 {
+            self.0.length.into()
+        }
+    }
+
+    /// A record in a directory.
+    #[derive(Debug, Clone)]
+    #[non_exhaustive]
+    pub enum DirectoryRecord {
+        Directory(Directory),
+        File(File),
+    }
+
+    impl DirectoryRecord {
+        pub fn try_into_dir(self) -> Result<Directory> {
+            match self {
+                Self::Directory(d) => Ok(d),
+                Self::File(f) => Err(anyhow!("entry {} is a file", f.name())),
+            }
+        }
+
+        pub fn try_into_file(self) -> Result<File> {
+            match self {
+                Self::Directory(d) => Err(anyhow!("entry {} is a directory", d.name())),
+                Self::File(f) => Ok(f),
+            }
+        }
+    }
+
+    fn wrap_record(record: imp::DirectoryRecord) -> DirectoryRecord {
+        match record {
+            imp::DirectoryRecord::Directory(d) => DirectoryRecord::Directory(Directory(d)),
+            imp::DirectoryRecord::File(f) => DirectoryRecord::File(File(f)),
+        }
+    }
+
+    /// Iterator over the records of a directory.
+    pub struct IsoFsIterator(imp::IsoFsIterator);
+
+    impl Iterator for IsoFsIterator {
+        type Item = Result<DirectoryRecord>;
+        fn next(&mut self) -> Option<Self::Item> {
+            self.0.next().map(|r| r.map(wrap_record))
+        }
+    }
+
+    /// Iterator over every record in the filesystem, with its path.
+    pub struct IsoFsWalkIterator<'a, R = std::fs::File>(imp::IsoFsWalkIterator<'a, R>);
+
+    impl<R: Read + Seek> Iterator for IsoFsWalkIterator<'_, R> {
+        type Item = Result<(String, DirectoryRecord)>;
+        fn next(&mut self) -> Option<Self::Item> {
+            self.0
+                .next()
+                .map(|r| r.map(|(path, record)| (path, wrap_record(record))))
+        }
+    }
+}
+
+/// An Ignition-style message digest, parsed from `<type>-<value>`, e.g.
+/// `sha256-e3b0c442...`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IgnitionHash(imp_io::IgnitionHash);
+
+impl FromStr for IgnitionHash {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        imp_io::IgnitionHash::from_str(s).map(Self)
+    }
+}
+
+impl fmt::Display for IgnitionHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// How many times to retry a failed fetch.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FetchRetries {
+    Infinite,
+    Finite(NonZeroU32),
+    #[default]
+    None,
+}
+
+impl From<FetchRetries> for cmdline::FetchRetries {
+    fn from(retries: FetchRetries) -> Self {
+        match retries {
+            FetchRetries::Infinite => Self::Infinite,
+            FetchRetries::Finite(n) => Self::Finite(n),
+            FetchRetries::None => Self::None,
+        }
+    }
+}
+
+/// The image to install.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Source {
+    /// The metal image from the specified Fedora CoreOS stream
+    Stream(String),
+    /// An image URL
+    Url(Url),
+    /// A local image file
+    File(String),
+    /// A container image reference containing the disk image
+    ContainerImage(String),
+}
+
+/// Builder for installing CoreOS to one or more disks.
+///
+/// Options not set on the builder take the same defaults as the
+/// corresponding `coreos-installer install` options.
+#[derive(Debug, Default)]
+pub struct Installer {
+    config: InstallConfig,
+}
+
+impl Installer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the image to install.  Defaults to the Fedora CoreOS stable
+    /// stream.
+    pub fn source(mut self, source: Source) -> Self {
+        self.config.stream = None;
+        self.config.image_url = None;
+        self.config.image_file = None;
+        self.config.image_ref = None;
+        match source {
+            Source::Stream(stream) => self.config.stream = Some(stream),
+            Source::Url(url) => self.config.image_url = Some(url),
+            Source::File(path) => self.config.image_file = Some(path),
+            Source::ContainerImage(image_ref) => self.config.image_ref = Some(image_ref),
+        }
+        self
+    }
+
+    /// Add a destination device.  If called more than once, the image is
+    /// written to all the specified devices.
+    pub fn dest(mut self, device: &str) -> Self {
+        self.config.dest_device.push(device.into());
+        self
+    }
+
+    /// Write a disk image file instead of a destination device, optionally
+    /// extending it to `size` bytes.
+    pub fn dest_image_file(mut self, path: &str, size: Option<u64>) -> Self {
+        self.config.dest_image_file = Some(path.into());
+        self.config.image_size = size.map(ByteSize);
+        self
+    }
+
+    /// Set the target CPU architecture for stream installs.
+    pub fn architecture(mut self, architecture: &str) -> Self {
+        self.config.architecture =
+            DefaultedString::from_str(architecture).expect("parsing DefaultedString is infallible");
+        self
+    }
+
+    /// Set the Ignition platform ID.
+    pub fn platform(mut self, platform: &str) -> Self {
+        self.config.platform = Some(platform.into());
+        self
+    }
+
+    /// Embed an Ignition config from a local file.
+    pub fn ignition_file(mut self, path: &str) -> Self {
+        self.config.ignition_url = None;
+        self.config.ignition_file = Some(path.into());
+        self
+    }
+
+    /// Embed an Ignition config from a URL.
+    pub fn ignition_url(mut self, url: Url) -> Self {
+        self.config.ignition_file = None;
+        self.config.ignition_url = Some(url);
+        self
+    }
+
+    /// Verify the Ignition config against the specified digest.
+    pub fn ignition_hash(mut self, hash: IgnitionHash) -> Self {
+        self.config.ignition_hash = Some(hash.0);
+        self
+    }
+
+    /// Authorize an SSH public key for the `core` user, merging with any
+    /// specified Ignition config.
+    pub fn ssh_key(mut self, key: &str) -> Self {
+        self.config.ssh_key_string.push(key.into());
+        self
+    }
+
+    /// Append a default kernel argument.
+    pub fn append_karg(mut self, arg: &str) -> Self {
+        self.config.append_karg.push(arg.into());
+        self
+    }
+
+    /// Delete a default kernel argument.
+    pub fn delete_karg(mut self, arg: &str) -> Self {
+        self.config.delete_karg.push(arg.into());
+        self
+    }
+
+    /// Deduplicate the default kernel arguments after modifying them.
+    pub fn dedup_karg(mut self, dedup: bool) -> Self {
+        self.config.dedup_karg = dedup;
+        self
+    }
+
+    /// Copy network config from the install environment.
+    pub fn copy_network(mut self, copy: bool) -> Self {
+        self.config.copy_network = copy;
+        self
+    }
+
+    /// Save partitions with labels matching the specified glob.
+    pub fn save_partlabel(mut self, glob: &str) -> Self {
+        self.config.save_partlabel.push(glob.into());
+        self
+    }
+
+    /// Save partitions with the specified number or range.
+    pub fn save_partindex(mut self, index: &str) -> Self {
+        self.config.save_partindex.push(index.into());
+        self
+    }
+
+    /// Preserve the /var partition of an existing installation.  If
+    /// `mount` is true, also add an Ignition mount unit for it.
+    pub fn preserve_var(mut self, mount: bool) -> Self {
+        self.config.preserve_var = true;
+        self.config.var_mount_unit = mount;
+        self
+    }
+
+    /// Allow an unsigned image.
+    pub fn insecure(mut self, insecure: bool) -> Self {
+        self.config.insecure = insecure;
+        self
+    }
+
+    /// Set the number of fetch retries.
+    pub fn fetch_retries(mut self, retries: FetchRetries) -> Self {
+        self.config.fetch_retries = retries.into();
+        self
+    }
+
+    /// Fetch the image in the specified number of parallel streams.
+    pub fn download_threads(mut self, threads: NonZeroU32) -> Self {
+        self.config.download_threads = Some(threads);
+        self
+    }
+
+    /// Run the install.  Fails without doing anything if the options
+    /// conflict in the same way as the corresponding command-line
+    /// options would.
+    pub fn install(self) -> Result<()> {
+        install(self.config.checked()?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn installer_builder() {
+        let installer = Installer::new()
+            .source(Source::File("a".into()))
+            .source(Source::Stream("b".into()))
+            .dest("/dev/c")
+            .dest("/dev/d")
+            .architecture("e")
+            .ignition_url(Url::parse("https://example.com/f").unwrap())
+            .ignition_file("g")
+            .append_karg("h")
+            .save_partindex("5-");
+        let config = installer.config;
+        assert_eq!(config.stream.as_deref(), Some("b"));
+        assert_eq!(config.image_file, None);
+        assert_eq!(config.dest_device, ["/dev/c", "/dev/d"]);
+        assert_eq!(config.architecture.as_str(), "e");
+        assert_eq!(config.ignition_url, None);
+        assert_eq!(config.ignition_file.as_deref(), Some("g"));
+        assert_eq!(config.append_karg, ["h"]);
+        assert_eq!(config.save_partindex, ["5-"]);
+    }
+
+    #[test]
+    fn installer_checked() {
+        let config = Installer::new()
+            .source(Source::Stream("a".into()))
+            .dest("/dev/b")
+            .fetch_retries(FetchRetries::Infinite)
+            .config
+            .checked()
+            .unwrap();
+        assert_eq!(config.stream.as_deref(), Some("a"));
+        assert_eq!(config.dest_device, ["/dev/b"]);
+        assert_eq!(config.fetch_retries, cmdline::FetchRetries::Infinite);
+
+        // conflicting destinations
+        Installer::new()
+            .dest("/dev/b")
+            .dest_image_file("c", None)
+            .config
+            .checked()
+            .unwrap_err();
+        // no destination
+        Installer::new().config.checked().unwrap_err();
+    }
+
+    #[test]
+    fn initrd_wrapper() {
+        let mut initrd = Initrd::default();
+        initrd.add("a", b"b".to_vec());
+        initrd.add_symlink("c", "a");
+        initrd.set_compression("gzip:1".parse().unwrap());
+        let initrd = Initrd::from_reader(&*initrd.to_bytes().unwrap()).unwrap();
+        assert_eq!(initrd.get("a"), Some(&b"b"[..]));
+        assert_eq!(
+            initrd.metadata("c").unwrap().kind,
+            InitrdEntryKind::Symlink("a".into())
+        );
+        assert_eq!(initrd.find(&GlobMatcher::new(&["a*"]).unwrap()).len(), 1);
+
+        assert_eq!(InitrdCompression::default(), InitrdCompression::Xz(9));
+        assert_eq!(
+            "zstd".parse::<InitrdCompression>().unwrap().to_string(),
+            "zstd:19"
+        );
+        "zstd:20".parse::<InitrdCompression>().unwrap_err();
+    }
+}
@@ -17,6 +17,7 @@
 
 use clap::Parser;
 
+use libcoreinst::cmdline::ByteSize;
 #[cfg(target_arch = "s390x")]
 use libcoreinst::s390x;
 
@@ -32,6 +33,8 @@ pub enum Cmd {
     Rootmap(RootmapConfig),
     /// Generate bootmap kargs and binds bootfs to rootfs and GRUB
     BindBoot(BindBootConfig),
+    /// Copy network config from the live environment to an installed system
+    CopyNetwork(CopyNetworkConfig),
     /// Modify kargs in BLS configs
     Kargs(KargsConfig),
     /// Copy data from stdin to stdout, checking piecewise hashes
@@ -56,6 +59,9 @@ pub struct RootmapConfig {
     /// Boot mount containing BLS entries to modify
     #[arg(long, value_name = "BOOT_MOUNT", conflicts_with = "boot_device")]
     pub boot_mount: Option<String>,
+    /// Print computed kargs as JSON without modifying BLS entries
+    #[arg(long)]
+    pub dry_run: bool,
     /// Path to rootfs mount
     #[arg(value_name = "ROOT_MOUNT")]
     pub root_mount: String,
@@ -71,6 +77,17 @@ pub struct BindBootConfig {
     pub boot_mount: String,
 }
 
+#[derive(Debug, Parser)]
+pub struct CopyNetworkConfig {
+    /// Source directory of NetworkManager keyfiles
+    #[arg(long, value_name = "DIR")]
+    #[arg(default_value = "/etc/NetworkManager/system-connections/")]
+    pub from: String,
+    /// Boot device of the installed system
+    #[arg(long, value_name = "DEVPATH")]
+    pub to_boot: String,
+}
+
 #[derive(Debug, Parser)]
 pub struct KargsConfig {
     // see comment block in rootmap command above
@@ -104,6 +121,18 @@ pub struct KargsConfig {
     #[arg(long, value_name = "ARG")]
     #[arg(alias = "should-not-exist")]
     pub delete: Vec<String>,
+    /// Append kernel arg ARG if IF is present
+    ///
+    /// IF is checked against the original kernel args.  If IF doesn't
+    /// contain "=", it also matches any arg of the form IF=VALUE.
+    #[arg(long, num_args = 2, value_names = ["IF", "ARG"])]
+    pub append_if_present: Vec<String>,
+    /// Delete kernel arg ARG if IF is present
+    #[arg(long, num_args = 2, value_names = ["IF", "ARG"])]
+    pub delete_if_present: Vec<String>,
+    /// Delete kernel arg ARG if IF is missing
+    #[arg(long, num_args = 2, value_names = ["IF", "ARG"])]
+    pub delete_if_missing: Vec<String>,
 }
 
 #[derive(Debug, Parser)]
@@ -111,17 +140,34 @@ pub struct StreamHashConfig {
     /// Path to the piecewise hash file
     #[arg(value_name = "hash-file")]
     pub hash_file: String,
+    /// Detached GPG signature of the hash file
+    #[arg(long, value_name = "path")]
+    pub signature: Option<String>,
+    /// Report progress to the kernel console
+    #[arg(long)]
+    pub progress: bool,
+    /// Report progress after every N bytes
+    #[arg(long, value_name = "N", default_value = "64M", requires = "progress")]
+    pub bytes_per_tick: ByteSize,
 }
 
 #[derive(Debug, Parser)]
 pub struct VerifyUniqueFsLabelConfig {
-    /// Filesystem's label
-    #[arg(value_name = "LABEL")]
-    pub label: String,
+    /// Filesystem labels
+    #[arg(value_name = "LABEL", required = true)]
+    pub labels: Vec<String>,
 
     /// Force rereading of partition table
     #[arg(long)]
     pub rereadpt: bool,
+
+    /// Clear duplicate labels on disks other than --keep-disk
+    #[arg(long, requires = "keep_disk")]
+    pub fix: bool,
+
+    /// Disk whose filesystems to keep with --fix
+    #[arg(long, value_name = "DISK", requires = "fix")]
+    pub keep_disk: Option<String>,
 }
 
 #[cfg(target_arch = "s390x")]
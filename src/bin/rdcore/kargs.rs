@@ -71,6 +71,9 @@ fn modify_and_print(config: &KargsConfig, orig_options: &str) -> Result<Option<S
         .delete(config.delete.as_slice())
         .append(config.append.as_slice())
         .append_if_missing(config.append_if_missing.as_slice())
+        .append_if_present(&pairs(&config.append_if_present))
+        .delete_if_present(&pairs(&config.delete_if_present))
+        .delete_if_missing(&pairs(&config.delete_if_missing))
         .maybe_apply_to(orig_options)?;
 
     // we always print the final kargs
@@ -93,6 +96,15 @@ fn modify_and_print(config: &KargsConfig, orig_options: &str) -> Result<Option<S
     Ok(new_options)
 }
 
+/// Convert the flattened values of a two-valued option into (IF, ARG) pairs.
+fn pairs(values: &[String]) -> Vec<(String, String)> {
+    // clap ensures each occurrence has exactly two values
+    values
+        .chunks_exact(2)
+        .map(|c| (c[0].clone(), c[1].clone()))
+        .collect()
+}
+
 #[cfg(target_arch = "s390x")]
 pub fn zipl(config: ZiplConfig) -> Result<()> {
     let boot = Mount::from_existing(&config.boot_mount)?;
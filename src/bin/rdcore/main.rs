@@ -14,6 +14,7 @@
 
 mod cmdline;
 mod kargs;
+mod network;
 mod rootmap;
 mod stream_hash;
 mod unique_fs;
@@ -28,6 +29,7 @@ fn main() -> Result<()> {
         Cmd::Kargs(c) => kargs::kargs(c),
         Cmd::Rootmap(c) => rootmap::rootmap(c).context("Configuring rootmap"),
         Cmd::BindBoot(c) => rootmap::bind_boot(c).context("Failed to bind boot"),
+        Cmd::CopyNetwork(c) => network::copy_network(c).context("Copying network config"),
         Cmd::StreamHash(c) => stream_hash::stream_hash(c),
         Cmd::VerifyUniqueFsLabel(c) => unique_fs::verify_unique_fs(c),
         #[cfg(target_arch = "s390x")]
@@ -0,0 +1,26 @@
+// Copyright 2024 Red Hat, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::Result;
+
+use libcoreinst::install::copy_network_config;
+
+use crate::cmdline::*;
+use crate::rootmap::get_boot_mount_from_cmdline_args;
+
+pub fn copy_network(config: CopyNetworkConfig) -> Result<()> {
+    // the unwrap() here is safe because we always pass a boot device
+    let mount = get_boot_mount_from_cmdline_args(&None, &Some(config.to_boot))?.unwrap();
+    copy_network_config(mount.mountpoint(), &config.from)
+}
@@ -14,7 +14,9 @@
 
 use anyhow::{bail, Context, Result};
 use nix::mount;
+use serde::Serialize;
 use std::collections::HashMap;
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
@@ -30,6 +32,23 @@ use crate::cmdline::*;
 /// composefs, the mount of / and /sysroot will be distinct.
 const PHYSICAL_ROOT_MOUNT: &str = "sysroot";
 
+/// Computed rootmap, as printed by `rootmap --dry-run`.
+#[derive(Serialize)]
+struct RootmapOutput {
+    /// Backing devices of the physical root, from lowest to highest level
+    devices: Vec<DeviceKargs>,
+    root_uuid: String,
+    rootflags: Option<String>,
+    /// The complete kargs that would be injected into the BLS entries
+    kargs: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct DeviceKargs {
+    device: PathBuf,
+    kargs: Vec<String>,
+}
+
 pub fn rootmap(config: RootmapConfig) -> Result<()> {
     let root_mount_path = Path::new(&config.root_mount);
     // Get the mount point for the deployment root, which will have e.g. /etc which we might parse
@@ -45,19 +64,21 @@ pub fn rootmap(config: RootmapConfig) -> Result<()> {
 
     // for each of those, convert them to kargs
     let mut kargs = Vec::new();
+    let mut devices = Vec::new();
     for backing_device in backing_devices {
-        if let Some(dev_kargs) = device_to_kargs(&rootfs_mount, backing_device)? {
-            kargs.extend(dev_kargs);
-        }
+        let dev_kargs = device_to_kargs(&rootfs_mount, backing_device.clone())?.unwrap_or_default();
+        kargs.extend(dev_kargs.iter().cloned());
+        devices.push(DeviceKargs {
+            device: backing_device,
+            kargs: dev_kargs,
+        });
     }
 
     // we push the root kargs last, this has the nice property that the final order of kargs goes
     // from lowest level to highest; see also
     // https://github.com/coreos/fedora-coreos-tracker/issues/465
-    kargs.push(format!(
-        "root=UUID={}",
-        physical_mount.get_filesystem_uuid()?
-    ));
+    let root_uuid = physical_mount.get_filesystem_uuid()?;
+    kargs.push(format!("root=UUID={root_uuid}"));
 
     // we need this because with root= it's systemd that takes care of mounting via
     // systemd-fstab-generator, and it defaults to read-only otherwise
@@ -69,11 +90,28 @@ pub fn rootmap(config: RootmapConfig) -> Result<()> {
         kargs.push(format!("rootflags={rootflags}"));
     }
 
+    if config.dry_run {
+        // leave /boot alone and print everything we computed
+        let output = RootmapOutput {
+            devices,
+            root_uuid,
+            rootflags: (!rootflags.is_empty()).then(|| rootflags.to_string()),
+            kargs,
+        };
+        let stdout = std::io::stdout();
+        let mut out = stdout.lock();
+        serde_json::to_writer_pretty(&mut out, &output).context("failed to serialize rootmap")?;
+        out.write_all(b"\n").context("failed to write newline")?;
+        return Ok(());
+    }
+
     let boot_mount = get_boot_mount_from_cmdline_args(&config.boot_mount, &config.boot_device)?;
     if let Some(mount) = boot_mount {
         visit_bls_entry_options(mount.mountpoint(), |orig_options: &str| {
+            // don't repeat kargs already set at install time, such as
+            // rd.multipath=default from "iso customize --dest-multipath"
             KargsEditor::new()
-                .append(&kargs)
+                .append_if_missing(&kargs)
                 .maybe_apply_to(orig_options)
         })
         .context("appending rootmap kargs")?;
@@ -124,7 +162,9 @@ fn device_to_kargs(root: &Mount, device: PathBuf) -> Result<Option<Vec<String>>>
         } else {
             Ok(Some(get_luks_kargs(root, &device)?))
         }
-    } else if blktype == "part" || blktype == "disk" || blktype == "mpath" {
+    } else if blktype == "mpath" {
+        Ok(Some(vec!["rd.multipath=default".into()]))
+    } else if blktype == "part" || blktype == "disk" {
         Ok(None)
     } else {
         bail!("unknown block device type {}", blktype)
@@ -15,41 +15,117 @@
 use anyhow::{bail, Context, Result};
 use openssl::sha;
 use regex::Regex;
-use std::fs::OpenOptions;
-use std::io::{self, stdin, stdout, BufRead, BufReader, Read, Write};
+use std::fs::{read, OpenOptions};
+use std::io::{self, stdin, stdout, BufRead, BufReader, Cursor, Read, Write};
+
+use libcoreinst::io::{verify_detached, VerifyKeys};
 
 use crate::cmdline::*;
 
 const MAX_CHUNK_SIZE: usize = 64 * 1024 * 1024;
 
+type HashFunc = fn(&[u8]) -> Vec<u8>;
+
+/// Where --progress reports are written.
+const KMSG_PATH: &str = "/dev/kmsg";
+
 /// Copy a stream from stdin to stdout, verifying hashes of the data as we
 /// go.  Receives an input file in the following format:
 ///
-/// stream-hash sha256 <chunk-size>
-/// <hexdigest>
-/// <hexdigest>
-/// <hexdigest>
+/// stream-hash <algorithm>[,<algorithm>...] <chunk-size>
+/// <hexdigest>[ <hexdigest>...]
+/// <hexdigest>[ <hexdigest>...]
+/// <hexdigest>[ <hexdigest>...]
 /// ...
 ///
-/// Each digest represents exactly <chunk-size> bytes, except for the last
-/// one, which represents from one to <chunk-size> bytes.
+/// Supported algorithms are sha256 and sha512.  Each line has one digest
+/// per algorithm, in header order, separated by spaces.  Each line
+/// represents exactly <chunk-size> bytes, except for the last one, which
+/// represents from one to <chunk-size> bytes.
 ///
-/// We read <chunk-size> bytes into RAM, check their digest, write them to
+/// We read <chunk-size> bytes into RAM, check their digests, write them to
 /// stdout, and repeat.  We never write data to stdout until it's been
 /// verified, ensuring that the next program in the shell pipeline never
 /// sees untrusted data.
+///
+/// If a signature is specified, the hash file must have a valid detached
+/// GPG signature from the built-in keys.
 pub fn stream_hash(config: StreamHashConfig) -> Result<()> {
-    let mut hash_file = OpenOptions::new()
-        .read(true)
-        .open(&config.hash_file)
-        .with_context(|| format!("opening {}", config.hash_file))?;
-    do_stream_hash(&mut hash_file, &mut stdin().lock(), &mut stdout().lock())
+    let hash_file =
+        read(&config.hash_file).with_context(|| format!("reading {}", config.hash_file))?;
+    if let Some(path) = &config.signature {
+        let signature = read(path).with_context(|| format!("reading {path}"))?;
+        verify_detached(&hash_file, &signature, VerifyKeys::Production)
+            .with_context(|| format!("verifying signature of {}", config.hash_file))?;
+    }
+    let mut kmsg = None;
+    let progress = if config.progress {
+        if config.bytes_per_tick.0 == 0 {
+            bail!("--bytes-per-tick cannot be zero");
+        }
+        let f = kmsg.insert(
+            OpenOptions::new()
+                .write(true)
+                .open(KMSG_PATH)
+                .with_context(|| format!("opening {KMSG_PATH}"))?,
+        );
+        Some(Progress::new(f, config.bytes_per_tick.0))
+    } else {
+        None
+    };
+    do_stream_hash(
+        &mut Cursor::new(hash_file),
+        &mut stdin().lock(),
+        &mut stdout().lock(),
+        progress,
+    )
+}
+
+/// Periodic reports of the amount of verified data.
+struct Progress<'a> {
+    out: &'a mut dyn Write,
+    bytes_per_tick: u64,
+    next: u64,
+}
+
+impl<'a> Progress<'a> {
+    fn new(out: &'a mut dyn Write, bytes_per_tick: u64) -> Self {
+        Self {
+            out,
+            bytes_per_tick,
+            next: bytes_per_tick,
+        }
+    }
+
+    fn update(&mut self, offset: u64) {
+        if offset >= self.next {
+            self.report(offset, "");
+            self.next = (offset / self.bytes_per_tick + 1) * self.bytes_per_tick;
+        }
+    }
+
+    fn finish(&mut self, offset: u64) {
+        self.report(offset, ", done");
+    }
+
+    fn report(&mut self, offset: u64, suffix: &str) {
+        // Write each message in a single call, since each write to
+        // /dev/kmsg is a separate log record.  Ignore errors; progress
+        // reporting shouldn't interrupt the stream.
+        let msg = format!(
+            "rdcore stream-hash: verified {:.1} MiB{}\n",
+            offset as f64 / (1024 * 1024) as f64,
+            suffix
+        );
+        let _ = self.out.write_all(msg.as_bytes());
+    }
 }
 
 fn do_stream_hash(
     hash_file: &mut impl Read,
     input: &mut impl Read,
     output: &mut impl Write,
+    mut progress: Option<Progress>,
 ) -> Result<()> {
     // get buffered reader
     let mut hash_file = BufReader::new(hash_file);
@@ -65,18 +141,27 @@ fn do_stream_hash(
     }
 
     // parse it
-    let captures = Regex::new(r"^stream-hash ([a-z0-9]+) ([0-9]+)\n$")
+    let captures = Regex::new(r"^stream-hash ([a-z0-9]+(?:,[a-z0-9]+)*) ([0-9]+)\n$")
         .expect("compiling RE")
         .captures(&line)
         .context("couldn't parse hash file header")?;
-    let hash_func = match captures
+    let algorithms: Vec<&str> = captures
         .get(1)
         .expect("digest algorithm not found")
         .as_str()
-    {
-        "sha256" => sha::sha256,
-        d => bail!("unknown digest algorithm {}", d),
-    };
+        .split(',')
+        .collect();
+    let mut hash_funcs: Vec<HashFunc> = Vec::new();
+    for (i, algorithm) in algorithms.iter().enumerate() {
+        if algorithms[..i].contains(algorithm) {
+            bail!("duplicate digest algorithm {}", algorithm);
+        }
+        hash_funcs.push(match *algorithm {
+            "sha256" => |d| sha::sha256(d).to_vec(),
+            "sha512" => |d| sha::sha512(d).to_vec(),
+            d => bail!("unknown digest algorithm {}", d),
+        });
+    }
     let chunk_size = captures
         .get(2)
         .expect("chunk size not found")
@@ -97,10 +182,22 @@ fn do_stream_hash(
     let mut buf = vec![0u8; chunk_size];
     let mut offset: u64 = 0;
     for line in hash_file.lines() {
-        // get expected hash
+        // get expected hashes
         let line = line.context("couldn't read hash from hash file")?;
-        let expected_hash =
-            hex::decode(&line).with_context(|| format!("couldn't decode hash: {line:?}"))?;
+        let expected_hashes = line
+            .split(' ')
+            .map(|hash| {
+                hex::decode(hash).with_context(|| format!("couldn't decode hash: {hash:?}"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if expected_hashes.len() != hash_funcs.len() {
+            bail!(
+                "expected {} hashes, found {}: {:?}",
+                hash_funcs.len(),
+                expected_hashes.len(),
+                line
+            );
+        }
 
         // read data
         let mut count = 0;
@@ -118,25 +215,33 @@ fn do_stream_hash(
 
         // hash and compare
         let data = &buf[..count];
-        let found_hash = hash_func(data);
-        if expected_hash != found_hash {
-            bail!(
-                "hash mismatch at offset {}; expected {}, found {}",
-                offset,
-                hex::encode(expected_hash),
-                hex::encode(found_hash)
-            );
+        for (hash_func, expected_hash) in hash_funcs.iter().zip(expected_hashes) {
+            let found_hash = hash_func(data);
+            if expected_hash != found_hash {
+                bail!(
+                    "hash mismatch at offset {}; expected {}, found {}",
+                    offset,
+                    hex::encode(expected_hash),
+                    hex::encode(found_hash)
+                );
+            }
         }
 
         // write out buffer
         output.write_all(data).context("writing output")?;
         offset += data.len() as u64;
+        if let Some(progress) = progress.as_mut() {
+            progress.update(offset);
+        }
     }
 
     // ran out of hashes; make sure we ran out of data
     if input.read(&mut buf[..1]).context("draining input")? != 0 {
         bail!("found extra input data at offset {}", offset);
     }
+    if let Some(progress) = progress.as_mut() {
+        progress.finish(offset);
+    }
 
     Ok(())
 }
@@ -144,7 +249,6 @@ fn do_stream_hash(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Cursor;
 
     #[test]
     fn test_stream_hash() {
@@ -246,6 +350,47 @@ ef2323b075d71f44c62f62d37b29a5fc4f10c03579a3f6e5b00c2d9666a75e65
                 input: "asd",
                 err: None,
             },
+            // duplicate algorithm
+            Test {
+                hash_file: "stream-hash sha256,sha256 8\n",
+                input: "",
+                err: Some("duplicate digest algorithm sha256"),
+            },
+            // multiple digests
+            Test {
+                hash_file: "stream-hash sha256,sha512 8
+3af36011654a7bc5159ecf41c610f1f7dbd9deb0d5638f8626db66f7b6467106 5aaeba7fe585f817f0e37b9d4564c88cd52334314f0b08a4ca412be6c458b0b6034a97495596dd28dbb970529c799d0a129bb56d693a3601e4569f499403691d
+5f70ae29b3019ec851ef6b664b59d3fd88dda0de5eb58212ddbd97c65c3f8198 5c17b1679de0c60c7c4efb0c7144deddbbc127d117482af8a82d84a5cc5e43a3d9e82f35a96d4882a92704e9ecb1da0279ea1786d75619a4de3886ae8b6debff
+",
+                input: "asdfasd\nqwer\n",
+                err: None,
+            },
+            // sha512 only
+            Test {
+                hash_file: "stream-hash sha512 8
+5aaeba7fe585f817f0e37b9d4564c88cd52334314f0b08a4ca412be6c458b0b6034a97495596dd28dbb970529c799d0a129bb56d693a3601e4569f499403691d
+5c17b1679de0c60c7c4efb0c7144deddbbc127d117482af8a82d84a5cc5e43a3d9e82f35a96d4882a92704e9ecb1da0279ea1786d75619a4de3886ae8b6debff
+",
+                input: "asdfasd\nqwer\n",
+                err: None,
+            },
+            // second digest mismatch
+            Test {
+                hash_file: "stream-hash sha256,sha512 8
+3af36011654a7bc5159ecf41c610f1f7dbd9deb0d5638f8626db66f7b6467106 5aaeba7fe585f817f0e37b9d4564c88cd52334314f0b08a4ca412be6c458b0b6034a97495596dd28dbb970529c799d0a129bb56d693a3601e4569f499403691d
+5f70ae29b3019ec851ef6b664b59d3fd88dda0de5eb58212ddbd97c65c3f8198 6c17b1679de0c60c7c4efb0c7144deddbbc127d117482af8a82d84a5cc5e43a3d9e82f35a96d4882a92704e9ecb1da0279ea1786d75619a4de3886ae8b6debff
+",
+                input: "asdfasd\nqwer\n",
+                err: Some("hash mismatch at offset 8; expected 6c17b1679de0c60c7c4efb0c7144deddbbc127d117482af8a82d84a5cc5e43a3d9e82f35a96d4882a92704e9ecb1da0279ea1786d75619a4de3886ae8b6debff, found 5c17b1679de0c60c7c4efb0c7144deddbbc127d117482af8a82d84a5cc5e43a3d9e82f35a96d4882a92704e9ecb1da0279ea1786d75619a4de3886ae8b6debff"),
+            },
+            // missing digest
+            Test {
+                hash_file: "stream-hash sha256,sha512 8
+3af36011654a7bc5159ecf41c610f1f7dbd9deb0d5638f8626db66f7b6467106
+",
+                input: "asdfasd\n",
+                err: Some("expected 2 hashes, found 1: \"3af36011654a7bc5159ecf41c610f1f7dbd9deb0d5638f8626db66f7b6467106\""),
+            },
         ];
         for (i, test) in tests.iter().enumerate() {
             let mut output: Vec<u8> = Vec::new();
@@ -253,6 +398,7 @@ ef2323b075d71f44c62f62d37b29a5fc4f10c03579a3f6e5b00c2d9666a75e65
                 &mut Cursor::new(&test.hash_file),
                 &mut Cursor::new(&test.input),
                 &mut output,
+                None,
             ) {
                 Ok(_) => {
                     assert!(
@@ -270,4 +416,30 @@ ef2323b075d71f44c62f62d37b29a5fc4f10c03579a3f6e5b00c2d9666a75e65
             }
         }
     }
+
+    #[test]
+    fn test_stream_hash_progress() {
+        let hash_file = "stream-hash sha256 8
+3af36011654a7bc5159ecf41c610f1f7dbd9deb0d5638f8626db66f7b6467106
+3af36011654a7bc5159ecf41c610f1f7dbd9deb0d5638f8626db66f7b6467106
+3af36011654a7bc5159ecf41c610f1f7dbd9deb0d5638f8626db66f7b6467106
+5f70ae29b3019ec851ef6b664b59d3fd88dda0de5eb58212ddbd97c65c3f8198
+";
+        let input = "asdfasd\nasdfasd\nasdfasd\nqwer\n";
+        let mut reports: Vec<u8> = Vec::new();
+        let mut output: Vec<u8> = Vec::new();
+        do_stream_hash(
+            &mut Cursor::new(hash_file),
+            &mut Cursor::new(input),
+            &mut output,
+            Some(Progress::new(&mut reports, 16)),
+        )
+        .unwrap();
+        assert_eq!(output, input.as_bytes());
+        // one report after 16 bytes, and one at the end
+        assert_eq!(
+            String::from_utf8(reports).unwrap(),
+            "rdcore stream-hash: verified 0.0 MiB\nrdcore stream-hash: verified 0.0 MiB, done\n"
+        );
+    }
 }
@@ -14,19 +14,125 @@
 
 use crate::cmdline::*;
 use anyhow::{bail, Result};
+use std::collections::HashSet;
+use std::path::Path;
 
 use libcoreinst::blockdev::*;
 
 pub fn verify_unique_fs(config: VerifyUniqueFsLabelConfig) -> Result<()> {
-    let pts = get_filesystems_with_label(&config.label, config.rereadpt)?;
-    let count = pts.len();
-    if count != 1 {
-        bail!(
-            "System has {} devices with a filesystem labeled '{}': {:?}",
-            count,
-            config.label,
-            pts
-        );
+    // partitions of the disk whose filesystems we keep
+    let keep = match &config.keep_disk {
+        Some(disk) => device_and_holders(disk)?.into_iter().collect(),
+        None => HashSet::new(),
+    };
+
+    let mut rereadpt = config.rereadpt;
+    for label in &config.labels {
+        let mut pts = get_filesystems_with_label(label, rereadpt)?;
+        // no need to reread partition tables again for the other labels
+        rereadpt = false;
+        if config.fix && pts.len() > 1 {
+            let (kept, stale) = partition_kept(pts, &keep, device_and_holders)?;
+            if kept.len() != 1 {
+                bail!(
+                    "{} has {} filesystems labeled '{}'; not clearing duplicates: {:?}",
+                    config.keep_disk.as_deref().unwrap_or_default(),
+                    kept.len(),
+                    label,
+                    kept
+                );
+            }
+            for pt in &stale {
+                clear_filesystem_label(pt)?;
+                eprintln!("Cleared duplicate filesystem label '{label}' on {pt}");
+            }
+            udev_settle()?;
+            pts = get_filesystems_with_label(label, false)?;
+        }
+        let count = pts.len();
+        if count != 1 {
+            bail!(
+                "System has {} devices with a filesystem labeled '{}': {:?}",
+                count,
+                label,
+                pts
+            );
+        }
     }
     Ok(())
 }
+
+/// Return a device and the devices stacked on top of it, such as its
+/// partitions or the multipath device it's a path of.
+fn device_and_holders(dev: &str) -> Result<Vec<String>> {
+    Ok(lsblk(Path::new(dev), true)?
+        .into_iter()
+        .filter_map(|mut dev| dev.remove("NAME"))
+        .collect())
+}
+
+/// Split filesystems into those on the kept disk and stale duplicates.
+/// A multipath path device carries the same filesystem as the multipath
+/// device on top of it, so a device is kept if any of its holders is.
+fn partition_kept<F>(
+    pts: Vec<String>,
+    keep: &HashSet<String>,
+    holders: F,
+) -> Result<(Vec<String>, Vec<String>)>
+where
+    F: Fn(&str) -> Result<Vec<String>>,
+{
+    let mut kept = Vec::new();
+    let mut stale = Vec::new();
+    for pt in pts {
+        if holders(&pt)?.iter().any(|dev| keep.contains(dev)) {
+            kept.push(pt);
+        } else {
+            stale.push(pt);
+        }
+    }
+    Ok((kept, stale))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partition_kept() {
+        // mpatha is a multipath device over sda and sdb; sdc is a stale
+        // disk with a duplicate label
+        let holders = |dev: &str| -> Result<Vec<String>> {
+            let stack: &[&str] = match dev {
+                "/dev/sda4" | "/dev/sdb4" => &["/dev/mapper/mpatha4"],
+                _ => &[],
+            };
+            Ok(std::iter::once(dev)
+                .chain(stack.iter().copied())
+                .map(String::from)
+                .collect())
+        };
+        let keep: HashSet<String> = ["/dev/mapper/mpatha", "/dev/mapper/mpatha4"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let pts = |names: &[&str]| names.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        // the multipath device itself
+        let (kept, stale) =
+            partition_kept(pts(&["/dev/mapper/mpatha4", "/dev/sdc4"]), &keep, holders).unwrap();
+        assert_eq!(kept, pts(&["/dev/mapper/mpatha4"]));
+        assert_eq!(stale, pts(&["/dev/sdc4"]));
+
+        // a path device listed in place of the multipath device
+        let (kept, stale) =
+            partition_kept(pts(&["/dev/sdb4", "/dev/sdc4"]), &keep, holders).unwrap();
+        assert_eq!(kept, pts(&["/dev/sdb4"]));
+        assert_eq!(stale, pts(&["/dev/sdc4"]));
+
+        // nothing on the kept disk
+        let (kept, stale) = partition_kept(pts(&["/dev/sdc4"]), &keep, holders).unwrap();
+        assert!(kept.is_empty());
+        assert_eq!(stale, pts(&["/dev/sdc4"]));
+    }
+}
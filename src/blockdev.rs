@@ -18,6 +18,7 @@ use lazy_static::lazy_static;
 use nix::sys::stat::{major, minor};
 use nix::{errno::Errno, mount, sched};
 use regex::Regex;
+use serde::Serialize;
 use std::collections::{HashMap, HashSet};
 use std::fs::{
     canonicalize, metadata, read_dir, read_to_string, remove_dir, symlink_metadata, File,
@@ -25,6 +26,7 @@ use std::fs::{
 };
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::num::{NonZeroU32, NonZeroU64};
+use std::ops::Range;
 use std::os::linux::fs::MetadataExt;
 use std::os::raw::c_int;
 use std::os::unix::fs::FileTypeExt;
@@ -38,7 +40,7 @@ use uuid::Uuid;
 use crate::cmdline::PartitionFilter;
 use crate::util::*;
 
-use crate::{runcmd, runcmd_output};
+use crate::{runcmd, runcmd_output, status};
 
 #[derive(Debug)]
 pub struct Disk {
@@ -99,7 +101,7 @@ impl Disk {
         }
     }
 
-    fn get_partitions(&self) -> Result<Vec<Partition>> {
+    pub fn get_partitions(&self) -> Result<Vec<Partition>> {
         // walk each device in the output
         let mut result: Vec<Partition> = Vec::new();
         for devinfo in lsblk(Path::new(&self.path), true)? {
@@ -119,6 +121,7 @@ impl Disk {
                     result.push(Partition {
                         path: name.to_owned(),
                         label: devinfo.get("LABEL").map(<_>::to_string),
+                        partlabel: devinfo.get("PARTLABEL").map(<_>::to_string),
                         fstype: devinfo.get("FSTYPE").map(<_>::to_string),
                         parent: self.path.to_owned(),
                         mountpoint,
@@ -131,6 +134,7 @@ impl Disk {
                     result.push(Partition {
                         path: name.to_owned(),
                         label: devinfo.get("LABEL").map(<_>::to_string),
+                        partlabel: devinfo.get("PARTLABEL").map(<_>::to_string),
                         fstype: fstype.to_owned(),
                         parent: self.path.to_owned(),
                         mountpoint: None,
@@ -180,6 +184,30 @@ impl Disk {
         Ok(Vec::new())
     }
 
+    /// Find RAID and LVM member signatures on the disk and its partitions,
+    /// skipping partitions with the specified numbers.  If the number of
+    /// a partition can't be determined, it's skipped whenever any
+    /// partitions are.
+    pub fn get_stale_signatures(&self, skip_partitions: &[u32]) -> Result<Vec<Signature>> {
+        let mut devices = vec![self.path.clone()];
+        for part in self.get_partitions()? {
+            match get_partition_number(&part.path) {
+                Some(number) if skip_partitions.contains(&number) => continue,
+                None if !skip_partitions.is_empty() => continue,
+                _ => devices.push(part.path),
+            }
+        }
+        let mut ret = Vec::new();
+        for device in devices {
+            ret.extend(
+                find_signatures(&device)?
+                    .into_iter()
+                    .filter(|s| STALE_SIGNATURE_TYPES.contains(&s.kind.as_str())),
+            );
+        }
+        Ok(ret)
+    }
+
     /// Get a handle to the set of device nodes for individual partitions
     /// of the device.
     pub fn get_partition_table(&self) -> Result<Box<dyn PartTable>> {
@@ -334,10 +362,59 @@ impl Drop for PartTableKpartx {
     }
 }
 
+/// A loop device backed by a disk image file, with partition scanning
+/// enabled.  Detached when dropped.
+#[derive(Debug)]
+pub struct LoopDevice {
+    path: String,
+}
+
+impl LoopDevice {
+    pub fn new(file: &str) -> Result<Self> {
+        Self::attach(file, &[])
+    }
+
+    /// Attach the image file without allowing writes to it.
+    pub fn new_read_only(file: &str) -> Result<Self> {
+        Self::attach(file, &["--read-only"])
+    }
+
+    fn attach(file: &str, args: &[&str]) -> Result<Self> {
+        let path = cmd_output(
+            Command::new("losetup")
+                .args(["--find", "--show", "--partscan"])
+                .args(args)
+                .arg(file),
+        )
+        .with_context(|| format!("setting up loop device for {file}"))?
+        .trim()
+        .to_string();
+        if path.is_empty() {
+            bail!("losetup didn't report a loop device for {}", file);
+        }
+        let device = Self { path };
+        udev_settle()?;
+        Ok(device)
+    }
+
+    pub fn path(&self) -> &str {
+        self.path.as_str()
+    }
+}
+
+impl Drop for LoopDevice {
+    fn drop(&mut self) {
+        if let Err(e) = runcmd!("losetup", "--detach", &self.path) {
+            eprintln!("{e}")
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Partition {
     pub path: String,
     pub label: Option<String>,
+    pub partlabel: Option<String>,
     pub fstype: Option<String>,
 
     pub parent: String,
@@ -549,9 +626,8 @@ impl SavedPartitions {
         Self::new(disk, get_sector_size(disk)?.get() as u64, filters)
     }
 
-    /// Create a SavedPartitions for a file with a specified imputed sector
-    /// size.  Useful for unit tests, and fails on a real disk.
-    #[cfg(test)]
+    /// Create a SavedPartitions for a regular file, such as a disk image,
+    /// with a specified imputed sector size.  Fails on a real disk.
     pub fn new_from_file(
         disk: &mut File,
         sector_size: u64,
@@ -582,38 +658,31 @@ impl SavedPartitions {
             });
         }
 
-        // read GPT
-        let gpt = match GPT::find_from(disk) {
-            Ok(gpt) => gpt,
+        // read partition table and save partitions accepted by filters
+        let mut partitions = Vec::new();
+        match GPT::find_from(disk) {
+            Ok(gpt) => {
+                // cross-check GPT sector size
+                Self::verify_gpt_sector_size(&gpt, sector_size)?;
+
+                for (i, p) in gpt.iter() {
+                    if Self::matches_filters(i, p, filters) {
+                        partitions.push((i, p.clone()));
+                    }
+                }
+            }
             Err(gptman::Error::InvalidSignature) => {
-                // ensure no indexes are listed to be saved from a MBR disk
-                // we don't need to check for labels since MBR does not support them
+                // no GPT on this disk; look for an MBR instead.  we don't
+                // need to check for labels since MBR does not support them
                 if filters
                     .iter()
                     .any(|f| matches!(f, PartitionFilter::Index(_, _)))
                     && disk_has_mbr(disk).context("checking if disk has an MBR")?
                 {
-                    bail!("saving partitions from an MBR disk is not yet supported");
+                    partitions = Self::convert_mbr_partitions(disk, sector_size, filters)?;
                 }
-
-                // no GPT on this disk, so no partitions to save
-                return Ok(Self {
-                    sector_size,
-                    partitions: Vec::new(),
-                });
             }
             Err(e) => return Err(e).context("reading partition table"),
-        };
-
-        // cross-check GPT sector size
-        Self::verify_gpt_sector_size(&gpt, sector_size)?;
-
-        // save partitions accepted by filters
-        let mut partitions = Vec::new();
-        for (i, p) in gpt.iter() {
-            if Self::matches_filters(i, p, filters) {
-                partitions.push((i, p.clone()));
-            }
         }
         let result = Self {
             sector_size,
@@ -639,6 +708,14 @@ impl SavedPartitions {
         Ok(result)
     }
 
+    /// Return the original index and label of each saved partition.
+    pub fn get_saved_partitions(&self) -> Vec<(u32, &str)> {
+        self.partitions
+            .iter()
+            .map(|(i, p)| (*i, p.partition_name.as_str()))
+            .collect()
+    }
+
     fn verify_disk_sector_size(&self, disk: &File) -> Result<()> {
         if !disk
             .metadata()
@@ -671,19 +748,70 @@ impl SavedPartitions {
     }
 
     fn matches_filters(i: u32, p: &GPTPartitionEntry, filters: &[PartitionFilter]) -> bool {
+        p.is_used() && Self::matches_index_or_label(i, Some(p.partition_name.as_str()), filters)
+    }
+
+    /// Partitions without a label never match label filters.
+    fn matches_index_or_label(i: u32, label: Option<&str>, filters: &[PartitionFilter]) -> bool {
         use PartitionFilter::*;
-        if !p.is_used() {
-            return false;
-        }
         filters.iter().any(|f| match f {
             Index(Some(first), _) if first.get() > i => false,
             Index(_, Some(last)) if last.get() < i => false,
             Index(_, _) => true,
-            Label(glob) if glob.matches(p.partition_name.as_str()) => true,
-            _ => false,
+            Label(glob) => label.is_some_and(|l| glob.matches(l)),
         })
     }
 
+    /// Read the partitions accepted by index filters from an MBR partition
+    /// table and convert them to GPT partition entries.  Fail if a partition
+    /// can't be represented in a GPT on this disk.
+    fn convert_mbr_partitions(
+        disk: &mut File,
+        sector_size: u64,
+        filters: &[PartitionFilter],
+    ) -> Result<Vec<(u32, GPTPartitionEntry)>> {
+        // The GPT header and partition entries live after the MBR at the
+        // start of the disk, and a backup copy lives at the end.
+        let gpt_sectors = 1 + (128 * 128) / sector_size;
+        let disk_sectors = disk.seek(SeekFrom::End(0)).context("getting disk size")? / sector_size;
+        let first_usable = 1 + gpt_sectors;
+        let last_usable = disk_sectors
+            .checked_sub(gpt_sectors + 1)
+            .context("disk too small for GPT")?;
+
+        let mut partitions = Vec::new();
+        for p in read_mbr_partitions(disk, sector_size)? {
+            if !Self::matches_index_or_label(p.index, None, filters) {
+                continue;
+            }
+            let type_guid = mbr_type_to_gpt(p.sys).with_context(|| {
+                format!(
+                    "can't convert MBR partition {} of type {:#04x} to GPT",
+                    p.index, p.sys
+                )
+            })?;
+            let ending_lba = p.starting_lba + p.sectors - 1;
+            if p.starting_lba < first_usable || ending_lba > last_usable {
+                bail!(
+                    "MBR partition {} overlaps space needed by GPT; can't convert to GPT",
+                    p.index
+                );
+            }
+            partitions.push((
+                p.index,
+                GPTPartitionEntry {
+                    partition_type_guid: guid_to_gpt_bytes(type_guid),
+                    unique_partition_guid: *Uuid::new_v4().as_bytes(),
+                    starting_lba: p.starting_lba,
+                    ending_lba,
+                    attribute_bits: 0,
+                    partition_name: "".into(),
+                },
+            ));
+        }
+        Ok(partitions)
+    }
+
     /// Unconditionally write the saved partitions, and only the saved
     /// partitions, to the disk.  Write a protective MBR and overwrite any
     /// MBR boot code.  Updating the kernel partition table is the caller's
@@ -749,9 +877,11 @@ impl SavedPartitions {
             // use the next partition number in the sequence if we have to,
             // or the partition's original number if it's larger
             next = next.max(*i);
-            eprintln!(
+            status!(
                 "Saving partition {} (\"{}\") to new partition {}",
-                i, p.partition_name, next
+                i,
+                p.partition_name,
+                next
             );
             gpt[next] = p.clone();
             next += 1;
@@ -788,6 +918,22 @@ impl SavedPartitions {
     pub fn is_saved(&self) -> bool {
         !self.partitions.is_empty()
     }
+
+    /// Get the byte ranges of the saved partitions, sorted by offset.
+    pub fn get_saved_ranges(&self) -> Result<Vec<Range<u64>>> {
+        let mut ranges = self
+            .partitions
+            .iter()
+            .map(|(_, p)| {
+                let start = p.starting_lba.checked_mul(self.sector_size);
+                let end = (p.ending_lba + 1).checked_mul(self.sector_size);
+                Ok(start.context("overflow calculating partition start")?
+                    ..end.context("overflow calculating partition end")?)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        ranges.sort_unstable_by_key(|r| r.start);
+        Ok(ranges)
+    }
 }
 
 fn read_sysfs_dev_block_value_u64(maj: u64, min: u64, field: &str) -> Result<u64> {
@@ -873,13 +1019,28 @@ pub fn get_filesystems_with_label(label: &str, rereadpt: bool) -> Result<Vec<Str
     Ok(result)
 }
 
+/// Remove the label from the filesystem on a device.
+pub fn clear_filesystem_label(dev: &str) -> Result<()> {
+    let fstype = blkid_single(Path::new(dev))?
+        .remove("TYPE")
+        .with_context(|| format!("no filesystem type for {dev}"))?;
+    match fstype.as_str() {
+        "ext2" | "ext3" | "ext4" => runcmd!("e2label", dev, ""),
+        // "--" clears the label
+        "xfs" => runcmd!("xfs_admin", "-L", "--", dev),
+        "vfat" => runcmd!("fatlabel", "--reset", dev),
+        _ => bail!("don't know how to clear label of {fstype} filesystem on {dev}"),
+    }
+    .with_context(|| format!("clearing label of {dev}"))
+}
+
 pub fn lsblk(dev: &Path, with_deps: bool) -> Result<Vec<HashMap<String, String>>> {
     let mut cmd = Command::new("lsblk");
     // Older lsblk, e.g. in CentOS 7.6, doesn't support PATH, but --paths option
     cmd.arg("--pairs")
         .arg("--paths")
         .arg("--output")
-        .arg("NAME,LABEL,FSTYPE,TYPE,MOUNTPOINT,UUID")
+        .arg("NAME,LABEL,PARTLABEL,FSTYPE,TYPE,MOUNTPOINT,UUID")
         .arg(dev);
     if !with_deps {
         cmd.arg("--nodeps");
@@ -893,6 +1054,96 @@ pub fn lsblk(dev: &Path, with_deps: bool) -> Result<Vec<HashMap<String, String>>
     Ok(result)
 }
 
+/// A whole disk that could be an install destination.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskInfo {
+    pub path: String,
+    /// Size in bytes
+    pub size: u64,
+    pub model: Option<String>,
+    pub serial: Option<String>,
+    pub wwn: Option<String>,
+    pub removable: bool,
+    pub rotational: bool,
+}
+
+/// List the whole disks on the system, skipping read-only, zero-sized,
+/// and zram devices.
+pub fn list_disks() -> Result<Vec<DiskInfo>> {
+    let mut cmd = Command::new("lsblk");
+    cmd.arg("--pairs")
+        .arg("--paths")
+        .arg("--nodeps")
+        .arg("--bytes")
+        .arg("--output")
+        .arg("NAME,TYPE,SIZE,RO,RM,ROTA,MODEL,SERIAL,WWN");
+    parse_disk_list(&cmd_output(&mut cmd)?)
+}
+
+fn parse_disk_list(output: &str) -> Result<Vec<DiskInfo>> {
+    let mut result = Vec::new();
+    for line in output.lines() {
+        let dev = split_lsblk_line(line);
+        let field = |name: &str| dev.get(name).map(String::as_str);
+        let text = |name: &str| {
+            field(name)
+                .map(unescape_lsblk_value)
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+        };
+        let path = field("NAME").context("lsblk device missing NAME")?;
+        if field("TYPE") != Some("disk")
+            || field("RO") == Some("1")
+            || path.starts_with("/dev/zram")
+        {
+            continue;
+        }
+        let size = field("SIZE")
+            .with_context(|| format!("lsblk device {path} missing SIZE"))?
+            .parse()
+            .with_context(|| format!("parsing size of {path}"))?;
+        if size == 0 {
+            // e.g. card reader with no card
+            continue;
+        }
+        result.push(DiskInfo {
+            path: path.to_string(),
+            size,
+            model: text("MODEL"),
+            serial: text("SERIAL"),
+            wwn: text("WWN"),
+            removable: field("RM") == Some("1"),
+            rotational: field("ROTA") == Some("1"),
+        });
+    }
+    Ok(result)
+}
+
+/// Resolve the \xHH escapes lsblk uses for special characters in values.
+fn unescape_lsblk_value(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(pos) = rest.find("\\x") {
+        result.push_str(&rest[..pos]);
+        let escape = &rest[pos..];
+        match escape
+            .get(2..4)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            Some(byte) => {
+                result.push(byte as char);
+                rest = &escape[4..];
+            }
+            None => {
+                result.push_str(&escape[..2]);
+                rest = &escape[2..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
 /// Parse key-value pairs from blkid.
 fn split_blkid_line(line: &str) -> HashMap<String, String> {
     let (name, data) = match line.find(':') {
@@ -1045,6 +1296,178 @@ pub fn find_efi_vendor_dir(efi_mount: &Mount) -> Result<PathBuf> {
     Ok(vendor_dir.pop().unwrap())
 }
 
+/// Signature types which can cause a stale RAID array or LVM volume to be
+/// assembled if left on an install destination.
+const STALE_SIGNATURE_TYPES: &[&str] = &[
+    "linux_raid_member",
+    "LVM2_member",
+    "isw_raid_member",
+    "ddf_raid_member",
+];
+
+/// A superblock or other signature found by wipefs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub device: String,
+    pub offset: u64,
+    /// blkid type, e.g. "linux_raid_member"
+    pub kind: String,
+}
+
+impl Signature {
+    /// Erase the signature.
+    pub fn wipe(&self) -> Result<()> {
+        runcmd!(
+            "wipefs",
+            "--quiet",
+            "--offset",
+            format!("{:#x}", self.offset),
+            &self.device
+        )
+    }
+}
+
+/// List the signatures wipefs finds on a device.  Unlike blkid, wipefs
+/// reports every signature rather than only the most likely one.
+fn find_signatures(device: &str) -> Result<Vec<Signature>> {
+    let output = runcmd_output!(
+        "wipefs",
+        "--noheadings",
+        "--parsable",
+        "--output",
+        "OFFSET,TYPE",
+        device
+    )?;
+    parse_wipefs_output(device, &output)
+}
+
+fn parse_wipefs_output(device: &str, output: &str) -> Result<Vec<Signature>> {
+    output
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (offset, kind) = line
+                .split_once(',')
+                .with_context(|| format!("invalid wipefs output line: {line}"))?;
+            let offset = u64::from_str_radix(offset.trim_start_matches("0x"), 16)
+                .with_context(|| format!("parsing wipefs offset '{offset}'"))?;
+            Ok(Signature {
+                device: device.into(),
+                offset,
+                kind: kind.into(),
+            })
+        })
+        .collect()
+}
+
+/// Get the partition number of a partition device node, if it's a
+/// kernel partition.
+fn get_partition_number(path: &str) -> Option<u32> {
+    let dev = metadata(path).ok()?.st_rdev();
+    read_sysfs_dev_block_value(major(dev), minor(dev), "partition")
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// Mountpoints whose backing disks are in use by the running system.
+/// /run/media/iso holds the live ISO when booted from a disk rather than
+/// via PXE.
+const ACTIVE_MOUNTPOINTS: &[&str] = &["/", "/sysroot", "/var", "/run/media/iso"];
+
+/// Return the mountpoints in ACTIVE_MOUNTPOINTS whose filesystems are
+/// stored on `device`, either directly or via a partition, device-mapper
+/// device, or MD RAID array.
+pub fn find_active_mounts(device: &str) -> Result<Vec<String>> {
+    let dest = metadata(device)
+        .with_context(|| format!("getting metadata for {device}"))?
+        .st_rdev();
+    let dest = (major(dest), minor(dest));
+    let mounts = read_to_string("/proc/self/mounts").context("reading mount table")?;
+    let mut ret = Vec::new();
+    for &mountpoint in ACTIVE_MOUNTPOINTS {
+        // the last mount on a mountpoint is the visible one
+        let source = mounts
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>())
+            .filter(|mount| mount.len() == 6 && mount[1] == mountpoint)
+            .last()
+            .map(|mount| mount[0].to_string());
+        let Some(dev) = source.and_then(|s| get_mount_source_device(&s, mountpoint)) else {
+            continue;
+        };
+        if get_blkdev_ancestors(dev)?.contains(&dest) {
+            ret.push(mountpoint.to_string());
+        }
+    }
+    Ok(ret)
+}
+
+/// Get the major and minor number of the block device backing a mount,
+/// if any.  The mount source is usually the device node, but can be a
+/// placeholder such as /dev/root, so fall back to the device number of
+/// the mountpoint.  The latter isn't a real block device for e.g. btrfs
+/// or overlayfs, so check sysfs.
+fn get_mount_source_device(source: &str, mountpoint: &str) -> Option<(u64, u64)> {
+    let dev = metadata(source)
+        .ok()
+        .filter(|m| m.file_type().is_block_device())
+        .map(|m| m.st_rdev())
+        .or_else(|| metadata(mountpoint).ok().map(|m| m.st_dev()))?;
+    let dev = (major(dev), minor(dev));
+    Path::new(&format!("/sys/dev/block/{}:{}", dev.0, dev.1))
+        .exists()
+        .then_some(dev)
+}
+
+/// Return the major and minor number of a block device, followed by
+/// those of every device it's stored on: the disk containing a partition,
+/// and the underlying devices of a device-mapper device or RAID array,
+/// recursively.
+fn get_blkdev_ancestors(dev: (u64, u64)) -> Result<Vec<(u64, u64)>> {
+    let sysfs = format!("/sys/dev/block/{}:{}", dev.0, dev.1);
+    let dir = canonicalize(&sysfs).with_context(|| format!("canonicalizing {sysfs}"))?;
+    let mut parents = Vec::new();
+    if dir.join("partition").exists() {
+        // /sys/devices/.../block/sda/sda1
+        parents.push(
+            dir.parent()
+                .with_context(|| format!("finding parent of {}", dir.display()))?
+                .to_path_buf(),
+        );
+    }
+    let slaves = dir.join("slaves");
+    match read_dir(&slaves) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e).with_context(|| format!("reading dir {}", slaves.display())),
+        Ok(it) => {
+            for ent in it {
+                let ent = ent.with_context(|| format!("reading {} entry", slaves.display()))?;
+                parents.push(ent.path());
+            }
+        }
+    }
+
+    let mut ret = vec![dev];
+    for parent in parents {
+        let path = parent.join("dev");
+        let contents =
+            read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
+        let (maj, min) = contents
+            .trim_end()
+            .split_once(':')
+            .with_context(|| format!("parsing {}", path.display()))?;
+        let parent_dev = (
+            maj.parse()
+                .with_context(|| format!("parsing major number in {}", path.display()))?,
+            min.parse()
+                .with_context(|| format!("parsing minor number in {}", path.display()))?,
+        );
+        ret.extend(get_blkdev_ancestors(parent_dev)?);
+    }
+    Ok(ret)
+}
+
 /// Parse key-value pairs from lsblk --pairs.
 /// Newer versions of lsblk support JSON but the one in CentOS 7 doesn't.
 fn split_lsblk_line(line: &str) -> HashMap<String, String> {
@@ -1161,12 +1584,219 @@ pub fn get_block_device_size(file: &File) -> Result<NonZeroU64> {
     }
 }
 
+/// Drop the kernel's cached copy of a block device's contents, so
+/// subsequent reads come from the device.
+pub fn flush_buffer_cache(file: &File) -> Result<()> {
+    let fd = file.as_raw_fd();
+    match unsafe { ioctl::blkflsbuf(fd) } {
+        Ok(_) => Ok(()),
+        Err(e) => Err(anyhow!(e).context("flushing buffer cache")),
+    }
+}
+
+/// Discard a byte range of a block device.  Returns false if the device
+/// doesn't support discard.
+pub fn discard_range(file: &File, range: &Range<u64>) -> Result<bool> {
+    let fd = file.as_raw_fd();
+    let arg: [u64; 2] = [range.start, range.end - range.start];
+    match unsafe { ioctl::blkdiscard(fd, &arg) } {
+        Ok(_) => Ok(true),
+        Err(Errno::EOPNOTSUPP) => Ok(false),
+        Err(e) => {
+            Err(anyhow!(e).context(format!("discarding bytes {}-{}", range.start, range.end)))
+        }
+    }
+}
+
 /// Get the size of the GPT metadata at the start of the disk.
 pub fn get_gpt_size(file: &mut (impl Read + Seek)) -> Result<u64> {
     let gpt = GPT::find_from(file).context("reading GPT")?;
     Ok(gpt.header.first_usable_lba * gpt.sector_size)
 }
 
+/// Get the size of the disk image from the location of its backup GPT
+/// header.  This is only meaningful until the backup header is relocated
+/// to the end of the disk, which CoreOS does on first boot.
+pub fn get_gpt_image_size(file: &mut (impl Read + Seek)) -> Result<u64> {
+    let gpt = GPT::find_from(file).context("reading GPT")?;
+    Ok((gpt.header.backup_lba + 1) * gpt.sector_size)
+}
+
+/// Partition tables of a disk or disk image, as shown by `dev show disk`.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct DiskLayout {
+    pub sector_size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mbr: Option<MbrLayout>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gpt: Option<GptLayout>,
+    /// Partition numbers of the CoreOS partitions, if the disk has the
+    /// CoreOS layout
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coreos: Option<CoreosLayout>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct MbrLayout {
+    /// Whether the MBR only protects a GPT
+    pub protective: bool,
+    /// Primary partition entries; logical partitions aren't listed
+    pub partitions: Vec<MbrEntry>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct MbrEntry {
+    pub index: u32,
+    #[serde(rename = "type")]
+    pub sys: String,
+    pub start_lba: u64,
+    pub sectors: u64,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct GptLayout {
+    pub disk_guid: String,
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    pub backup_lba: u64,
+    pub partitions: Vec<GptPartition>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct GptPartition {
+    pub index: u32,
+    pub name: String,
+    pub type_guid: String,
+    pub guid: String,
+    pub start_lba: u64,
+    /// Inclusive
+    pub end_lba: u64,
+    /// Byte offset of the partition
+    pub offset: u64,
+    /// Size in bytes
+    pub size: u64,
+    pub attributes: u64,
+    /// Whether the partition matches the --save-partlabel and
+    /// --save-partindex filters
+    pub saved: bool,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct CoreosLayout {
+    pub boot: u32,
+    pub root: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub efi: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bios_boot: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prep_boot: Option<u32>,
+}
+
+impl DiskLayout {
+    /// Read the partition tables of a disk or disk image.  Partitions
+    /// matching the filters are marked as saved, the same way `install`
+    /// would select them.
+    pub fn read(disk: &mut File, filters: &[PartitionFilter]) -> Result<Self> {
+        let blkdev_sector_size = if disk
+            .metadata()
+            .context("getting disk metadata")?
+            .file_type()
+            .is_block_device()
+        {
+            Some(get_sector_size(disk)?.get() as u64)
+        } else {
+            None
+        };
+        let mbr = if disk_has_mbr(disk)? {
+            let partitions: Vec<MbrEntry> = read_mbr_entries(disk, 0)?
+                .into_iter()
+                .enumerate()
+                .filter(|(_, (sys, _, _))| *sys != 0)
+                .map(|(i, (sys, start_lba, sectors))| MbrEntry {
+                    index: i as u32 + 1,
+                    sys: format!("{sys:#04x}"),
+                    start_lba,
+                    sectors,
+                })
+                .collect();
+            Some(MbrLayout {
+                protective: partitions.iter().any(|p| p.sys == "0xee"),
+                partitions,
+            })
+        } else {
+            None
+        };
+
+        let gpt = match blkdev_sector_size {
+            Some(sector_size) => GPT::read_from(disk, sector_size),
+            None => GPT::find_from(disk),
+        };
+        let gpt = match gpt {
+            Ok(gpt) => Some(gpt),
+            Err(e) if mbr.as_ref().is_some_and(|m| m.protective) => {
+                return Err(e).context("found protective MBR but couldn't read GPT")
+            }
+            // no GPT
+            Err(_) => None,
+        };
+        let sector_size = gpt
+            .as_ref()
+            .map(|gpt| gpt.sector_size)
+            .or(blkdev_sector_size)
+            .unwrap_or(512);
+        let gpt = gpt.map(|gpt| GptLayout {
+            disk_guid: gpt_bytes_to_guid(&gpt.header.disk_guid),
+            first_usable_lba: gpt.header.first_usable_lba,
+            last_usable_lba: gpt.header.last_usable_lba,
+            backup_lba: gpt.header.backup_lba,
+            partitions: gpt
+                .iter()
+                .filter(|(_, p)| p.is_used())
+                .map(|(i, p)| GptPartition {
+                    index: i,
+                    name: p.partition_name.as_str().into(),
+                    type_guid: gpt_bytes_to_guid(&p.partition_type_guid),
+                    guid: gpt_bytes_to_guid(&p.unique_partition_guid),
+                    start_lba: p.starting_lba,
+                    end_lba: p.ending_lba,
+                    offset: p.starting_lba * sector_size,
+                    size: (p.ending_lba + 1).saturating_sub(p.starting_lba) * sector_size,
+                    attributes: p.attribute_bits,
+                    saved: SavedPartitions::matches_filters(i, p, filters),
+                })
+                .collect(),
+        });
+        let coreos = gpt.as_ref().and_then(|gpt| {
+            let find = |label: &str| {
+                gpt.partitions
+                    .iter()
+                    .find(|p| p.name == label)
+                    .map(|p| p.index)
+            };
+            Some(CoreosLayout {
+                boot: find("boot")?,
+                root: find("root")?,
+                efi: find("EFI-SYSTEM"),
+                bios_boot: find("BIOS-BOOT"),
+                prep_boot: find("PowerPC-PReP-boot"),
+            })
+        });
+        Ok(Self {
+            sector_size,
+            mbr,
+            gpt,
+            coreos,
+        })
+    }
+}
+
 fn disk_has_mbr(file: &mut (impl Read + Seek)) -> Result<bool> {
     let mut sig = [0u8; 2];
     file.seek(SeekFrom::Start(510))
@@ -1175,6 +1805,133 @@ fn disk_has_mbr(file: &mut (impl Read + Seek)) -> Result<bool> {
     Ok(sig == [0x55, 0xaa])
 }
 
+/// A primary or logical partition from an MBR partition table.
+#[derive(Debug, PartialEq, Eq)]
+struct MbrPartition {
+    index: u32,
+    sys: u8,
+    starting_lba: u64,
+    sectors: u64,
+}
+
+/// Read the primary and logical partitions from an MBR partition table.
+/// Extended partitions are walked but not returned.
+fn read_mbr_partitions(
+    disk: &mut (impl Read + Seek),
+    sector_size: u64,
+) -> Result<Vec<MbrPartition>> {
+    let mut result = Vec::new();
+    let mut logical_index = 5;
+    for (i, (sys, start, sectors)) in read_mbr_entries(disk, 0)?.into_iter().enumerate() {
+        match sys {
+            0 => continue,
+            0xee => bail!("found protective MBR without a valid GPT"),
+            0x05 | 0x0f | 0x85 => (),
+            _ => {
+                if sectors > 0 {
+                    result.push(MbrPartition {
+                        index: i as u32 + 1,
+                        sys,
+                        starting_lba: start,
+                        sectors,
+                    });
+                }
+                continue;
+            }
+        }
+
+        // walk the chain of extended boot records; each one describes a
+        // logical partition relative to itself, and the next EBR relative
+        // to the start of the extended partition
+        let mut ebr = start;
+        loop {
+            let entries = read_mbr_entries(disk, ebr * sector_size)
+                .with_context(|| format!("reading extended boot record at sector {ebr}"))?;
+            let (sys, offset, sectors) = entries[0];
+            if sys != 0 && sectors > 0 {
+                result.push(MbrPartition {
+                    index: logical_index,
+                    sys,
+                    starting_lba: ebr + offset,
+                    sectors,
+                });
+                logical_index += 1;
+            }
+            let (next_sys, next_offset, _) = entries[1];
+            if next_sys == 0 {
+                break;
+            }
+            if start + next_offset <= ebr {
+                bail!("extended boot record at sector {ebr} points backward");
+            }
+            ebr = start + next_offset;
+        }
+    }
+    Ok(result)
+}
+
+/// Read the (type, starting LBA, sector count) of the four entries of the
+/// partition table at the specified byte offset.
+fn read_mbr_entries(disk: &mut (impl Read + Seek), offset: u64) -> Result<[(u8, u64, u64); 4]> {
+    let mut buf = [0u8; 512];
+    disk.seek(SeekFrom::Start(offset))
+        .context("seeking to partition table")?;
+    disk.read_exact(&mut buf)
+        .context("reading partition table")?;
+    if buf[510..] != [0x55, 0xaa] {
+        bail!("missing partition table signature at offset {}", offset);
+    }
+    let mut entries = [(0, 0, 0); 4];
+    for (i, entry) in entries.iter_mut().enumerate() {
+        let e = &buf[446 + 16 * i..446 + 16 * (i + 1)];
+        *entry = (
+            e[4],
+            u32::from_le_bytes(e[8..12].try_into().unwrap()) as u64,
+            u32::from_le_bytes(e[12..16].try_into().unwrap()) as u64,
+        );
+    }
+    Ok(entries)
+}
+
+/// Map an MBR partition type to the equivalent GPT partition type GUID.
+fn mbr_type_to_gpt(sys: u8) -> Option<&'static str> {
+    match sys {
+        // Microsoft basic data
+        0x01 | 0x04 | 0x06 | 0x07 | 0x0b | 0x0c | 0x0e => {
+            Some("EBD0A0A2-B9E5-4433-87C0-68B6B72699C7")
+        }
+        // Linux swap
+        0x82 => Some("0657FD6D-A4AB-43C4-84E5-0933C84B4F4F"),
+        // Linux filesystem
+        0x83 => Some("0FC63DAF-8483-4772-8E79-3D69D8477DE4"),
+        // Linux LVM
+        0x8e => Some("E6D6D379-F507-44C2-A23C-238F2A3DF928"),
+        // EFI System
+        0xef => Some("C12A7328-F81F-11D2-BA4B-00A0C93EC93B"),
+        // Linux RAID
+        0xfd => Some("A19D880F-05FC-4D3B-A006-743F0F84911E"),
+        _ => None,
+    }
+}
+
+/// Convert a GUID string to the mixed-endian byte order used in the GPT.
+fn guid_to_gpt_bytes(guid: &str) -> [u8; 16] {
+    let mut bytes = *Uuid::parse_str(guid).expect("invalid GUID").as_bytes();
+    bytes[0..4].reverse();
+    bytes[4..6].reverse();
+    bytes[6..8].reverse();
+    bytes
+}
+
+/// Format a GUID stored in the mixed-endian byte order used in the GPT.
+fn gpt_bytes_to_guid(bytes: &[u8; 16]) -> String {
+    let mut bytes = *bytes;
+    bytes[0..4].reverse();
+    bytes[4..6].reverse();
+    bytes[6..8].reverse();
+    Uuid::from_bytes(bytes).to_string().to_uppercase()
+}
+
 pub fn have_udev() -> bool {
     Path::new("/run/udev/control").exists()
 }
@@ -1250,10 +2007,12 @@ pub fn is_dasd(device: &str, fd: Option<&mut File>) -> Result<bool> {
 #[allow(clippy::missing_safety_doc)]
 mod ioctl {
     use super::c_int;
-    use nix::{ioctl_none, ioctl_read, ioctl_read_bad, request_code_none};
+    use nix::{ioctl_none, ioctl_read, ioctl_read_bad, ioctl_write_ptr_bad, request_code_none};
     ioctl_none!(blkrrpart, 0x12, 95);
+    ioctl_none!(blkflsbuf, 0x12, 97);
     ioctl_read_bad!(blksszget, request_code_none!(0x12, 104), c_int);
     ioctl_read!(blkgetsize64, 0x12, 114, libc::size_t);
+    ioctl_write_ptr_bad!(blkdiscard, request_code_none!(0x12, 119), [u64; 2]);
 }
 
 #[cfg(test)]
@@ -1299,6 +2058,73 @@ mod tests {
         );
     }
 
+    #[test]
+    fn disk_list_parse() {
+        let output = r#"NAME="/dev/sda" TYPE="disk" SIZE="500107862016" RO="0" RM="0" ROTA="0" MODEL="Samsung\x20SSD\x20860 " SERIAL="S3Z9NB0K" WWN="0x5002538e40a1b2c3"
+NAME="/dev/sdb" TYPE="disk" SIZE="16008609792" RO="0" RM="1" ROTA="1" MODEL="Flash Disk"
+NAME="/dev/sr0" TYPE="rom" SIZE="1073741312" RO="0" RM="1"
+NAME="/dev/sdc" TYPE="disk" SIZE="0" RO="0" RM="1" MODEL="Card Reader"
+NAME="/dev/sdd" TYPE="disk" SIZE="1024" RO="1" RM="0"
+NAME="/dev/zram0" TYPE="disk" SIZE="8589934592" RO="0" RM="0"
+NAME="/dev/loop0" TYPE="loop" SIZE="1024" RO="1" RM="0""#;
+        assert_eq!(
+            parse_disk_list(output).unwrap(),
+            vec![
+                DiskInfo {
+                    path: "/dev/sda".into(),
+                    size: 500107862016,
+                    model: Some("Samsung SSD 860".into()),
+                    serial: Some("S3Z9NB0K".into()),
+                    wwn: Some("0x5002538e40a1b2c3".into()),
+                    removable: false,
+                    rotational: false,
+                },
+                DiskInfo {
+                    path: "/dev/sdb".into(),
+                    size: 16008609792,
+                    model: Some("Flash Disk".into()),
+                    serial: None,
+                    wwn: None,
+                    removable: true,
+                    rotational: true,
+                },
+            ]
+        );
+        parse_disk_list(r#"NAME="/dev/sda" TYPE="disk" SIZE="x""#).unwrap_err();
+        assert_eq!(unescape_lsblk_value(r#"a\x20b\x2"#), r#"a b\x2"#);
+    }
+
+    #[test]
+    fn wipefs_parse() {
+        assert_eq!(
+            parse_wipefs_output(
+                "/dev/sda1",
+                "0x1000,linux_raid_member\n0x218,LVM2_member\n0x438,ext4\n"
+            )
+            .unwrap(),
+            vec![
+                Signature {
+                    device: "/dev/sda1".into(),
+                    offset: 0x1000,
+                    kind: "linux_raid_member".into(),
+                },
+                Signature {
+                    device: "/dev/sda1".into(),
+                    offset: 0x218,
+                    kind: "LVM2_member".into(),
+                },
+                Signature {
+                    device: "/dev/sda1".into(),
+                    offset: 0x438,
+                    kind: "ext4".into(),
+                },
+            ]
+        );
+        assert_eq!(parse_wipefs_output("/dev/sda", "").unwrap(), vec![]);
+        parse_wipefs_output("/dev/sda", "0x1000").unwrap_err();
+        parse_wipefs_output("/dev/sda", "zzz,ext4").unwrap_err();
+    }
+
     #[test]
     fn blkid_split() {
         assert_eq!(split_blkid_line(r#""#), std::collections::HashMap::new());
@@ -1621,7 +2447,7 @@ mod tests {
             "incorrect error: {err:#}"
         );
 
-        // test trying to save partitions from a MBR disk
+        // test trying to save partitions from a protective MBR without a GPT
         let mut disk = make_unformatted_disk();
         gptman::GPT::write_protective_mbr_into(&mut disk, 512).unwrap();
         // label only
@@ -1631,14 +2457,14 @@ mod tests {
             SavedPartitions::new(&mut disk, 512, &[Index(index(1), index(1))])
                 .unwrap_err()
                 .to_string(),
-            "saving partitions from an MBR disk is not yet supported"
+            "found protective MBR without a valid GPT"
         );
         // label and index
         assert_eq!(
             SavedPartitions::new(&mut disk, 512, &[Index(index(1), index(1)), label("*i*")])
                 .unwrap_err()
                 .to_string(),
-            "saving partitions from an MBR disk is not yet supported"
+            "found protective MBR without a valid GPT"
         );
 
         // test sector size mismatch
@@ -1701,6 +2527,208 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_gpt_image_size() {
+        // the disk is resized after the GPT is written
+        let mut disk = make_disk(512, &Vec::new());
+        assert_eq!(get_gpt_image_size(&mut disk).unwrap(), 1024 * 1024);
+    }
+
+    #[test]
+    fn test_disk_layout() {
+        use PartitionFilter::*;
+        let make_part = |name: &str, start: u64, end: u64| GPTPartitionEntry {
+            partition_type_guid: guid_to_gpt_bytes("C12A7328-F81F-11D2-BA4B-00A0C93EC93B"),
+            unique_partition_guid: make_guid(name),
+            starting_lba: start * 2048,
+            ending_lba: end * 2048 - 1,
+            attribute_bits: 0,
+            partition_name: name.into(),
+        };
+        let parts = vec![
+            (1, make_part("BIOS-BOOT", 1, 2)),
+            (2, make_part("EFI-SYSTEM", 2, 4)),
+            (3, make_part("boot", 4, 8)),
+            (4, make_part("root", 8, 16)),
+            (5, make_part("data", 16, 32)),
+        ];
+        let mut disk = make_disk(512, &parts);
+        GPT::write_protective_mbr_into(&mut disk, 512).unwrap();
+        let filters = [Label(glob::Pattern::new("da*").unwrap())];
+        let layout = DiskLayout::read(&mut disk, &filters).unwrap();
+
+        assert_eq!(layout.sector_size, 512);
+        let mbr = layout.mbr.unwrap();
+        assert!(mbr.protective);
+        assert_eq!(mbr.partitions.len(), 1);
+        assert_eq!(mbr.partitions[0].sys, "0xee");
+        let gpt = layout.gpt.unwrap();
+        assert_eq!(gpt.partitions.len(), 5);
+        let esp = &gpt.partitions[1];
+        assert_eq!(esp.type_guid, "C12A7328-F81F-11D2-BA4B-00A0C93EC93B");
+        assert_eq!(esp.offset, 2 << 20);
+        assert_eq!(esp.size, 2 << 20);
+        assert_eq!(
+            gpt.partitions.iter().map(|p| p.saved).collect::<Vec<_>>(),
+            [false, false, false, false, true]
+        );
+        assert_eq!(
+            layout.coreos,
+            Some(CoreosLayout {
+                boot: 3,
+                root: 4,
+                efi: Some(2),
+                bios_boot: Some(1),
+                prep_boot: None,
+            })
+        );
+
+        // no partition table
+        let layout = DiskLayout::read(&mut make_unformatted_disk(), &[]).unwrap();
+        assert_eq!(layout.mbr, None);
+        assert_eq!(layout.gpt, None);
+        assert_eq!(layout.coreos, None);
+    }
+
+    #[test]
+    fn test_saved_partitions_mbr() {
+        use PartitionFilter::*;
+        let index = |i| Some(NonZeroU32::new(i).unwrap());
+        let label = |l| Label(glob::Pattern::new(l).unwrap());
+        // sectors per MiB
+        let mib = 2048;
+
+        // primary partitions 1 and 4, and extended partition 2 containing
+        // logical partitions 5 and 6
+        let mut base = make_unformatted_disk();
+        write_mbr(
+            &mut base,
+            0,
+            &[
+                (0x83, 4096 * mib, 1024 * mib),
+                (0x05, 5120 * mib, 2048 * mib),
+                (0, 0, 0),
+                (0x83, 2, 10),
+            ],
+        );
+        write_mbr(
+            &mut base,
+            5120 * mib * 512,
+            &[
+                (0x82, mib, 1023 * mib),
+                (0x05, 1024 * mib, 1024 * mib),
+                (0, 0, 0),
+                (0, 0, 0),
+            ],
+        );
+        write_mbr(
+            &mut base,
+            6144 * mib * 512,
+            &[(0x42, mib, 1023 * mib), (0, 0, 0), (0, 0, 0), (0, 0, 0)],
+        );
+        let mbr_part = |index, sys, starting_lba, sectors| MbrPartition {
+            index,
+            sys,
+            starting_lba,
+            sectors,
+        };
+        assert_eq!(
+            read_mbr_partitions(&mut base, 512).unwrap(),
+            vec![
+                mbr_part(1, 0x83, 4096 * mib, 1024 * mib),
+                mbr_part(5, 0x82, 5121 * mib, 1023 * mib),
+                mbr_part(6, 0x42, 6145 * mib, 1023 * mib),
+                mbr_part(4, 0x83, 2, 10),
+            ]
+        );
+
+        // MBR partitions have no labels
+        let saved = SavedPartitions::new(&mut base, 512, &[label("*")]).unwrap();
+        assert!(!saved.is_saved());
+        let saved =
+            SavedPartitions::new(&mut base, 512, &[label("*"), Index(index(7), None)]).unwrap();
+        assert!(!saved.is_saved());
+
+        // convert a primary and a logical partition and merge them into
+        // an image
+        let saved = SavedPartitions::new(
+            &mut base,
+            512,
+            &[Index(index(1), index(1)), Index(index(5), index(5))],
+        )
+        .unwrap();
+        assert_eq!(
+            saved.get_offset().unwrap(),
+            Some((4096 * mib * 512, "partition 1 (\"\")".into()))
+        );
+        let image_part = GPTPartitionEntry {
+            partition_type_guid: make_guid("type"),
+            unique_partition_guid: make_guid("root"),
+            starting_lba: mib,
+            ending_lba: 2200 * mib - 1,
+            attribute_bits: 0,
+            partition_name: "root".into(),
+        };
+        let mut image = make_disk(512, &vec![(1, image_part.clone())]);
+        let mut disk = make_unformatted_disk();
+        saved.merge(&mut image, &mut disk).unwrap();
+        let result = GPT::find_from(&mut disk).unwrap();
+        let found: Vec<(u32, [u8; 16], u64, u64)> = result
+            .iter()
+            .filter(|(_, p)| p.is_used())
+            .map(|(i, p)| (i, p.partition_type_guid, p.starting_lba, p.ending_lba))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (1, make_guid("type"), mib, 2200 * mib - 1),
+                (
+                    2,
+                    [
+                        0xaf, 0x3d, 0xc6, 0x0f, 0x83, 0x84, 0x72, 0x47, 0x8e, 0x79, 0x3d, 0x69,
+                        0xd8, 0x47, 0x7d, 0xe4
+                    ],
+                    4096 * mib,
+                    5120 * mib - 1
+                ),
+                (
+                    5,
+                    guid_to_gpt_bytes("0657FD6D-A4AB-43C4-84E5-0933C84B4F4F"),
+                    5121 * mib,
+                    6144 * mib - 1
+                ),
+            ]
+        );
+
+        // unknown partition type
+        assert_eq!(
+            SavedPartitions::new(&mut base, 512, &[Index(index(6), None)])
+                .unwrap_err()
+                .to_string(),
+            "can't convert MBR partition 6 of type 0x42 to GPT"
+        );
+        // partition overlapping the GPT
+        assert_eq!(
+            SavedPartitions::new(&mut base, 512, &[Index(index(4), index(4))])
+                .unwrap_err()
+                .to_string(),
+            "MBR partition 4 overlaps space needed by GPT; can't convert to GPT"
+        );
+    }
+
+    fn write_mbr(disk: &mut File, offset: u64, entries: &[(u8, u64, u64); 4]) {
+        let mut buf = [0u8; 512];
+        for (i, (sys, start, sectors)) in entries.iter().enumerate() {
+            let e = &mut buf[446 + 16 * i..446 + 16 * (i + 1)];
+            e[4] = *sys;
+            e[8..12].copy_from_slice(&(*start as u32).to_le_bytes());
+            e[12..16].copy_from_slice(&(*sectors as u32).to_le_bytes());
+        }
+        buf[510..].copy_from_slice(&[0x55, 0xaa]);
+        disk.seek(SeekFrom::Start(offset)).unwrap();
+        disk.write_all(&buf).unwrap();
+    }
+
     // TODO: The partitions array assumes 512-byte sectors and we don't
     // scale the start/end values for 4096.  This doesn't matter right now
     // because the only use of 4096-byte sectors is in an error test.
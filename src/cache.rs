@@ -0,0 +1,518 @@
+// Copyright 2024 Red Hat, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Local cache of downloaded images and stream metadata.  Each object is
+//! stored under its SHA-256 digest, and `index.json` records where it came
+//! from and when it was last used.  Objects are verified against their
+//! digest before every use.
+
+use anyhow::{Context, Result};
+use openssl::sha;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::cmdline::*;
+use crate::download::format_bytes;
+use crate::io::IgnitionHash;
+use crate::source::print_table;
+use crate::util::set_die_on_sigpipe;
+
+const CACHE_INDEX: &str = "index.json";
+
+/// Total size of cached objects above which the least recently used
+/// objects are evicted.
+pub const DEFAULT_CACHE_MAX_SIZE: u64 = 10 << 30;
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct CacheIndex {
+    entries: Vec<CacheEntry>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct CacheEntry {
+    pub sha256: String,
+    pub kind: CacheKind,
+    /// Filename of the original artifact
+    pub name: String,
+    pub url: String,
+    pub size: u64,
+    /// Whether a detached signature is stored alongside the object
+    pub signature: bool,
+    /// Seconds since the epoch
+    pub last_used: u64,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheKind {
+    Image,
+    Stream,
+}
+
+#[derive(Debug)]
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    /// Use the specified cache directory, or the default one.  Returns
+    /// None if no directory is specified and there's no default.
+    pub fn new(dir: Option<&str>) -> Option<Self> {
+        let dir = match dir {
+            Some(dir) => PathBuf::from(dir),
+            None => default_cache_dir()?,
+        };
+        Some(Self { dir })
+    }
+
+    /// The cache for an install or download, unless caching is disabled.
+    pub fn for_config(dir: Option<&str>, disabled: bool) -> Option<Self> {
+        if disabled {
+            None
+        } else {
+            Self::new(dir)
+        }
+    }
+
+    pub fn entries(&self) -> Result<Vec<CacheEntry>> {
+        Ok(self.read_index()?.entries)
+    }
+
+    /// Look up an image by digest, verify it, and return it along with its
+    /// signature, if any.  An object that fails verification is evicted.
+    pub fn get_image(&self, sha256: &str) -> Result<Option<(File, Option<Vec<u8>>)>> {
+        let hash = IgnitionHash::Sha256(hex::decode(sha256).context("decoding digest")?);
+        let mut index = self.read_index()?;
+        let Some(entry) = index.entries.iter_mut().find(|e| e.sha256 == sha256) else {
+            return Ok(None);
+        };
+        let path = self.object_path(sha256);
+        let verified = File::open(&path).ok().and_then(|mut file| {
+            hash.validate(&mut file).ok()?;
+            file.rewind().ok()?;
+            Some(file)
+        });
+        let Some(file) = verified else {
+            eprintln!("Discarding corrupt cached image {}", entry.name);
+            index.entries.retain(|e| e.sha256 != sha256);
+            self.remove_objects(sha256);
+            self.write_index(&index)?;
+            return Ok(None);
+        };
+        let signature = if entry.signature {
+            fs::read(self.signature_path(sha256)).ok()
+        } else {
+            None
+        };
+        entry.last_used = now();
+        self.write_index(&index)?;
+        Ok(Some((file, signature)))
+    }
+
+    /// Add a downloaded image to the cache if it matches the expected
+    /// digest.  Returns false if it doesn't match.
+    pub fn insert_image(
+        &self,
+        path: &Path,
+        sha256: &str,
+        url: &str,
+        signature: Option<&[u8]>,
+    ) -> Result<bool> {
+        let mut index = self.read_index()?;
+        if let Some(entry) = index.entries.iter_mut().find(|e| e.sha256 == sha256) {
+            if self.object_path(sha256).exists() {
+                entry.last_used = now();
+                self.write_index(&index)?;
+                return Ok(true);
+            }
+        }
+
+        let hash = IgnitionHash::Sha256(hex::decode(sha256).context("decoding digest")?);
+        let mut file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+        if hash.validate(&mut file).is_err() {
+            return Ok(false);
+        }
+        let size = file
+            .metadata()
+            .with_context(|| format!("reading metadata of {}", path.display()))?
+            .len();
+
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("creating {}", self.dir.display()))?;
+        let object = self.object_path(sha256);
+        let _ = fs::remove_file(&object);
+        // a hard link costs no space; if the original is later modified,
+        // verification will catch it
+        if fs::hard_link(path, &object).is_err() {
+            fs::copy(path, &object)
+                .with_context(|| format!("copying {} to cache", path.display()))?;
+        }
+        if let Some(signature) = signature {
+            fs::write(self.signature_path(sha256), signature)
+                .context("writing cached signature")?;
+        }
+
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        self.add_entry(CacheEntry {
+            sha256: sha256.into(),
+            kind: CacheKind::Image,
+            name,
+            url: url.into(),
+            size,
+            signature: signature.is_some(),
+            last_used: now(),
+        })?;
+        Ok(true)
+    }
+
+    /// Return the most recently cached stream metadata from the specified
+    /// URL, if it's intact, along with the time since it was fetched.
+    pub fn get_stream(&self, url: &str) -> Result<Option<(Vec<u8>, Duration)>> {
+        let mut index = self.read_index()?;
+        let Some(entry) = index
+            .entries
+            .iter_mut()
+            .find(|e| e.kind == CacheKind::Stream && e.url == url)
+        else {
+            return Ok(None);
+        };
+        let path = self.object_path(&entry.sha256);
+        let data = match fs::read(&path) {
+            Ok(data) if hex::encode(sha::sha256(&data)) == entry.sha256 => data,
+            _ => return Ok(None),
+        };
+        // the object is rewritten whenever the metadata is fetched
+        let age = fs::metadata(&path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.elapsed().ok())
+            .unwrap_or_default();
+        entry.last_used = now();
+        self.write_index(&index)?;
+        Ok(Some((data, age)))
+    }
+
+    /// Cache stream metadata fetched from the specified URL, replacing
+    /// any older copy.
+    pub fn insert_stream(&self, url: &str, data: &[u8]) -> Result<()> {
+        let sha256 = hex::encode(sha::sha256(data));
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("creating {}", self.dir.display()))?;
+        fs::write(self.object_path(&sha256), data).context("writing cached stream metadata")?;
+        let name = url.rsplit('/').next().unwrap_or_default().to_string();
+        self.add_entry(CacheEntry {
+            sha256,
+            kind: CacheKind::Stream,
+            name,
+            url: url.into(),
+            size: data.len() as u64,
+            signature: false,
+            last_used: now(),
+        })
+    }
+
+    /// Evict objects unused for longer than `max_age`, then the least
+    /// recently used objects until the cache is no larger than `max_size`.
+    /// Returns the evicted entries.
+    pub fn prune(
+        &self,
+        max_size: Option<u64>,
+        max_age: Option<Duration>,
+    ) -> Result<Vec<CacheEntry>> {
+        let mut index = self.read_index()?;
+        let evicted = self.evict(&mut index, max_size, max_age);
+        self.write_index(&index)?;
+        Ok(evicted)
+    }
+
+    fn add_entry(&self, entry: CacheEntry) -> Result<()> {
+        let mut index = self.read_index()?;
+        let mut replaced = Vec::new();
+        index.entries.retain(|e| {
+            let keep = e.sha256 != entry.sha256
+                && !(entry.kind == CacheKind::Stream
+                    && e.kind == CacheKind::Stream
+                    && e.url == entry.url);
+            if !keep && e.sha256 != entry.sha256 {
+                replaced.push(e.sha256.clone());
+            }
+            keep
+        });
+        for sha256 in replaced {
+            self.remove_objects(&sha256);
+        }
+        index.entries.push(entry);
+        self.evict(&mut index, Some(DEFAULT_CACHE_MAX_SIZE), None);
+        self.write_index(&index)
+    }
+
+    fn evict(
+        &self,
+        index: &mut CacheIndex,
+        max_size: Option<u64>,
+        max_age: Option<Duration>,
+    ) -> Vec<CacheEntry> {
+        let now = now();
+        let mut total: u64 = index.entries.iter().map(|e| e.size).sum();
+        let mut evicted = Vec::new();
+        // oldest first
+        index.entries.sort_by_key(|e| e.last_used);
+        index.entries.retain(|e| {
+            let expired =
+                max_age.is_some_and(|age| now.saturating_sub(e.last_used) > age.as_secs());
+            let oversize = max_size.is_some_and(|max| total > max);
+            if expired || oversize {
+                total -= e.size;
+                evicted.push(e.clone());
+            }
+            !(expired || oversize)
+        });
+        for entry in &evicted {
+            self.remove_objects(&entry.sha256);
+        }
+        evicted
+    }
+
+    fn read_index(&self) -> Result<CacheIndex> {
+        let path = self.dir.join(CACHE_INDEX);
+        match fs::read(&path) {
+            Ok(data) => {
+                serde_json::from_slice(&data).with_context(|| format!("parsing {}", path.display()))
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(CacheIndex::default()),
+            Err(e) => Err(e).with_context(|| format!("reading {}", path.display())),
+        }
+    }
+
+    fn write_index(&self, index: &CacheIndex) -> Result<()> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("creating {}", self.dir.display()))?;
+        let mut file = tempfile::Builder::new()
+            .prefix(".index-")
+            .tempfile_in(&self.dir)
+            .context("creating temporary file")?;
+        serde_json::to_writer_pretty(&mut file, index).context("serializing cache index")?;
+        file.write_all(b"\n").context("writing cache index")?;
+        file.persist(self.dir.join(CACHE_INDEX))
+            .map_err(|e| e.error)
+            .context("writing cache index")?;
+        Ok(())
+    }
+
+    fn object_path(&self, sha256: &str) -> PathBuf {
+        self.dir.join(sha256)
+    }
+
+    fn signature_path(&self, sha256: &str) -> PathBuf {
+        self.dir.join(format!("{sha256}.sig"))
+    }
+
+    fn remove_objects(&self, sha256: &str) {
+        let _ = fs::remove_file(self.object_path(sha256));
+        let _ = fs::remove_file(self.signature_path(sha256));
+    }
+}
+
+/// $XDG_CACHE_HOME/coreos-installer, falling back to
+/// ~/.cache/coreos-installer.
+fn default_cache_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| {
+            env::var_os("HOME")
+                .map(PathBuf::from)
+                .filter(|p| p.is_absolute())
+                .map(|p| p.join(".cache"))
+        })?;
+    Some(base.join("coreos-installer"))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+pub fn cache_list(config: CacheListConfig) -> Result<()> {
+    let cache = Cache::new(config.cache_dir.as_deref()).context("no cache directory found")?;
+    let mut entries = cache.entries()?;
+    // most recently used first
+    entries.sort_by_key(|e| std::cmp::Reverse(e.last_used));
+
+    set_die_on_sigpipe()?;
+    match config.format {
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&entries).context("serializing cache listing")?
+        ),
+        OutputFormat::Text => {
+            let now = now();
+            let fields: Vec<[String; 5]> = entries
+                .iter()
+                .map(|e| {
+                    [
+                        e.sha256[..12.min(e.sha256.len())].to_string(),
+                        match e.kind {
+                            CacheKind::Image => "image".into(),
+                            CacheKind::Stream => "stream".into(),
+                        },
+                        format_bytes(e.size),
+                        format!("{}d", now.saturating_sub(e.last_used) / 86400),
+                        e.name.clone(),
+                    ]
+                })
+                .collect();
+            let mut rows = vec![vec!["SHA256", "Kind", "Size", "Unused", "Name"]];
+            rows.extend(
+                fields
+                    .iter()
+                    .map(|f| f.iter().map(|s| s.as_str()).collect()),
+            );
+            print_table(&rows);
+        }
+    }
+    Ok(())
+}
+
+pub fn cache_prune(config: CachePruneConfig) -> Result<()> {
+    let cache = Cache::new(config.cache_dir.as_deref()).context("no cache directory found")?;
+    let (max_size, max_age) = if config.all {
+        (Some(0), None)
+    } else if config.max_size.is_none() && config.max_age.is_none() {
+        (Some(DEFAULT_CACHE_MAX_SIZE), None)
+    } else {
+        (
+            config.max_size.map(|s| s.0),
+            config
+                .max_age
+                .map(|days| Duration::from_secs(u64::from(days) * 86400)),
+        )
+    };
+    let evicted = cache.prune(max_size, max_age)?;
+    for entry in &evicted {
+        eprintln!("Removed {} ({})", entry.name, format_bytes(entry.size));
+    }
+    eprintln!(
+        "Freed {}",
+        format_bytes(evicted.iter().map(|e| e.size).sum())
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path().to_str()).unwrap();
+        assert!(cache.entries().unwrap().is_empty());
+
+        // images are only accepted with the right digest
+        let image = dir.path().join("image.raw.xz");
+        fs::write(&image, b"image").unwrap();
+        let sha256 = hex::encode(sha::sha256(b"image"));
+        let wrong = hex::encode(sha::sha256(b"other"));
+        assert!(!cache
+            .insert_image(&image, &wrong, "https://example.com/i", None)
+            .unwrap());
+        assert!(cache
+            .insert_image(&image, &sha256, "https://example.com/i", Some(b"sig"))
+            .unwrap());
+        let (_, signature) = cache.get_image(&sha256).unwrap().unwrap();
+        assert_eq!(signature.unwrap(), b"sig");
+        assert!(cache.get_image(&wrong).unwrap().is_none());
+
+        // corrupt objects are evicted
+        fs::remove_file(dir.path().join(&sha256)).unwrap();
+        fs::write(dir.path().join(&sha256), b"corrupt").unwrap();
+        assert!(cache.get_image(&sha256).unwrap().is_none());
+        assert!(cache.entries().unwrap().is_empty());
+
+        // newer stream metadata replaces older
+        cache
+            .insert_stream("https://example.com/s.json", b"1")
+            .unwrap();
+        cache
+            .insert_stream("https://example.com/s.json", b"2")
+            .unwrap();
+        assert_eq!(
+            cache
+                .get_stream("https://example.com/s.json")
+                .unwrap()
+                .unwrap()
+                .0,
+            b"2"
+        );
+        assert!(cache
+            .get_stream("https://example.com/t.json")
+            .unwrap()
+            .is_none());
+        assert_eq!(cache.entries().unwrap().len(), 1);
+        assert!(!dir.path().join(hex::encode(sha::sha256(b"1"))).exists());
+    }
+
+    #[test]
+    fn test_evict() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path().to_str()).unwrap();
+        let entry = |sha256: &str, size, last_used| CacheEntry {
+            sha256: sha256.into(),
+            kind: CacheKind::Image,
+            name: sha256.into(),
+            url: String::new(),
+            size,
+            signature: false,
+            last_used,
+        };
+        let now = now();
+        let mut index = CacheIndex {
+            entries: vec![
+                entry("a", 10, now - 10),
+                entry("b", 10, now - 30 * 86400),
+                entry("c", 10, now - 20),
+            ],
+        };
+        let names = |entries: &[CacheEntry]| -> Vec<String> {
+            entries.iter().map(|e| e.sha256.clone()).collect()
+        };
+
+        // nothing to do
+        assert!(cache.evict(&mut index, Some(30), None).is_empty());
+        // least recently used first
+        let evicted = cache.evict(&mut index, Some(15), None);
+        assert_eq!(names(&evicted), ["b", "c"]);
+        assert_eq!(names(&index.entries), ["a"]);
+
+        let mut index = CacheIndex {
+            entries: vec![entry("a", 10, now - 10), entry("b", 10, now - 30 * 86400)],
+        };
+        let evicted = cache.evict(&mut index, None, Some(Duration::from_secs(86400)));
+        assert_eq!(names(&evicted), ["b"]);
+        let evicted = cache.evict(&mut index, Some(0), None);
+        assert_eq!(names(&evicted), ["a"]);
+        assert!(index.entries.is_empty());
+    }
+}
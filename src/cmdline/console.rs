@@ -95,6 +95,24 @@ impl Console {
         format!("{KARG_PREFIX}{self}")
     }
 
+    /// The console device name, without serial parameters.
+    pub fn device(&self) -> String {
+        match self {
+            Self::Graphical(c) => c.device.clone(),
+            Self::Serial(c) => format!("{}{}", c.prefix, c.port),
+        }
+    }
+
+    /// Parse the "console=" arguments in kargs, skipping any we can't
+    /// parse.
+    pub fn from_kargs(kargs: &[String]) -> Vec<Self> {
+        kargs
+            .iter()
+            .filter_map(|a| a.strip_prefix(KARG_PREFIX))
+            .filter_map(|a| Self::from_str(a).ok())
+            .collect()
+    }
+
     /// Write a warning message to stdout if kargs contains "console="
     /// arguments we can parse and no "console=" arguments we can't.  The
     /// warning suggests that users use console_option instead of
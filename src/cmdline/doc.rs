@@ -15,8 +15,8 @@
 //! Support for generating docs.
 
 use anyhow::{bail, Context, Result};
-use clap::{crate_version, ArgAction, Command, CommandFactory};
-use serde::{de, forward_to_deserialize_any, Deserialize};
+use clap::{crate_version, Arg, ArgAction, Command, CommandFactory};
+use serde_json::json;
 use std::collections::{hash_map::RandomState, HashMap};
 use std::fmt::Write as _;
 use std::fs::OpenOptions;
@@ -25,6 +25,7 @@ use std::path::Path;
 
 use crate::io::BUFFER_SIZE;
 
+use super::serializer::{struct_fields, CONFIG_VERSION};
 use super::{Cmd, InstallConfig, PackExampleConfigConfig, PackManConfig};
 
 pub fn pack_man(config: PackManConfig) -> Result<()> {
@@ -62,111 +63,171 @@ fn pack_one_man(config: &PackManConfig, cmd: Command) -> Result<()> {
     Ok(())
 }
 
-pub fn pack_example_config(_config: PackExampleConfigConfig) -> Result<()> {
+pub fn pack_example_config(config: PackExampleConfigConfig) -> Result<()> {
     // first get the field list from serde's perspective, so we omit any
     // fields hidden from it
-    let mut fields = Vec::new();
-    // we usually skip empty fields on serialize, so we have to do this
-    // via the deserializer instead
-    InstallConfig::deserialize(&mut FieldLister {
-        fields: &mut fields,
-    })
-    .unwrap_err();
+    let fields = struct_fields::<InstallConfig>();
 
     // now use that list to query clap arguments and format output
-    let mut out = String::new();
     let cmd = InstallConfig::command();
     // argument name -> clap::Arg
     let arg_map: HashMap<_, _, RandomState> =
         HashMap::from_iter(cmd.get_arguments().map(|arg| (arg.get_id().as_str(), arg)));
-    for field in &fields {
-        if let Some(arg) = arg_map.get(&*field.replace('-', "_")) {
-            // output help comment
-            // we can't serialize through serde_yaml because it doesn't
-            // support comments
-            if let Some(help) = arg.get_help() {
-                // strip StyledStr
-                let help = format!("{}", help);
-                let help = match field.as_ref() {
-                    // clarify YAML syntax for "infinite"
-                    "fetch-retries" => r#"Fetch retries, or string "infinite""#,
-                    // don't reference -n short option
-                    "network-dir" => "Source directory for copy-network",
-                    // "arg" => "arguments"
-                    "append-karg" => "Append default kernel arguments",
-                    "delete-karg" => "Delete default kernel arguments",
-                    _ => &help,
-                };
-                writeln!(out, "# {help}").unwrap();
-            }
+    let args = fields
+        .iter()
+        .map(|field| match arg_map.get(&*field.replace('-', "_")) {
+            Some(arg) => Ok((field.as_str(), *arg)),
+            None => bail!("couldn't look up field {}", field),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if config.schema {
+        let schema = serde_json::to_string_pretty(&example_config_schema(&args))
+            .context("serializing schema")?;
+        println!("{schema}");
+    } else {
+        print!("{}", example_config_yaml(&args)?);
+    }
+    Ok(())
+}
 
-            // output "field: argument-description"
-            let value_names = arg.get_value_names().map(|v| v[0].as_str());
-            let desc = match arg.get_action() {
-                ArgAction::Set => {
-                    // option with argument
-                    match field.as_ref() {
-                        // positional arguments have different formatting
-                        "dest-device" => "path",
-                        _ => value_names.expect("missing value name"),
-                    }
-                    .into()
-                }
-                ArgAction::Append => {
-                    // value array
-                    let value_name = match field.as_ref() {
-                        // more verbose than 80 columns will allow
-                        "save-partlabel" => "glob",
-                        "save-partindex" => "id-or-range",
-                        _ => value_names.expect("missing value name"),
-                    };
-                    format!("[{0}, {0}]", value_name)
-                }
-                _ => {
-                    // option flag
-                    "true".into()
-                }
-            };
-            writeln!(out, "{field}: {desc}").unwrap();
-        } else {
-            bail!("couldn't look up field {}", field);
+fn example_config_yaml(args: &[(&str, &Arg)]) -> Result<String> {
+    let mut out = String::new();
+    writeln!(out, "# Config file format version").unwrap();
+    writeln!(out, "version: {CONFIG_VERSION}").unwrap();
+    for &(field, arg) in args {
+        // output help comment
+        // we can't serialize through serde_yaml because it doesn't
+        // support comments
+        if let Some(help) = field_help(field, arg) {
+            writeln!(out, "# {help}").unwrap();
         }
+
+        // output "field: argument-description"
+        let value_names = arg.get_value_names().map(|v| v[0].as_str());
+        let desc = match arg.get_action() {
+            ArgAction::Set => {
+                // option with argument
+                value_names.expect("missing value name").into()
+            }
+            ArgAction::Append if field == "dest-device" => {
+                // positional arguments have different formatting, and
+                // a single device is the common case
+                "path".into()
+            }
+            ArgAction::Append => {
+                // value array
+                let value_name = match field {
+                    // more verbose than 80 columns will allow
+                    "save-partlabel" => "glob",
+                    "save-partindex" => "id-or-range",
+                    _ => value_names.expect("missing value name"),
+                };
+                format!("[{0}, {0}]", value_name)
+            }
+            _ => {
+                // option flag
+                "true".into()
+            }
+        };
+        writeln!(out, "{field}: {desc}").unwrap();
     }
 
     // since we hand-rolled YAML output, make sure it parses
     serde_yaml::from_str::<serde_yaml::Value>(&out).context("re-parsing output")?;
-
-    print!("{}", out);
-    Ok(())
-}
-
-struct FieldLister<'a> {
-    fields: &'a mut Vec<String>,
+    Ok(out)
 }
 
-impl<'de, 'a> de::Deserializer<'de> for &'a mut FieldLister<'a> {
-    type Error = de::value::Error;
-
-    fn deserialize_struct<V: de::Visitor<'de>>(
-        self,
-        _name: &'static str,
-        fields: &'static [&'static str],
-        _visitor: V,
-    ) -> Result<V::Value, Self::Error> {
-        self.fields.extend(fields.iter().map(|v| v.to_string()));
-        // we don't want to bother generating a default struct and throwing
-        // it away, so fail
-        Err(de::Error::custom("expected failure"))
-    }
-
-    // fill out the API contract
-    fn deserialize_any<V: de::Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
-        unimplemented!()
+/// JSON Schema for install config files.  Clap doesn't know the types
+/// of option values, so fields with structured values are listed here,
+/// and the rest are described as strings.
+fn example_config_schema(args: &[(&str, &Arg)]) -> serde_json::Value {
+    let mut properties = serde_json::Map::new();
+    properties.insert(
+        "version".into(),
+        json!({
+            "description": "Config file format version",
+            "type": "integer",
+            "minimum": 1,
+            "maximum": CONFIG_VERSION,
+        }),
+    );
+    for &(field, arg) in args {
+        let value = match field {
+            "fetch-retries" | "metadata-fetch-retries" => json!({
+                "anyOf": [
+                    {"type": "integer", "minimum": 0},
+                    {"type": "string", "pattern": "^([0-9]+|infinite)$"},
+                ]
+            }),
+            "fetch-retry-max-delay" | "download-threads" => {
+                json!({"type": "integer", "minimum": 1})
+            }
+            "limit-rate" | "write-rate" | "sync-interval" | "image-size" => json!({
+                "anyOf": [
+                    {"type": "integer", "minimum": 0},
+                    {"type": "string", "pattern": "^[0-9]+ *([KMGTkmgt]([Ii][Bb])?)?$"},
+                ]
+            }),
+            "image-url" | "ignition-url" | "stream-base-url" | "http-proxy" | "https-proxy" => {
+                json!({"type": "string", "format": "uri"})
+            }
+            "ignition-hash" => {
+                json!({"type": "string", "pattern": "^(sha256-[0-9a-fA-F]{64}|sha512-[0-9a-fA-F]{128})$"})
+            }
+            "encrypt-root" => json!({"type": "string", "pattern": "^(tpm2|tang=.+,.+)$"}),
+            "discard" => json!({"enum": ["before", "after", "both"]}),
+            _ => {
+                let values: Vec<_> = arg
+                    .get_possible_values()
+                    .iter()
+                    .map(|v| v.get_name().to_string())
+                    .collect();
+                if values.is_empty() {
+                    json!({"type": "string"})
+                } else {
+                    json!({"enum": values})
+                }
+            }
+        };
+        let mut schema = match arg.get_action() {
+            ArgAction::Set => value,
+            ArgAction::Append if field == "dest-device" => json!({
+                "anyOf": [value, {"type": "array", "items": value}]
+            }),
+            ArgAction::Append => json!({"type": "array", "items": value}),
+            _ => json!({"type": "boolean"}),
+        };
+        if let Some(help) = field_help(field, arg) {
+            schema["description"] = help.into();
+        }
+        properties.insert(field.into(), schema);
     }
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "coreos-installer install config",
+        "type": "object",
+        "properties": properties,
+        "additionalProperties": false,
+    })
+}
 
-    forward_to_deserialize_any! {
-        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
-        bytes byte_buf option unit unit_struct newtype_struct seq tuple
-        tuple_struct map enum identifier ignored_any
-    }
+/// Short help for a config field, adjusted for config file syntax.
+fn field_help(field: &str, arg: &Arg) -> Option<String> {
+    // strip StyledStr
+    let help = format!("{}", arg.get_help()?);
+    Some(
+        match field {
+            // clarify YAML syntax for "infinite"
+            "fetch-retries" => r#"Fetch retries, or string "infinite""#,
+            "metadata-fetch-retries" => r#"Metadata fetch retries, or string "infinite""#,
+            // don't reference -n short option
+            "network-dir" => "Source directory for copy-network",
+            // "arg" => "arguments"
+            "append-karg" => "Append default kernel arguments",
+            "delete-karg" => "Delete default kernel arguments",
+            _ => &help,
+        }
+        .to_string(),
+    )
 }
@@ -0,0 +1,421 @@
+// Copyright 2019 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Struct definition and support code for download subcommand.
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, skip_serializing_none, DisplayFromStr};
+use std::default::Default;
+use std::ffi::OsStr;
+use std::num::NonZeroU32;
+
+use super::serializer;
+use super::types::*;
+use super::Cmd;
+
+// Args are listed in --help in the order declared in these structs/enums.
+// Please keep the entire help text to 80 columns.
+
+// This struct supports Serialize and Deserialize for config file parsing.
+// The rules are the same as for InstallConfig; see the comment there.
+#[serde_as]
+#[skip_serializing_none]
+#[derive(Debug, Default, Parser, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", default, deny_unknown_fields)]
+#[command(args_override_self = true)]
+pub struct DownloadConfig {
+    /// YAML config file with download options
+    ///
+    /// Load additional config options from the specified YAML config file.
+    /// Later config files override earlier ones, and command-line options
+    /// override config files.
+    ///
+    /// Config file keys are long option names without the leading "--".
+    /// Values are strings for non-repeatable options and "true" for flags.
+    #[serde(skip)]
+    #[arg(short, long, value_name = "path")]
+    pub config_file: Vec<String>,
+    /// Fedora CoreOS stream
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(short, long, value_name = "name", default_value_t)]
+    pub stream: DefaultedString<StreamName>,
+    /// Target CPU architecture
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(short, long, value_name = "name", default_value_t)]
+    pub architecture: DefaultedString<Architecture>,
+    /// Fedora CoreOS platform name
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(short, long, value_name = "name", default_value_t)]
+    pub platform: DefaultedString<PlatformName>,
+    /// Image format
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(short, long, value_name = "name", default_value_t)]
+    pub format: DefaultedString<ImageFormat>,
+    /// Manually specify the image URL
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[arg(short = 'u', long, value_name = "URL")]
+    pub image_url: Option<Url>,
+    /// Destination directory
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(short = 'C', long, value_name = "path", default_value_t)]
+    pub directory: DefaultedString<DownloadDir>,
+    /// Decompress image and don't save signature
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(short, long)]
+    pub decompress: bool,
+    /// Allow unsigned image
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long)]
+    pub insecure: bool,
+    /// GPG keyring for verifying signatures
+    ///
+    /// Verify image signatures with the keys in the specified armored or
+    /// binary keyring instead of the built-in keys.
+    #[arg(long, value_name = "path")]
+    pub gpg_keyring: Option<String>,
+    /// GPG key for verifying signatures
+    ///
+    /// Verify image signatures with the specified public key, read from a
+    /// file or fetched from an HTTPS URL, instead of the built-in keys.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, value_name = "path|URL")]
+    pub gpg_key: Vec<String>,
+    /// Require signed stream metadata
+    ///
+    /// Require a valid GPG signature on the stream metadata, fetched from
+    /// the metadata URL with ".sig" appended, so that metadata from a
+    /// mirror specified with --stream-base-url is authenticated.  Has no
+    /// effect with --image-url.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long)]
+    pub verify_stream_signature: bool,
+    /// Base URL for Fedora CoreOS stream metadata
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[arg(long, value_name = "URL")]
+    pub stream_base_url: Option<Url>,
+    /// Fetch retries, or "infinite"
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, value_name = "N", default_value_t)]
+    pub fetch_retries: FetchRetries,
+    /// Metadata fetch retries, or "infinite" [default: --fetch-retries]
+    #[arg(long, value_name = "N")]
+    pub metadata_fetch_retries: Option<FetchRetries>,
+    /// Maximum seconds between fetch retries [default: 600]
+    #[arg(long, value_name = "secs")]
+    pub fetch_retry_max_delay: Option<NonZeroU32>,
+    /// Fetch image in N parallel streams
+    #[arg(long, value_name = "N")]
+    pub download_threads: Option<NonZeroU32>,
+    /// Limit image fetches to this many bytes per second, e.g. "10M"
+    ///
+    /// Parallel streams and mirrored artifacts share the limit.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[arg(long, value_name = "BYTES/S")]
+    pub limit_rate: Option<ByteSize>,
+    /// Image cache directory [default: ~/.cache/coreos-installer]
+    #[arg(long, value_name = "path")]
+    pub cache_dir: Option<String>,
+    /// Don't use the image cache
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, conflicts_with = "cache_dir")]
+    pub no_cache: bool,
+    /// Proxy for HTTP requests
+    ///
+    /// Fetch http:// URLs through the specified proxy.  By default, the
+    /// http_proxy, https_proxy, and no_proxy environment variables are
+    /// honored; specifying either proxy option overrides all of them.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[arg(long, value_name = "URL")]
+    pub http_proxy: Option<Url>,
+    /// Proxy for HTTPS requests
+    ///
+    /// Fetch https:// URLs through the specified proxy.  By default, the
+    /// http_proxy, https_proxy, and no_proxy environment variables are
+    /// honored; specifying either proxy option overrides all of them.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[arg(long, value_name = "URL")]
+    pub https_proxy: Option<Url>,
+    /// Additional trusted CA certificates
+    ///
+    /// Trust the CA certificates in the specified PEM file for HTTPS
+    /// requests, in addition to the system trust store, such as when a
+    /// proxy intercepts TLS connections.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, value_name = "path")]
+    pub cacert: Vec<String>,
+    /// Mirror all artifacts for the stream and architecture
+    ///
+    /// Download every artifact for the stream and architecture, with
+    /// signatures, into a directory tree matching the layout of the
+    /// upstream URLs.  Also save the stream metadata and write a summary
+    /// to mirror-manifest.json.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, conflicts_with_all = ["image_url", "decompress"])]
+    pub mirror: bool,
+    /// Only mirror artifacts for this platform
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, value_name = "name", requires = "mirror")]
+    pub mirror_platform: Vec<String>,
+    /// Mirror N artifacts in parallel
+    #[arg(long, value_name = "N", requires = "mirror")]
+    pub mirror_jobs: Option<NonZeroU32>,
+    /// Print image sizes instead of downloading
+    ///
+    /// Print the size of each image after decompression, followed by a
+    /// tab and the image filename, without downloading the image.  The
+    /// size is read from the index of an xz-compressed image, so other
+    /// compressed images are rejected.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, conflicts_with = "mirror")]
+    pub print_size: bool,
+}
+
+impl DownloadConfig {
+    pub fn expand_config_files(self) -> Result<Self> {
+        if self.config_file.is_empty() {
+            return Ok(self);
+        }
+
+        let configs: Vec<Self> = serializer::parse_config_files(&self.config_file)?;
+        let args = serializer::merge_config_args(&configs, &self.config_file, &self)?;
+
+        eprintln!("Running with arguments: {}", args.join(" "));
+        Self::from_args(&args)
+    }
+
+    fn from_args<T: AsRef<OsStr>>(args: &[T]) -> Result<Self> {
+        match Cmd::try_parse_from(
+            vec![
+                std::env::args_os().next().expect("no program name"),
+                "download".into(),
+            ]
+            .into_iter()
+            .chain(args.iter().map(<_>::into)),
+        )
+        .context("reprocessing command-line arguments")?
+        {
+            Cmd::Download(c) => Ok(c),
+            _ => unreachable!(),
+        }
+    }
+
+    fn to_args(&self) -> Result<Vec<String>> {
+        serializer::to_args(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use std::str::FromStr;
+    use tempfile::NamedTempFile;
+
+    /// Check that full DownloadConfig serializes as expected
+    #[test]
+    fn serialize_full_download_config() {
+        let config = DownloadConfig {
+            // skipped
+            config_file: vec!["a".into()],
+            stream: DefaultedString::<StreamName>::from_str("b").unwrap(),
+            architecture: DefaultedString::<Architecture>::from_str("c").unwrap(),
+            platform: DefaultedString::<PlatformName>::from_str("d").unwrap(),
+            format: DefaultedString::<ImageFormat>::from_str("e").unwrap(),
+            image_url: Some(Url::parse("http://example.com/f").unwrap()),
+            directory: DefaultedString::<DownloadDir>::from_str("g").unwrap(),
+            decompress: true,
+            insecure: true,
+            gpg_keyring: Some("i".into()),
+            gpg_key: vec!["j".into(), "https://example.com/k".into()],
+            verify_stream_signature: true,
+            stream_base_url: Some(Url::parse("http://example.com/h").unwrap()),
+            fetch_retries: FetchRetries::from_str("infinite").unwrap(),
+            metadata_fetch_retries: Some(FetchRetries::from_str("2").unwrap()),
+            fetch_retry_max_delay: Some(NonZeroU32::new(60).unwrap()),
+            download_threads: Some(NonZeroU32::new(4).unwrap()),
+            limit_rate: Some(ByteSize(10 << 20)),
+            cache_dir: Some("cd".into()),
+            no_cache: false,
+            http_proxy: Some(Url::parse("http://example.com/n").unwrap()),
+            https_proxy: Some(Url::parse("http://example.com/o").unwrap()),
+            cacert: vec!["p".into(), "q".into()],
+            mirror: true,
+            mirror_platform: vec!["l".into(), "m".into()],
+            mirror_jobs: Some(NonZeroU32::new(2).unwrap()),
+            print_size: true,
+        };
+        let expected = vec![
+            "--stream",
+            "b",
+            "--architecture",
+            "c",
+            "--platform",
+            "d",
+            "--format",
+            "e",
+            "--image-url",
+            "http://example.com/f",
+            "--directory",
+            "g",
+            "--decompress",
+            "--insecure",
+            "--gpg-keyring",
+            "i",
+            "--gpg-key",
+            "j",
+            "--gpg-key",
+            "https://example.com/k",
+            "--verify-stream-signature",
+            "--stream-base-url",
+            "http://example.com/h",
+            "--fetch-retries",
+            "infinite",
+            "--metadata-fetch-retries",
+            "2",
+            "--fetch-retry-max-delay",
+            "60",
+            "--download-threads",
+            "4",
+            "--limit-rate",
+            "10M",
+            "--cache-dir",
+            "cd",
+            "--http-proxy",
+            "http://example.com/n",
+            "--https-proxy",
+            "http://example.com/o",
+            "--cacert",
+            "p",
+            "--cacert",
+            "q",
+            "--mirror",
+            "--mirror-platform",
+            "l",
+            "--mirror-platform",
+            "m",
+            "--mirror-jobs",
+            "2",
+            "--print-size",
+        ];
+        assert_eq!(config.to_args().unwrap(), expected);
+    }
+
+    /// Test that full config file deserializes as expected
+    #[test]
+    fn parse_full_download_config_file() {
+        let mut f = NamedTempFile::new().unwrap();
+        f.as_file_mut()
+            .write_all(
+                r#"
+stream: b
+architecture: c
+platform: d
+format: e
+image-url: http://example.com/f
+directory: g
+decompress: true
+insecure: true
+gpg-keyring: i
+gpg-key: [j, https://example.com/k]
+verify-stream-signature: true
+stream-base-url: http://example.com/h
+fetch-retries: 3
+metadata-fetch-retries: 2
+fetch-retry-max-delay: 60
+download-threads: 4
+limit-rate: 512K
+cache-dir: cd
+http-proxy: http://example.com/n
+https-proxy: http://example.com/o
+cacert: [p, q]
+"#
+                .as_bytes(),
+            )
+            .unwrap();
+        let expected = DownloadConfig {
+            // skipped
+            config_file: Vec::new(),
+            stream: DefaultedString::<StreamName>::from_str("b").unwrap(),
+            architecture: DefaultedString::<Architecture>::from_str("c").unwrap(),
+            platform: DefaultedString::<PlatformName>::from_str("d").unwrap(),
+            format: DefaultedString::<ImageFormat>::from_str("e").unwrap(),
+            image_url: Some(Url::parse("http://example.com/f").unwrap()),
+            directory: DefaultedString::<DownloadDir>::from_str("g").unwrap(),
+            decompress: true,
+            insecure: true,
+            gpg_keyring: Some("i".into()),
+            gpg_key: vec!["j".into(), "https://example.com/k".into()],
+            verify_stream_signature: true,
+            stream_base_url: Some(Url::parse("http://example.com/h").unwrap()),
+            fetch_retries: FetchRetries::from_str("3").unwrap(),
+            metadata_fetch_retries: Some(FetchRetries::from_str("2").unwrap()),
+            fetch_retry_max_delay: Some(NonZeroU32::new(60).unwrap()),
+            download_threads: Some(NonZeroU32::new(4).unwrap()),
+            limit_rate: Some(ByteSize(512 << 10)),
+            cache_dir: Some("cd".into()),
+            no_cache: false,
+            http_proxy: Some(Url::parse("http://example.com/n").unwrap()),
+            https_proxy: Some(Url::parse("http://example.com/o").unwrap()),
+            cacert: vec!["p".into(), "q".into()],
+            // conflict
+            mirror: false,
+            // conflict
+            mirror_platform: Vec::new(),
+            // conflict
+            mirror_jobs: None,
+            // conflict
+            print_size: false,
+        };
+        let config = DownloadConfig::from_args(&["--config-file", f.path().to_str().unwrap()])
+            .unwrap()
+            .expand_config_files()
+            .unwrap();
+        assert_eq!(expected, config);
+    }
+
+    /// Check that default DownloadConfig serializes to empty arg list
+    #[test]
+    fn serialize_default_download_config_args() {
+        let config = DownloadConfig::default();
+        let expected: Vec<String> = Vec::new();
+        assert_eq!(config.to_args().unwrap(), expected);
+    }
+
+    /// Test a config file overlapping with command-line arguments
+    #[test]
+    fn download_config_file_overlapping_field() {
+        let mut f = NamedTempFile::new().unwrap();
+        f.as_file_mut()
+            .write_all(b"stream: next\nplatform: qemu\nfetch-retries: 2")
+            .unwrap();
+        let config = DownloadConfig::from_args(&[
+            "--stream",
+            "testing",
+            "--config-file",
+            f.path().to_str().unwrap(),
+            "--fetch-retries",
+            "3",
+        ])
+        .unwrap()
+        .expand_config_files()
+        .unwrap();
+        assert_eq!(config.stream.as_str(), "testing");
+        assert_eq!(config.platform.as_str(), "qemu");
+        assert_eq!(config.fetch_retries, FetchRetries::from_str("3").unwrap());
+        assert_eq!(config.format.as_str(), "raw.xz");
+    }
+}
@@ -14,16 +14,17 @@
 
 //! Struct definition and support code for install subcommand.
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use clap::Parser;
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
-use serde_with::{serde_as, skip_serializing_none, DisplayFromStr};
+use serde_with::formats::PreferOne;
+use serde_with::{serde_as, skip_serializing_none, DisplayFromStr, OneOrMany};
 use std::default::Default;
 use std::ffi::OsStr;
-use std::fs::OpenOptions;
+use std::num::NonZeroU32;
 
-use crate::io::IgnitionHash;
+use crate::io::{ClevisPin, IgnitionHash};
 
 use super::console::Console;
 use super::serializer;
@@ -68,7 +69,14 @@ pub struct InstallConfig {
     /// Config file keys are long option names without the leading "--".
     /// Values are strings for non-repeatable options, arrays of strings for
     /// repeatable options, and "true" for flags.  The destination device
-    /// can be specified with the "dest-device" key.
+    /// can be specified with the "dest-device" key, as a string or an array
+    /// of strings.
+    ///
+    /// A config file can declare the version of the format it uses with
+    /// the "version" key.  Files without one are treated as version 1.
+    /// Files in older versions of the format are migrated automatically,
+    /// and newer versions than this program supports are rejected.
+    /// Unknown keys are errors.
     #[serde(skip)]
     #[arg(short, long, value_name = "path")]
     pub config_file: Vec<String>,
@@ -79,7 +87,7 @@ pub struct InstallConfig {
     /// The name of the Fedora CoreOS stream to install, such as "stable",
     /// "testing", or "next".
     #[arg(short, long, value_name = "name")]
-    #[arg(conflicts_with_all = ["image_file", "image_url"])]
+    #[arg(conflicts_with_all = ["image_file", "image_url", "image_ref"])]
     pub stream: Option<String>,
     /// Manually specify the image URL
     ///
@@ -88,7 +96,7 @@ pub struct InstallConfig {
     /// ignored with --insecure.
     #[serde_as(as = "Option<DisplayFromStr>")]
     #[arg(short = 'u', long, value_name = "URL")]
-    #[arg(conflicts_with_all = ["stream", "image_file"])]
+    #[arg(conflicts_with_all = ["stream", "image_file", "image_ref"])]
     pub image_url: Option<Url>,
     /// Manually specify a local image file
     ///
@@ -96,8 +104,32 @@ pub struct InstallConfig {
     /// image, which must exist and be valid.  A missing signature can be
     /// ignored with --insecure.
     #[arg(short = 'f', long, value_name = "path")]
-    #[arg(conflicts_with_all = ["stream", "image_url"])]
+    #[arg(conflicts_with_all = ["stream", "image_url", "image_ref"])]
     pub image_file: Option<String>,
+    /// Pull the image from a container image reference
+    ///
+    /// Fetch the disk image with skopeo from a container image containing
+    /// it as the only layer, such as "quay.io/example/image@sha256:...".
+    /// References without a transport are pulled from a registry.  The
+    /// image is verified with the containers signature policy rather than
+    /// a GPG signature.
+    #[arg(long, value_name = "ref")]
+    #[arg(conflicts_with_all = ["stream", "image_url", "image_file"])]
+    pub image_ref: Option<String>,
+    /// Signature policy for --image-ref
+    ///
+    /// Verify the container image using the specified containers-policy.json
+    /// file instead of the system default.
+    #[arg(long, value_name = "path", requires = "image_ref")]
+    pub image_ref_policy: Option<String>,
+    /// Directory for the image pulled by --image-ref
+    ///
+    /// skopeo stores the whole container image on disk before it's
+    /// written.  By default it goes in $TMPDIR or /var/tmp, which is in
+    /// RAM on a live system; specify a directory on a disk for large
+    /// images.
+    #[arg(long, value_name = "path", requires = "image_ref")]
+    pub image_ref_tmpdir: Option<String>,
 
     // postprocessing options
     /// Embed an Ignition config from a file
@@ -121,6 +153,36 @@ pub struct InstallConfig {
     /// formatted as <type>-<hexvalue>.  <type> can be sha256 or sha512.
     #[arg(long, value_name = "digest")]
     pub ignition_hash: Option<IgnitionHash>,
+    /// Set a variable for the Ignition config, as KEY=VALUE
+    ///
+    /// Replace each "${installer:var:KEY}" in the string values of the
+    /// Ignition config with VALUE before embedding it.  VALUE is
+    /// percent-encoded within data URLs, which can't also carry a
+    /// verification hash.  The install fails if the config references a
+    /// variable that isn't set.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, value_name = "KEY=VALUE")]
+    pub ignition_var: Vec<String>,
+    /// Authorize SSH keys from a file for the core user
+    ///
+    /// Add the SSH public keys in the specified authorized_keys file to the
+    /// "core" user, merging with any specified Ignition config.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, value_name = "path")]
+    pub ssh_key: Vec<String>,
+    /// Authorize an SSH key for the core user
+    ///
+    /// Add the specified SSH public key to the "core" user, merging with
+    /// any specified Ignition config.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, value_name = "key")]
+    pub ssh_key_string: Vec<String>,
+    /// Static hostname for the installed system
+    ///
+    /// Write the specified hostname to /etc/hostname, merging with any
+    /// specified Ignition config.
+    #[arg(long, value_name = "name")]
+    pub hostname: Option<String>,
     /// Target CPU architecture
     ///
     /// Create an install disk for a different CPU architecture than the
@@ -137,10 +199,20 @@ pub struct InstallConfig {
     /// Kernel and bootloader console
     ///
     /// Set the kernel and bootloader console, using the same syntax as the
-    /// parameter to the "console=" kernel argument.
+    /// parameter to the "console=" kernel argument.  If the platform
+    /// configures default consoles, the specified consoles are checked
+    /// against them unless --allow-unsupported-console is specified.  The
+    /// last console is the primary one.
     #[serde(skip_serializing_if = "is_default")]
     #[arg(long, value_name = "spec")]
     pub console: Vec<Console>,
+    /// Allow consoles the platform doesn't configure
+    ///
+    /// Accept --console values that aren't among the platform's default
+    /// consoles.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, help_heading = ADVANCED)]
+    pub allow_unsupported_console: bool,
     /// Additional kernel args for the first boot
     // This used to be for configuring networking from the cmdline, but it has
     // been obsoleted by the nicer `--copy-network` approach. We still need it
@@ -160,6 +232,18 @@ pub struct InstallConfig {
     #[serde(skip_serializing_if = "is_default")]
     #[arg(long, value_name = "arg")]
     pub delete_karg: Vec<String>,
+    /// Deduplicate default kernel args
+    ///
+    /// Normalize the kernel arguments of the installed system after
+    /// applying --append-karg and --delete-karg.  Exact repeats are
+    /// dropped, and the last value wins for arguments such as root= which
+    /// only take one value.  Other arguments, such as console=, may
+    /// legitimately be repeated with different values and are kept.  Arguments
+    /// whose meaning depends on their position, such as hugepages= and
+    /// rd.*, are never dropped.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long)]
+    pub dedup_karg: bool,
     /// Copy network config from install environment
     ///
     /// Copy NetworkManager keyfiles from the install environment to the
@@ -202,6 +286,10 @@ pub struct InstallConfig {
     /// Multiple numbers or ranges can be specified in multiple options, or
     /// in a single option separated by commas.
     ///
+    /// Partitions can also be saved from a disk with an MBR (DOS) partition
+    /// table.  They're converted to GPT partitions in the installed
+    /// partition table.
+    ///
     /// Saved partitions will be renumbered if necessary.  If partitions
     /// overlap with the install image, or installation fails for any other
     /// reason, the specified partitions will still be preserved.
@@ -213,6 +301,127 @@ pub struct InstallConfig {
     // Allow ranges like "-2".
     #[arg(allow_hyphen_values = true)]
     pub save_partindex: Vec<String>,
+    /// Reinstall, keeping the existing /var partition
+    ///
+    /// Check that the destination contains an existing CoreOS
+    /// installation, and save partitions whose labels start with "var",
+    /// as if --save-partlabel 'var*' had been specified.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long)]
+    pub preserve_var: bool,
+    /// Mount the preserved /var partition
+    ///
+    /// Add an Ignition filesystem entry and mount unit for the partition
+    /// labeled "var" preserved by --preserve-var, merging them with any
+    /// specified Ignition config.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, requires = "preserve_var")]
+    pub var_mount_unit: bool,
+    /// Mirror the boot disk to this device
+    ///
+    /// Configure Ignition to mirror the boot disk across the specified
+    /// devices on first boot, with the same RAID1 layout as the Butane
+    /// "boot_device.mirror" option.  Specify at least two devices.  The
+    /// image is installed to the destination device, which defaults to the
+    /// first mirror device and must be one of them.  All data on the
+    /// mirror devices is destroyed.  The layout is merged with any
+    /// specified Ignition config.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, value_name = "device")]
+    pub mirror: Vec<String>,
+    /// Encrypt the root filesystem with this Clevis pin
+    ///
+    /// Configure Ignition to encrypt the root filesystem with LUKS on first
+    /// boot, as with the Butane "boot_device.luks" option.  The pin can be
+    /// "tpm2" or "tang=URL,THUMBPRINT".  If multiple pins are specified,
+    /// any one of them can unlock the filesystem.  Works with --mirror.
+    /// The configuration is merged with any specified Ignition config.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, value_name = "pin")]
+    pub encrypt_root: Vec<ClevisPin>,
+    /// Run an executable before installing
+    ///
+    /// Run the specified executable before writing to each destination
+    /// device.  The device path is passed in the environment variable
+    /// COREOS_INSTALLER_DEST_DEVICE.  Installation is aborted if the
+    /// executable fails.
+    #[arg(long, value_name = "path")]
+    pub pre_install_script: Option<String>,
+    /// Run an executable after installing
+    ///
+    /// Run the specified executable after installing to each destination
+    /// device, with the installed boot filesystem mounted.  The device path
+    /// and boot filesystem mountpoint are passed in the environment
+    /// variables COREOS_INSTALLER_DEST_DEVICE and
+    /// COREOS_INSTALLER_BOOT_DIR.  Installation fails if the executable
+    /// fails.
+    #[arg(long, value_name = "path")]
+    pub post_install_script: Option<String>,
+    /// Copy a file into the installed boot filesystem
+    ///
+    /// Copy the local file SRC to path DEST in the boot filesystem of the
+    /// installed system, such as a device tree blob or a firmware payload.
+    /// DEST is relative to the root of the boot filesystem and can't
+    /// contain ".." components.  Missing parent directories are created.
+    /// Existing files, including those shipped in the image, can't be
+    /// replaced, and symlinks in DEST are not followed.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, value_name = "src:dest")]
+    pub boot_file: Vec<String>,
+    /// Copy a firmware payload into the EFI System Partition
+    ///
+    /// Copy the contents of the specified directory, such as the UEFI
+    /// firmware for the Raspberry Pi 4, into the EFI System Partition of
+    /// the installed system.  config.txt is then updated to boot in 64-bit
+    /// mode and, if the payload includes RPI_EFI.fd, to load it as the
+    /// ARM stub.  Only supported for aarch64.
+    #[arg(long, value_name = "path")]
+    pub firmware_dir: Option<String>,
+    /// Suppress all non-error output
+    ///
+    /// Don't report progress or informational messages.  Errors are
+    /// still printed to stderr.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(short, long)]
+    pub quiet: bool,
+    /// Output format
+    ///
+    /// With "json", print a result object on stdout when the install
+    /// finishes, including on failure.  It reports the image, the
+    /// destination devices, the image digest, warnings, hardware checks,
+    /// and the installed boot configuration.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, value_enum, default_value_t, value_name = "FORMAT")]
+    pub format: OutputFormat,
+    /// Report what would be done without writing anything
+    ///
+    /// Evaluate config files, resolve the image source, check the
+    /// destination devices, and print the install plan, including the
+    /// partitions that would be saved.  Nothing is written to the
+    /// destination.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Check hardware before installing
+    ///
+    /// Before writing anything, check that each destination is large
+    /// enough for the image without overwriting saved partitions, that its
+    /// sector size matches the image, that the firmware can boot the
+    /// image, whether IBM DASDs need formatting, and that a TPM 2.0 device
+    /// is present for --encrypt-root tpm2.  Print a report, and stop if
+    /// any check fails.  The size of a compressed image can't be checked.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long)]
+    pub check_hardware: bool,
+    /// Interactively select the destination disk
+    ///
+    /// List candidate destination disks with their size, model, serial
+    /// number, and WWN, prompt for a selection, and require the device
+    /// path to be typed to confirm before installing.  Requires a
+    /// terminal.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, conflicts_with_all = ["dest_device", "dest_image_file", "mirror"])]
+    pub interactive: bool,
 
     // obscure options without short names
     /// Force offline installation
@@ -226,6 +435,28 @@ pub struct InstallConfig {
     #[serde(skip_serializing_if = "is_default")]
     #[arg(long, help_heading = ADVANCED)]
     pub insecure: bool,
+    /// GPG keyring for verifying signatures
+    ///
+    /// Verify image signatures with the keys in the specified armored or
+    /// binary keyring instead of the built-in keys.
+    #[arg(long, value_name = "path", help_heading = ADVANCED)]
+    pub gpg_keyring: Option<String>,
+    /// GPG key for verifying signatures
+    ///
+    /// Verify image signatures with the specified public key, read from a
+    /// file or fetched from an HTTPS URL, instead of the built-in keys.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, value_name = "path|URL", help_heading = ADVANCED)]
+    pub gpg_key: Vec<String>,
+    /// Require signed stream metadata
+    ///
+    /// Require a valid GPG signature on the stream metadata, fetched from
+    /// the metadata URL with ".sig" appended, so that metadata from a
+    /// mirror specified with --stream-base-url is authenticated.  Has no
+    /// effect when installing from an image file, URL, or container image.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, help_heading = ADVANCED)]
+    pub verify_stream_signature: bool,
     /// Allow Ignition URL without HTTPS or hash
     #[serde(skip_serializing_if = "is_default")]
     #[arg(long, help_heading = ADVANCED)]
@@ -245,6 +476,24 @@ pub struct InstallConfig {
     #[serde(skip_serializing_if = "is_default")]
     #[arg(long, help_heading = ADVANCED)]
     pub preserve_on_error: bool,
+    /// Allow installing to the disk backing the running system
+    ///
+    /// Normally coreos-installer refuses to overwrite a disk holding the
+    /// root filesystem, /var, or the live ISO image of the running
+    /// system.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, help_heading = ADVANCED)]
+    pub allow_active_disk: bool,
+    /// Wipe RAID and LVM signatures from the destination
+    ///
+    /// Old mdraid, LVM, or firmware RAID metadata on the destination that
+    /// isn't overwritten by the image can cause the installed system to
+    /// assemble stale devices on first boot.  By default coreos-installer
+    /// warns about such signatures; with this option, it erases them
+    /// before installing.  Signatures in saved partitions are kept.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, help_heading = ADVANCED)]
+    pub auto_wipe_signatures: bool,
     /// Fetch retries, or "infinite"
     ///
     /// Number of times to retry network fetches, or the string "infinite"
@@ -252,18 +501,162 @@ pub struct InstallConfig {
     #[serde(skip_serializing_if = "is_default")]
     #[arg(long, value_name = "N", default_value_t, help_heading = ADVANCED)]
     pub fetch_retries: FetchRetries,
+    /// Metadata fetch retries, or "infinite"
+    ///
+    /// Number of times to retry fetches of stream metadata, GPG keys, and
+    /// the Ignition config, or the string "infinite" to retry
+    /// indefinitely.  Defaults to the value of --fetch-retries.
+    #[arg(long, value_name = "N", help_heading = ADVANCED)]
+    pub metadata_fetch_retries: Option<FetchRetries>,
+    /// Maximum delay between fetch retries
+    ///
+    /// Retries back off exponentially with random jitter, starting at one
+    /// second, up to this many seconds.  A Retry-After delay from the
+    /// server is honored up to the same limit.  Defaults to 600.
+    #[arg(long, value_name = "secs", help_heading = ADVANCED)]
+    pub fetch_retry_max_delay: Option<NonZeroU32>,
+    /// Fetch image in N parallel streams
+    ///
+    /// Fetch the image using N concurrent HTTP range requests, which can
+    /// improve throughput from distant mirrors.  If the server doesn't
+    /// support range requests, fetch the image in a single stream.
+    #[arg(long, value_name = "N", help_heading = ADVANCED)]
+    pub download_threads: Option<NonZeroU32>,
+    /// Limit image fetches to this many bytes per second, e.g. "10M"
+    ///
+    /// Hold the image download to an average of the specified rate, so
+    /// installs on shared infrastructure don't starve other tenants of
+    /// bandwidth.  Parallel streams from --download-threads share the
+    /// limit.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[arg(long, value_name = "BYTES/S", help_heading = ADVANCED)]
+    pub limit_rate: Option<ByteSize>,
+    /// Image cache directory [default: ~/.cache/coreos-installer]
+    ///
+    /// Reuse images cached by an earlier download, after verifying them
+    /// against the digests in the stream metadata.  Stream metadata is
+    /// also cached, and the cached copy is used if a later fetch fails.
+    #[arg(long, value_name = "path", help_heading = ADVANCED)]
+    pub cache_dir: Option<String>,
+    /// Don't use the image cache
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, conflicts_with = "cache_dir", help_heading = ADVANCED)]
+    pub no_cache: bool,
+    /// Proxy for HTTP requests
+    ///
+    /// Fetch http:// URLs through the specified proxy.  By default, the
+    /// http_proxy, https_proxy, and no_proxy environment variables are
+    /// honored; specifying either proxy option overrides all of them.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[arg(long, value_name = "URL", help_heading = ADVANCED)]
+    pub http_proxy: Option<Url>,
+    /// Proxy for HTTPS requests
+    ///
+    /// Fetch https:// URLs through the specified proxy.  By default, the
+    /// http_proxy, https_proxy, and no_proxy environment variables are
+    /// honored; specifying either proxy option overrides all of them.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[arg(long, value_name = "URL", help_heading = ADVANCED)]
+    pub https_proxy: Option<Url>,
+    /// Additional trusted CA certificates
+    ///
+    /// Trust the CA certificates in the specified PEM file for HTTPS
+    /// requests, in addition to the system trust store, such as when a
+    /// proxy intercepts TLS connections.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, value_name = "path", help_heading = ADVANCED)]
+    pub cacert: Vec<String>,
     /// Enable IBM Secure IPL
     #[serde(skip_serializing_if = "is_default")]
     #[arg(long, help_heading = ADVANCED)]
     pub secure_ipl: bool,
+    /// Rewrite PReP partition from installed GRUB (ppc64le only)
+    ///
+    /// After writing the image, copy the GRUB core image from the
+    /// installed boot filesystem into the PowerPC PReP boot partition,
+    /// replacing its previous contents.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, help_heading = ADVANCED)]
+    pub refresh_prep: bool,
+    /// Discard device blocks: before, after, or both
+    ///
+    /// Issue discard (TRIM) requests for the destination device before
+    /// writing the image, for the part of the device not covered by the
+    /// image afterward, or both.  Saved partitions are never discarded.
+    /// Defaults to "before" if no value is specified.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[arg(long, value_name = "when", help_heading = ADVANCED)]
+    #[arg(num_args = 0..=1, require_equals = true, default_missing_value = "before")]
+    #[arg(conflicts_with = "dest_image_file")]
+    pub discard: Option<DiscardMode>,
+    /// Write image with O_DIRECT
+    ///
+    /// Bypass the page cache when writing the image, to avoid evicting
+    /// cached data used by other workloads.  Falls back to buffered
+    /// writes if the destination doesn't support direct I/O.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, help_heading = ADVANCED)]
+    pub odirect: bool,
+    /// Limit image writes to this many bytes per second, e.g. "50M"
+    ///
+    /// Hold writes of the image to each destination to an average of
+    /// the specified rate, to leave I/O bandwidth for other workloads
+    /// sharing the storage.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[arg(long, value_name = "BYTES/S", help_heading = ADVANCED)]
+    pub write_rate: Option<ByteSize>,
+    /// Flush image writes to disk every this many bytes, e.g. "64M"
+    ///
+    /// Periodically wait for written data to reach the destination rather
+    /// than leaving it in the page cache until the end of the copy.  This
+    /// keeps progress reporting accurate on slow media such as USB sticks.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[arg(long, value_name = "BYTES", help_heading = ADVANCED)]
+    pub sync_interval: Option<ByteSize>,
+    /// Read back the image after writing it
+    ///
+    /// Drop the kernel's cached copy of the destination and read the
+    /// image back from it, failing if it doesn't match what was written.
+    /// Skipped on DASDs and when partitions are saved, since the written
+    /// layout differs from the image.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, help_heading = ADVANCED)]
+    pub verify_write: bool,
+    /// Install to a disk image file
+    ///
+    /// Write a raw disk image to the specified file instead of a
+    /// destination device, creating the file if necessary.  Postprocessing
+    /// mounts the image with a loop device.  The image uses 512-byte
+    /// sectors.
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(long, value_name = "path", conflicts_with = "dest_device")]
+    pub dest_image_file: Option<String>,
+    /// Size of the disk image file, e.g. "10G"
+    ///
+    /// Extend the file specified with --dest-image-file to this size.
+    /// Defaults to the size of the OS image.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[arg(long, value_name = "size", requires = "dest_image_file")]
+    pub image_size: Option<ByteSize>,
 
     // positional args
     /// Destination device
     ///
     /// Path to the device node for the destination disk.  The beginning of
-    /// the device will be overwritten without further confirmation.
-    #[arg(required_unless_present = "config_file")]
-    pub dest_device: Option<String>,
+    /// the device will be overwritten without further confirmation.  If
+    /// multiple devices are specified, the image is written to all of them
+    /// in parallel.
+    ///
+    /// Instead of a path, a selector can match the disk by its properties:
+    /// a comma-separated list of "wwn:GLOB", "serial:GLOB", "model:GLOB",
+    /// "min-size:SIZE", "max-size:SIZE", "ssd", "hdd", "removable", or
+    /// "fixed", all of which must match exactly one disk.  Add "smallest"
+    /// or "largest" to pick among several matches, optionally with a
+    /// filter appended, as in "smallest-ssd".
+    #[serde_as(as = "OneOrMany<_, PreferOne>")]
+    #[serde(skip_serializing_if = "is_default")]
+    #[arg(required_unless_present_any = ["config_file", "dest_image_file", "mirror", "interactive"])]
+    pub dest_device: Vec<String>,
 }
 
 impl InstallConfig {
@@ -272,31 +665,49 @@ impl InstallConfig {
             return Ok(self);
         }
 
-        let args = self
-            .config_file
+        let configs: Vec<Self> = serializer::parse_config_files(&self.config_file)?;
+
+        // Destination devices would otherwise be accumulated across config
+        // files and the command line.  Since multiple devices are allowed,
+        // that could silently overwrite a device the user didn't intend.
+        let device_sources = configs
             .iter()
-            .map(|path| {
-                serde_yaml::from_reader::<_, InstallConfig>(
-                    OpenOptions::new()
-                        .read(true)
-                        .open(path)
-                        .with_context(|| format!("opening config file {path}"))?,
-                )
-                .with_context(|| format!("parsing config file {path}"))?
-                .to_args()
-                .with_context(|| format!("serializing config file {path}"))
-            })
-            .collect::<Result<Vec<Vec<_>>>>()?
-            .into_iter()
-            .flatten()
-            .chain(
-                self.to_args()
-                    .context("serializing command-line arguments")?,
-            )
-            .collect::<Vec<_>>();
+            .map(|c| &c.dest_device)
+            .chain(std::iter::once(&self.dest_device))
+            .filter(|d| !d.is_empty())
+            .count();
+        if device_sources > 1 {
+            bail!("destination devices specified in multiple places");
+        }
+
+        let args = serializer::merge_config_args(&configs, &self.config_file, &self)?;
 
-        println!("Running with arguments: {}", args.join(" "));
-        Self::from_args(&args)
+        let config = Self::from_args(&args)?;
+        if !config.quiet {
+            // in JSON mode, stdout is reserved for the command report
+            match config.format {
+                OutputFormat::Text => println!("Running with arguments: {}", args.join(" ")),
+                OutputFormat::Json => eprintln!("Running with arguments: {}", args.join(" ")),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Parse the contents of an installer config file, as for
+    /// --config-file.
+    pub fn from_config_file_data(data: &[u8]) -> Result<Self> {
+        serializer::parse_config(data)
+    }
+
+    /// Reprocess a config built without the command-line parser, so that
+    /// conflicting or missing options are reported as they would be on
+    /// the command line.
+    pub(crate) fn checked(self) -> Result<Self> {
+        let mut config = Self::from_args(&self.to_args()?)?;
+        // not serialized
+        config.config_file = self.config_file;
+        config.firstboot_args = self.firstboot_args;
+        Ok(config)
     }
 
     fn from_args<T: AsRef<OsStr>>(args: &[T]) -> Result<Self> {
@@ -337,6 +748,9 @@ mod test {
             stream: Some("c".into()),
             image_url: Some(Url::parse("http://example.com/d").unwrap()),
             image_file: Some("e".into()),
+            image_ref: Some("e1".into()),
+            image_ref_policy: Some("e2".into()),
+            image_ref_tmpdir: Some("e3".into()),
             ignition_file: Some("f".into()),
             ignition_url: Some(Url::parse("http://example.com/g").unwrap()),
             ignition_hash: Some(
@@ -345,28 +759,72 @@ mod test {
                 )
                 .unwrap(),
             ),
+            ignition_var: vec!["site=lab".into(), "rack=3".into()],
+            ssh_key: vec!["g1".into()],
+            ssh_key_string: vec!["g2".into()],
+            hostname: Some("g3".into()),
             architecture: DefaultedString::<Architecture>::from_str("h").unwrap(),
             platform: Some("i".into()),
             console: vec![
                 Console::from_str("ttyS0").unwrap(),
                 Console::from_str("ttyS1,115200n8").unwrap(),
             ],
+            allow_unsupported_console: true,
             // skipped
             firstboot_args: Some("j".into()),
             append_karg: vec!["k".into(), "l".into()],
             delete_karg: vec!["m".into(), "n".into()],
+            dedup_karg: true,
             copy_network: true,
             network_dir: DefaultedString::<NetworkDir>::from_str("o").unwrap(),
             save_partlabel: vec!["p".into(), "q".into()],
             save_partindex: vec!["r".into(), "s".into()],
+            preserve_var: true,
+            var_mount_unit: true,
+            mirror: vec!["m1".into(), "m2".into()],
+            encrypt_root: vec![
+                ClevisPin::Tpm2,
+                ClevisPin::from_str("tang=http://example.com/t,tp").unwrap(),
+            ],
+            pre_install_script: Some("s1".into()),
+            post_install_script: Some("s2".into()),
+            boot_file: vec!["f1:dtb/a.dtb".into(), "f2:b".into()],
+            firmware_dir: Some("fw".into()),
+            quiet: true,
+            format: OutputFormat::Json,
+            dry_run: true,
+            check_hardware: true,
+            interactive: true,
             offline: true,
             insecure: true,
+            gpg_keyring: Some("kr".into()),
+            gpg_key: vec!["ks".into(), "https://example.com/kt".into()],
+            verify_stream_signature: true,
             insecure_ignition: true,
             stream_base_url: Some(Url::parse("http://example.com/t").unwrap()),
             preserve_on_error: true,
+            allow_active_disk: true,
+            auto_wipe_signatures: true,
             fetch_retries: FetchRetries::from_str("3").unwrap(),
+            metadata_fetch_retries: Some(FetchRetries::from_str("infinite").unwrap()),
+            fetch_retry_max_delay: Some(NonZeroU32::new(60).unwrap()),
+            download_threads: Some(NonZeroU32::new(4).unwrap()),
+            limit_rate: Some(ByteSize::from_str("10M").unwrap()),
+            cache_dir: Some("cd".into()),
+            no_cache: false,
+            http_proxy: Some(Url::parse("http://example.com/p1").unwrap()),
+            https_proxy: Some(Url::parse("http://example.com/p2").unwrap()),
+            cacert: vec!["p3".into(), "p4".into()],
             secure_ipl: true,
-            dest_device: Some("u".into()),
+            refresh_prep: true,
+            discard: Some(DiscardMode::Both),
+            odirect: true,
+            write_rate: Some(ByteSize::from_str("50M").unwrap()),
+            sync_interval: Some(ByteSize::from_str("64M").unwrap()),
+            verify_write: true,
+            dest_image_file: None,
+            image_size: None,
+            dest_device: vec!["u".into(), "v".into()],
         };
         let expected = vec![
             "--stream",
@@ -375,12 +833,28 @@ mod test {
             "http://example.com/d",
             "--image-file",
             "e",
+            "--image-ref",
+            "e1",
+            "--image-ref-policy",
+            "e2",
+            "--image-ref-tmpdir",
+            "e3",
             "--ignition-file",
             "f",
             "--ignition-url",
             "http://example.com/g",
             "--ignition-hash",
             "sha256-e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            "--ignition-var",
+            "site=lab",
+            "--ignition-var",
+            "rack=3",
+            "--ssh-key",
+            "g1",
+            "--ssh-key-string",
+            "g2",
+            "--hostname",
+            "g3",
             "--architecture",
             "h",
             "--platform",
@@ -390,6 +864,7 @@ mod test {
             "ttyS0,9600n8",
             "--console",
             "ttyS1,115200n8",
+            "--allow-unsupported-console",
             "--append-karg",
             "k",
             "--append-karg",
@@ -398,6 +873,7 @@ mod test {
             "m",
             "--delete-karg",
             "n",
+            "--dedup-karg",
             "--copy-network",
             "--network-dir",
             "o",
@@ -409,16 +885,78 @@ mod test {
             "r",
             "--save-partindex",
             "s",
+            "--preserve-var",
+            "--var-mount-unit",
+            "--mirror",
+            "m1",
+            "--mirror",
+            "m2",
+            "--encrypt-root",
+            "tpm2",
+            "--encrypt-root",
+            "tang=http://example.com/t,tp",
+            "--pre-install-script",
+            "s1",
+            "--post-install-script",
+            "s2",
+            "--boot-file",
+            "f1:dtb/a.dtb",
+            "--boot-file",
+            "f2:b",
+            "--firmware-dir",
+            "fw",
+            "--quiet",
+            "--format",
+            "json",
+            "--dry-run",
+            "--check-hardware",
+            "--interactive",
             "--offline",
             "--insecure",
+            "--gpg-keyring",
+            "kr",
+            "--gpg-key",
+            "ks",
+            "--gpg-key",
+            "https://example.com/kt",
+            "--verify-stream-signature",
             "--insecure-ignition",
             "--stream-base-url",
             "http://example.com/t",
             "--preserve-on-error",
+            "--allow-active-disk",
+            "--auto-wipe-signatures",
             "--fetch-retries",
             "3",
+            "--metadata-fetch-retries",
+            "infinite",
+            "--fetch-retry-max-delay",
+            "60",
+            "--download-threads",
+            "4",
+            "--limit-rate",
+            "10M",
+            "--cache-dir",
+            "cd",
+            "--http-proxy",
+            "http://example.com/p1",
+            "--https-proxy",
+            "http://example.com/p2",
+            "--cacert",
+            "p3",
+            "--cacert",
+            "p4",
             "--secure-ipl",
+            "--refresh-prep",
+            "--discard=both",
+            "--odirect",
+            "--write-rate",
+            "50M",
+            "--sync-interval",
+            "64M",
+            "--verify-write",
             "u",
+            "v",
         ];
         assert_eq!(config.to_args().unwrap(), expected);
     }
@@ -433,21 +971,58 @@ mod test {
 image-url: http://example.com/d
 ignition-url: http://example.com/g
 ignition-hash: sha256-e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855
+ignition-var: [site=lab, rack=3]
+ssh-key: [g1]
+ssh-key-string: [g2]
+hostname: g3
 architecture: h
 platform: i
 console: [ttyS0, "ttyS1,115200n8"]
+allow-unsupported-console: true
 append-karg: [k, l]
 delete-karg: [m, n]
+dedup-karg: true
 copy-network: true
 network-dir: o
 save-partlabel: [p, q]
 save-partindex: [r, s]
+preserve-var: true
+var-mount-unit: true
+mirror: [m1, m2]
+encrypt-root: [tpm2, "tang=http://example.com/t,tp"]
+pre-install-script: s1
+post-install-script: s2
+boot-file: ["f1:dtb/a.dtb", "f2:b"]
+firmware-dir: fw
+quiet: true
+format: json
+dry-run: true
+check-hardware: true
+interactive: true
 offline: true
 insecure: true
+gpg-keyring: kr
+gpg-key: [ks, https://example.com/kt]
+verify-stream-signature: true
 insecure-ignition: true
 stream-base-url: http://example.com/t
 preserve-on-error: true
+allow-active-disk: true
+auto-wipe-signatures: true
 fetch-retries: 3
+metadata-fetch-retries: infinite
+fetch-retry-max-delay: 60
+download-threads: 4
+limit-rate: 10M
+cache-dir: cd
+http-proxy: http://example.com/p1
+https-proxy: http://example.com/p2
+cacert: [p3, p4]
+refresh-prep: true
+odirect: true
+write-rate: 50M
+sync-interval: 64M
+verify-write: true
 dest-device: u
 "#
                 .as_bytes(),
@@ -462,6 +1037,12 @@ dest-device: u
             // conflict
             image_file: None,
             // conflict
+            image_ref: None,
+            // conflict
+            image_ref_policy: None,
+            // conflict
+            image_ref_tmpdir: None,
+            // conflict
             ignition_file: None,
             ignition_url: Some(Url::parse("http://example.com/g").unwrap()),
             ignition_hash: Some(
@@ -470,28 +1051,75 @@ dest-device: u
                 )
                 .unwrap(),
             ),
+            ignition_var: vec!["site=lab".into(), "rack=3".into()],
+            ssh_key: vec!["g1".into()],
+            ssh_key_string: vec!["g2".into()],
+            hostname: Some("g3".into()),
             architecture: DefaultedString::<Architecture>::from_str("h").unwrap(),
             platform: Some("i".into()),
             console: vec![
                 Console::from_str("ttyS0").unwrap(),
                 Console::from_str("ttyS1,115200n8").unwrap(),
             ],
+            allow_unsupported_console: true,
             // skipped
             firstboot_args: None,
             append_karg: vec!["k".into(), "l".into()],
             delete_karg: vec!["m".into(), "n".into()],
+            dedup_karg: true,
             copy_network: true,
             network_dir: DefaultedString::<NetworkDir>::from_str("o").unwrap(),
             save_partlabel: vec!["p".into(), "q".into()],
             save_partindex: vec!["r".into(), "s".into()],
+            preserve_var: true,
+            var_mount_unit: true,
+            mirror: vec!["m1".into(), "m2".into()],
+            encrypt_root: vec![
+                ClevisPin::Tpm2,
+                ClevisPin::from_str("tang=http://example.com/t,tp").unwrap(),
+            ],
+            pre_install_script: Some("s1".into()),
+            post_install_script: Some("s2".into()),
+            boot_file: vec!["f1:dtb/a.dtb".into(), "f2:b".into()],
+            firmware_dir: Some("fw".into()),
+            quiet: true,
+            format: OutputFormat::Json,
+            dry_run: true,
+            check_hardware: true,
+            interactive: true,
             offline: true,
             insecure: true,
+            gpg_keyring: Some("kr".into()),
+            gpg_key: vec!["ks".into(), "https://example.com/kt".into()],
+            verify_stream_signature: true,
             insecure_ignition: true,
             stream_base_url: Some(Url::parse("http://example.com/t").unwrap()),
             preserve_on_error: true,
+            allow_active_disk: true,
+            auto_wipe_signatures: true,
             fetch_retries: FetchRetries::from_str("3").unwrap(),
+            metadata_fetch_retries: Some(FetchRetries::from_str("infinite").unwrap()),
+            fetch_retry_max_delay: Some(NonZeroU32::new(60).unwrap()),
+            download_threads: Some(NonZeroU32::new(4).unwrap()),
+            limit_rate: Some(ByteSize::from_str("10M").unwrap()),
+            cache_dir: Some("cd".into()),
+            no_cache: false,
+            http_proxy: Some(Url::parse("http://example.com/p1").unwrap()),
+            https_proxy: Some(Url::parse("http://example.com/p2").unwrap()),
+            cacert: vec!["p3".into(), "p4".into()],
             secure_ipl: false,
-            dest_device: Some("u".into()),
+            refresh_prep: true,
+            // conflict
+            discard: None,
+            odirect: true,
+            write_rate: Some(ByteSize::from_str("50M").unwrap()),
+            sync_interval: Some(ByteSize::from_str("64M").unwrap()),
+            verify_write: true,
+            // conflict
+            dest_image_file: None,
+            // conflict
+            image_size: None,
+            dest_device: vec!["u".into()],
         };
         let config = InstallConfig::from_args(&["--config-file", f.path().to_str().unwrap()])
             .unwrap()
@@ -500,6 +1128,27 @@ dest-device: u
         assert_eq!(expected, config);
     }
 
+    /// Check that a disk image file can replace the destination device
+    /// in a config file
+    #[test]
+    fn parse_dest_image_file_config_file() {
+        let mut f = NamedTempFile::new().unwrap();
+        f.as_file_mut()
+            .write_all(b"dest-image-file: u1\nimage-size: 10G\n")
+            .unwrap();
+        let config = InstallConfig::from_args(&["--config-file", f.path().to_str().unwrap()])
+            .unwrap()
+            .expand_config_files()
+            .unwrap();
+        assert_eq!(config.dest_image_file.as_deref(), Some("u1"));
+        assert_eq!(config.image_size, Some(ByteSize::from_str("10G").unwrap()));
+        assert!(config.dest_device.is_empty());
+        assert_eq!(
+            config.to_args().unwrap(),
+            ["--dest-image-file", "u1", "--image-size", "10G"]
+        );
+    }
+
     /// Check that default InstallConfig serializes to empty arg list
     #[test]
     fn serialize_default_install_config_args() {
@@ -526,6 +1175,14 @@ dest-device: u
         assert_eq!(config.to_args().unwrap(), vec!["foo"]);
     }
 
+    /// Check that multiple destination devices round-trip through a config file
+    #[test]
+    fn serialize_multiple_dest_devices() {
+        let config: InstallConfig = serde_yaml::from_str("dest-device: [foo, bar]").unwrap();
+        assert_eq!(config.dest_device, ["foo", "bar"]);
+        assert_eq!(config.to_args().unwrap(), vec!["foo", "bar"]);
+    }
+
     /// Check that empty command line serializes to empty arg list
     #[test]
     fn serialize_empty_command_line() {
@@ -534,6 +1191,16 @@ dest-device: u
         assert_eq!(config.to_args().unwrap(), expected);
     }
 
+    /// Check that --interactive replaces the destination device
+    #[test]
+    fn interactive_dest_device() {
+        let config = InstallConfig::from_args(&["--interactive"]).unwrap();
+        assert!(config.interactive);
+        assert!(config.dest_device.is_empty());
+        InstallConfig::from_args(&["--interactive", "/dev/missing"]).unwrap_err();
+        InstallConfig::from_args(&["--interactive", "--dest-image-file", "f"]).unwrap_err();
+    }
+
     /// Test multiple config files overlapping with command-line arguments
     #[test]
     fn install_config_file_overlapping_field() {
@@ -578,4 +1245,43 @@ dest-device: u
         .expand_config_files()
         .unwrap_err();
     }
+
+    /// Test config file versions and error reporting
+    #[test]
+    fn install_config_file_version() {
+        let parse = |data: &str| InstallConfig::from_config_file_data(data.as_bytes());
+        let err = |data: &str| format!("{:#}", parse(data).unwrap_err());
+
+        assert_eq!(
+            parse("version: 1\nfetch-retries: 2\ndest-device: /dev/missing").unwrap(),
+            parse("fetch-retries: \"2\"\ndest-device: /dev/missing").unwrap()
+        );
+        assert_eq!(parse("").unwrap(), InstallConfig::default());
+        assert_eq!(parse("version: 1").unwrap(), InstallConfig::default());
+
+        assert!(err("version: 0").contains("positive integer"));
+        assert!(err("version: one").contains("positive integer"));
+        assert!(err(&format!("version: {}", serializer::CONFIG_VERSION + 1)).contains("newer"));
+        assert!(err("[a, b]").contains("mapping"));
+        assert!(err("fetch-retries: 2\ncolor: blue").contains("unknown key 'color'"));
+        assert!(err("append_karg: [a]").contains("did you mean 'append-karg'?"));
+        assert!(err("--insecure: true").contains("did you mean 'insecure'?"));
+        // skipped fields aren't allowed either
+        assert!(err("config-file: foo").contains("unknown key 'config-file'"));
+        assert!(err("insecure: true\nfetch-retries: lots")
+            .contains("invalid value for key 'fetch-retries'"));
+
+        // file errors name the file
+        let mut f = NamedTempFile::new().unwrap();
+        f.as_file_mut().write_all(b"color: blue").unwrap();
+        let path = f.path().to_str().unwrap();
+        let e = InstallConfig::from_args(&["--config-file", path])
+            .unwrap()
+            .expand_config_files()
+            .unwrap_err();
+        assert_eq!(
+            format!("{e:#}"),
+            format!("parsing config file {path}: unknown key 'color'")
+        );
+    }
 }
@@ -17,18 +17,29 @@
 
 use clap::Parser;
 use reqwest::Url;
+use std::net::IpAddr;
+use std::num::NonZeroU32;
+
+use crate::io::{IgnitionHash, InitrdCompression};
+use crate::osmet::OsmetCompression;
 
 mod console;
 #[cfg(feature = "docgen")]
 mod doc;
+mod download;
 mod install;
+mod network;
+mod report;
 mod serializer;
 mod types;
 
 pub use self::console::*;
 #[cfg(feature = "docgen")]
 pub use self::doc::*;
+pub use self::download::DownloadConfig;
 pub use self::install::InstallConfig;
+pub use self::network::*;
+pub use self::report::*;
 pub use self::types::*;
 
 // Args are listed in --help in the order declared in these structs/enums.
@@ -47,6 +58,9 @@ pub enum Cmd {
     Download(DownloadConfig),
     /// List available images in a Fedora CoreOS stream
     ListStream(ListStreamConfig),
+    /// Commands to manage the image cache
+    #[command(subcommand)]
+    Cache(CacheCmd),
     /// Commands to manage a CoreOS live ISO image
     #[command(subcommand)]
     Iso(IsoCmd),
@@ -61,6 +75,14 @@ pub enum Cmd {
     Dev(DevCmd),
 }
 
+#[derive(Debug, Parser)]
+pub enum CacheCmd {
+    /// List cached images and stream metadata
+    List(CacheListConfig),
+    /// Remove cached images and stream metadata
+    Prune(CachePruneConfig),
+}
+
 #[derive(Debug, Parser)]
 pub enum IsoCmd {
     /// Embed an Ignition config in an ISO image
@@ -91,6 +113,14 @@ pub enum IsoCmd {
     Extract(IsoExtractCmd),
     /// Restore a CoreOS live ISO image to default settings
     Reset(IsoResetConfig),
+    /// Check the embedded customizations of a CoreOS live ISO image
+    Verify(IsoVerifyConfig),
+    /// Show the OS version and features of a CoreOS live ISO image
+    Info(IsoInfoConfig),
+    /// Change the volume ID of a CoreOS live ISO image
+    SetLabel(IsoSetLabelConfig),
+    /// Write a CoreOS live ISO image to a USB stick or other block device
+    Write(IsoWriteConfig),
 }
 
 #[derive(Debug, Parser)]
@@ -101,6 +131,8 @@ pub enum IsoIgnitionCmd {
     Show(IsoIgnitionShowConfig),
     /// Remove an existing embedded Ignition config from an ISO image
     Remove(IsoIgnitionRemoveConfig),
+    /// Edit the embedded Ignition config of an ISO image
+    Edit(IsoIgnitionEditConfig),
 }
 
 #[derive(Debug, Parser)]
@@ -129,6 +161,8 @@ pub enum IsoExtractCmd {
     Pxe(IsoExtractPxeConfig),
     /// Extract a minimal ISO from a CoreOS live ISO image
     MinimalIso(IsoExtractMinimalIsoConfig),
+    /// Extract files from an ISO image
+    File(IsoExtractFileConfig),
 }
 
 #[derive(Debug, Parser)]
@@ -141,6 +175,9 @@ pub enum PxeCmd {
     /// Commands to manage live PXE network settings
     #[command(subcommand)]
     Network(PxeNetworkCmd),
+    /// Commands to generate live PXE kernel arguments
+    #[command(subcommand)]
+    Kargs(PxeKargsCmd),
 }
 
 #[derive(Debug, Parser)]
@@ -159,6 +196,14 @@ pub enum PxeNetworkCmd {
     Unwrap(PxeNetworkUnwrapConfig),
 }
 
+#[derive(Debug, Parser)]
+pub enum PxeKargsCmd {
+    /// Print modified kernel args for a live PXE boot
+    Modify(PxeKargsModifyConfig),
+    /// Show default kernel args for a live PXE boot
+    Show(PxeKargsShowConfig),
+}
+
 #[derive(Debug, Parser)]
 // users shouldn't be interacting with this command normally
 #[command(hide = true)]
@@ -185,16 +230,31 @@ pub enum DevCmd {
     /// Commands to extract data
     #[command(subcommand)]
     Extract(DevExtractCmd),
+    /// Commands to verify data
+    #[command(subcommand)]
+    Verify(DevVerifyCmd),
+    /// Clear the partition table of a disk, as after a failed install
+    Wipe(DevWipeConfig),
+    /// Serve PXE artifacts from a live ISO over HTTP for testing
+    ServeIso(DevServeIsoConfig),
 }
 
 #[derive(Debug, Parser)]
 pub enum DevShowCmd {
     /// Inspect the CoreOS live ISO image
     Iso(DevShowIsoConfig),
+    /// Compare the contents of two ISO images
+    IsoDiff(DevShowIsoDiffConfig),
     /// Show the contents of an initrd image
     Initrd(DevShowInitrdConfig),
     /// Print file extent mapping of specific file
     Fiemap(DevShowFiemapConfig),
+    /// Show the platform table of an installed CoreOS disk
+    Platforms(DevShowPlatformsConfig),
+    /// Show the install record of an installed CoreOS disk
+    InstallRecord(DevShowInstallRecordConfig),
+    /// Show the partition table of a disk or disk image
+    Disk(DevShowDiskConfig),
 }
 
 #[derive(Debug, Parser)]
@@ -206,37 +266,11 @@ pub enum DevExtractCmd {
 }
 
 #[derive(Debug, Parser)]
-pub struct DownloadConfig {
-    /// Fedora CoreOS stream
-    #[arg(short, long, value_name = "name", default_value = "stable")]
-    pub stream: String,
-    /// Target CPU architecture
-    #[arg(short, long, value_name = "name", default_value_t)]
-    pub architecture: DefaultedString<Architecture>,
-    /// Fedora CoreOS platform name
-    #[arg(short, long, value_name = "name", default_value = "metal")]
-    pub platform: String,
-    /// Image format
-    #[arg(short, long, value_name = "name", default_value = "raw.xz")]
-    pub format: String,
-    /// Manually specify the image URL
-    #[arg(short = 'u', long, value_name = "URL")]
-    pub image_url: Option<Url>,
-    /// Destination directory
-    #[arg(short = 'C', long, value_name = "path", default_value = ".")]
-    pub directory: String,
-    /// Decompress image and don't save signature
-    #[arg(short, long)]
-    pub decompress: bool,
-    /// Allow unsigned image
-    #[arg(long)]
-    pub insecure: bool,
-    /// Base URL for Fedora CoreOS stream metadata
-    #[arg(long, value_name = "URL")]
-    pub stream_base_url: Option<Url>,
-    /// Fetch retries, or "infinite"
-    #[arg(long, value_name = "N", default_value_t)]
-    pub fetch_retries: FetchRetries,
+pub enum DevVerifyCmd {
+    /// Check the digest of the image written to a disk
+    Disk(DevVerifyDiskConfig),
+    /// Check the integrity of an osmet file against an OSTree repo
+    Osmet(DevVerifyOsmetConfig),
 }
 
 #[derive(Debug, Parser)]
@@ -244,9 +278,62 @@ pub struct ListStreamConfig {
     /// Fedora CoreOS stream
     #[arg(short, long, value_name = "name", default_value = "stable")]
     pub stream: String,
+    /// Only list this CPU architecture
+    #[arg(short, long, value_name = "name")]
+    pub architecture: Option<String>,
+    /// Only list this platform
+    #[arg(short, long, value_name = "name")]
+    pub platform: Option<String>,
+    /// List each artifact with its SHA-256 digest and URL
+    #[arg(long)]
+    pub artifacts: bool,
+    /// Output format
+    ///
+    /// JSON output always includes the artifacts.
+    #[arg(long, value_enum, default_value_t, value_name = "FORMAT")]
+    pub format: OutputFormat,
     /// Base URL for Fedora CoreOS stream metadata
     #[arg(long, value_name = "URL")]
     pub stream_base_url: Option<Url>,
+    /// Proxy for HTTP requests
+    #[arg(long, value_name = "URL")]
+    pub http_proxy: Option<Url>,
+    /// Proxy for HTTPS requests
+    #[arg(long, value_name = "URL")]
+    pub https_proxy: Option<Url>,
+    /// Additional trusted CA certificates
+    #[arg(long, value_name = "path")]
+    pub cacert: Vec<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct CacheListConfig {
+    /// Cache directory [default: ~/.cache/coreos-installer]
+    #[arg(long, value_name = "path")]
+    pub cache_dir: Option<String>,
+    /// Output format
+    #[arg(long, value_enum, default_value_t, value_name = "FORMAT")]
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Parser)]
+pub struct CachePruneConfig {
+    /// Cache directory [default: ~/.cache/coreos-installer]
+    #[arg(long, value_name = "path")]
+    pub cache_dir: Option<String>,
+    /// Evict least recently used objects above this total size
+    ///
+    /// Accepts a K, M, G, or T suffix.  If neither this nor --max-age is
+    /// specified, defaults to 10G, the limit enforced whenever an object is
+    /// added to the cache.
+    #[arg(long, value_name = "bytes")]
+    pub max_size: Option<ByteSize>,
+    /// Evict objects not used in this many days
+    #[arg(long, value_name = "days")]
+    pub max_age: Option<u32>,
+    /// Remove everything
+    #[arg(long, conflicts_with_all = ["max_size", "max_age"])]
+    pub all: bool,
 }
 
 #[derive(Debug, Parser)]
@@ -257,11 +344,22 @@ pub struct CommonCustomizeConfig {
     /// into the config for the destination system.
     #[arg(long, value_name = "path")]
     pub dest_ignition: Vec<String>,
+    /// Ignition config fragment URL for dest sys
+    ///
+    /// Fetch the Ignition config from the URL, automatically run installer,
+    /// and merge the config into the config for the destination system.
+    /// Append "#<type>-<hexvalue>" to the URL to verify the config's
+    /// digest, where <type> is sha256 or sha512.  HTTP URLs require a
+    /// digest.
+    #[arg(long, value_name = "URL")]
+    pub dest_ignition_url: Vec<PinnedUrl>,
     /// Install destination device
     ///
     /// Automatically run installer, installing to the specified destination
     /// device.  The resulting boot media will overwrite the destination
-    /// device without confirmation.
+    /// device without confirmation.  The device can also be a selector
+    /// such as "model:*SSD*" or "smallest-ssd", resolved on the target
+    /// machine; see `coreos-installer install --help`.
     #[arg(long, value_name = "path")]
     pub dest_device: Option<String>,
     /// Kernel and bootloader console for dest
@@ -283,6 +381,31 @@ pub struct CommonCustomizeConfig {
     /// for every boot of the destination system.
     #[arg(long, value_name = "arg")]
     pub dest_karg_delete: Vec<String>,
+    /// Templated destination kernel argument to append
+    ///
+    /// Automatically run installer, adding the specified kernel argument
+    /// for every boot of the destination system.  Placeholders are
+    /// resolved from the hardware when the live system boots:
+    /// "@primary-serial@" becomes the first serial port with a UART (e.g.
+    /// "ttyS0") and "@primary-nic@" the first physical network interface.
+    #[arg(long, value_name = "arg")]
+    pub dest_karg_template: Vec<String>,
+    /// Install to multipath storage
+    ///
+    /// Automatically run installer, enabling multipath in the live
+    /// environment before installing and adding the kernel arguments
+    /// needed to boot the destination system from multipathed storage.
+    /// The destination device should be a multipath device such as
+    /// /dev/mapper/mpatha.
+    #[arg(long, requires = "dest_device")]
+    pub dest_multipath: bool,
+    /// Systemd unit for dest sys
+    ///
+    /// Automatically run installer and add the specified systemd unit file
+    /// to the destination system, enabled.  The unit is named after the
+    /// file and must have an [Install] section.
+    #[arg(long, value_name = "path")]
+    pub dest_unit: Vec<String>,
     /// NetworkManager keyfile for live & dest
     ///
     /// Configure networking using the specified NetworkManager keyfile.
@@ -301,6 +424,31 @@ pub struct CommonCustomizeConfig {
     /// destination system, including when Ignition is run.
     #[arg(long, value_name = "path")]
     pub network_nmstate: Vec<String>,
+    /// Bond interface for live & dest
+    ///
+    /// Configure a bond interface NAME over the specified Ethernet ports,
+    /// with optional bonding options such as "mode=802.3ad,miimon=100".
+    /// The default mode is active-backup.  The bond uses DHCP unless it
+    /// has a VLAN or static address.  Applied like --network-keyfile.
+    #[arg(long, value_name = "name:port,port[:opts]")]
+    pub network_bond: Vec<NetworkBond>,
+    /// VLAN interface for live & dest
+    ///
+    /// Configure VLAN ID on top of the PARENT interface, which can be a
+    /// bond configured with --network-bond.  The VLAN uses DHCP unless it
+    /// has a static address.  Applied like --network-keyfile.
+    #[arg(long, value_name = "parent.id")]
+    pub network_vlan: Vec<NetworkVlan>,
+    /// Static address for live & dest
+    ///
+    /// Configure a static IPv4 or IPv6 address on an interface, which can
+    /// be a bond or VLAN configured with --network-bond or --network-vlan.
+    /// The gateway and DNS servers can be empty; separate multiple DNS
+    /// servers with ";".  For example,
+    /// "192.0.2.10/24,192.0.2.1,192.0.2.53,bond0.100".  Applied like
+    /// --network-keyfile.
+    #[arg(long, value_name = "addr/prefix,gw,dns,iface")]
+    pub network_static: Vec<NetworkStatic>,
     /// Ignition PEM CA bundle for live & dest
     ///
     /// Specify additional TLS certificate authorities to be trusted by
@@ -336,6 +484,28 @@ pub struct CommonCustomizeConfig {
     /// environment.
     #[arg(long, value_name = "path")]
     pub live_ignition: Vec<String>,
+    /// Ignition config fragment URL for live env
+    ///
+    /// Fetch the Ignition config from the URL and merge it into the config
+    /// for the live environment.  Append "#<type>-<hexvalue>" to the URL to
+    /// verify the config's digest, where <type> is sha256 or sha512.  HTTP
+    /// URLs require a digest.
+    #[arg(long, value_name = "URL")]
+    pub live_ignition_url: Vec<PinnedUrl>,
+    /// Systemd unit for live env
+    ///
+    /// Add the specified systemd unit file to the live environment, enabled,
+    /// so that it runs on every live boot.  The unit is named after the file
+    /// and must have an [Install] section.
+    #[arg(long, value_name = "path")]
+    pub live_unit: Vec<String>,
+    /// File to add to live initramfs
+    ///
+    /// Copy the local file SRC into the live initramfs at the absolute
+    /// path DEST, preserving its permission bits.  The file will be
+    /// available in the live environment, starting in the initramfs.
+    #[arg(long, value_name = "src:dest")]
+    pub live_file: Vec<String>,
 }
 
 #[derive(Debug, Parser)]
@@ -360,17 +530,67 @@ pub struct IsoCustomizeConfig {
     /// "--live-karg-replace a=b=c" will produce the argument "a=c".
     #[arg(long, value_name = "k=o=n")]
     pub live_karg_replace: Vec<String>,
+    /// File to add to ISO image
+    ///
+    /// Copy the local file SRC into the ISO9660 filesystem at path DEST.
+    /// The parent directory of DEST must already exist, and the filename
+    /// must contain only uppercase letters, digits, underscores, and at
+    /// most one period.  The file is stored in unused space at the end of
+    /// the image and is not visible in Joliet or Rock Ridge listings.
+    /// It is removed by "iso reset".
+    #[arg(long, value_name = "src:dest")]
+    pub iso_file: Vec<String>,
+    /// Volume ID of ISO image
+    ///
+    /// Change the ISO9660 volume ID (the filesystem label) and update the
+    /// coreos.liveiso kernel argument to match.  The volume ID can be up
+    /// to 32 characters: letters, digits, "-", ".", and "_".  It can't be
+    /// longer than the original if the bootloader config refers to the
+    /// original.  It is not restored by "iso reset".
+    #[arg(long, value_name = "name")]
+    pub volume_id: Option<String>,
+    /// Live initramfs compression [default: xz:9]
+    ///
+    /// Compress the customizations embedded in the live initramfs with
+    /// "gzip", "xz", or "zstd", optionally followed by ":LEVEL", such as
+    /// "zstd:19".  zstd is fastest to decompress at boot but requires a
+    /// kernel from 5.9 or later; xz produces the smallest output.
+    #[arg(long, value_name = "format[:level]")]
+    pub initrd_compression: Option<InitrdCompression>,
+    /// Timestamp of customizations, in seconds since the epoch
+    ///
+    /// Record this time, rather than the current time, in the manifest of
+    /// customizations embedded in the ISO.  Defaults to the
+    /// SOURCE_DATE_EPOCH environment variable if set.  Other output
+    /// doesn't depend on the time, so identical inputs and timestamps
+    /// produce identical images.
+    #[arg(long, value_name = "secs")]
+    pub source_date_epoch: Option<u64>,
 
     // I/O configuration
     /// Overwrite existing customizations
     #[arg(short, long)]
     pub force: bool,
+    /// Allow an unsigned ISO image fetched from a URL
+    #[arg(long)]
+    pub insecure: bool,
     /// Write ISO to a new output file
     #[arg(short, long, value_name = "path")]
     pub output: Option<String>,
-    /// ISO image
+    /// ISO image path or URL
+    ///
+    /// An http or https URL is fetched to a temporary file and checked
+    /// against its detached signature, fetched from the URL with ".sig"
+    /// appended.  Requires --output.
     #[arg(value_name = "ISO")]
     pub input: String,
+    /// Also write a customized PXE initramfs
+    ///
+    /// Write the PXE initramfs from the ISO image with the same
+    /// customizations appended, as "pxe customize" would produce from the
+    /// initramfs extracted with "iso extract pxe".
+    #[arg(long, value_name = "path")]
+    pub pxe_output: Option<String>,
 }
 
 #[derive(Debug, Parser)]
@@ -412,18 +632,47 @@ pub struct IsoIgnitionEmbedConfig {
     #[arg(short, long)]
     pub force: bool,
     /// Ignition config to embed [default: stdin]
+    ///
+    /// If specified more than once, the configs are merged into a single
+    /// wrapper config in the order given.
     #[arg(short, long, value_name = "path")]
-    pub ignition_file: Option<String>,
+    pub ignition_file: Vec<String>,
+    /// Allow an unsigned ISO image fetched from a URL
+    #[arg(long)]
+    pub insecure: bool,
     /// Write ISO to a new output file
     #[arg(short, long, value_name = "path")]
     pub output: Option<String>,
-    /// ISO image
+    /// ISO image path or URL
+    ///
+    /// An http or https URL is fetched to a temporary file and checked
+    /// against its detached signature, fetched from the URL with ".sig"
+    /// appended.  Requires --output.
     #[arg(value_name = "ISO")]
     pub input: String,
 }
 
 #[derive(Debug, Parser)]
 pub struct IsoIgnitionShowConfig {
+    /// Pretty-print the config
+    #[arg(long)]
+    pub pretty: bool,
+    /// Validate the config and report its spec version
+    ///
+    /// Parse the config as Ignition would, printing the spec version and
+    /// any warnings to stderr.  Fail if the config is invalid.
+    #[arg(long)]
+    pub validate: bool,
+    /// ISO image
+    #[arg(value_name = "ISO")]
+    pub input: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct IsoIgnitionEditConfig {
+    /// Write ISO to a new output file
+    #[arg(short, long, value_name = "path")]
+    pub output: Option<String>,
     /// ISO image
     #[arg(value_name = "ISO")]
     pub input: String,
@@ -449,12 +698,18 @@ pub struct IsoNetworkEmbedConfig {
     /// Overwrite existing network settings
     #[arg(short, long)]
     pub force: bool,
+    /// Fail if a keyfile contains plaintext secrets
+    #[arg(long)]
+    pub strict: bool,
+    /// List keyfile fields containing plaintext secrets and exit
+    #[arg(long, conflicts_with_all = ["force", "output", "strict"])]
+    pub redact_check: bool,
     /// Write ISO to a new output file
     #[arg(short, long, value_name = "path")]
     pub output: Option<String>,
     /// ISO image
-    #[arg(value_name = "ISO")]
-    pub input: String,
+    #[arg(value_name = "ISO", required_unless_present = "redact_check")]
+    pub input: Option<String>,
 }
 
 #[derive(Debug, Parser)]
@@ -488,6 +743,15 @@ pub struct IsoKargsModifyConfig {
     /// Kernel argument to replace
     #[arg(short, long, value_name = "KARG=OLDVAL=NEWVAL")]
     pub replace: Vec<String>,
+    /// Deduplicate kernel arguments after modifying them
+    ///
+    /// Drop exact repeats of kernel arguments, and let the last value win
+    /// for arguments such as root= which only take one value.  Other
+    /// arguments, such as console=, may legitimately be repeated with
+    /// different values and are kept.  Arguments whose meaning depends on
+    /// their position, such as hugepages= and rd.*, are never dropped.
+    #[arg(long)]
+    pub dedup: bool,
     /// Write ISO to a new output file
     #[arg(short, long, value_name = "PATH")]
     pub output: Option<String>,
@@ -509,8 +773,18 @@ pub struct IsoKargsResetConfig {
 #[derive(Debug, Parser)]
 pub struct IsoKargsShowConfig {
     /// Show default kernel args
+    ///
+    /// Not supported with JSON output, which always includes the default
+    /// kernel args.
     #[arg(short, long)]
     pub default: bool,
+    /// Output format
+    ///
+    /// The JSON format includes both the current and default kernel
+    /// args, the embed area layout, the ISO architecture, and the
+    /// space available for kernel args.
+    #[arg(long, value_enum, default_value_t, value_name = "FORMAT")]
+    pub format: OutputFormat,
     /// ISO image
     #[arg(value_name = "ISO")]
     pub input: String,
@@ -529,6 +803,16 @@ pub struct DevShowIsoConfig {
     pub input: String,
 }
 
+#[derive(Debug, Parser)]
+pub struct DevShowIsoDiffConfig {
+    /// ISO image
+    #[arg(value_name = "ISO")]
+    pub input: String,
+    /// ISO image to compare against
+    #[arg(value_name = "OTHER_ISO")]
+    pub other: String,
+}
+
 #[derive(Debug, Parser)]
 pub struct IsoExtractPxeConfig {
     /// ISO image
@@ -537,6 +821,47 @@ pub struct IsoExtractPxeConfig {
     /// Output directory
     #[arg(short, long, value_name = "PATH", default_value = ".")]
     pub output_dir: String,
+    /// Write JSON manifest of extracted files and PXE kargs
+    #[arg(long, value_name = "PATH")]
+    pub manifest: Option<String>,
+    /// Write PXE kargs to a file
+    ///
+    /// Write the kernel arguments needed to PXE boot the extracted
+    /// artifacts to NAME.kargs in the output directory, where NAME is the
+    /// ISO filename without its extension.  These are the default live
+    /// kargs without the ISO-specific coreos.liveiso argument, and with
+    /// any --karg-* edits applied.  The coreos.live.rootfs_url argument
+    /// must still be added.
+    #[arg(long)]
+    pub kargs: bool,
+    /// PXE kernel argument to append
+    ///
+    /// Applies to the kargs file and the manifest.  Implies --kargs.
+    #[arg(long, value_name = "KARG")]
+    pub karg_append: Vec<String>,
+    /// PXE kernel argument to delete
+    ///
+    /// Applies to the kargs file and the manifest.  Implies --kargs.
+    #[arg(long, value_name = "KARG")]
+    pub karg_delete: Vec<String>,
+    /// PXE kernel argument to replace
+    ///
+    /// Applies to the kargs file and the manifest.  Implies --kargs.
+    #[arg(long, value_name = "KARG=OLDVAL=NEWVAL")]
+    pub karg_replace: Vec<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct IsoExtractFileConfig {
+    /// ISO image
+    #[arg(value_name = "ISO")]
+    pub input: String,
+    /// Paths of files in the ISO image (e.g. IMAGES/PXEBOOT/VMLINUZ)
+    #[arg(value_name = "PATH", required = true)]
+    pub paths: Vec<String>,
+    /// Output directory, or "-" for stdout
+    #[arg(short, long, value_name = "PATH", default_value = ".")]
+    pub output_dir: String,
 }
 
 #[derive(Debug, Parser)]
@@ -553,6 +878,13 @@ pub struct IsoExtractMinimalIsoConfig {
     /// Inject rootfs URL karg into minimal ISO
     #[arg(long, value_name = "URL")]
     pub rootfs_url: Option<String>,
+    /// Verify that signed EFI boot files match the full ISO
+    ///
+    /// Check that the Authenticode digest of each EFI binary in the minimal
+    /// ISO matches the full ISO, so Secure Boot signatures remain valid, and
+    /// that the EFI boot image is identical.
+    #[arg(long)]
+    pub verify_signatures: bool,
 }
 
 #[derive(Debug, Parser)]
@@ -570,9 +902,70 @@ pub struct PackMinimalIsoConfig {
 
 #[derive(Debug, Parser)]
 pub struct IsoResetConfig {
+    /// Only remove the embedded Ignition config
+    ///
+    /// The --ignition, --network, and --kargs options can be combined.
+    /// If none is specified, all customizations are removed.
+    #[arg(long)]
+    pub ignition: bool,
+    /// Only remove embedded network settings
+    #[arg(long)]
+    pub network: bool,
+    /// Only reset kernel arguments to their defaults
+    #[arg(long, conflicts_with = "keep_kargs")]
+    pub kargs: bool,
+    /// Remove all customizations except kernel arguments
+    #[arg(long, conflicts_with_all = ["ignition", "network"])]
+    pub keep_kargs: bool,
+    /// Write ISO to a new output file
+    #[arg(short, long, value_name = "path")]
+    pub output: Option<String>,
+    /// ISO image
+    #[arg(value_name = "ISO")]
+    pub input: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct IsoSetLabelConfig {
     /// Write ISO to a new output file
     #[arg(short, long, value_name = "path")]
     pub output: Option<String>,
+    /// New volume ID
+    #[arg(value_name = "LABEL")]
+    pub label: String,
+    /// ISO image
+    #[arg(value_name = "ISO")]
+    pub input: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct IsoWriteConfig {
+    /// Embed this Ignition config in the written image
+    #[arg(short, long, value_name = "path")]
+    pub ignition_file: Option<String>,
+    /// Append this live kernel argument to the written image
+    #[arg(short, long, value_name = "KARG")]
+    pub append: Vec<String>,
+    /// ISO image
+    #[arg(value_name = "ISO")]
+    pub input: String,
+    /// Destination device
+    #[arg(value_name = "DEVICE")]
+    pub device: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct IsoVerifyConfig {
+    /// ISO image
+    #[arg(value_name = "ISO")]
+    pub input: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct IsoInfoConfig {
+    /// Output format
+    #[arg(long, value_enum, default_value_t, value_name = "FORMAT")]
+    pub format: OutputFormat,
     /// ISO image
     #[arg(value_name = "ISO")]
     pub input: String,
@@ -582,11 +975,10 @@ pub struct IsoResetConfig {
 // default usage line lists all mandatory options and so exceeds 80 characters
 #[command(override_usage = "coreos-installer pack osmet [OPTIONS]")]
 pub struct PackOsmetConfig {
-    /// Path to osmet file to write
-    // could output to stdout if missing?
+    /// Path to osmet file to write ("-" for stdout)
     #[arg(long, required = true, value_name = "FILE")]
     pub output: String,
-    /// Expected SHA256 of block device
+    /// Expected SHA256 of block device (512-byte sector device if combined)
     // XXX: rebase on top of
     // https://github.com/coreos/coreos-installer/pull/178 and use the same
     // type-digest format
@@ -598,22 +990,89 @@ pub struct PackOsmetConfig {
     /// Use worse compression, for development builds
     #[arg(long)]
     pub fast: bool,
+    /// Compression format for the packed image
+    ///
+    /// zstd produces larger files than xz, but decompresses much faster
+    /// during installation.
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t)]
+    pub compression: OsmetCompression,
+    /// Number of xz compression threads [default: number of CPUs]
+    ///
+    /// Each thread compresses a separate block of the image, so memory
+    /// usage grows with the thread count.
+    #[arg(long, value_name = "N")]
+    pub threads: Option<NonZeroU32>,
+    /// Map file extents without FIEMAP
+    ///
+    /// Locate file data by searching the source partitions for matching
+    /// blocks instead of querying the filesystem with FIEMAP.  This is
+    /// slower, but works on filesystems and devices that don't support
+    /// FIEMAP.
+    #[arg(long)]
+    pub no_fiemap: bool,
+    /// Read image contents from file ("-" for stdin)
+    ///
+    /// Read the raw disk image from the specified file rather than from the
+    /// source device.  The contents must be identical to those of the source
+    /// device, which is still used to map filesystem extents.
+    #[arg(long, value_name = "PATH", conflicts_with = "device_512")]
+    pub image_file: Option<String>,
+    /// Size of image contents in bytes
+    ///
+    /// Required when reading the image from stdin.  Defaults to the size of
+    /// the image file or source device.
+    #[arg(long, value_name = "BYTES", conflicts_with = "device_512")]
+    pub size: Option<u64>,
+    /// Source device with 512-byte sectors, for combined osmet
+    ///
+    /// Pack images for both 512-byte and 4096-byte sector sizes into a
+    /// single osmet container.  Requires --device-4k and --checksum-4k.
+    #[arg(
+        long,
+        value_name = "DEV",
+        conflicts_with = "device",
+        requires = "device_4k"
+    )]
+    pub device_512: Option<String>,
+    /// Source device with 4096-byte sectors, for combined osmet
+    #[arg(
+        long,
+        value_name = "DEV",
+        requires_all = ["device_512", "checksum_4k"]
+    )]
+    pub device_4k: Option<String>,
+    /// Expected SHA256 of 4096-byte sector device
+    #[arg(long, value_name = "SHA256", requires = "device_4k")]
+    pub checksum_4k: Option<String>,
+    /// OSTree repo the image will be unpacked from
+    ///
+    /// Objects in the root filesystem's OSTree repo that are missing from
+    /// this repo are matched by content against its objects.  Defaults to
+    /// the repo on the root filesystem.
+    #[arg(long, value_name = "PATH")]
+    pub repo: Option<String>,
     /// Source device
-    #[arg(value_name = "DEV")]
-    pub device: String,
+    #[arg(value_name = "DEV", required_unless_present = "device_512")]
+    pub device: Option<String>,
 }
 
 #[derive(Debug, Parser)]
 pub struct DevExtractOsmetConfig {
-    /// osmet file
+    /// osmet file ("-" for stdin)
     #[arg(long, required = true, value_name = "PATH")]
     pub osmet: String,
+    /// Write image to file ("-" for stdout)
+    #[arg(short, long, value_name = "PATH", conflicts_with = "device")]
+    pub output: Option<String>,
+    /// Sector size of image to extract from osmet container
+    #[arg(long, value_name = "BYTES")]
+    pub sector_size: Option<u32>,
     /// OSTree repo
     #[arg(value_name = "PATH")]
     pub repo: String,
     /// Destination device
-    #[arg(value_name = "DEV")]
-    pub device: String,
+    #[arg(value_name = "DEV", required_unless_present = "output")]
+    pub device: Option<String>,
 }
 
 #[derive(Debug, Parser)]
@@ -623,16 +1082,95 @@ pub struct DevShowFiemapConfig {
     pub file: String,
 }
 
+#[derive(Debug, Parser)]
+pub struct DevShowPlatformsConfig {
+    /// CoreOS disk
+    #[arg(value_name = "DEV")]
+    pub device: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct DevShowInstallRecordConfig {
+    /// CoreOS disk
+    #[arg(value_name = "DEV")]
+    pub device: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct DevShowDiskConfig {
+    /// Mark partitions with this label glob as saved
+    ///
+    /// Mark partitions that `install --save-partlabel` would preserve.
+    #[arg(long, value_name = "lx", value_delimiter = ',')]
+    pub save_partlabel: Vec<String>,
+    /// Mark partitions with this number or range as saved
+    ///
+    /// Mark partitions that `install --save-partindex` would preserve.
+    #[arg(long, value_name = "id", value_delimiter = ',')]
+    pub save_partindex: Vec<String>,
+    /// Disk or disk image
+    #[arg(value_name = "DEV|IMAGE")]
+    pub device: String,
+}
+
 #[derive(Debug, Parser)]
 pub struct PxeCustomizeConfig {
     // Customizations
     #[command(flatten)]
     pub common: CommonCustomizeConfig,
+    /// Live kernel argument to append
+    ///
+    /// Kernel argument to append to boots of the live environment.  PXE
+    /// kernel arguments are set by the boot configuration, so this option
+    /// only affects the script written by --ipxe-script.
+    #[arg(long, value_name = "arg", requires = "ipxe_script")]
+    pub live_karg_append: Vec<String>,
+
+    // iPXE script
+    /// Write an iPXE script to boot the output
+    ///
+    /// Write an iPXE script that boots the kernel, the unmodified live
+    /// initramfs, the customization initramfs written by --separate-output,
+    /// and the rootfs from the specified URLs, with any kernel arguments
+    /// required by the customizations.
+    #[arg(long, value_name = "path", requires = "separate_output")]
+    #[arg(requires_all = ["ipxe_kernel_url", "ipxe_initrd_url", "ipxe_custom_initrd_url", "ipxe_rootfs_url"])]
+    pub ipxe_script: Option<String>,
+    /// Kernel URL for iPXE script
+    #[arg(long, value_name = "URL", requires = "ipxe_script")]
+    pub ipxe_kernel_url: Option<Url>,
+    /// Live initramfs URL for iPXE script
+    #[arg(long, value_name = "URL", requires = "ipxe_script")]
+    pub ipxe_initrd_url: Option<Url>,
+    /// Customization initramfs URL for iPXE script
+    #[arg(long, value_name = "URL", requires = "ipxe_script")]
+    pub ipxe_custom_initrd_url: Option<Url>,
+    /// Rootfs URL for iPXE script
+    #[arg(long, value_name = "URL", requires = "ipxe_script")]
+    pub ipxe_rootfs_url: Option<Url>,
 
     // I/O configuration
+    /// Add to the customizations of an already-customized input
+    ///
+    /// Keep the customizations in the input initramfs and layer the new
+    /// ones on top.  The new live Ignition configs are merged after the
+    /// existing one, so they take precedence; new installer configs are
+    /// applied after existing ones; and new network keyfiles replace
+    /// existing ones with the same name.
+    #[arg(long)]
+    pub merge: bool,
     /// Output file
     #[arg(short, long, value_name = "path")]
-    pub output: String,
+    #[arg(required_unless_present = "separate_output")]
+    pub output: Option<String>,
+    /// Write only the customizations to a separate initramfs
+    ///
+    /// Write the customizations to a standalone initramfs file, to be
+    /// loaded after the unmodified input initramfs by listing both in the
+    /// PXE configuration.  The input is still checked to be a CoreOS live
+    /// initramfs.
+    #[arg(long, value_name = "path", conflicts_with = "output")]
+    pub separate_output: Option<String>,
     /// CoreOS live initramfs image
     #[arg(value_name = "path")]
     pub input: String,
@@ -677,8 +1215,43 @@ pub struct PxeNetworkUnwrapConfig {
     pub input: Option<String>,
 }
 
+#[derive(Debug, Parser)]
+pub struct PxeKargsModifyConfig {
+    /// Kernel argument to append
+    #[arg(short, long, value_name = "KARG")]
+    pub append: Vec<String>,
+    /// Kernel argument to delete
+    #[arg(short, long, value_name = "KARG")]
+    pub delete: Vec<String>,
+    /// Kernel argument to replace
+    #[arg(short, long, value_name = "KARG=OLDVAL=NEWVAL")]
+    pub replace: Vec<String>,
+    /// URL to the live rootfs
+    #[arg(long, value_name = "URL")]
+    pub rootfs_url: Option<Url>,
+    /// CoreOS live ISO image the PXE artifacts come from
+    #[arg(value_name = "ISO")]
+    pub input: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct PxeKargsShowConfig {
+    /// URL to the live rootfs
+    #[arg(long, value_name = "URL")]
+    pub rootfs_url: Option<Url>,
+    /// CoreOS live ISO image the PXE artifacts come from
+    #[arg(value_name = "ISO")]
+    pub input: String,
+}
+
 #[derive(Debug, Parser)]
 pub struct DevShowInitrdConfig {
+    /// Output format
+    ///
+    /// JSON output includes directories and symlinks, and the size, mode,
+    /// owner, and SHA-256 digest of each file.
+    #[arg(long, value_enum, default_value_t, value_name = "FORMAT")]
+    pub format: OutputFormat,
     /// initrd image ("-" for stdin)
     #[arg(value_name = "initrd")]
     pub input: String,
@@ -695,6 +1268,11 @@ pub struct DevExtractInitrdConfig {
     /// List extracted contents
     #[arg(short, long)]
     pub verbose: bool,
+    /// Print a JSON listing of the matching initrd contents
+    ///
+    /// The listing has the same format as `dev show initrd --format json`.
+    #[arg(long, conflicts_with = "verbose")]
+    pub list_json: bool,
     /// initrd image ("-" for stdin)
     #[arg(value_name = "initrd")]
     pub input: String,
@@ -703,6 +1281,96 @@ pub struct DevExtractInitrdConfig {
     pub filter: Vec<String>,
 }
 
+#[derive(Debug, Parser)]
+pub struct DevVerifyOsmetConfig {
+    /// Sector size of image to verify from osmet container
+    ///
+    /// By default, all images in an osmet container are verified.
+    #[arg(long, value_name = "BYTES")]
+    pub sector_size: Option<u32>,
+    /// osmet file
+    #[arg(value_name = "OSMET")]
+    pub osmet: String,
+    /// OSTree repo
+    #[arg(value_name = "PATH")]
+    pub repo: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct DevVerifyDiskConfig {
+    /// Expected digest of the written image
+    ///
+    /// Expected digest of the decompressed image, in the form
+    /// <type>-<value>, where type is sha256 or sha512.  coreos-installer
+    /// install reports the SHA-256 digest of the image it writes.
+    /// Only disks installed without customizations can be verified;
+    /// customizations such as an Ignition config, kernel arguments, or
+    /// saved partitions modify the disk, as does booting it.  If the
+    /// digest doesn't match, the modifications that could be found are
+    /// reported.
+    #[arg(long, value_name = "digest")]
+    pub checksum: IgnitionHash,
+    /// Length of the written image in bytes
+    ///
+    /// Number of bytes to read back from the disk.  By default, the length
+    /// is derived from the image's partition table, which is only possible
+    /// before the disk is first booted.
+    #[arg(long, value_name = "bytes")]
+    pub length: Option<u64>,
+    /// Disk to verify
+    #[arg(value_name = "device")]
+    pub device: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct DevWipeConfig {
+    /// Save partitions with this label glob
+    ///
+    /// Preserve any existing partitions on the disk whose partition label
+    /// (not filesystem label) matches the specified glob pattern, as with
+    /// coreos-installer install.  All other partitions are removed.
+    #[arg(long, value_name = "lx")]
+    #[arg(value_delimiter = ',')]
+    pub save_partlabel: Vec<String>,
+    /// Save partitions with this number or range
+    ///
+    /// Preserve any existing partitions on the disk whose partition number
+    /// matches the specified value or range, as with coreos-installer
+    /// install.  Saved partitions will be renumbered if necessary.
+    #[arg(long, value_name = "id")]
+    #[arg(value_delimiter = ',')]
+    #[arg(allow_hyphen_values = true)]
+    pub save_partindex: Vec<String>,
+    /// Disk to wipe
+    #[arg(value_name = "device")]
+    pub device: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct DevServeIsoConfig {
+    /// Address to listen on
+    #[arg(short, long, value_name = "ADDR", default_value = "0.0.0.0")]
+    pub address: IpAddr,
+    /// Port to listen on
+    #[arg(short, long, value_name = "PORT", default_value = "8080")]
+    pub port: u16,
+    /// PXE kernel argument to append
+    ///
+    /// Append to the kernel arguments in the generated iPXE script, for
+    /// example to select a serial console.
+    #[arg(long, value_name = "KARG")]
+    pub karg_append: Vec<String>,
+    /// ISO image
+    ///
+    /// The kernel, initramfs, and rootfs are read directly from the ISO
+    /// image and served at /vmlinuz, /initrd.img, and /rootfs.img, with
+    /// an iPXE script booting them at /boot.ipxe.  Customizations
+    /// embedded in the ISO are served at /custom.img and loaded after the
+    /// live initramfs.
+    #[arg(value_name = "ISO")]
+    pub input: String,
+}
+
 #[cfg(feature = "docgen")]
 #[derive(Debug, Parser)]
 pub struct PackManConfig {
@@ -713,7 +1381,11 @@ pub struct PackManConfig {
 
 #[cfg(feature = "docgen")]
 #[derive(Debug, Parser)]
-pub struct PackExampleConfigConfig {}
+pub struct PackExampleConfigConfig {
+    /// Output a JSON Schema for config files instead
+    #[arg(long)]
+    pub schema: bool,
+}
 
 #[cfg(test)]
 mod test {
@@ -724,4 +1396,39 @@ mod test {
     fn clap_app() {
         Cmd::command().debug_assert()
     }
+
+    #[test]
+    fn dev_extract_osmet_output() {
+        let parse = |args: &[&str]| {
+            Cmd::try_parse_from(
+                [
+                    "coreos-installer",
+                    "dev",
+                    "extract",
+                    "osmet",
+                    "--osmet",
+                    "-",
+                ]
+                .iter()
+                .chain(args)
+                .copied(),
+            )
+        };
+        match parse(&["--output", "-", "repo"]).unwrap() {
+            Cmd::Dev(DevCmd::Extract(DevExtractCmd::Osmet(c))) => {
+                assert_eq!(c.output.as_deref(), Some("-"));
+                assert_eq!(c.device, None);
+            }
+            _ => panic!("unexpected subcommand"),
+        }
+        match parse(&["repo", "/dev/vda"]).unwrap() {
+            Cmd::Dev(DevCmd::Extract(DevExtractCmd::Osmet(c))) => {
+                assert_eq!(c.output, None);
+                assert_eq!(c.device.as_deref(), Some("/dev/vda"));
+            }
+            _ => panic!("unexpected subcommand"),
+        }
+        parse(&["repo"]).unwrap_err();
+        parse(&["--output", "-", "repo", "/dev/vda"]).unwrap_err();
+    }
 }
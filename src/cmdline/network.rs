@@ -0,0 +1,512 @@
+// Copyright 2024 Red Hat, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helper types for network convenience arguments, and generation of
+//! NetworkManager keyfiles from them.
+
+use anyhow::{bail, Context, Error, Result};
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Bond mode used if none is specified, since it doesn't need switch
+/// support.
+const DEFAULT_BOND_MODE: &str = "active-backup";
+
+/// A bond interface: NAME:PORT,PORT[:OPTION=VALUE,...]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NetworkBond {
+    pub name: String,
+    pub ports: Vec<String>,
+    pub options: Vec<(String, String)>,
+}
+
+/// A VLAN interface: PARENT.ID
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NetworkVlan {
+    pub parent: String,
+    pub id: u16,
+}
+
+/// Static addressing for an interface:
+/// ADDRESS/PREFIX,[GATEWAY],[DNS[;DNS...]],INTERFACE
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NetworkStatic {
+    pub address: IpAddr,
+    pub prefix: u8,
+    pub gateway: Option<IpAddr>,
+    pub dns: Vec<IpAddr>,
+    pub interface: String,
+}
+
+fn check_interface_name(name: &str) -> Result<()> {
+    // IFNAMSIZ, less the terminating NUL
+    if name.is_empty() || name.len() > 15 {
+        bail!("interface name '{name}' must be 1-15 characters");
+    }
+    if name
+        .chars()
+        .any(|c| c.is_whitespace() || "/:,;".contains(c))
+    {
+        bail!("invalid character in interface name '{name}'");
+    }
+    Ok(())
+}
+
+impl FromStr for NetworkBond {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.splitn(3, ':');
+        let name = fields.next().expect("splitn always returns an element");
+        let ports = fields
+            .next()
+            .with_context(|| format!("bond '{s}' must be in the form NAME:PORT,PORT[:OPTIONS]"))?;
+        check_interface_name(name)?;
+        let ports: Vec<String> = ports.split(',').map(String::from).collect();
+        for port in &ports {
+            check_interface_name(port)?;
+            if port == name {
+                bail!("bond {name} can't be its own port");
+            }
+        }
+        let options = match fields.next() {
+            Some(options) => options
+                .split(',')
+                .map(|option| match option.split_once('=') {
+                    Some((k, v)) if !k.is_empty() && !v.is_empty() => Ok((k.into(), v.into())),
+                    _ => bail!("bond option '{option}' must be in the form KEY=VALUE"),
+                })
+                .collect::<Result<_>>()?,
+            None => Vec::new(),
+        };
+        Ok(Self {
+            name: name.into(),
+            ports,
+            options,
+        })
+    }
+}
+
+impl fmt::Display for NetworkBond {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.name, self.ports.join(","))?;
+        if !self.options.is_empty() {
+            let options: Vec<String> = self
+                .options
+                .iter()
+                .map(|(k, v)| format!("{k}={v}"))
+                .collect();
+            write!(f, ":{}", options.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+impl NetworkVlan {
+    pub fn name(&self) -> String {
+        format!("{}.{}", self.parent, self.id)
+    }
+}
+
+impl FromStr for NetworkVlan {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (parent, id) = s
+            .rsplit_once('.')
+            .with_context(|| format!("VLAN '{s}' must be in the form PARENT.ID"))?;
+        let id: u16 = id
+            .parse()
+            .with_context(|| format!("invalid VLAN ID in '{s}'"))?;
+        if !(1..=4094).contains(&id) {
+            bail!("VLAN ID in '{s}' must be 1-4094");
+        }
+        check_interface_name(parent)?;
+        let vlan = Self {
+            parent: parent.into(),
+            id,
+        };
+        check_interface_name(&vlan.name())?;
+        Ok(vlan)
+    }
+}
+
+impl fmt::Display for NetworkVlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for NetworkStatic {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split(',').collect();
+        let [address, gateway, dns, interface] = fields[..] else {
+            bail!("static address '{s}' must be in the form ADDRESS/PREFIX,GATEWAY,DNS,INTERFACE");
+        };
+        let (address, prefix) = address
+            .split_once('/')
+            .with_context(|| format!("address '{address}' must be in the form ADDRESS/PREFIX"))?;
+        let address: IpAddr = address
+            .parse()
+            .with_context(|| format!("invalid address '{address}'"))?;
+        let prefix: u8 = prefix
+            .parse()
+            .with_context(|| format!("invalid prefix length '{prefix}'"))?;
+        let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+        if prefix > max_prefix {
+            bail!("prefix length of {address} must be at most {max_prefix}");
+        }
+        let same_family = |addr: &str| -> Result<IpAddr> {
+            let parsed: IpAddr = addr
+                .parse()
+                .with_context(|| format!("invalid address '{addr}'"))?;
+            if parsed.is_ipv4() != address.is_ipv4() {
+                bail!("{parsed} is not in the same address family as {address}");
+            }
+            Ok(parsed)
+        };
+        let gateway = match gateway {
+            "" => None,
+            gateway => Some(same_family(gateway)?),
+        };
+        let dns = match dns {
+            "" => Vec::new(),
+            dns => dns.split(';').map(same_family).collect::<Result<_>>()?,
+        };
+        check_interface_name(interface)?;
+        Ok(Self {
+            address,
+            prefix,
+            gateway,
+            dns,
+            interface: interface.into(),
+        })
+    }
+}
+
+impl fmt::Display for NetworkStatic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dns: Vec<String> = self.dns.iter().map(|a| a.to_string()).collect();
+        write!(
+            f,
+            "{}/{},{},{},{}",
+            self.address,
+            self.prefix,
+            self.gateway.map(|g| g.to_string()).unwrap_or_default(),
+            dns.join(";"),
+            self.interface
+        )
+    }
+}
+
+/// One generated connection, before rendering
+#[derive(Default)]
+struct Connection {
+    /// connection.type and settings group
+    kind: &'static str,
+    settings: Vec<(String, String)>,
+    /// Bond port connections have no IP configuration
+    controller: Option<String>,
+    /// Has a VLAN on top, so needs no addresses of its own
+    is_parent: bool,
+    statics: Vec<NetworkStatic>,
+}
+
+/// Generate NetworkManager keyfiles for the specified bonds, VLANs, and
+/// static addresses.  Returns a map from filename to contents.
+/// Interfaces with static addresses but no bond or VLAN are configured
+/// as Ethernet.
+pub fn network_keyfiles(
+    bonds: &[NetworkBond],
+    vlans: &[NetworkVlan],
+    statics: &[NetworkStatic],
+) -> Result<BTreeMap<String, String>> {
+    let mut conns: BTreeMap<String, Connection> = BTreeMap::new();
+    let mut add = |name: String, conn: Connection| {
+        if conns.contains_key(&name) {
+            bail!("interface {name} is configured more than once");
+        }
+        conns.insert(name, conn);
+        Ok(())
+    };
+    for bond in bonds {
+        let mut settings = bond.options.clone();
+        if !settings.iter().any(|(k, _)| k == "mode") {
+            settings.insert(0, ("mode".into(), DEFAULT_BOND_MODE.into()));
+        }
+        add(
+            bond.name.clone(),
+            Connection {
+                kind: "bond",
+                settings,
+                ..Default::default()
+            },
+        )?;
+        for port in &bond.ports {
+            add(
+                port.clone(),
+                Connection {
+                    kind: "ethernet",
+                    controller: Some(bond.name.clone()),
+                    ..Default::default()
+                },
+            )?;
+        }
+    }
+    for vlan in vlans {
+        add(
+            vlan.name(),
+            Connection {
+                kind: "vlan",
+                settings: vec![
+                    ("id".into(), vlan.id.to_string()),
+                    ("parent".into(), vlan.parent.clone()),
+                ],
+                ..Default::default()
+            },
+        )?;
+    }
+    for vlan in vlans {
+        let parent = conns.entry(vlan.parent.clone()).or_insert(Connection {
+            kind: "ethernet",
+            ..Default::default()
+        });
+        if parent.controller.is_some() {
+            bail!("VLAN parent {} is a bond port", vlan.parent);
+        }
+        parent.is_parent = true;
+    }
+    for addr in statics {
+        let conn = conns.entry(addr.interface.clone()).or_insert(Connection {
+            kind: "ethernet",
+            ..Default::default()
+        });
+        if conn.controller.is_some() {
+            bail!("can't configure addresses on bond port {}", addr.interface);
+        }
+        if let Some(gateway) = addr.gateway {
+            if conn.statics.iter().any(|s| {
+                s.address.is_ipv4() == gateway.is_ipv4() && s.gateway.is_some_and(|g| g != gateway)
+            }) {
+                bail!("interface {} has conflicting gateways", addr.interface);
+            }
+        }
+        conn.statics.push(addr.clone());
+    }
+
+    Ok(conns
+        .iter()
+        .map(|(name, conn)| (format!("{name}.nmconnection"), conn.render(name)))
+        .collect())
+}
+
+impl Connection {
+    fn render(&self, name: &str) -> String {
+        let mut out = String::new();
+        self.write(name, &mut out)
+            .expect("writing to a String can't fail");
+        out
+    }
+
+    fn write(&self, name: &str, out: &mut impl Write) -> fmt::Result {
+        writeln!(out, "[connection]")?;
+        writeln!(out, "id={name}")?;
+        writeln!(out, "type={}", self.kind)?;
+        writeln!(out, "interface-name={name}")?;
+        if let Some(controller) = &self.controller {
+            writeln!(out, "master={controller}")?;
+            writeln!(out, "slave-type=bond")?;
+            return Ok(());
+        }
+        if !self.settings.is_empty() {
+            writeln!(out, "\n[{}]", self.kind)?;
+            for (k, v) in &self.settings {
+                writeln!(out, "{k}={v}")?;
+            }
+        }
+        for (group, ipv4) in [("ipv4", true), ("ipv6", false)] {
+            let statics: Vec<&NetworkStatic> = self
+                .statics
+                .iter()
+                .filter(|s| s.address.is_ipv4() == ipv4)
+                .collect();
+            writeln!(out, "\n[{group}]")?;
+            if statics.is_empty() {
+                let method = if self.is_parent && self.statics.is_empty() {
+                    "disabled"
+                } else {
+                    "auto"
+                };
+                writeln!(out, "method={method}")?;
+                continue;
+            }
+            writeln!(out, "method=manual")?;
+            for (i, s) in statics.iter().enumerate() {
+                writeln!(out, "address{}={}/{}", i + 1, s.address, s.prefix)?;
+            }
+            if let Some(gateway) = statics.iter().find_map(|s| s.gateway) {
+                writeln!(out, "gateway={gateway}")?;
+            }
+            let dns: Vec<String> = statics
+                .iter()
+                .flat_map(|s| &s.dns)
+                .map(|a| format!("{a};"))
+                .collect();
+            if !dns.is_empty() {
+                writeln!(out, "dns={}", dns.concat())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let bond: NetworkBond = "bond0:eno1,eno2:mode=802.3ad,miimon=100".parse().unwrap();
+        assert_eq!(bond.name, "bond0");
+        assert_eq!(bond.ports, ["eno1", "eno2"]);
+        assert_eq!(
+            bond.options,
+            [
+                ("mode".into(), "802.3ad".into()),
+                ("miimon".into(), "100".into())
+            ]
+        );
+        assert_eq!(bond.to_string(), "bond0:eno1,eno2:mode=802.3ad,miimon=100");
+        assert_eq!(
+            "bond0:eno1".parse::<NetworkBond>().unwrap().to_string(),
+            "bond0:eno1"
+        );
+
+        let vlan: NetworkVlan = "bond0.100".parse().unwrap();
+        assert_eq!(vlan.parent, "bond0");
+        assert_eq!(vlan.id, 100);
+        assert_eq!(vlan.to_string(), "bond0.100");
+
+        let addr: NetworkStatic = "192.0.2.10/24,192.0.2.1,192.0.2.53;192.0.2.54,bond0.100"
+            .parse()
+            .unwrap();
+        assert_eq!(addr.prefix, 24);
+        assert_eq!(addr.dns.len(), 2);
+        assert_eq!(
+            addr.to_string(),
+            "192.0.2.10/24,192.0.2.1,192.0.2.53;192.0.2.54,bond0.100"
+        );
+        let addr: NetworkStatic = "2001:db8::10/64,,,eno1".parse().unwrap();
+        assert_eq!(addr.gateway, None);
+        assert!(addr.dns.is_empty());
+
+        for bad in ["bond0", "bond0:", "bond0:bond0", "bond0:eno1:mode", ":eno1"] {
+            bad.parse::<NetworkBond>().unwrap_err();
+        }
+        for bad in [
+            "eno1",
+            "eno1.0",
+            "eno1.4095",
+            "eno1.x",
+            ".100",
+            "verylongname0.100",
+        ] {
+            bad.parse::<NetworkVlan>().unwrap_err();
+        }
+        for bad in [
+            "192.0.2.10,,,eno1",
+            "192.0.2.10/33,,,eno1",
+            "192.0.2.10/24,2001:db8::1,,eno1",
+            "192.0.2.10/24,,2001:db8::53,eno1",
+            "192.0.2.10/24,,,",
+            "192.0.2.10/24,,eno1",
+        ] {
+            bad.parse::<NetworkStatic>().unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_network_keyfiles() {
+        let keyfiles = network_keyfiles(
+            &["bond0:eno1,eno2".parse().unwrap()],
+            &["bond0.100".parse().unwrap()],
+            &[
+                "192.0.2.10/24,192.0.2.1,192.0.2.53,bond0.100"
+                    .parse()
+                    .unwrap(),
+                "198.51.100.10/24,,,eno3".parse().unwrap(),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            keyfiles.keys().collect::<Vec<_>>(),
+            [
+                "bond0.100.nmconnection",
+                "bond0.nmconnection",
+                "eno1.nmconnection",
+                "eno2.nmconnection",
+                "eno3.nmconnection",
+            ]
+        );
+        assert_eq!(
+            keyfiles["bond0.nmconnection"],
+            "[connection]\nid=bond0\ntype=bond\ninterface-name=bond0\n\n[bond]\nmode=active-backup\n\n[ipv4]\nmethod=disabled\n\n[ipv6]\nmethod=disabled\n"
+        );
+        assert_eq!(
+            keyfiles["eno1.nmconnection"],
+            "[connection]\nid=eno1\ntype=ethernet\ninterface-name=eno1\nmaster=bond0\nslave-type=bond\n"
+        );
+        assert_eq!(
+            keyfiles["bond0.100.nmconnection"],
+            "[connection]\nid=bond0.100\ntype=vlan\ninterface-name=bond0.100\n\n[vlan]\nid=100\nparent=bond0\n\n[ipv4]\nmethod=manual\naddress1=192.0.2.10/24\ngateway=192.0.2.1\ndns=192.0.2.53;\n\n[ipv6]\nmethod=auto\n"
+        );
+        assert_eq!(
+            keyfiles["eno3.nmconnection"],
+            "[connection]\nid=eno3\ntype=ethernet\ninterface-name=eno3\n\n[ipv4]\nmethod=manual\naddress1=198.51.100.10/24\n\n[ipv6]\nmethod=auto\n"
+        );
+
+        // conflicts
+        network_keyfiles(
+            &["bond0:eno1".parse().unwrap(), "bond1:eno1".parse().unwrap()],
+            &[],
+            &[],
+        )
+        .unwrap_err();
+        network_keyfiles(
+            &["bond0:eno1".parse().unwrap()],
+            &["eno1.100".parse().unwrap()],
+            &[],
+        )
+        .unwrap_err();
+        network_keyfiles(
+            &["bond0:eno1".parse().unwrap()],
+            &[],
+            &["192.0.2.10/24,,,eno1".parse().unwrap()],
+        )
+        .unwrap_err();
+        network_keyfiles(
+            &[],
+            &[],
+            &[
+                "192.0.2.10/24,192.0.2.1,,eno1".parse().unwrap(),
+                "192.0.2.11/24,192.0.2.2,,eno1".parse().unwrap(),
+            ],
+        )
+        .unwrap_err();
+    }
+}
@@ -0,0 +1,157 @@
+// Copyright 2024 Red Hat, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Machine-readable command results for `install --format json`.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::io::{stdout, Write};
+use std::sync::Mutex;
+
+/// Result of a top-level command, printed on stdout in JSON mode.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CommandReport {
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Name of the installed image
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub devices: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    /// Results of install --check-hardware
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hardware_checks: Vec<HardwareCheck>,
+    /// Configuration written to each installed boot filesystem
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub boot_configs: Vec<BootConfig>,
+}
+
+/// Summary of the configuration on an installed boot filesystem.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BootConfig {
+    pub device: String,
+    /// SHA-256 of the embedded Ignition config, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ignition_sha256: Option<String>,
+    /// Kernel arguments of the default boot entry
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kargs: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kargs_sha256: Option<String>,
+}
+
+/// Result of one install preflight check.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct HardwareCheck {
+    pub check: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    pub status: CheckStatus,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+    /// The check doesn't apply or couldn't be performed
+    Skip,
+}
+
+static REPORT: Mutex<CommandReport> = Mutex::new(CommandReport {
+    success: false,
+    error: None,
+    image: None,
+    devices: Vec::new(),
+    digest: None,
+    warnings: Vec::new(),
+    hardware_checks: Vec::new(),
+    boot_configs: Vec::new(),
+});
+
+/// Record information about the result of the running command.
+pub fn report(f: impl FnOnce(&mut CommandReport)) {
+    f(&mut REPORT.lock().expect("locking command report"))
+}
+
+/// Record a warning that was shown to the user.
+pub fn report_warning(warning: impl Into<String>) {
+    report(|r| r.warnings.push(warning.into()))
+}
+
+/// Print the accumulated report for a command that finished with `result`.
+pub fn print_report(result: &Result<()>) -> Result<()> {
+    let mut report = REPORT.lock().expect("locking command report");
+    report.success = result.is_ok();
+    report.error = result.as_ref().err().map(|e| format!("{e:#}"));
+    let mut out = stdout().lock();
+    serde_json::to_writer_pretty(&mut out, &*report).context("serializing command report")?;
+    writeln!(out).context("writing command report")?;
+    out.flush().context("flushing command report")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize() {
+        let mut r = CommandReport {
+            success: true,
+            ..Default::default()
+        };
+        assert_eq!(serde_json::to_string(&r).unwrap(), r#"{"success":true}"#);
+        r.devices.push("/dev/vda".into());
+        r.digest = Some("abcd".into());
+        r.warnings.push("oops".into());
+        assert_eq!(
+            serde_json::to_string(&r).unwrap(),
+            r#"{"success":true,"devices":["/dev/vda"],"digest":"abcd","warnings":["oops"]}"#
+        );
+        r.devices.clear();
+        r.digest = None;
+        r.warnings.clear();
+        r.hardware_checks.push(HardwareCheck {
+            check: "sector-size".into(),
+            device: Some("/dev/vda".into()),
+            status: CheckStatus::Fail,
+            message: "mismatch".into(),
+        });
+        assert_eq!(
+            serde_json::to_string(&r).unwrap(),
+            r#"{"success":true,"hardware-checks":[{"check":"sector-size","device":"/dev/vda","status":"fail","message":"mismatch"}]}"#
+        );
+        r.hardware_checks.clear();
+        r.boot_configs.push(BootConfig {
+            device: "/dev/vda".into(),
+            ignition_sha256: None,
+            kargs: Some("rw".into()),
+            kargs_sha256: Some("cd".into()),
+        });
+        assert_eq!(
+            serde_json::to_string(&r).unwrap(),
+            r#"{"success":true,"boot-configs":[{"device":"/dev/vda","kargs":"rw","kargs-sha256":"cd"}]}"#
+        );
+    }
+}
@@ -15,8 +15,181 @@
 //! Serde serializer for a Parser struct, producing a Vec of command-line
 //! arguments.
 
+use anyhow::{anyhow, bail, Context};
 use clap::Parser;
-use serde::{ser, Serialize};
+use serde::de::{self, DeserializeOwned};
+use serde::{forward_to_deserialize_any, ser, Serialize};
+use serde_yaml::{Mapping, Value};
+use std::fs::read;
+
+/// Current version of the config file format.  Files without a "version"
+/// key are version 1.
+pub(super) const CONFIG_VERSION: u64 = 1;
+
+/// Rewrites of a parsed config file from each version of the format to
+/// the next.  Entry N migrates from version N + 1.
+type ConfigMigration = fn(&mut Mapping) -> anyhow::Result<()>;
+const CONFIG_MIGRATIONS: &[ConfigMigration] = &[];
+const _: () = assert!(CONFIG_MIGRATIONS.len() as u64 == CONFIG_VERSION - 1);
+
+/// Parse YAML config files containing subcommand options.
+pub(super) fn parse_config_files<T>(paths: &[String]) -> anyhow::Result<Vec<T>>
+where
+    T: DeserializeOwned,
+{
+    paths
+        .iter()
+        .map(|path| {
+            let data = read(path).with_context(|| format!("opening config file {path}"))?;
+            parse_config(&data).with_context(|| format!("parsing config file {path}"))
+        })
+        .collect()
+}
+
+/// Parse a YAML config file, migrating it to the current version of the
+/// format and naming the key responsible for any error.
+pub(super) fn parse_config<T>(data: &[u8]) -> anyhow::Result<T>
+where
+    T: DeserializeOwned,
+{
+    let config = match serde_yaml::from_slice::<Value>(data)? {
+        Value::Mapping(config) => config,
+        // empty file
+        Value::Null => Mapping::new(),
+        _ => bail!("config file must be a mapping of option names to values"),
+    };
+
+    let mut version = None;
+    let mut config: Mapping = config
+        .into_iter()
+        .filter_map(|(key, value)| {
+            if key.as_str() == Some("version") {
+                version = Some(value);
+                None
+            } else {
+                Some((key, value))
+            }
+        })
+        .collect();
+    let version = match version {
+        Some(version) => version
+            .as_u64()
+            .filter(|v| *v > 0)
+            .context("config version must be a positive integer")?,
+        None => 1,
+    };
+    if version > CONFIG_VERSION {
+        bail!(
+            "config version {} is newer than the latest supported version {}; upgrade coreos-installer",
+            version,
+            CONFIG_VERSION
+        );
+    }
+    for migrate in &CONFIG_MIGRATIONS[version as usize - 1..] {
+        migrate(&mut config)?;
+    }
+
+    let fields = struct_fields::<T>();
+    for (key, _) in &config {
+        let key = key.as_str().context("config file keys must be strings")?;
+        if !fields.iter().any(|f| f == key) {
+            // common mistakes: option syntax, field names
+            let normalized = key.trim_start_matches('-').replace('_', "-");
+            if normalized != key && fields.contains(&normalized) {
+                bail!("unknown key '{}'; did you mean '{}'?", key, normalized);
+            }
+            bail!("unknown key '{}'", key);
+        }
+    }
+
+    // Round-trip through a string rather than deserializing the Value
+    // directly, since the YAML deserializer accepts scalars like 3 for
+    // string-typed fields and the Value deserializer doesn't.
+    let deserialize = |config: &Mapping| -> std::result::Result<T, serde_yaml::Error> {
+        serde_yaml::from_str(&serde_yaml::to_string(config)?)
+    };
+    deserialize(&config).or_else(|err| {
+        // find the key that failed, since the error can't tell us
+        for (key, value) in &config {
+            let single = Mapping::from_iter([(key.clone(), value.clone())]);
+            if let Err(err) = deserialize(&single) {
+                return Err(anyhow!(err).context(format!(
+                    "invalid value for key '{}'",
+                    key.as_str().unwrap_or_default()
+                )));
+            }
+        }
+        Err(err.into())
+    })
+}
+
+/// Get the names of the fields of a struct, from serde's perspective.
+pub(super) fn struct_fields<T: DeserializeOwned>() -> Vec<String> {
+    let mut fields = Vec::new();
+    // we usually skip empty fields on serialize, so we have to do this
+    // via the deserializer instead
+    let _ = T::deserialize(&mut FieldLister {
+        fields: &mut fields,
+    });
+    fields
+}
+
+struct FieldLister<'a> {
+    fields: &'a mut Vec<String>,
+}
+
+impl<'de, 'a> de::Deserializer<'de> for &'a mut FieldLister<'a> {
+    type Error = de::value::Error;
+
+    fn deserialize_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> std::result::Result<V::Value, Self::Error> {
+        self.fields.extend(fields.iter().map(|v| v.to_string()));
+        // we don't want to bother generating a default struct and throwing
+        // it away, so fail
+        Err(de::Error::custom("expected failure"))
+    }
+
+    // fill out the API contract
+    fn deserialize_any<V: de::Visitor<'de>>(
+        self,
+        _visitor: V,
+    ) -> std::result::Result<V::Value, Self::Error> {
+        unimplemented!()
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+/// Serialize the parsed config files, followed by the command-line options,
+/// into a single argument list.  Later options override earlier ones.
+pub(super) fn merge_config_args<T>(
+    configs: &[T],
+    paths: &[String],
+    command_line: &T,
+) -> anyhow::Result<Vec<String>>
+where
+    T: Serialize + Parser,
+{
+    Ok(configs
+        .iter()
+        .zip(paths)
+        .map(|(config, path)| {
+            to_args(config).with_context(|| format!("serializing config file {path}"))
+        })
+        .collect::<anyhow::Result<Vec<Vec<_>>>>()?
+        .into_iter()
+        .flatten()
+        .chain(to_args(command_line).context("serializing command-line arguments")?)
+        .collect())
+}
 
 pub(super) fn to_args<T>(value: &T) -> anyhow::Result<Vec<String>>
 where
@@ -42,13 +215,17 @@ where
 struct Serializer {
     help_text: String,
     output: Vec<String>,
-    field_stack: Vec<Option<&'static str>>,
+    /// Option name, and whether its value must be attached with "="
+    field_stack: Vec<Option<(&'static str, bool)>>,
 }
 
 impl Serializer {
     fn push_field(&mut self, name: &'static str) {
         let field = if self.help_text.contains(&format!(" --{name} ")) {
-            Some(name)
+            Some((name, false))
+        } else if self.help_text.contains(&format!(" --{name}[=")) {
+            // optional value, which can only be specified as --name=value
+            Some((name, true))
         } else {
             // don't serialize to --option
             None
@@ -63,13 +240,23 @@ impl Serializer {
     fn output_option(&mut self) {
         match &self.field_stack[self.field_stack.len() - 1] {
             None => (),
-            Some(name) => {
+            Some((name, _)) => {
                 let option = format!("--{name}");
                 self.output_argument(option);
             }
         }
     }
 
+    fn output_option_value<T: ToString>(&mut self, value: T) {
+        match self.field_stack[self.field_stack.len() - 1] {
+            Some((name, true)) => self.output_argument(format!("--{name}={}", value.to_string())),
+            _ => {
+                self.output_option();
+                self.output_argument(value);
+            }
+        }
+    }
+
     fn output_argument<T: ToString>(&mut self, arg: T) {
         self.output.push(arg.to_string());
     }
@@ -115,8 +302,7 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     }
 
     fn serialize_i64(self, v: i64) -> Result<()> {
-        self.output_option();
-        self.output_argument(v);
+        self.output_option_value(v);
         Ok(())
     }
 
@@ -133,8 +319,7 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     }
 
     fn serialize_u64(self, v: u64) -> Result<()> {
-        self.output_option();
-        self.output_argument(v);
+        self.output_option_value(v);
         Ok(())
     }
 
@@ -143,8 +328,7 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     }
 
     fn serialize_f64(self, v: f64) -> Result<()> {
-        self.output_option();
-        self.output_argument(v);
+        self.output_option_value(v);
         Ok(())
     }
 
@@ -153,8 +337,7 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     }
 
     fn serialize_str(self, v: &str) -> Result<()> {
-        self.output_option();
-        self.output_argument(v);
+        self.output_option_value(v);
         Ok(())
     }
 
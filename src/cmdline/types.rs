@@ -14,7 +14,9 @@
 
 //! Miscellaneous helper types.
 
-use anyhow::{anyhow, Error, Result};
+use anyhow::{anyhow, Context, Error, Result};
+use clap::ValueEnum;
+use reqwest::Url;
 use serde::{Deserialize, Serialize};
 use serde_with::{DeserializeFromStr, SerializeDisplay};
 use std::default::Default;
@@ -23,6 +25,8 @@ use std::marker::PhantomData;
 use std::num::NonZeroU32;
 use std::str::FromStr;
 
+use crate::io::IgnitionHash;
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum PartitionFilter {
     Label(glob::Pattern),
@@ -65,6 +69,128 @@ impl Default for FetchRetries {
     }
 }
 
+/// A size in bytes, optionally with a K, M, G, or T (binary) suffix.
+#[derive(Debug, DeserializeFromStr, SerializeDisplay, Clone, Copy, PartialEq, Eq)]
+pub struct ByteSize(pub u64);
+
+const BYTE_SIZE_SUFFIXES: [(&str, u32); 4] = [("T", 40), ("G", 30), ("M", 20), ("K", 10)];
+
+impl FromStr for ByteSize {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let upper = s.trim().to_uppercase();
+        let num = upper.strip_suffix("IB").unwrap_or(&upper);
+        for (suffix, shift) in BYTE_SIZE_SUFFIXES {
+            if let Some(num) = num.strip_suffix(suffix) {
+                return num
+                    .trim()
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|n| n.checked_mul(1 << shift))
+                    .map(Self)
+                    .with_context(|| format!("invalid size '{s}'"));
+            }
+        }
+        num.parse::<u64>()
+            .map(Self)
+            .with_context(|| format!("invalid size '{s}'"))
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (suffix, shift) in BYTE_SIZE_SUFFIXES {
+            if self.0 != 0 && self.0 % (1 << shift) == 0 {
+                return write!(f, "{}{}", self.0 >> shift, suffix);
+            }
+        }
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A URL, optionally pinned to a digest of its contents with a
+/// "#<type>-<hexvalue>" fragment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinnedUrl {
+    pub url: Url,
+    pub hash: Option<IgnitionHash>,
+}
+
+impl FromStr for PinnedUrl {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut url = Url::parse(s)?;
+        let hash = url
+            .fragment()
+            .map(|f| IgnitionHash::from_str(f).context("parsing digest in URL fragment"))
+            .transpose()?;
+        url.set_fragment(None);
+        Ok(Self { url, hash })
+    }
+}
+
+impl fmt::Display for PinnedUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.hash {
+            Some(hash) => write!(f, "{}#{}", self.url, hash),
+            None => write!(f, "{}", self.url),
+        }
+    }
+}
+
+/// When to discard the blocks of a destination device.
+#[derive(Debug, DeserializeFromStr, SerializeDisplay, Clone, Copy, PartialEq, Eq)]
+pub enum DiscardMode {
+    Before,
+    After,
+    Both,
+}
+
+impl DiscardMode {
+    /// Whether to discard the device before writing the image.
+    pub fn before(&self) -> bool {
+        matches!(self, Self::Before | Self::Both)
+    }
+
+    /// Whether to discard the unwritten part of the device afterward.
+    pub fn after(&self) -> bool {
+        matches!(self, Self::After | Self::Both)
+    }
+}
+
+impl FromStr for DiscardMode {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "before" => Ok(Self::Before),
+            "after" => Ok(Self::After),
+            "both" => Ok(Self::Both),
+            _ => Err(anyhow!("invalid discard mode '{s}'")),
+        }
+    }
+}
+
+impl fmt::Display for DiscardMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Before => write!(f, "before"),
+            Self::After => write!(f, "after"),
+            Self::Both => write!(f, "both"),
+        }
+    }
+}
+
+/// Output format for commands that print information.
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Human-readable text
+    #[default]
+    Text,
+    /// JSON
+    Json,
+}
+
 /// A String wrapper that takes a parameterized type defining the default
 /// value of the String.
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -155,6 +281,86 @@ impl DefaultString for NetworkDir {
     }
 }
 
+/// The default Fedora CoreOS stream.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StreamName {}
+impl DefaultString for StreamName {
+    fn default() -> String {
+        "stable".into()
+    }
+}
+
+/// The default Fedora CoreOS platform.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlatformName {}
+impl DefaultString for PlatformName {
+    fn default() -> String {
+        "metal".into()
+    }
+}
+
+/// The default image format.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImageFormat {}
+impl DefaultString for ImageFormat {
+    fn default() -> String {
+        "raw.xz".into()
+    }
+}
+
+/// The default directory for downloaded images.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DownloadDir {}
+impl DefaultString for DownloadDir {
+    fn default() -> String {
+        ".".into()
+    }
+}
+
 pub(super) fn is_default<T: Default + PartialEq>(value: &T) -> bool {
     value == &T::default()
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pinned_url() {
+        let url = PinnedUrl::from_str("https://example.com/a.ign").unwrap();
+        assert_eq!(url.url.as_str(), "https://example.com/a.ign");
+        assert_eq!(url.hash, None);
+        assert_eq!(url.to_string(), "https://example.com/a.ign");
+
+        let s = "http://example.com/a.ign#sha256-e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let url = PinnedUrl::from_str(s).unwrap();
+        assert_eq!(url.url.as_str(), "http://example.com/a.ign");
+        assert!(matches!(url.hash, Some(IgnitionHash::Sha256(_))));
+        assert_eq!(url.to_string(), s);
+
+        PinnedUrl::from_str("https://example.com/a.ign#foo").unwrap_err();
+        PinnedUrl::from_str("a.ign").unwrap_err();
+    }
+
+    #[test]
+    fn byte_size() {
+        for (s, bytes, display) in [
+            ("0", 0, "0"),
+            ("1000", 1000, "1000"),
+            ("1024", 1024, "1K"),
+            ("512M", 512 << 20, "512M"),
+            ("10G", 10 << 30, "10G"),
+            ("10g", 10 << 30, "10G"),
+            ("10GiB", 10 << 30, "10G"),
+            ("1536M", 1536 << 20, "1536M"),
+            ("2T", 2 << 40, "2T"),
+        ] {
+            let size = ByteSize::from_str(s).unwrap();
+            assert_eq!(size.0, bytes, "{s}");
+            assert_eq!(size.to_string(), display, "{s}");
+        }
+        for s in ["", "G", "-1G", "1.5G", "10X", "99999999999T"] {
+            ByteSize::from_str(s).unwrap_err();
+        }
+    }
+}
@@ -15,35 +15,89 @@
 use anyhow::{anyhow, bail, Context, Result};
 use byte_unit::Byte;
 use nix::unistd::isatty;
-use reqwest::Url;
-use std::fs::{remove_file, File, OpenOptions};
+use reqwest::{blocking, StatusCode, Url};
+use serde::Serialize;
+use std::fs::{create_dir_all, remove_file, rename, File, OpenOptions};
 use std::io::{self, copy, stderr, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
 use std::num::{NonZeroU32, NonZeroU64};
+use std::ops::Range;
+use std::os::unix::fs::FileTypeExt;
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
 
-use crate::blockdev::{detect_formatted_sector_size, get_gpt_size, SavedPartitions};
+use crate::blockdev::{
+    detect_formatted_sector_size, get_gpt_size, get_sector_size, SavedPartitions,
+};
+use crate::cache::Cache;
 use crate::cmdline::*;
 use crate::io::*;
+use crate::signal::CancelReader;
 use crate::source::*;
+use crate::status;
+use crate::util::{is_quiet, set_quiet};
 
 // Download all artifacts for an image and verify their signatures.
 pub fn download(config: DownloadConfig) -> Result<()> {
+    let config = config.expand_config_files()?;
+    set_http_options(
+        config.http_proxy.as_ref(),
+        config.https_proxy.as_ref(),
+        &config.cacert,
+        config.limit_rate,
+    )?;
+    let retries = RetryPolicy::new(config.fetch_retries, config.fetch_retry_max_delay);
+    let metadata_retries = config.metadata_fetch_retries.map(|n| retries.with_count(n));
+
     // Build image location.  Ideally the parser would use conflicts_with
     // (and an ArgGroup for streams), but that doesn't play well with
     // default arguments, so we manually prioritize modes.
+    // Interrupted downloads are saved to .partial files in the output
+    // directory so a later run can resume them.
+    let directory = Path::new(config.directory.as_str());
+    let keys = load_verify_keys(
+        config.gpg_keyring.as_deref(),
+        &config.gpg_key,
+        metadata_retries.unwrap_or(retries),
+    )?;
+    if config.verify_stream_signature && config.image_url.is_some() && !config.mirror {
+        eprintln!("Warning: --verify-stream-signature has no effect with --image-url");
+    }
+    if config.mirror {
+        return download_mirror(&config, directory, &keys);
+    }
+    if config.print_size {
+        return print_sizes(&config, &keys);
+    }
     let location: Box<dyn ImageLocation> = if let Some(image_url) = &config.image_url {
-        Box::new(UrlLocation::new(image_url, config.fetch_retries))
+        Box::new(
+            UrlLocation::new(image_url, retries)
+                .with_partial_dir(directory)
+                .with_download_threads(config.download_threads),
+        )
     } else {
-        Box::new(StreamLocation::new(
-            &config.stream,
-            config.architecture.as_str(),
-            &config.platform,
-            &config.format,
-            config.stream_base_url.as_ref(),
-            config.fetch_retries,
-        )?)
+        Box::new(
+            StreamLocation::new(
+                config.stream.as_str(),
+                config.architecture.as_str(),
+                config.platform.as_str(),
+                config.format.as_str(),
+                config.stream_base_url.as_ref(),
+                retries,
+            )?
+            .with_partial_dir(directory)
+            .with_download_threads(config.download_threads)
+            .with_signature_keys(config.verify_stream_signature.then(|| keys.clone()))
+            .with_metadata_retries(metadata_retries)
+            .with_cache(Cache::for_config(
+                config.cache_dir.as_deref(),
+                config.no_cache,
+            )),
+        )
     };
     eprintln!("{location}");
 
@@ -53,59 +107,312 @@ pub fn download(config: DownloadConfig) -> Result<()> {
         bail!("no artifacts found");
     }
     for source in sources.iter_mut() {
-        // set up image source
-        if source.signature.is_none() {
-            if config.insecure {
-                eprintln!("Signature not found; skipping verification as requested");
-            } else {
-                bail!("--insecure not specified and signature not found");
-            }
+        let path = download_source(source, directory, &config, &keys)?;
+        cache_download(source, &path, &config);
+        // report the output file path
+        println!("{}", path.display());
+    }
+
+    Ok(())
+}
+
+/// Print the decompressed size of each artifact without downloading it.
+fn print_sizes(config: &DownloadConfig, keys: &VerifyKeys) -> Result<()> {
+    let retries = RetryPolicy::new(config.fetch_retries, config.fetch_retry_max_delay);
+    let location: Box<dyn ImageLocation> = match &config.image_url {
+        Some(image_url) => Box::new(UrlLocation::new(image_url, retries).with_size_lookup(true)),
+        None => Box::new(
+            StreamLocation::new(
+                config.stream.as_str(),
+                config.architecture.as_str(),
+                config.platform.as_str(),
+                config.format.as_str(),
+                config.stream_base_url.as_ref(),
+                retries,
+            )?
+            .with_signature_keys(config.verify_stream_signature.then(|| keys.clone()))
+            .with_metadata_retries(config.metadata_fetch_retries.map(|n| retries.with_count(n)))
+            .with_size_lookup(true),
+        ),
+    };
+    let sources = location.sources()?;
+    if sources.is_empty() {
+        bail!("no artifacts found");
+    }
+    for source in sources {
+        let size = source.image_size().with_context(|| {
+            format!(
+                "couldn't determine the uncompressed size of {}",
+                source.filename
+            )
+        })?;
+        println!("{size}\t{}", source.filename);
+    }
+    Ok(())
+}
+
+/// Add a downloaded artifact to the cache, if it has a known digest and
+/// wasn't decompressed.  Failures aren't fatal.
+fn cache_download(source: &ImageSource, path: &Path, config: &DownloadConfig) {
+    let (Some(cache), Some(sha256)) = (
+        Cache::for_config(config.cache_dir.as_deref(), config.no_cache),
+        &source.sha256,
+    ) else {
+        return;
+    };
+    if should_decompress(config.decompress, &source.filename).0 {
+        return;
+    }
+    match cache.insert_image(path, sha256, &source.location, source.signature.as_deref()) {
+        Ok(true) => (),
+        Ok(false) => eprintln!("Not caching {}: digest mismatch", path.display()),
+        Err(e) => eprintln!("Couldn't cache {}: {e:#}", path.display()),
+    }
+}
+
+/// Name of the manifest written to the top of a mirror directory.
+const MIRROR_MANIFEST: &str = "mirror-manifest.json";
+
+/// Summary of a mirrored stream.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct MirrorManifest {
+    stream: String,
+    architecture: String,
+    /// Path to the stream metadata, relative to the mirror directory
+    metadata: String,
+    artifacts: Vec<MirrorManifestArtifact>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct MirrorManifestArtifact {
+    platform: String,
+    format: String,
+    #[serde(rename = "type")]
+    artifact_type: String,
+    url: String,
+    /// Path to the artifact, relative to the mirror directory
+    path: String,
+    size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+}
+
+/// Download all the artifacts of a stream for an architecture, with their
+/// signatures, into a directory tree matching their URLs.  Also save the
+/// stream metadata and write a manifest.
+fn download_mirror(config: &DownloadConfig, directory: &Path, keys: &VerifyKeys) -> Result<()> {
+    let retries = RetryPolicy::new(config.fetch_retries, config.fetch_retry_max_delay);
+    let location = StreamLocation::new(
+        config.stream.as_str(),
+        config.architecture.as_str(),
+        config.platform.as_str(),
+        config.format.as_str(),
+        config.stream_base_url.as_ref(),
+        retries,
+    )?
+    .with_download_threads(config.download_threads)
+    .with_signature_keys(config.verify_stream_signature.then(|| keys.clone()))
+    .with_metadata_retries(config.metadata_fetch_retries.map(|n| retries.with_count(n)));
+    eprintln!(
+        "Mirroring {} artifacts from {}",
+        config.architecture,
+        location.stream_url()
+    );
+    let (metadata, artifacts) = location.all_artifacts(&config.mirror_platform)?;
+    if artifacts.is_empty() {
+        bail!("no artifacts found");
+    }
+
+    // save the stream metadata
+    let metadata_path = mirror_path(directory, location.stream_url())?;
+    create_dir_all(metadata_path.parent().unwrap())
+        .with_context(|| format!("creating directory for {}", metadata_path.display()))?;
+    std::fs::write(&metadata_path, metadata)
+        .with_context(|| format!("writing {}", metadata_path.display()))?;
+
+    // download artifacts, several at a time if requested
+    let jobs = config.mirror_jobs.map(|n| n.get() as usize).unwrap_or(1);
+    if jobs > 1 {
+        // progress reports from parallel downloads would overwrite each other
+        set_quiet(true);
+    }
+    let queue = Mutex::new(artifacts.iter().enumerate());
+    let results = Mutex::new(Vec::new());
+    thread::scope(|s| {
+        for _ in 0..jobs.min(artifacts.len()) {
+            s.spawn(|| loop {
+                let Some((i, artifact)) = queue.lock().expect("locking queue").next() else {
+                    break;
+                };
+                let result = mirror_artifact(&location, artifact, directory, config, keys);
+                match &result {
+                    Ok(entry) => println!("{}", directory.join(&entry.path).display()),
+                    Err(e) => eprintln!("Error downloading {}: {e:#}", artifact.location),
+                }
+                results.lock().expect("locking results").push((i, result));
+            });
         }
+    });
+    let mut results = results.into_inner().expect("unlocking results");
+    results.sort_by_key(|(i, _)| *i);
+    let total = results.len();
+    let entries: Vec<MirrorManifestArtifact> =
+        results.into_iter().filter_map(|(_, r)| r.ok()).collect();
+    if entries.len() < total {
+        bail!(
+            "failed to download {} of {} artifacts",
+            total - entries.len(),
+            total
+        );
+    }
 
-        // calculate paths
-        let (decompress, filename) = should_decompress(config.decompress, &source.filename);
-        let mut path = PathBuf::new();
-        path.push(&config.directory);
-        path.push(filename);
-        let sig_path = path.with_file_name(format!("{filename}.sig"));
-
-        // check existing image and signature; don't redownload if OK
-        // If we decompressed last time, the call will fail because we can't
-        // check the old signature.  If we didn't decompress last time but are
-        // decompressing this time, we're not smart enough to decompress the
-        // existing file.
-        if !decompress
-            && check_image_and_sig(source, &path, &sig_path, VerifyKeys::Production).is_ok()
-        {
-            // report the output file path and keep going
-            println!("{}", path.display());
-            continue;
+    // write manifest
+    let manifest = MirrorManifest {
+        stream: config.stream.as_str().into(),
+        architecture: config.architecture.as_str().into(),
+        metadata: relative_path(directory, &metadata_path),
+        artifacts: entries,
+    };
+    let manifest_path = directory.join(MIRROR_MANIFEST);
+    let mut data = serde_json::to_vec_pretty(&manifest).context("serializing manifest")?;
+    data.push(b'\n');
+    std::fs::write(&manifest_path, data)
+        .with_context(|| format!("writing {}", manifest_path.display()))?;
+    eprintln!(
+        "Mirrored {} artifacts ({}) to {}",
+        manifest.artifacts.len(),
+        format_bytes(manifest.artifacts.iter().map(|a| a.size).sum()),
+        directory.display()
+    );
+    Ok(())
+}
+
+/// Download one artifact for download_mirror().
+fn mirror_artifact(
+    location: &StreamLocation,
+    artifact: &StreamArtifact,
+    directory: &Path,
+    config: &DownloadConfig,
+    keys: &VerifyKeys,
+) -> Result<MirrorManifestArtifact> {
+    let artifact_dir = mirror_path(directory, &artifact.location)?
+        .parent()
+        .unwrap()
+        .to_path_buf();
+    create_dir_all(&artifact_dir)
+        .with_context(|| format!("creating directory {}", artifact_dir.display()))?;
+    let mut sources = location
+        .artifact_location(artifact)
+        .with_partial_dir(&artifact_dir)
+        .sources()?;
+    let mut source = sources.pop().context("no artifacts found")?;
+    let path = download_source(&mut source, &artifact_dir, config, keys)?;
+    let size = path
+        .metadata()
+        .with_context(|| format!("getting metadata for {}", path.display()))?
+        .len();
+    Ok(MirrorManifestArtifact {
+        platform: artifact.platform.clone(),
+        format: artifact.format.clone(),
+        artifact_type: artifact.artifact_type.clone(),
+        url: artifact.location.to_string(),
+        path: relative_path(directory, &path),
+        size,
+        sha256: artifact.sha256.clone(),
+    })
+}
+
+/// Map a URL to a path under the mirror directory, following the path
+/// components of the URL.
+fn mirror_path(directory: &Path, url: &Url) -> Result<PathBuf> {
+    let mut path = directory.to_path_buf();
+    for segment in url
+        .path_segments()
+        .with_context(|| format!("URL {url} has no path"))?
+    {
+        if segment.is_empty() || segment == "." || segment == ".." {
+            bail!("can't mirror URL {url}");
         }
+        path.push(segment);
+    }
+    Ok(path)
+}
 
-        // Write the image and signature.  Only write the signature if we
-        // weren't asked to decompress, regardless of whether we actually
-        // did.
-        if let Err(err) = write_image_and_sig(
-            source,
-            &path,
-            &sig_path,
-            decompress,
-            !config.decompress,
-            VerifyKeys::Production,
-        ) {
-            // delete output files, which may not have been created yet
-            let _ = remove_file(&path);
-            let _ = remove_file(&sig_path);
-
-            // fail
-            return Err(err);
+fn relative_path(directory: &Path, path: &Path) -> String {
+    path.strip_prefix(directory)
+        .unwrap_or(path)
+        .display()
+        .to_string()
+}
+
+/// Download an artifact and its signature into a directory, unless a
+/// valid copy is already there.  Returns the path of the image.
+fn download_source(
+    source: &mut ImageSource,
+    directory: &Path,
+    config: &DownloadConfig,
+    keys: &VerifyKeys,
+) -> Result<PathBuf> {
+    // set up image source
+    if source.signature.is_none() {
+        if config.insecure {
+            eprintln!("Signature not found; skipping verification as requested");
+        } else {
+            bail!("--insecure not specified and signature not found");
         }
+    }
 
-        // report the output file path
-        println!("{}", path.display());
+    // calculate paths
+    let (decompress, filename) = should_decompress(config.decompress, &source.filename);
+    let mut path = PathBuf::new();
+    path.push(directory);
+    path.push(filename);
+    let sig_path = path.with_file_name(format!("{filename}.sig"));
+    let partial_path = partial_download_path(directory, &source.filename);
+
+    // check existing image and signature; don't redownload if OK
+    // If we decompressed last time, the call will fail because we can't
+    // check the old signature.  If we didn't decompress last time but are
+    // decompressing this time, we're not smart enough to decompress the
+    // existing file.
+    if !decompress && check_image_and_sig(source, &path, &sig_path, keys.clone()).is_ok() {
+        remove_partial_download(&partial_path);
+        return Ok(path);
     }
 
-    Ok(())
+    // Write the image and signature.  Only write the signature if we
+    // weren't asked to decompress, regardless of whether we actually
+    // did.
+    if let Err(err) = write_image_and_sig(
+        source,
+        &path,
+        &sig_path,
+        decompress,
+        !config.decompress,
+        keys.clone(),
+    ) {
+        // delete output files, which may not have been created yet
+        let _ = remove_file(&path);
+        let _ = remove_file(&sig_path);
+        // Keep any partial download for next time.  If we fetched the
+        // whole image, it failed verification, and resuming would only
+        // fail the same way.
+        match partial_path.metadata() {
+            Ok(meta) if meta.len() > 0 && Some(meta.len()) != source.length_hint => eprintln!(
+                "Partial download saved to {}; rerun to resume",
+                partial_path.display()
+            ),
+            _ => remove_partial_download(&partial_path),
+        }
+
+        // fail
+        return Err(err);
+    }
+
+    remove_partial_download(&partial_path);
+    Ok(path)
 }
 
 /// Take the value of the command-line compression option and the remote
@@ -121,7 +428,10 @@ fn should_decompress(enabled: bool, filename: &str) -> (bool, &str) {
     #[allow(clippy::if_same_then_else)] // readability
     if !enabled {
         (false, filename)
-    } else if filename.ends_with(".tar.gz") || filename.ends_with(".tar.xz") {
+    } else if filename.ends_with(".tar.gz")
+        || filename.ends_with(".tar.xz")
+        || filename.ends_with(".tar.zst")
+    {
         // In general, an uncompressed .tar file isn't especially useful,
         // since we've only done half the decoding.  In particular, GCP
         // images are .tar.gz but are not intended to be unpacked; GCP will
@@ -131,6 +441,8 @@ fn should_decompress(enabled: bool, filename: &str) -> (bool, &str) {
         (true, filename.trim_end_matches(".gz"))
     } else if filename.ends_with(".xz") {
         (true, filename.trim_end_matches(".xz"))
+    } else if filename.ends_with(".zst") {
+        (true, filename.trim_end_matches(".zst"))
     } else {
         (false, filename)
     }
@@ -191,26 +503,45 @@ fn write_image_and_sig(
     save_sig: bool,
     keys: VerifyKeys,
 ) -> Result<()> {
-    // open output
-    let mut dest = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(path)
-        .with_context(|| format!("opening {}", path.display()))?;
-
-    // download and verify image
-    // don't check sector size
-    write_image(
-        source,
-        &mut dest,
-        path,
-        image_copy_default,
-        decompress,
-        None,
-        None,
-        keys,
-    )?;
+    match source.partial.clone() {
+        // The download is already being saved to the partial file, so
+        // verify it and move it into place rather than writing it twice.
+        // A decompressed image is different from the partial file, which
+        // has to keep the compressed data to be resumable.
+        Some(partial) if !decompress => {
+            read_image(source, false, None, None, keys, None, |_, reader| {
+                copy(reader, &mut io::sink()).context("reading image")?;
+                Ok(())
+            })?;
+            File::open(&partial)
+                .and_then(|f| f.sync_all())
+                .with_context(|| format!("syncing {}", partial.display()))?;
+            rename(&partial, path)
+                .with_context(|| format!("renaming {} to {}", partial.display(), path.display()))?;
+        }
+        _ => {
+            // open output
+            let mut dest = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)
+                .with_context(|| format!("opening {}", path.display()))?;
+
+            // download and verify image
+            // don't check sector size
+            write_image(
+                source,
+                &mut dest,
+                path,
+                image_copy_default,
+                decompress,
+                None,
+                None,
+                keys,
+            )?;
+        }
+    }
 
     // write signature, if requested
     if let (true, Some(signature)) = (save_sig, source.signature.as_ref()) {
@@ -228,6 +559,16 @@ fn write_image_and_sig(
     Ok(())
 }
 
+/// The digest and length of the data written to the destination, after
+/// decompression.
+#[derive(Debug)]
+pub struct WrittenImage {
+    pub digest: IgnitionHash,
+    pub length: u64,
+    /// Time spent reading and writing the image.
+    pub elapsed: Duration,
+}
+
 /// Copy the image to disk and verify its signature.
 #[allow(clippy::too_many_arguments)]
 pub fn write_image<F>(
@@ -239,21 +580,197 @@ pub fn write_image<F>(
     saved: Option<&SavedPartitions>,
     expected_sector_size: Option<NonZeroU32>,
     keys: VerifyKeys,
-) -> Result<()>
+) -> Result<WrittenImage>
 where
     F: FnOnce(&[u8], &mut dyn Read, &mut File, &Path, Option<&SavedPartitions>) -> Result<()>,
 {
+    let written = read_image(
+        source,
+        decompress,
+        saved,
+        expected_sector_size,
+        keys,
+        None,
+        |first_mb, reader| image_copy(first_mb, reader, dest, dest_path, saved),
+    )?;
+
+    // finish I/O before closing the progress bar
+    dest.sync_all().context("syncing data to disk")?;
+
+    Ok(written)
+}
+
+/// Like write_image(), but write the same image to several destinations in
+/// parallel.  Progress is reported for each destination.  If any
+/// destination fails, the others are still completed, but an error is
+/// returned and none of them are reported as written.
+pub fn write_image_multi<F>(
+    source: &mut ImageSource,
+    dests: &mut [(&mut File, &Path)],
+    image_copy: F,
+    decompress: bool,
+    expected_sector_size: Option<NonZeroU32>,
+    keys: VerifyKeys,
+) -> Result<WrittenImage>
+where
+    F: Fn(&[u8], &mut dyn Read, &mut File, &Path, Option<&SavedPartitions>) -> Result<()> + Sync,
+{
+    let progress = dests
+        .iter()
+        .map(|(_, path)| DestProgress::new(path))
+        .collect::<Vec<_>>();
+    let detail = || format_dest_progress(&progress);
+    let mut failed = false;
+    let written = read_image(
+        source,
+        decompress,
+        None,
+        expected_sector_size,
+        keys,
+        Some(&detail),
+        |first_mb, reader| {
+            let (writer, readers) = fanout(dests.len());
+            let (copy_result, results) = thread::scope(|s| {
+                let handles = dests
+                    .iter_mut()
+                    .zip(readers)
+                    .zip(&progress)
+                    .map(|(((dest, path), reader), progress)| {
+                        let image_copy = &image_copy;
+                        s.spawn(move || -> Result<()> {
+                            let mut reader = CountingReader {
+                                source: reader,
+                                count: &progress.written,
+                            };
+                            let result = image_copy(first_mb, &mut reader, dest, path, None)
+                                .and_then(|_| dest.sync_all().context("syncing data to disk"));
+                            if result.is_err() {
+                                progress.failed.store(true, Ordering::SeqCst);
+                            }
+                            result
+                        })
+                    })
+                    .collect::<Vec<_>>();
+
+                // If the copy fails, the writer is dropped without being
+                // finished, and the destination threads fail rather than
+                // writing the first MiB of a partial image.
+                let mut buf_writer = BufWriter::with_capacity(BUFFER_SIZE, writer);
+                let copy_result = copy(reader, &mut buf_writer)
+                    .context("decoding image")
+                    .and_then(|_| {
+                        buf_writer
+                            .into_inner()
+                            .map_err(|_| anyhow!("flushing image to destinations"))
+                    })
+                    .map(FanoutWriter::finish);
+                let results = handles
+                    .into_iter()
+                    .map(|handle| handle.join().expect("image copy thread panicked"))
+                    .collect::<Vec<_>>();
+                (copy_result, results)
+            });
+
+            for ((_, path), result) in dests.iter().zip(results) {
+                if let Err(err) = result {
+                    eprintln!("Error writing {}: {err:?}", path.display());
+                    failed = true;
+                }
+            }
+            copy_result
+        },
+    )?;
+    if failed {
+        bail!("failed to write one or more destination devices");
+    }
+    // only now has the signature been checked and every destination
+    // finished
+    for (_, path) in dests.iter() {
+        status!("Wrote {}", path.display());
+    }
+
+    Ok(written)
+}
+
+/// Bytes written to one destination of write_image_multi().
+struct DestProgress {
+    name: String,
+    written: AtomicU64,
+    failed: AtomicBool,
+}
+
+impl DestProgress {
+    fn new(path: &Path) -> Self {
+        Self {
+            name: path.display().to_string(),
+            written: AtomicU64::new(0),
+            failed: AtomicBool::new(false),
+        }
+    }
+}
+
+/// Summarize the progress of each destination for the progress line.
+fn format_dest_progress(progress: &[DestProgress]) -> String {
+    progress
+        .iter()
+        .map(|p| {
+            if p.failed.load(Ordering::SeqCst) {
+                format!("{} failed", p.name)
+            } else {
+                format!(
+                    "{} {}",
+                    p.name,
+                    format_bytes(p.written.load(Ordering::SeqCst))
+                )
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Count the bytes read from a reader that is moved into another thread.
+struct CountingReader<'a, R: Read> {
+    source: R,
+    count: &'a AtomicU64,
+}
+
+impl<'a, R: Read> Read for CountingReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.source.read(buf)?;
+        self.count.fetch_add(count as u64, Ordering::SeqCst);
+        Ok(count)
+    }
+}
+
+/// Open the image for reading, check its first MiB, pass the stream to
+/// the callback, and then check the image signature.
+fn read_image<F>(
+    source: &mut ImageSource,
+    decompress: bool,
+    saved: Option<&SavedPartitions>,
+    expected_sector_size: Option<NonZeroU32>,
+    keys: VerifyKeys,
+    progress_detail: Option<&dyn Fn() -> String>,
+    image_copy: F,
+) -> Result<WrittenImage>
+where
+    F: FnOnce(&[u8], &mut dyn Read) -> Result<()>,
+{
+    let start = Instant::now();
+
     // wrap source for signature verification, if available
     // keep the reader so we can explicitly check the result afterward
     let mut verify_reader =
         VerifyReader::new(&mut source.reader, source.signature.as_deref(), keys)?;
 
     // wrap again for progress reporting
-    let mut reader: Box<dyn Read> = Box::new(ProgressReader::new(
+    let mut progress_reader = ProgressReader::new(
         &mut verify_reader,
         source.length_hint,
         &source.artifact_type,
-    ));
+    );
+    progress_reader.detail = progress_detail;
+    let mut reader: Box<dyn Read> = Box::new(progress_reader);
 
     // Wrap in a PeekReader so DecompressReader can peek at the first few
     // bytes for format sniffing, and to amortize read overhead.  Don't
@@ -273,6 +790,12 @@ where
         reader = Box::new(LimitReader::new(reader, limit, conflict));
     }
 
+    // Stop copying if we're interrupted.
+    reader = Box::new(CancelReader::new(reader));
+
+    // Record the digest and length of the data we write.
+    let mut reader = DigestReader::new(reader);
+
     // Read the first MiB of input and, if requested, check it against the
     // image's formatted sector size.
     let mut first_mb = [0u8; 1024 * 1024];
@@ -295,16 +818,17 @@ where
     }
 
     // call the callback to copy the image
-    image_copy(&first_mb, &mut reader, dest, dest_path, saved)?;
+    image_copy(&first_mb, &mut reader)?;
 
     // check signature
-    drop(reader);
+    let (digest, length) = reader.finish();
     verify_reader.verify()?;
 
-    // finish I/O before closing the progress bar
-    dest.sync_all().context("syncing data to disk")?;
-
-    Ok(())
+    Ok(WrittenImage {
+        digest,
+        length,
+        elapsed: start.elapsed(),
+    })
 }
 
 pub fn image_copy_default(
@@ -313,6 +837,29 @@ pub fn image_copy_default(
     dest: &mut File,
     _dest_path: &Path,
     saved: Option<&SavedPartitions>,
+) -> Result<()> {
+    image_copy(first_mb, source, dest, saved, false)
+}
+
+/// Like image_copy_default(), but write the bulk of the image with
+/// O_DIRECT to avoid filling the page cache.  Falls back to buffered
+/// writes if the destination doesn't support direct I/O.
+pub fn image_copy_direct(
+    first_mb: &[u8],
+    source: &mut dyn Read,
+    dest: &mut File,
+    _dest_path: &Path,
+    saved: Option<&SavedPartitions>,
+) -> Result<()> {
+    image_copy(first_mb, source, dest, saved, true)
+}
+
+fn image_copy(
+    first_mb: &[u8],
+    source: &mut dyn Read,
+    dest: &mut File,
+    saved: Option<&SavedPartitions>,
+    direct: bool,
 ) -> Result<()> {
     // Don't write the first MiB yet.  This ensures that the disk image
     // can't be used accidentally before its GPG signature is verified.  If
@@ -347,13 +894,27 @@ pub fn image_copy_default(
     //
     // Amortize write overhead.  The decompressor will produce bytes in
     // whatever chunk size it chooses.
-    let mut buf_dest = BufWriter::with_capacity(BUFFER_SIZE, dest);
-    copy(source, &mut buf_dest).context("decoding and writing image")?;
-    // we can't retain the original error via context() because of lifetime
-    // issues
-    let dest = buf_dest
-        .into_inner()
-        .map_err(|_| anyhow!("flushing data to disk"))?;
+    let direct = direct
+        && match check_direct_io(dest) {
+            Ok(()) => true,
+            Err(e) => {
+                status!("Couldn't use direct I/O; falling back to buffered writes: {e:#}");
+                false
+            }
+        };
+    if direct {
+        let mut direct_dest = DirectWriter::new(dest)?;
+        copy(source, &mut direct_dest).context("decoding and writing image")?;
+        direct_dest.finish().context("flushing data to disk")?;
+    } else {
+        let mut buf_dest = BufWriter::with_capacity(BUFFER_SIZE, &mut *dest);
+        copy(source, &mut buf_dest).context("decoding and writing image")?;
+        // we can't retain the original error via context() because of
+        // lifetime issues
+        buf_dest
+            .into_inner()
+            .map_err(|_| anyhow!("flushing data to disk"))?;
+    }
 
     // verify_reader has now checked the signature, so fill in the first MiB
     let offset = match saved {
@@ -387,11 +948,64 @@ pub fn image_copy_default(
     Ok(())
 }
 
-pub fn download_to_tempfile(url: &Url, retries: FetchRetries) -> Result<File> {
+/// Check that the destination supports O_DIRECT with our alignment.
+fn check_direct_io(dest: &File) -> Result<()> {
+    if dest
+        .metadata()
+        .context("getting disk metadata")?
+        .file_type()
+        .is_block_device()
+    {
+        let sector_size = get_sector_size(dest)?.get() as usize;
+        if DIRECT_IO_ALIGNMENT % sector_size != 0 {
+            bail!("sector size {sector_size} is incompatible with direct I/O");
+        }
+    }
+    set_direct_io(dest, true).context("enabling direct I/O")?;
+    set_direct_io(dest, false).context("disabling direct I/O")?;
+    Ok(())
+}
+
+/// Load the GPG keys used to verify image signatures.  If a keyring or
+/// any keys are specified, they replace the built-in keys.  Keys can be
+/// read from files or fetched from HTTPS URLs.
+pub fn load_verify_keys(
+    keyring: Option<&str>,
+    keys: &[String],
+    retries: RetryPolicy,
+) -> Result<VerifyKeys> {
+    if keyring.is_none() && keys.is_empty() {
+        return Ok(VerifyKeys::Production);
+    }
+    let mut loaded = Vec::new();
+    if let Some(path) = keyring {
+        loaded.push(std::fs::read(path).with_context(|| format!("reading GPG keyring {path}"))?);
+    }
+    for key in keys {
+        let data = match Url::parse(key) {
+            Ok(url) if url.scheme() == "https" => {
+                let mut data = Vec::new();
+                download_to_tempfile(&url, retries)
+                    .and_then(|mut f| f.read_to_end(&mut data).context("reading"))
+                    .with_context(|| format!("fetching GPG key {url}"))?;
+                data
+            }
+            Ok(url) if url.scheme() == "http" => {
+                bail!("refusing to fetch GPG key over HTTP: {url}")
+            }
+            _ => std::fs::read(key).with_context(|| format!("reading GPG key {key}"))?,
+        };
+        loaded.push(data);
+    }
+    Ok(VerifyKeys::Custom(loaded))
+}
+
+pub fn download_to_tempfile(url: &Url, retries: RetryPolicy) -> Result<File> {
     let mut f = tempfile::tempfile()?;
 
     let client = new_http_client()?;
-    let mut resp = http_get(client, url, retries)?;
+    let resp = http_get(client.clone(), url, retries)?;
+    let mut resp = ResumableReader::new(client, resp, 0, retries);
 
     let mut writer = BufWriter::with_capacity(BUFFER_SIZE, &mut f);
     copy(
@@ -409,14 +1023,225 @@ pub fn download_to_tempfile(url: &Url, retries: FetchRetries) -> Result<File> {
     Ok(f)
 }
 
+/// Size of each ranged request made by ChunkedReader.
+const DOWNLOAD_CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Reader that fetches a byte range of a URL using several concurrent
+/// ranged requests, and returns the data in order.  Chunks are assigned to
+/// worker threads round-robin, and each worker can only get one chunk
+/// ahead of the reader, so memory usage is bounded.  Dropping the reader
+/// cancels the workers and waits for them to exit.
+pub struct ChunkedReader {
+    receivers: Vec<Receiver<Result<Vec<u8>>>>,
+    workers: Vec<JoinHandle<()>>,
+    cancel: Arc<AtomicBool>,
+    current: Cursor<Vec<u8>>,
+    next_chunk: u64,
+    chunk_count: u64,
+}
+
+impl ChunkedReader {
+    pub fn new(
+        client: blocking::Client,
+        url: &Url,
+        range: Range<u64>,
+        threads: NonZeroU32,
+        validator: Option<String>,
+        retries: RetryPolicy,
+    ) -> Self {
+        Self::with_chunk_size(
+            client,
+            url,
+            range,
+            threads,
+            validator,
+            retries,
+            DOWNLOAD_CHUNK_SIZE,
+        )
+    }
+
+    fn with_chunk_size(
+        client: blocking::Client,
+        url: &Url,
+        range: Range<u64>,
+        threads: NonZeroU32,
+        validator: Option<String>,
+        retries: RetryPolicy,
+        chunk_size: u64,
+    ) -> Self {
+        let chunk_count = (range.end - range.start).div_ceil(chunk_size);
+        let threads = u64::from(threads.get()).clamp(1, chunk_count.max(1));
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (receivers, workers) = (0..threads)
+            .map(|worker| {
+                let (sender, receiver) = sync_channel(1);
+                let client = client.clone();
+                let url = url.clone();
+                let validator = validator.clone();
+                let cancel = cancel.clone();
+                let handle = thread::spawn(move || {
+                    let mut chunk = worker;
+                    while chunk < chunk_count && !cancel.load(Ordering::SeqCst) {
+                        let start = range.start + chunk * chunk_size;
+                        let end = (start + chunk_size).min(range.end);
+                        let result = fetch_chunk(
+                            client.clone(),
+                            &url,
+                            start..end,
+                            validator.as_deref(),
+                            retries,
+                            &cancel,
+                        );
+                        let failed = result.is_err();
+                        // stop if the reader has gone away or we failed
+                        if sender.send(result).is_err() || failed {
+                            break;
+                        }
+                        chunk += threads;
+                    }
+                });
+                (receiver, handle)
+            })
+            .unzip();
+        Self {
+            receivers,
+            workers,
+            cancel,
+            current: Cursor::new(Vec::new()),
+            next_chunk: 0,
+            chunk_count,
+        }
+    }
+}
+
+impl Read for ChunkedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let count = self.current.read(buf)?;
+            if count > 0 || buf.is_empty() || self.next_chunk == self.chunk_count {
+                return Ok(count);
+            }
+            let index = (self.next_chunk % self.receivers.len() as u64) as usize;
+            let data = self.receivers[index]
+                .recv()
+                .map_err(|_| io::Error::new(io::ErrorKind::Other, "download thread exited"))?
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{e:#}")))?;
+            self.current = Cursor::new(data);
+            self.next_chunk += 1;
+        }
+    }
+}
+
+impl Drop for ChunkedReader {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::SeqCst);
+        // unblock any worker waiting to hand over a chunk
+        self.receivers.clear();
+        for worker in self.workers.drain(..) {
+            // a panicked worker has already been reported by the reader
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Fetch one chunk for ChunkedReader, giving up early if `cancel` is set.
+fn fetch_chunk(
+    client: blocking::Client,
+    url: &Url,
+    range: Range<u64>,
+    validator: Option<&str>,
+    retries: RetryPolicy,
+    cancel: &AtomicBool,
+) -> Result<Vec<u8>> {
+    let length = range.end - range.start;
+    let resp = http_get_range(
+        client.clone(),
+        url,
+        range.start,
+        Some(range.end),
+        validator,
+        retries,
+    )?;
+    match resp.status() {
+        StatusCode::PARTIAL_CONTENT => check_content_range(&resp, range.start)?,
+        StatusCode::OK => bail!(
+            "server ignored range request for '{}', or it changed during download",
+            url
+        ),
+        s => bail!(
+            "fetching bytes {}-{} of '{}': {}",
+            range.start,
+            range.end,
+            url,
+            s
+        ),
+    }
+    let mut reader = ResumableReader::new(client, resp, range.start, retries).take(length);
+    let mut buf = Vec::with_capacity(length as usize);
+    loop {
+        if cancel.load(Ordering::SeqCst) {
+            bail!("download cancelled");
+        }
+        let count = (&mut reader)
+            .take(BUFFER_SIZE as u64)
+            .read_to_end(&mut buf)
+            .with_context(|| {
+                format!("fetching bytes {}-{} of '{}'", range.start, range.end, url)
+            })?;
+        if count == 0 {
+            break;
+        }
+    }
+    if buf.len() as u64 != length {
+        bail!(
+            "short read fetching bytes {}-{} of '{}'",
+            range.start,
+            range.end,
+            url
+        );
+    }
+    Ok(buf)
+}
+
+/// Format a size in bytes.
+pub(crate) fn format_bytes(count: u64) -> String {
+    Byte::from_bytes(count.into())
+        .get_appropriate_unit(true)
+        .format(1)
+}
+
+/// Format the average throughput of transferring `count` bytes in `elapsed`.
+pub fn format_rate(count: u64, elapsed: Duration) -> String {
+    let secs = elapsed.as_secs_f64();
+    if secs > 0.0 {
+        format!("{}/s", format_bytes((count as f64 / secs) as u64))
+    } else {
+        "-- B/s".into()
+    }
+}
+
+/// Format a duration with one-second precision, e.g. "1h02m", "3m05s", "9s".
+pub fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{s}s"),
+        (0, m, s) => format!("{m}m{s:02}s"),
+        (h, m, _) => format!("{h}h{m:02}m"),
+    }
+}
+
 struct ProgressReader<'a, R: Read> {
     source: R,
     length: Option<(NonZeroU64, String)>,
     artifact_type: &'a str,
+    /// Extra status to append to each report
+    detail: Option<&'a dyn Fn() -> String>,
 
     position: u64,
+    start: Instant,
     last_report: Instant,
 
+    quiet: bool,
     tty: bool,
     prologue: &'static str,
     epilogue: &'static str,
@@ -433,58 +1258,79 @@ impl<'a, R: Read> ProgressReader<'a, R> {
         let length = length.and_then(NonZeroU64::new);
         ProgressReader {
             source,
-            length: length.map(|l| (l, Self::format_bytes(l.get()))),
+            length: length.map(|l| (l, format_bytes(l.get()))),
             artifact_type,
+            detail: None,
 
             position: 0,
+            start: Instant::now(),
             last_report: Instant::now(),
 
+            quiet: is_quiet(),
             tty,
             // If stderr is a tty, draw a status line that updates itself in
             // place.  The prologue leaves a place for the cursor to rest
-            // between updates.  The epilogue writes three spaces to cover
-            // the switch from e.g.  1000 KiB to 1 MiB, and then uses CR to
+            // between updates.  The epilogue writes spaces to cover the
+            // line getting shorter, e.g. on the switch from 1000 KiB to
+            // 1 MiB or from "1m00s left" to "59s left", and then uses CR to
             // return to the start of the line.
             //
             // Otherwise, stderr is being read by another process, e.g.
             // journald, and fanciness may confuse it.  Just log regular
             // lines.
             prologue: if tty { "> " } else { "" },
-            epilogue: if tty { "   \r" } else { "\n" },
+            epilogue: if tty { "      \r" } else { "\n" },
         }
     }
-
-    /// Format a size in bytes.
-    fn format_bytes(count: u64) -> String {
-        Byte::from_bytes(count.into())
-            .get_appropriate_unit(true)
-            .format(1)
-    }
 }
 
 impl<'a, R: Read> Read for ProgressReader<'a, R> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         let count = self.source.read(buf)?;
         self.position += count as u64;
+        if self.quiet {
+            return Ok(count);
+        }
         if self.last_report.elapsed() >= Duration::from_secs(1)
             || self.length.as_ref().map(|(l, _)| l.get()) == Some(self.position)
         {
             self.last_report = Instant::now();
+            let elapsed = self.start.elapsed();
+            let rate = format_rate(self.position, elapsed);
+            let detail = self
+                .detail
+                .map(|f| format!("; {}", f()))
+                .unwrap_or_default();
             match self.length {
-                Some((length, ref length_str)) => eprint!(
-                    "{}Read {} {}/{} ({}%){}",
-                    self.prologue,
-                    self.artifact_type,
-                    Self::format_bytes(self.position),
-                    length_str,
-                    100 * self.position / length.get(),
-                    self.epilogue
-                ),
+                Some((length, ref length_str)) => {
+                    // estimate the remaining time from the average rate so far
+                    let remaining = length.get().saturating_sub(self.position);
+                    let eta = if remaining > 0 && self.position > 0 {
+                        let secs = elapsed.as_secs_f64() * remaining as f64 / self.position as f64;
+                        format!(", {} left", format_duration(Duration::from_secs_f64(secs)))
+                    } else {
+                        String::new()
+                    };
+                    eprint!(
+                        "{}Read {} {}/{} ({}%), {}{}{}{}",
+                        self.prologue,
+                        self.artifact_type,
+                        format_bytes(self.position),
+                        length_str,
+                        100 * self.position / length.get(),
+                        rate,
+                        eta,
+                        detail,
+                        self.epilogue
+                    )
+                }
                 None => eprint!(
-                    "{}Read {} {}{}",
+                    "{}Read {} {}, {}{}{}",
                     self.prologue,
                     self.artifact_type,
-                    Self::format_bytes(self.position),
+                    format_bytes(self.position),
+                    rate,
+                    detail,
                     self.epilogue
                 ),
             }
@@ -497,7 +1343,7 @@ impl<'a, R: Read> Read for ProgressReader<'a, R> {
 impl<'a, R: Read> Drop for ProgressReader<'a, R> {
     fn drop(&mut self) {
         // if we reported progress using CRs, log final newline
-        if self.tty {
+        if self.tty && !self.quiet {
             eprintln!();
         }
     }
@@ -506,6 +1352,7 @@ impl<'a, R: Read> Drop for ProgressReader<'a, R> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::{ranged_response, HttpResponse, HttpServer};
     use gptman::{GPTPartitionEntry, GPT};
     use std::fs::{read, write};
     use std::io::{Seek, SeekFrom};
@@ -580,7 +1427,7 @@ mod tests {
             .remove(0);
         let out_path = dir.path().join("out");
         let mut out_file = File::create(&out_path).unwrap();
-        write_image(
+        let written = write_image(
             &mut source,
             &mut out_file,
             &out_path,
@@ -592,6 +1439,38 @@ mod tests {
         )
         .unwrap();
         assert_eq!(&read(&out_path).unwrap(), decompressed_data);
+        assert_eq!(written.length, decompressed_data.len() as u64);
+        written
+            .digest
+            .validate(&mut &decompressed_data[..])
+            .unwrap();
+
+        // new copies to multiple destinations
+        let mut source = FileLocation::new(good_path.to_str().unwrap())
+            .sources()
+            .unwrap()
+            .remove(0);
+        let out_paths = [dir.path().join("out1"), dir.path().join("out2")];
+        let mut out_files = [
+            File::create(&out_paths[0]).unwrap(),
+            File::create(&out_paths[1]).unwrap(),
+        ];
+        let [out_file1, out_file2] = &mut out_files;
+        write_image_multi(
+            &mut source,
+            &mut [
+                (out_file1, out_paths[0].as_path()),
+                (out_file2, out_paths[1].as_path()),
+            ],
+            image_copy_default,
+            true,
+            None,
+            VerifyKeys::InsecureTest,
+        )
+        .unwrap();
+        for path in &out_paths {
+            assert_eq!(&read(path).unwrap(), decompressed_data);
+        }
 
         // new copy with bad sig
         let mut source = FileLocation::new(bad_path.to_str().unwrap())
@@ -623,6 +1502,73 @@ mod tests {
         assert_eq!(should_decompress(false, "foo.xz"), (false, "foo.xz"));
         assert_eq!(should_decompress(true, "foo.xz"), (true, "foo"));
         assert_eq!(should_decompress(true, "foo.tar.xz"), (false, "foo.tar.xz"));
+        assert_eq!(should_decompress(false, "foo.zst"), (false, "foo.zst"));
+        assert_eq!(should_decompress(true, "foo.zst"), (true, "foo"));
+        assert_eq!(
+            should_decompress(true, "foo.tar.zst"),
+            (false, "foo.tar.zst")
+        );
+    }
+
+    #[test]
+    fn test_format_progress() {
+        assert_eq!(format_duration(Duration::from_millis(9500)), "9s");
+        assert_eq!(format_duration(Duration::from_secs(185)), "3m05s");
+        assert_eq!(format_duration(Duration::from_secs(3725)), "1h02m");
+        assert_eq!(format_rate(4 << 20, Duration::from_secs(2)), "2.0 MiB/s");
+        assert_eq!(format_rate(4 << 20, Duration::ZERO), "-- B/s");
+
+        let progress = [
+            DestProgress::new(Path::new("/dev/sda")),
+            DestProgress::new(Path::new("/dev/sdb")),
+        ];
+        progress[0].written.store(3 << 20, Ordering::SeqCst);
+        progress[1].failed.store(true, Ordering::SeqCst);
+        assert_eq!(
+            format_dest_progress(&progress),
+            "/dev/sda 3.0 MiB, /dev/sdb failed"
+        );
+    }
+
+    #[test]
+    fn test_write_image_multi_failure() {
+        let dir = TempDir::new().unwrap();
+        let data: Vec<u8> = (0..3 << 20).map(|i: u32| (i % 251) as u8).collect();
+        let source_path = dir.path().join("image");
+        write(&source_path, &data).unwrap();
+        let paths = [dir.path().join("good"), dir.path().join("bad")];
+        let [mut good, mut bad] = paths.clone().map(|p| File::create(p).unwrap());
+
+        // one destination fails partway through; the other is completed,
+        // but the write as a whole fails
+        let err = write_image_multi(
+            &mut FileLocation::new(source_path.to_str().unwrap())
+                .sources()
+                .unwrap()
+                .remove(0),
+            &mut [
+                (&mut good, paths[0].as_path()),
+                (&mut bad, paths[1].as_path()),
+            ],
+            |first_mb, source, dest, path, saved| {
+                if path.ends_with("bad") {
+                    let mut buf = [0; 4096];
+                    source.read_exact(&mut buf)?;
+                    bail!("injected failure");
+                }
+                image_copy_default(first_mb, source, dest, path, saved)
+            },
+            false,
+            None,
+            VerifyKeys::InsecureTest,
+        )
+        .unwrap_err();
+        assert!(
+            format!("{err:#}").contains("one or more destination devices"),
+            "incorrect error: {err:#}"
+        );
+        assert!(read(&paths[0]).unwrap() == data);
+        assert!(read(&paths[1]).unwrap().is_empty());
     }
 
     #[test]
@@ -771,6 +1717,114 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_mirror_path() {
+        let dir = Path::new("/m");
+        let url = |s| Url::parse(s).unwrap();
+        assert_eq!(
+            mirror_path(dir, &url("https://example.com/streams/stable.json")).unwrap(),
+            Path::new("/m/streams/stable.json")
+        );
+        assert_eq!(
+            mirror_path(dir, &url("https://example.com/a/../b/./c.raw.xz?x=1")).unwrap(),
+            Path::new("/m/b/c.raw.xz")
+        );
+        // empty segments
+        mirror_path(dir, &url("https://example.com/")).unwrap_err();
+        mirror_path(dir, &url("https://example.com/a//b")).unwrap_err();
+        mirror_path(dir, &url("https://example.com/a/")).unwrap_err();
+        // no path
+        mirror_path(dir, &url("data:text/plain,a")).unwrap_err();
+    }
+
+    fn chunked_reader(server: &HttpServer, range: Range<u64>, threads: u32) -> ChunkedReader {
+        ChunkedReader::with_chunk_size(
+            new_http_client().unwrap(),
+            &server.url("/image.raw"),
+            range,
+            NonZeroU32::new(threads).unwrap(),
+            Some("\"abc\"".into()),
+            RetryPolicy::new(FetchRetries::None, None),
+            1000,
+        )
+    }
+
+    #[test]
+    fn test_chunked_reader() {
+        let data: Vec<u8> = (0..10_500u32).map(|i| (i * 7 % 251) as u8).collect();
+        let server = {
+            let data = data.clone();
+            HttpServer::new(move |req| {
+                // make the first chunk arrive last
+                if req.header("range") == Some("bytes=0-999") {
+                    thread::sleep(Duration::from_millis(200));
+                }
+                ranged_response(req, &data, "\"abc\"")
+            })
+        };
+
+        // chunks are reassembled in order, and the short final chunk is
+        // requested with the right range
+        let mut out = Vec::new();
+        chunked_reader(&server, 0..10_500, 3)
+            .read_to_end(&mut out)
+            .unwrap();
+        assert!(out == data);
+        let mut ranges: Vec<String> = server
+            .requests()
+            .iter()
+            .map(|r| {
+                assert_eq!(r.header("if-range"), Some("\"abc\""));
+                r.header("range").unwrap().to_string()
+            })
+            .collect();
+        ranges.sort_by_key(|r| r[6..].split('-').next().unwrap().parse::<u64>().unwrap());
+        let expected: Vec<String> = (0..11)
+            .map(|i| format!("bytes={}-{}", i * 1000, (i * 1000 + 999).min(10_499)))
+            .collect();
+        assert_eq!(ranges, expected);
+
+        // a subrange, with more threads than chunks
+        let mut out = Vec::new();
+        chunked_reader(&server, 1500..3200, 8)
+            .read_to_end(&mut out)
+            .unwrap();
+        assert!(out == data[1500..3200]);
+
+        // an empty range
+        let mut out = Vec::new();
+        chunked_reader(&server, 0..0, 3)
+            .read_to_end(&mut out)
+            .unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_chunked_reader_failure() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 253) as u8).collect();
+        let server = {
+            let data = data.clone();
+            HttpServer::new(move |req| match req.header("range") {
+                Some("bytes=5000-5999") => HttpResponse::new("404 Not Found", b""),
+                _ => ranged_response(req, &data, "\"abc\""),
+            })
+        };
+
+        // the chunks before the failed one are returned, then the error
+        let mut reader = chunked_reader(&server, 0..10_000, 4);
+        let mut out = vec![0; 5000];
+        reader.read_exact(&mut out).unwrap();
+        assert!(out == data[..5000]);
+        let err = reader.read(&mut out).unwrap_err();
+        assert!(err.to_string().contains("404"), "{err}");
+        // the other workers are blocked handing over chunks; dropping the
+        // reader must stop them rather than hang
+        drop(reader);
+
+        // dropping the reader before reading anything also stops the workers
+        drop(chunked_reader(&server, 0..10_000, 4));
+    }
+
     fn partition(f: &mut (impl Read + Write + Seek), start_mb: Option<u64>) {
         let mut gpt = GPT::new_from(f, 512, *Uuid::new_v4().as_bytes()).unwrap();
         if let Some(start_mb) = start_mb {
@@ -118,8 +118,43 @@ pub struct KargsEditor {
     append_if_missing: Vec<String>,
     replace: Vec<String>,
     delete: Vec<String>,
+    /// (condition, karg) pairs; conditions are evaluated against the
+    /// original kargs
+    append_if_present: Vec<(String, String)>,
+    delete_if_present: Vec<(String, String)>,
+    delete_if_missing: Vec<(String, String)>,
+    dedup: bool,
 }
 
+/// Kargs which only take one value, so that a later instance overrides
+/// an earlier one.  Deduplication keeps the last of these; other kargs,
+/// such as console=, may legitimately be repeated with different values,
+/// so only exact repeats of them are dropped.
+const SINGLE_VALUED_KARGS: &[&str] = &[
+    "audit",
+    "coreos.live.rootfs_url",
+    "coreos.liveiso",
+    "crashkernel",
+    "enforcing",
+    "ignition.config.url",
+    "ignition.platform.id",
+    "init",
+    "loglevel",
+    "mitigations",
+    "ostree",
+    "rd.neednet",
+    "root",
+    "rootflags",
+    "rootfstype",
+    "selinux",
+    "systemd.unit",
+];
+
+/// Prefixes of kargs whose meaning depends on their position relative to
+/// other kargs, such as hugepages= applying to the preceding hugepagesz=.
+/// Deduplication never drops these, even if repeated exactly.
+const POSITIONAL_KARG_PREFIXES: &[&str] = &["default_hugepagesz", "hugepages", "rd."];
+
 impl KargsEditor {
     pub fn new() -> Self {
         Default::default()
@@ -145,6 +180,33 @@ impl KargsEditor {
         self
     }
 
+    /// Append each karg if its condition is present in the original kargs.
+    /// A condition without "=" also matches any "condition=value" karg.
+    pub fn append_if_present(&mut self, args: &[(String, String)]) -> &mut Self {
+        self.append_if_present.extend_from_slice(args);
+        self
+    }
+
+    /// Delete each karg if its condition is present in the original kargs.
+    pub fn delete_if_present(&mut self, args: &[(String, String)]) -> &mut Self {
+        self.delete_if_present.extend_from_slice(args);
+        self
+    }
+
+    /// Delete each karg if its condition is missing from the original kargs.
+    pub fn delete_if_missing(&mut self, args: &[(String, String)]) -> &mut Self {
+        self.delete_if_missing.extend_from_slice(args);
+        self
+    }
+
+    /// Normalize the resulting kargs: collapse whitespace, drop repeated
+    /// kargs, and let the last occurrence of a `key=value` karg win.
+    /// Surviving kargs keep the relative order of their last occurrence.
+    pub fn dedup(&mut self) -> &mut Self {
+        self.dedup = true;
+        self
+    }
+
     // XXX: Need a proper parser here and share it with afterburn. The approach we use here
     // is to just do a dumb substring search and replace. This is naive (e.g. doesn't
     // handle occurrences in quoted args) but will work for now (one thing that saves us is
@@ -154,11 +216,44 @@ impl KargsEditor {
             static ref RE: Regex = Regex::new(r"^([^=]+)=([^=]+)=([^=]+)$").unwrap();
         }
         let mut new_kargs: String = format!(" {current_kargs} ");
-        for karg in &self.delete {
+        // evaluate conditions before we change anything
+        let present = |cond: &str| {
+            let cond = cond.trim();
+            new_kargs.contains(&format!(" {cond} "))
+                || (!cond.contains('=') && new_kargs.contains(&format!(" {cond}=")))
+        };
+        let delete: Vec<&String> = self
+            .delete
+            .iter()
+            .chain(
+                self.delete_if_present
+                    .iter()
+                    .filter(|(cond, _)| present(cond))
+                    .map(|(_, karg)| karg),
+            )
+            .chain(
+                self.delete_if_missing
+                    .iter()
+                    .filter(|(cond, _)| !present(cond))
+                    .map(|(_, karg)| karg),
+            )
+            .collect();
+        let append: Vec<&String> = self
+            .append
+            .iter()
+            .chain(
+                self.append_if_present
+                    .iter()
+                    .filter(|(cond, _)| present(cond))
+                    .map(|(_, karg)| karg),
+            )
+            .collect();
+
+        for karg in delete {
             let s = format!(" {} ", karg.trim());
             new_kargs = new_kargs.replace(&s, " ");
         }
-        for karg in &self.append {
+        for karg in append {
             new_kargs.push_str(karg.trim());
             new_kargs.push(' ');
         }
@@ -179,6 +274,9 @@ impl KargsEditor {
             let new = format!(" {}={} ", &caps[1], &caps[3]);
             new_kargs = new_kargs.replace(&old, &new);
         }
+        if self.dedup {
+            return Ok(dedup_kargs(&new_kargs));
+        }
         Ok(new_kargs.trim().into())
     }
 
@@ -194,6 +292,60 @@ impl KargsEditor {
     }
 }
 
+/// Split a kernel command line into arguments.  As in the kernel,
+/// whitespace inside double quotes doesn't separate arguments.
+fn split_kargs(kargs: &str) -> Vec<&str> {
+    let mut ret = Vec::new();
+    let mut start = None;
+    let mut quoted = false;
+    for (i, c) in kargs.char_indices() {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        if c.is_whitespace() && !quoted {
+            if let Some(s) = start.take() {
+                ret.push(&kargs[s..i]);
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(s) = start {
+        ret.push(&kargs[s..]);
+    }
+    ret
+}
+
+fn dedup_kargs(kargs: &str) -> String {
+    let args = split_kargs(kargs);
+    // the key which a later argument must match to supersede this one, or
+    // None if it must be kept
+    let key = |arg: &str| -> Option<String> {
+        let arg = arg.trim_start_matches('"');
+        let name = arg.split_once('=').map_or(arg, |(name, _)| name);
+        if SINGLE_VALUED_KARGS.contains(&name) && arg.contains('=') {
+            Some(format!("{name}="))
+        } else if POSITIONAL_KARG_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+        {
+            None
+        } else {
+            Some(arg.to_string())
+        }
+    };
+    let keys: Vec<Option<String>> = args.iter().map(|arg| key(arg)).collect();
+    args.iter()
+        .enumerate()
+        .filter(|(i, _)| match &keys[*i] {
+            Some(k) => !keys[i + 1..].iter().flatten().any(|later| later == k),
+            None => true,
+        })
+        .map(|(_, arg)| *arg)
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -291,6 +443,106 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_apply_to_conditional() {
+        let orig_kargs = "foo ignition.firstboot bar=1 baz";
+        let pair = |cond: &str, karg: &str| (cond.to_string(), karg.to_string());
+
+        let new_kargs = KargsEditor::new()
+            .append_if_present(&[
+                pair("ignition.firstboot", "rd.neednet=1"),
+                pair("bar", "bar-key"),
+                pair("bar=1", "bar-exact"),
+                pair("bar=2", "wrong-value"),
+                pair("missing", "nope"),
+            ])
+            .apply_to(orig_kargs)
+            .unwrap();
+        assert_eq!(
+            new_kargs,
+            "foo ignition.firstboot bar=1 baz rd.neednet=1 bar-key bar-exact"
+        );
+
+        // conditions are evaluated against the original kargs
+        let new_kargs = KargsEditor::new()
+            .delete(&["baz".into()])
+            .delete_if_present(&[pair("ignition.firstboot", "foo"), pair("baz", "bar=1")])
+            .delete_if_missing(&[
+                pair("ignition.firstboot", "ignition.firstboot"),
+                pair("quux", "baz"),
+            ])
+            .append_if_present(&[pair("baz", "appended")])
+            .apply_to(orig_kargs)
+            .unwrap();
+        assert_eq!(new_kargs, "ignition.firstboot appended");
+
+        // conditional operations count as operations
+        assert!(KargsEditor::new()
+            .delete_if_missing(&[pair("foo", "bar")])
+            .maybe_apply_to(orig_kargs)
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn test_apply_to_dedup() {
+        let dedup = |kargs: &str| KargsEditor::new().dedup().apply_to(kargs).unwrap();
+
+        // whitespace is collapsed
+        assert_eq!(dedup("  foo \t bar\n"), "foo bar");
+        assert_eq!(dedup(""), "");
+        // exact repeats keep the last position
+        assert_eq!(dedup("foo bar foo baz"), "bar foo baz");
+        // last value of a single-valued karg wins
+        assert_eq!(
+            dedup("root=/dev/sda b root=/dev/sdb c mitigations=off root=UUID=x"),
+            "b c mitigations=off root=UUID=x"
+        );
+        // flags and key=value args are distinct
+        assert_eq!(dedup("root root=a root=b"), "root root=b");
+        // other kargs only drop exact repeats
+        assert_eq!(
+            dedup("console=ttyS0 console=tty0 ip=dhcp console=ttyS0"),
+            "console=tty0 ip=dhcp console=ttyS0"
+        );
+        assert_eq!(
+            dedup("hugepagesz=2M hugepages=512 hugepagesz=1G hugepages=4"),
+            "hugepagesz=2M hugepages=512 hugepagesz=1G hugepages=4"
+        );
+        // position-dependent kargs are kept even if repeated exactly
+        assert_eq!(
+            dedup("hugepagesz=1G hugepages=4 hugepagesz=2M hugepages=4"),
+            "hugepagesz=1G hugepages=4 hugepagesz=2M hugepages=4"
+        );
+        assert_eq!(
+            dedup("rd.lvm.lv=a/b rd.md=0 rd.lvm.lv=a/b quiet quiet"),
+            "rd.lvm.lv=a/b rd.md=0 rd.lvm.lv=a/b quiet"
+        );
+        // single-valued kargs are still deduplicated
+        assert_eq!(dedup("rd.neednet=1 rd.neednet=0"), "rd.neednet=0");
+        assert_eq!(
+            dedup("rd.driver.blacklist=a rd.driver.blacklist=b a=1 a=2 a=1"),
+            "rd.driver.blacklist=a rd.driver.blacklist=b a=2 a=1"
+        );
+        // quoted values keep their whitespace and are compared by key
+        assert_eq!(
+            dedup(r#"root="a  b" bar root="c d"  "baz=e f" baz=g"#),
+            r#"bar root="c d" "baz=e f" baz=g"#
+        );
+        assert_eq!(dedup(r#"x="y z" x="y z""#), r#"x="y z""#);
+
+        // dedup applies after other operations and counts as an operation
+        let new_kargs = KargsEditor::new()
+            .append(&["console=ttyS0".into(), "mitigations=off".into()])
+            .dedup()
+            .maybe_apply_to("mitigations=auto console=ttyS0 quiet")
+            .unwrap();
+        assert_eq!(
+            new_kargs.as_deref(),
+            Some("quiet console=ttyS0 mitigations=off")
+        );
+    }
+
     #[test]
     fn test_maybe_apply_to() {
         // no arguments
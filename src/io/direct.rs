@@ -0,0 +1,159 @@
+// Copyright 2024 Red Hat, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Writing with O_DIRECT, bypassing the page cache.
+
+use anyhow::{bail, Context, Result};
+use std::fs::File;
+use std::io::{self, Seek, Write};
+use std::os::unix::io::AsRawFd;
+
+use crate::io::BUFFER_SIZE;
+
+/// Alignment of buffers, offsets, and lengths for O_DIRECT writes.  This
+/// must be a multiple of the logical sector size of the destination.
+pub const DIRECT_IO_ALIGNMENT: usize = 4096;
+
+/// Enable or disable O_DIRECT on an open file.
+pub fn set_direct_io(file: &File, enable: bool) -> io::Result<()> {
+    let fd = file.as_raw_fd();
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let flags = if enable {
+        flags | libc::O_DIRECT
+    } else {
+        flags & !libc::O_DIRECT
+    };
+    if unsafe { libc::fcntl(fd, libc::F_SETFL, flags) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Writer that enables O_DIRECT on a file and writes to it in aligned
+/// chunks from an aligned buffer.  Any unaligned data at the end of the
+/// stream is written by finish() after O_DIRECT is disabled again.
+pub struct DirectWriter<'a> {
+    file: &'a mut File,
+    buf: Vec<u8>,
+    /// Offset of the aligned start of the buffer within buf
+    start: usize,
+    len: usize,
+}
+
+impl<'a> DirectWriter<'a> {
+    /// Enable O_DIRECT on the file, which must be positioned at an aligned
+    /// offset.  Fails if the file doesn't support O_DIRECT.
+    pub fn new(file: &'a mut File) -> Result<Self> {
+        let pos = file.stream_position().context("getting file position")?;
+        if pos % DIRECT_IO_ALIGNMENT as u64 != 0 {
+            bail!("file offset {pos} isn't aligned for direct I/O");
+        }
+        set_direct_io(file, true).context("enabling direct I/O")?;
+        let buf = vec![0; BUFFER_SIZE + DIRECT_IO_ALIGNMENT];
+        let start = buf.as_ptr().align_offset(DIRECT_IO_ALIGNMENT);
+        Ok(Self {
+            file,
+            buf,
+            start,
+            len: 0,
+        })
+    }
+
+    /// Write out any buffered data and disable O_DIRECT.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.flush()?;
+        set_direct_io(self.file, false)?;
+        let start = self.start;
+        self.file.write_all(&self.buf[start..start + self.len])?;
+        self.len = 0;
+        Ok(())
+    }
+}
+
+impl<'a> Write for DirectWriter<'a> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let count = data.len().min(BUFFER_SIZE - self.len);
+        let pos = self.start + self.len;
+        self.buf[pos..pos + count].copy_from_slice(&data[..count]);
+        self.len += count;
+        if self.len == BUFFER_SIZE {
+            self.flush()?;
+        }
+        Ok(count)
+    }
+
+    /// Write out all the aligned blocks in the buffer.  Unaligned data
+    /// stays buffered.
+    fn flush(&mut self) -> io::Result<()> {
+        let aligned = self.len - self.len % DIRECT_IO_ALIGNMENT;
+        if aligned > 0 {
+            let start = self.start;
+            self.file.write_all(&self.buf[start..start + aligned])?;
+            self.buf
+                .copy_within(start + aligned..start + self.len, start);
+            self.len -= aligned;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Drop for DirectWriter<'a> {
+    fn drop(&mut self) {
+        // don't leave O_DIRECT enabled for later, unaligned writes
+        let _ = set_direct_io(self.file, false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn direct_writer() {
+        // older kernels don't support O_DIRECT on tmpfs; we skip the
+        // test below if enabling it fails
+        let dir = tempfile::tempdir().unwrap();
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(dir.path().join("direct"))
+            .unwrap();
+        let data: Vec<u8> = (0..3 * BUFFER_SIZE + 1234).map(|i| i as u8).collect();
+        let mut writer = match DirectWriter::new(&mut file) {
+            Ok(writer) => writer,
+            Err(e) => {
+                eprintln!("skipping test: {e:#}");
+                return;
+            }
+        };
+        for chunk in data.chunks(10000) {
+            writer.write_all(chunk).unwrap();
+        }
+        writer.flush().unwrap();
+        writer.finish().unwrap();
+
+        // O_DIRECT has been disabled, so an unaligned write works
+        file.write_all(b"x").unwrap();
+        file.rewind().unwrap();
+        let mut result = Vec::new();
+        file.read_to_end(&mut result).unwrap();
+        assert_eq!(&result[..data.len()], data);
+        assert_eq!(&result[data.len()..], b"x");
+    }
+}
@@ -93,9 +93,35 @@ impl fmt::Display for IgnitionHash {
 impl IgnitionHash {
     /// Digest and validate input data.
     pub fn validate(&self, input: &mut impl Read) -> Result<()> {
-        let (mut hasher, digest) = match self {
-            IgnitionHash::Sha256(val) => (IgnitionHasher::Sha256(sha::Sha256::new()), val),
-            IgnitionHash::Sha512(val) => (IgnitionHasher::Sha512(sha::Sha512::new()), val),
+        let computed = self.compute(input)?;
+        let digest = self.expected();
+        if computed != digest {
+            bail!(
+                "hash mismatch, computed '{}' but expected '{}'",
+                hex::encode(computed),
+                hex::encode(digest),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Digest input data and report whether it matches.  Only fails if
+    /// the input can't be read.
+    pub fn matches(&self, input: &mut impl Read) -> Result<bool> {
+        Ok(self.compute(input)? == self.expected())
+    }
+
+    fn expected(&self) -> &[u8] {
+        match self {
+            IgnitionHash::Sha256(val) | IgnitionHash::Sha512(val) => val,
+        }
+    }
+
+    fn compute(&self, input: &mut impl Read) -> Result<Vec<u8>> {
+        let mut hasher = match self {
+            IgnitionHash::Sha256(_) => IgnitionHasher::Sha256(sha::Sha256::new()),
+            IgnitionHash::Sha512(_) => IgnitionHasher::Sha512(sha::Sha512::new()),
         };
         let mut buf = [0u8; 128 * 1024];
         loop {
@@ -109,24 +135,14 @@ impl IgnitionHash {
                 Err(e) => return Err(e).context("reading input"),
             };
         }
-        let computed = match hasher {
+        Ok(match hasher {
             IgnitionHasher::Sha256(h) => h.finish().to_vec(),
             IgnitionHasher::Sha512(h) => h.finish().to_vec(),
-        };
-
-        if &computed != digest {
-            bail!(
-                "hash mismatch, computed '{}' but expected '{}'",
-                hex::encode(computed),
-                hex::encode(digest),
-            );
-        }
-
-        Ok(())
+        })
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone, Default)]
 pub struct Sha256Digest(pub [u8; 32]);
 
 impl TryFrom<Hasher> for Sha256Digest {
@@ -224,6 +240,41 @@ impl<W: Write> TryFrom<WriteHasher<W>> for Sha256Digest {
     }
 }
 
+/// Reader wrapper that computes the SHA-256 digest and length of the data
+/// read through it.
+pub struct DigestReader<R: Read> {
+    source: R,
+    hasher: sha::Sha256,
+    length: u64,
+}
+
+impl<R: Read> DigestReader<R> {
+    pub fn new(source: R) -> Self {
+        Self {
+            source,
+            hasher: sha::Sha256::new(),
+            length: 0,
+        }
+    }
+
+    /// Return the digest and length of the data read so far.
+    pub fn finish(self) -> (IgnitionHash, u64) {
+        (
+            IgnitionHash::Sha256(self.hasher.finish().to_vec()),
+            self.length,
+        )
+    }
+}
+
+impl<R: Read> Read for DigestReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.source.read(buf)?;
+        self.hasher.update(&buf[..count]);
+        self.length += count as u64;
+        Ok(count)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -252,6 +303,20 @@ mod tests {
             let hasher = IgnitionHash::from_str(hash_arg).unwrap();
             let mut rd = std::io::Cursor::new(&input);
             assert!(hasher.validate(&mut rd).is_ok() == *valid);
+            let mut rd = std::io::Cursor::new(&input);
+            assert_eq!(hasher.matches(&mut rd).unwrap(), *valid);
         }
     }
+
+    #[test]
+    fn test_digest_reader() {
+        let mut reader = DigestReader::new(&b"abc"[..]);
+        io::copy(&mut reader, &mut io::sink()).unwrap();
+        let (hash, length) = reader.finish();
+        assert_eq!(
+            hash.to_string(),
+            "sha256-ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(length, 3);
+    }
 }
@@ -12,13 +12,59 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Error, Result};
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
-use flate2::read::GzEncoder;
+use flate2::read::{GzDecoder, GzEncoder};
 use flate2::Compression;
 use ignition_config as ign_multi;
 use ignition_config::v3_3 as ign;
+use reqwest::Url;
+use serde_json::json;
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+use std::fmt;
 use std::io::Read;
+use std::str::FromStr;
+
+/// A Clevis pin that can unlock an encrypted root filesystem: "tpm2", or
+/// "tang=<url>,<thumbprint>".
+#[derive(Clone, Debug, DeserializeFromStr, SerializeDisplay, PartialEq, Eq)]
+pub enum ClevisPin {
+    Tpm2,
+    Tang { url: String, thumbprint: String },
+}
+
+impl FromStr for ClevisPin {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "tpm2" {
+            return Ok(Self::Tpm2);
+        }
+        let (url, thumbprint) = s
+            .strip_prefix("tang=")
+            .and_then(|v| v.rsplit_once(','))
+            .ok_or_else(|| {
+                anyhow!("invalid pin '{s}'; expected 'tpm2' or 'tang=URL,THUMBPRINT'")
+            })?;
+        Url::parse(url).with_context(|| format!("invalid Tang URL '{url}'"))?;
+        if thumbprint.is_empty() {
+            bail!("missing thumbprint for Tang server {}", url);
+        }
+        Ok(Self::Tang {
+            url: url.into(),
+            thumbprint: thumbprint.into(),
+        })
+    }
+}
+
+impl fmt::Display for ClevisPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tpm2 => write!(f, "tpm2"),
+            Self::Tang { url, thumbprint } => write!(f, "tang={url},{thumbprint}"),
+        }
+    }
+}
 
 #[derive(Debug, Default)]
 pub struct Ignition {
@@ -28,13 +74,19 @@ pub struct Ignition {
 impl Ignition {
     pub fn merge_config(&mut self, config: &ign_multi::Config) -> Result<()> {
         let buf = serde_json::to_vec(config).context("serializing child Ignition config")?;
+        self.merge_bytes(&buf)
+    }
+
+    /// Merge a serialized child config, which is not parsed and may use
+    /// any config version.
+    pub fn merge_bytes(&mut self, data: &[u8]) -> Result<()> {
         self.config
             .ignition
             .config
             .get_or_insert_with(Default::default)
             .merge
             .get_or_insert_with(Default::default)
-            .push(make_resource(&buf)?);
+            .push(make_resource(data)?);
         Ok(())
     }
 
@@ -76,6 +128,227 @@ impl Ignition {
         Ok(())
     }
 
+    /// Mount an existing filesystem, both while Ignition runs and in the
+    /// booted system, without reformatting it.
+    pub fn add_mount(&mut self, device: &str, format: &str, path: &str) -> Result<()> {
+        let fsck = format!("systemd-fsck@{}.service", systemd_escape_path(device));
+        self.add_unit(
+            format!("{}.mount", systemd_escape_path(path)),
+            format!(
+                "[Unit]\nRequires={fsck}\nAfter={fsck}\n\n[Mount]\nWhere={path}\nWhat={device}\nType={format}\n\n[Install]\nRequiredBy=local-fs.target\n"
+            ),
+            true,
+        )?;
+        self.config
+            .storage
+            .get_or_insert_with(Default::default)
+            .filesystems
+            .get_or_insert_with(Default::default)
+            .push(ign::Filesystem {
+                format: Some(format.into()),
+                path: Some(path.into()),
+                wipe_filesystem: Some(false),
+                ..ign::Filesystem::new(device.into())
+            });
+        Ok(())
+    }
+
+    /// Mirror the boot disk across the specified devices, with the layout
+    /// used by Butane's `boot_device.mirror`: each device gets its own
+    /// firmware partitions, and /boot and the root filesystem are RAID1
+    /// arrays.  The OS copies itself into the new layout on first boot.
+    pub fn add_boot_mirror(&mut self, devices: &[String], arch: &str) -> Result<()> {
+        // label, size in MiB, and type GUID of the firmware partitions
+        let firmware: &[(&str, u32, &str)] = match arch {
+            "x86_64" => &[
+                ("bios", 1, "21686148-6449-6E6F-744E-656564454649"),
+                ("esp", 127, "C12A7328-F81F-11D2-BA4B-00A0C93EC93B"),
+            ],
+            "aarch64" => &[("esp", 127, "C12A7328-F81F-11D2-BA4B-00A0C93EC93B")],
+            "ppc64le" => &[("prep", 4, "9E1A2D38-C612-4316-AA26-8B49521E5A8B")],
+            _ => bail!("boot disk mirroring is not supported on {}", arch),
+        };
+        if devices.len() < 2 {
+            bail!("boot disk mirroring requires at least two devices");
+        }
+
+        let mut disks = Vec::new();
+        let mut filesystems = Vec::new();
+        let mut boot_devices = Vec::new();
+        let mut root_devices = Vec::new();
+        for (i, device) in devices.iter().enumerate() {
+            let n = i + 1;
+            let mut partitions = Vec::new();
+            for (label, size, type_guid) in firmware {
+                partitions.push(json!({
+                    "label": format!("{label}-{n}"),
+                    "sizeMiB": size,
+                    "typeGuid": type_guid,
+                }));
+                if *label == "esp" {
+                    filesystems.push(json!({
+                        "device": format!("/dev/disk/by-partlabel/esp-{n}"),
+                        "format": "vfat",
+                        "label": format!("esp-{n}"),
+                        "wipeFilesystem": true,
+                    }));
+                }
+            }
+            partitions.push(json!({"label": format!("boot-{n}"), "sizeMiB": 384}));
+            partitions.push(json!({"label": format!("root-{n}")}));
+            boot_devices.push(format!("/dev/disk/by-partlabel/boot-{n}"));
+            root_devices.push(format!("/dev/disk/by-partlabel/root-{n}"));
+            disks.push(json!({
+                "device": device,
+                "partitions": partitions,
+                "wipeTable": true,
+            }));
+        }
+        let raid = json!([
+            {
+                "name": "md-boot",
+                "level": "raid1",
+                "devices": boot_devices,
+                // the firmware reads /boot before the array is assembled
+                "options": ["--metadata=1.0"],
+            },
+            {
+                "name": "md-root",
+                "level": "raid1",
+                "devices": root_devices,
+            },
+        ]);
+        filesystems.push(json!({
+            "device": "/dev/md/md-boot",
+            "format": "ext4",
+            "label": "boot",
+            "wipeFilesystem": true,
+        }));
+        filesystems.push(json!({
+            "device": "/dev/md/md-root",
+            "format": "xfs",
+            "label": "root",
+            "wipeFilesystem": true,
+        }));
+
+        let disks: Vec<ign::Disk> =
+            serde_json::from_value(json!(disks)).context("building mirrored disks")?;
+        let raid: Vec<ign::Raid> =
+            serde_json::from_value(raid).context("building mirrored RAID arrays")?;
+        let filesystems: Vec<ign::Filesystem> =
+            serde_json::from_value(json!(filesystems)).context("building mirrored filesystems")?;
+        let storage = self.config.storage.get_or_insert_with(Default::default);
+        storage
+            .disks
+            .get_or_insert_with(Default::default)
+            .extend(disks);
+        storage
+            .raid
+            .get_or_insert_with(Default::default)
+            .extend(raid);
+        storage
+            .filesystems
+            .get_or_insert_with(Default::default)
+            .extend(filesystems);
+        Ok(())
+    }
+
+    /// Encrypt the root filesystem on the specified device with LUKS on
+    /// first boot, as Butane's `boot_device.luks` does, replacing any
+    /// existing filesystem entry for the device.  The volume is unlocked
+    /// by any one of the specified Clevis pins.
+    pub fn add_root_luks(&mut self, device: &str, pins: &[ClevisPin]) -> Result<()> {
+        if pins.is_empty() {
+            bail!("at least one Clevis pin is required");
+        }
+        let tang: Vec<_> = pins
+            .iter()
+            .filter_map(|pin| match pin {
+                ClevisPin::Tang { url, thumbprint } => {
+                    Some(json!({"url": url, "thumbprint": thumbprint}))
+                }
+                ClevisPin::Tpm2 => None,
+            })
+            .collect();
+        let mut clevis = json!({"tpm2": pins.contains(&ClevisPin::Tpm2)});
+        if !tang.is_empty() {
+            clevis["tang"] = json!(tang);
+        }
+        let luks: ign::Luks = serde_json::from_value(json!({
+            "name": "root",
+            "label": "luks-root",
+            "device": device,
+            "clevis": clevis,
+            "wipeVolume": true,
+        }))
+        .context("building LUKS volume")?;
+        let filesystem: ign::Filesystem = serde_json::from_value(json!({
+            "device": "/dev/mapper/root",
+            "format": "xfs",
+            "label": "root",
+            "wipeFilesystem": true,
+        }))
+        .context("building encrypted root filesystem")?;
+
+        let storage = self.config.storage.get_or_insert_with(Default::default);
+        let luks_volumes = storage.luks.get_or_insert_with(Default::default);
+        if luks_volumes.iter().any(|l| l.name == "root") {
+            bail!("config already specifies LUKS volume root");
+        }
+        luks_volumes.push(luks);
+        let filesystems = storage.filesystems.get_or_insert_with(Default::default);
+        filesystems.retain(|f| f.device != device);
+        filesystems.push(filesystem);
+        Ok(())
+    }
+
+    /// Set a static hostname by writing /etc/hostname.
+    pub fn add_hostname(&mut self, hostname: &str) -> Result<()> {
+        if hostname.is_empty()
+            || hostname.len() > 64
+            || !hostname.split('.').all(|label| {
+                !label.is_empty()
+                    && !label.starts_with('-')
+                    && !label.ends_with('-')
+                    && label
+                        .bytes()
+                        .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+            })
+        {
+            bail!("invalid hostname {:?}", hostname);
+        }
+        self.add_file(
+            "/etc/hostname".into(),
+            format!("{hostname}\n").as_bytes(),
+            0o644,
+        )
+    }
+
+    pub fn add_ssh_keys(&mut self, user: &str, keys: &[String]) -> Result<()> {
+        for key in keys {
+            if key.trim().is_empty() || key.contains('\n') {
+                bail!("invalid SSH key {:?}", key);
+            }
+        }
+        let users = self
+            .config
+            .passwd
+            .get_or_insert_with(Default::default)
+            .users
+            .get_or_insert_with(Default::default);
+        let user = match users.iter_mut().position(|u| u.name == user) {
+            Some(i) => &mut users[i],
+            None => {
+                users.push(ign::User::new(user.into()));
+                users.last_mut().unwrap()
+            }
+        };
+        user.ssh_authorized_keys
+            .get_or_insert_with(Default::default)
+            .extend(keys.iter().cloned());
+        Ok(())
+    }
+
     pub fn add_ca(&mut self, data: &[u8]) -> Result<()> {
         self.config
             .ignition
@@ -114,6 +387,89 @@ impl Ignition {
     }
 }
 
+/// Combines user-supplied Ignition configs with directives of our own.
+#[derive(Debug, Default)]
+pub struct IgnitionBuilder {
+    /// Our own directives, if any
+    config: Option<Ignition>,
+    /// Serialized user-supplied configs
+    children: Vec<Vec<u8>>,
+}
+
+impl IgnitionBuilder {
+    /// Whether the builder would produce an empty config.
+    pub fn is_empty(&self) -> bool {
+        self.config.is_none() && self.children.is_empty()
+    }
+
+    /// The config for our own directives, created on first use.
+    pub fn config(&mut self) -> &mut Ignition {
+        self.config.get_or_insert_with(Default::default)
+    }
+
+    pub fn add_child(&mut self, config: &ign_multi::Config) -> Result<()> {
+        self.children
+            .push(serde_json::to_vec(config).context("serializing child Ignition config")?);
+        Ok(())
+    }
+
+    /// Add a serialized child config, which is passed through unparsed.
+    pub fn add_child_bytes(&mut self, data: Vec<u8>) {
+        self.children.push(data);
+    }
+
+    pub fn to_bytes(mut self) -> Result<Vec<u8>> {
+        if self.config.is_none() && self.children.len() == 1 {
+            // Special case: the user supplied exactly one config and we
+            // didn't add any directives of our own.  Avoid another level
+            // of wrapping by embedding the user's config directly.
+            let mut buf = self.children.pop().unwrap();
+            if !buf.ends_with(b"\n") {
+                buf.push(b'\n');
+            }
+            return Ok(buf);
+        }
+        let config = self.config.get_or_insert_with(Default::default);
+        for child in &self.children {
+            config.merge_bytes(child)?;
+        }
+        config.to_bytes()
+    }
+}
+
+/// Read SSH public keys from an authorized_keys-style file, skipping blank
+/// lines and comments.
+pub fn read_ssh_keys(data: &str) -> Vec<String> {
+    data.lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+/// Escape a path for use in a unit name, like `systemd-escape --path`.
+fn systemd_escape_path(path: &str) -> String {
+    let path = path.trim_matches('/');
+    if path.is_empty() {
+        return "-".into();
+    }
+    let mut ret = String::new();
+    for (i, c) in path.char_indices() {
+        match c {
+            '/' => ret.push('-'),
+            'a'..='z' | 'A'..='Z' | '0'..='9' | ':' | '_' => ret.push(c),
+            '.' if i > 0 => ret.push(c),
+            _ => {
+                let mut buf = [0; 4];
+                for b in c.encode_utf8(&mut buf).bytes() {
+                    ret.push_str(&format!("\\x{b:02x}"));
+                }
+            }
+        }
+    }
+    ret
+}
+
 fn make_resource(data: &[u8]) -> Result<ign::Resource> {
     let mut compressed = Vec::new();
     GzEncoder::new(data, Compression::best()).read_to_end(&mut compressed)?;
@@ -124,14 +480,312 @@ fn make_resource(data: &[u8]) -> Result<ign::Resource> {
     })
 }
 
+/// List the paths of files written by a serialized config, including
+/// those in child configs embedded as data URLs by merge_bytes().  Child
+/// configs fetched from elsewhere are not followed.
+pub fn config_file_paths(data: &[u8]) -> Result<Vec<String>> {
+    let config: serde_json::Value =
+        serde_json::from_slice(data).context("parsing Ignition config")?;
+    let mut paths: Vec<String> = config
+        .pointer("/storage/files")
+        .and_then(|files| files.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|file| file["path"].as_str().map(String::from))
+        .collect();
+    let children = config
+        .pointer("/ignition/config/merge")
+        .and_then(|merge| merge.as_array())
+        .into_iter()
+        .flatten();
+    for child in children {
+        if let Some(data) = read_data_resource(child)? {
+            paths.extend(config_file_paths(&data)?);
+        }
+    }
+    Ok(paths)
+}
+
+/// Decode a resource produced by make_resource().  Return None for other
+/// sources.
+fn read_data_resource(resource: &serde_json::Value) -> Result<Option<Vec<u8>>> {
+    let encoded = match resource["source"]
+        .as_str()
+        .and_then(|s| s.strip_prefix("data:;base64,"))
+    {
+        Some(encoded) => encoded,
+        None => return Ok(None),
+    };
+    let data = BASE64.decode(encoded).context("decoding data URL")?;
+    match resource["compression"].as_str() {
+        None | Some("") => Ok(Some(data)),
+        Some("gzip") => {
+            let mut decompressed = Vec::new();
+            GzDecoder::new(&*data)
+                .read_to_end(&mut decompressed)
+                .context("decompressing child config")?;
+            Ok(Some(decompressed))
+        }
+        Some(compression) => bail!("unsupported compression '{}'", compression),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn file_paths() {
+        let mut child = Ignition::default();
+        child.add_file("/b".into(), b"b", 0o644).unwrap();
+        let mut ignition = Ignition::default();
+        ignition.add_file("/a".into(), b"a", 0o644).unwrap();
+        ignition.merge_bytes(&child.to_bytes().unwrap()).unwrap();
+        ignition
+            .merge_bytes(
+                br#"{"ignition": {"version": "3.3.0"}, "storage": {"files": [{"path": "/c"}]}}"#,
+            )
+            .unwrap();
+        assert_eq!(
+            config_file_paths(&ignition.to_bytes().unwrap()).unwrap(),
+            ["/a", "/b", "/c"]
+        );
+    }
+
     #[test]
     fn duplicate_path() {
         let mut ignition = Ignition::default();
         ignition.add_file("/a/b".into(), &[], 0o755).unwrap();
         ignition.add_file("/a/b".into(), &[], 0o755).unwrap_err();
     }
+
+    #[test]
+    fn ssh_keys() {
+        let mut ignition = Ignition::default();
+        ignition
+            .add_ssh_keys("core", &["ssh-ed25519 AAAA a".into()])
+            .unwrap();
+        ignition
+            .add_ssh_keys("core", &["ssh-ed25519 AAAA b".into()])
+            .unwrap();
+        ignition
+            .add_ssh_keys("core", &["ssh-ed25519 AAAA\nc".into()])
+            .unwrap_err();
+        let users = ignition.config.passwd.unwrap().users.unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!(
+            users[0].ssh_authorized_keys.as_ref().unwrap(),
+            &["ssh-ed25519 AAAA a", "ssh-ed25519 AAAA b"]
+        );
+
+        assert_eq!(
+            read_ssh_keys("# comment\n\nssh-rsa AAAA x\n  ssh-ed25519 BBBB y  \n"),
+            vec!["ssh-rsa AAAA x", "ssh-ed25519 BBBB y"]
+        );
+    }
+
+    #[test]
+    fn hostname() {
+        let mut ignition = Ignition::default();
+        ignition.add_hostname("node-1.example.com").unwrap();
+        ignition.add_hostname("node-2").unwrap_err();
+        let files = ignition.config.storage.unwrap().files.unwrap();
+        assert_eq!(files[0].path, "/etc/hostname");
+        assert_eq!(files[0].mode, Some(0o644));
+
+        for hostname in ["", "-a", "a-", "a..b", "a_b", "a b", &"a".repeat(65)] {
+            Ignition::default().add_hostname(hostname).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn mount() {
+        assert_eq!(systemd_escape_path("/var"), "var");
+        assert_eq!(systemd_escape_path("/"), "-");
+        assert_eq!(
+            systemd_escape_path("/dev/disk/by-partlabel/var"),
+            r"dev-disk-by\x2dpartlabel-var"
+        );
+        assert_eq!(systemd_escape_path("/.a/b c/"), r"\x2ea-b\x20c");
+
+        let mut ignition = Ignition::default();
+        ignition
+            .add_mount("/dev/disk/by-partlabel/var", "xfs", "/var")
+            .unwrap();
+        ignition
+            .add_mount("/dev/disk/by-partlabel/var", "xfs", "/var")
+            .unwrap_err();
+        let config: serde_json::Value =
+            serde_json::from_slice(&ignition.to_bytes().unwrap()).unwrap();
+        let fs = &config["storage"]["filesystems"][0];
+        assert_eq!(fs["device"], "/dev/disk/by-partlabel/var");
+        assert_eq!(fs["format"], "xfs");
+        assert_eq!(fs["path"], "/var");
+        assert_eq!(fs["wipeFilesystem"], false);
+        let unit = &config["systemd"]["units"][0];
+        assert_eq!(unit["name"], "var.mount");
+        assert!(unit["contents"]
+            .as_str()
+            .unwrap()
+            .contains("Requires=systemd-fsck@dev-disk-by\\x2dpartlabel-var.service\n"));
+    }
+
+    #[test]
+    fn boot_mirror() {
+        let devices = ["/dev/sda".to_string(), "/dev/sdb".to_string()];
+        let mut ignition = Ignition::default();
+        ignition.add_boot_mirror(&devices, "x86_64").unwrap();
+        let config: serde_json::Value =
+            serde_json::from_slice(&ignition.to_bytes().unwrap()).unwrap();
+        let disks = config["storage"]["disks"].as_array().unwrap();
+        assert_eq!(disks.len(), 2);
+        assert_eq!(disks[1]["device"], "/dev/sdb");
+        assert_eq!(disks[1]["wipeTable"], true);
+        let labels: Vec<&str> = disks[1]["partitions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| p["label"].as_str().unwrap())
+            .collect();
+        assert_eq!(labels, ["bios-2", "esp-2", "boot-2", "root-2"]);
+        let raid = &config["storage"]["raid"];
+        assert_eq!(raid[0]["name"], "md-boot");
+        assert_eq!(
+            raid[0]["devices"],
+            json!([
+                "/dev/disk/by-partlabel/boot-1",
+                "/dev/disk/by-partlabel/boot-2"
+            ])
+        );
+        assert_eq!(raid[0]["options"], json!(["--metadata=1.0"]));
+        assert_eq!(raid[1]["name"], "md-root");
+        let filesystems: Vec<(&str, &str)> = config["storage"]["filesystems"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| (f["device"].as_str().unwrap(), f["label"].as_str().unwrap()))
+            .collect();
+        assert_eq!(
+            filesystems,
+            [
+                ("/dev/disk/by-partlabel/esp-1", "esp-1"),
+                ("/dev/disk/by-partlabel/esp-2", "esp-2"),
+                ("/dev/md/md-boot", "boot"),
+                ("/dev/md/md-root", "root"),
+            ]
+        );
+
+        let mut ignition = Ignition::default();
+        ignition.add_boot_mirror(&devices, "ppc64le").unwrap();
+        let config: serde_json::Value =
+            serde_json::from_slice(&ignition.to_bytes().unwrap()).unwrap();
+        let labels: Vec<&str> = config["storage"]["disks"][0]["partitions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| p["label"].as_str().unwrap())
+            .collect();
+        assert_eq!(labels, ["prep-1", "boot-1", "root-1"]);
+
+        let mut ignition = Ignition::default();
+        ignition.add_boot_mirror(&devices, "s390x").unwrap_err();
+        ignition
+            .add_boot_mirror(&devices[..1], "x86_64")
+            .unwrap_err();
+    }
+
+    #[test]
+    fn root_luks() {
+        for (s, pin) in [
+            ("tpm2", ClevisPin::Tpm2),
+            (
+                "tang=http://tang.example.com,abc123",
+                ClevisPin::Tang {
+                    url: "http://tang.example.com".into(),
+                    thumbprint: "abc123".into(),
+                },
+            ),
+        ] {
+            assert_eq!(ClevisPin::from_str(s).unwrap(), pin);
+            assert_eq!(pin.to_string(), s);
+        }
+        for s in [
+            "",
+            "tpm",
+            "tang=http://tang.example.com",
+            "tang=x,abc",
+            "tang=http://t,",
+        ] {
+            ClevisPin::from_str(s).unwrap_err();
+        }
+
+        // encrypting the mirrored root replaces its filesystem
+        let mut ignition = Ignition::default();
+        ignition
+            .add_boot_mirror(&["/dev/sda".into(), "/dev/sdb".into()], "x86_64")
+            .unwrap();
+        let pins = [
+            ClevisPin::Tpm2,
+            ClevisPin::from_str("tang=http://tang.example.com,abc123").unwrap(),
+        ];
+        ignition.add_root_luks("/dev/md/md-root", &pins).unwrap();
+        ignition
+            .add_root_luks("/dev/md/md-root", &pins)
+            .unwrap_err();
+        let config: serde_json::Value =
+            serde_json::from_slice(&ignition.to_bytes().unwrap()).unwrap();
+        let luks = &config["storage"]["luks"][0];
+        assert_eq!(luks["name"], "root");
+        assert_eq!(luks["device"], "/dev/md/md-root");
+        assert_eq!(luks["wipeVolume"], true);
+        assert_eq!(luks["clevis"]["tpm2"], true);
+        assert_eq!(
+            luks["clevis"]["tang"],
+            json!([{"url": "http://tang.example.com", "thumbprint": "abc123"}])
+        );
+        let root: Vec<&str> = config["storage"]["filesystems"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|f| f["label"] == "root")
+            .map(|f| f["device"].as_str().unwrap())
+            .collect();
+        assert_eq!(root, ["/dev/mapper/root"]);
+
+        Ignition::default()
+            .add_root_luks("/dev/disk/by-partlabel/root", &[])
+            .unwrap_err();
+    }
+
+    #[test]
+    fn builder() {
+        assert!(IgnitionBuilder::default().is_empty());
+
+        // single child passed through
+        let mut builder = IgnitionBuilder::default();
+        builder.add_child_bytes(br#"{"ignition": {"version": "3.99.0"}}"#.to_vec());
+        assert!(!builder.is_empty());
+        assert_eq!(
+            builder.to_bytes().unwrap(),
+            b"{\"ignition\": {\"version\": \"3.99.0\"}}\n"
+        );
+
+        // child merged into our own config
+        let mut builder = IgnitionBuilder::default();
+        builder.add_child_bytes(br#"{"ignition": {"version": "3.99.0"}}"#.to_vec());
+        builder
+            .config()
+            .add_ssh_keys("core", &["ssh-ed25519 AAAA".into()])
+            .unwrap();
+        let config: serde_json::Value =
+            serde_json::from_slice(&builder.to_bytes().unwrap()).unwrap();
+        assert_eq!(
+            config["ignition"]["config"]["merge"]
+                .as_array()
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(config["passwd"]["users"][0]["name"], "core");
+    }
 }
@@ -12,11 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Error, Result};
 use cpio::{write_cpio, NewcBuilder, NewcReader};
+use flate2::write::GzEncoder;
 use lazy_static::lazy_static;
 use std::collections::BTreeMap;
+use std::fmt;
 use std::io::{BufRead, Cursor, Read};
+use std::str::FromStr;
 use xz2::stream::{Check, Stream};
 use xz2::write::XzEncoder;
 
@@ -26,13 +29,121 @@ lazy_static! {
     static ref ALL_GLOB: GlobMatcher = GlobMatcher::new(&["*"]).unwrap();
 }
 
+/// Permission bits for files that don't specify a mode
+const DEFAULT_FILE_MODE: u32 = 0o600;
+/// Permission bits for directories that don't specify a mode
+const DEFAULT_DIR_MODE: u32 = 0o755;
+
+/// Type of an initrd member.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InitrdEntryKind {
+    File,
+    Directory,
+    /// Symbolic link to the specified target
+    Symlink(String),
+}
+
+/// Metadata of an initrd member, as stored in the CPIO archive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InitrdMetadata {
+    pub kind: InitrdEntryKind,
+    /// Permission bits
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Compression format and level for a generated initrd: "gzip", "xz", or
+/// "zstd", optionally followed by ":LEVEL".
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InitrdCompression {
+    /// Level 0-9, default 9
+    Gzip(u32),
+    /// Level 0-9, default 9
+    Xz(u32),
+    /// Level 1-19, default 19.  Requires kernel 5.9 or later.
+    Zstd(i32),
+}
+
+impl Default for InitrdCompression {
+    fn default() -> Self {
+        Self::Xz(9)
+    }
+}
+
+impl FromStr for InitrdCompression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (format, level) = match s.split_once(':') {
+            Some((format, level)) => (
+                format,
+                Some(
+                    level
+                        .parse::<u32>()
+                        .with_context(|| format!("invalid compression level '{level}'"))?,
+                ),
+            ),
+            None => (s, None),
+        };
+        let (min, max, default) = match format {
+            "gzip" | "xz" => (0, 9, 9),
+            "zstd" => (1, 19, 19),
+            _ => bail!("unknown compression '{format}'; expected gzip, xz, or zstd"),
+        };
+        let level = level.unwrap_or(default);
+        if !(min..=max).contains(&level) {
+            bail!("{format} compression level must be {min}-{max}");
+        }
+        Ok(match format {
+            "gzip" => Self::Gzip(level),
+            "xz" => Self::Xz(level),
+            _ => Self::Zstd(level as i32),
+        })
+    }
+}
+
+impl fmt::Display for InitrdCompression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Gzip(level) => write!(f, "gzip:{level}"),
+            Self::Xz(level) => write!(f, "xz:{level}"),
+            Self::Zstd(level) => write!(f, "zstd:{level}"),
+        }
+    }
+}
+
 #[derive(Default, Debug)]
 pub struct Initrd {
     members: BTreeMap<String, Vec<u8>>,
+    /// Permission bits of members not using DEFAULT_FILE_MODE
+    modes: BTreeMap<String, u32>,
+    /// Symlink path -> target
+    symlinks: BTreeMap<String, String>,
+    /// Directory path -> permission bits.  Parent directories not listed
+    /// here are created with DEFAULT_DIR_MODE.
+    dirs: BTreeMap<String, u32>,
+    /// (uid, gid) of members not owned by root
+    owners: BTreeMap<String, (u32, u32)>,
+    compression: InitrdCompression,
+}
+
+/// Contents of a member, for generating the archive
+enum Member<'a> {
+    File(&'a [u8]),
+    Directory,
+    Symlink(&'a str),
 }
 
 impl Initrd {
-    /// Generate an xz-compressed initrd.
+    /// Set the compression used by to_bytes().  Defaults to xz level 9.
+    pub fn set_compression(&mut self, compression: InitrdCompression) {
+        self.compression = compression;
+    }
+
+    /// Generate a compressed initrd.  The output depends only on the
+    /// contents and compression settings: members are sorted by path, and
+    /// timestamps and inode numbers are zero.
     pub fn to_bytes(&self) -> Result<Vec<u8>> {
         let mut members = Vec::new();
         // The CPIO archive needs to include parent directories for each
@@ -42,11 +153,22 @@ impl Initrd {
         // a virtual current working directory: only create files in the
         // current directory, and notionally "mkdir" and "chdir" our way
         // around the filesystem.
+        let all: BTreeMap<&str, Member> = self
+            .members
+            .iter()
+            .map(|(p, c)| (p.as_str(), Member::File(c)))
+            .chain(self.dirs.keys().map(|p| (p.as_str(), Member::Directory)))
+            .chain(
+                self.symlinks
+                    .iter()
+                    .map(|(p, t)| (p.as_str(), Member::Symlink(t))),
+            )
+            .collect();
         let mut cwd: Vec<&str> = Vec::new();
-        for (path, contents) in &self.members {
+        for (path, member) in all {
             // chdir to common ancestor of cwd and file
             let mut parent: Vec<&str> = path.split('/').collect();
-            parent.pop();
+            let name = parent.pop().expect("split always returns an element");
             cwd = cwd
                 .iter()
                 .zip(&parent)
@@ -57,25 +179,60 @@ impl Initrd {
             // chdir into them
             for component in parent.iter().skip(cwd.len()) {
                 cwd.push(component);
-                // S_IFDIR | 0755
-                members.push((
-                    NewcBuilder::new(&cwd.join("/")).mode(0o40_755),
-                    Cursor::new(&[][..]),
-                ));
+                members.push((self.dir_builder(&cwd.join("/")), Cursor::new(&[][..])));
+            }
+            match member {
+                Member::File(contents) => {
+                    // S_IFREG | mode
+                    let mode = self.modes.get(path).copied().unwrap_or(DEFAULT_FILE_MODE);
+                    members.push((self.builder(path, 0o100_000 | mode), Cursor::new(contents)));
+                }
+                Member::Directory => {
+                    members.push((self.dir_builder(path), Cursor::new(&[][..])));
+                    cwd.push(name);
+                }
+                Member::Symlink(target) => {
+                    // S_IFLNK | 0777
+                    members.push((
+                        self.builder(path, 0o120_777),
+                        Cursor::new(target.as_bytes()),
+                    ));
+                }
             }
-            // create file, S_IFREG | 0600
-            members.push((
-                NewcBuilder::new(path).mode(0o100_600),
-                Cursor::new(contents),
-            ));
         }
-        // kernel requires CRC32: https://www.kernel.org/doc/Documentation/xz.txt
-        let mut encoder = XzEncoder::new_stream(
-            Vec::new(),
-            Stream::new_easy_encoder(9, Check::Crc32).context("creating XZ encoder")?,
-        );
-        write_cpio(members.drain(..), &mut encoder).context("writing CPIO archive")?;
-        encoder.finish().context("closing XZ compressor")
+        match self.compression {
+            InitrdCompression::Gzip(level) => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::new(level));
+                write_cpio(members.drain(..), &mut encoder).context("writing CPIO archive")?;
+                encoder.finish().context("closing gzip compressor")
+            }
+            InitrdCompression::Xz(level) => {
+                // kernel requires CRC32: https://www.kernel.org/doc/Documentation/xz.txt
+                let mut encoder = XzEncoder::new_stream(
+                    Vec::new(),
+                    Stream::new_easy_encoder(level, Check::Crc32).context("creating XZ encoder")?,
+                );
+                write_cpio(members.drain(..), &mut encoder).context("writing CPIO archive")?;
+                encoder.finish().context("closing XZ compressor")
+            }
+            InitrdCompression::Zstd(level) => {
+                let mut encoder = zstd::stream::write::Encoder::new(Vec::new(), level)
+                    .context("creating zstd encoder")?;
+                write_cpio(members.drain(..), &mut encoder).context("writing CPIO archive")?;
+                encoder.finish().context("closing zstd compressor")
+            }
+        }
+    }
+
+    fn builder(&self, path: &str, mode: u32) -> NewcBuilder {
+        let (uid, gid) = self.owners.get(path).copied().unwrap_or_default();
+        NewcBuilder::new(path).mode(mode).uid(uid).gid(gid)
+    }
+
+    fn dir_builder(&self, path: &str) -> NewcBuilder {
+        // S_IFDIR | mode
+        let mode = self.dirs.get(path).copied().unwrap_or(DEFAULT_DIR_MODE);
+        self.builder(path, 0o40_000 | mode)
     }
 
     /// Read an initrd containing compressed and/or uncompressed archives.
@@ -104,13 +261,36 @@ impl Initrd {
                     break;
                 }
                 let name = entry.name().to_string();
-                if entry.mode() & 0o170_000 == 0o100_000 && filter.matches(&name) {
-                    // matching regular file
-                    let mut buf = Vec::with_capacity(entry.file_size() as usize);
-                    reader
-                        .read_to_end(&mut buf)
-                        .context("reading CPIO entry contents")?;
-                    result.members.insert(name, buf);
+                let (mode, uid, gid) = (entry.mode(), entry.uid(), entry.gid());
+                if filter.matches(&name) {
+                    let known = match mode & 0o170_000 {
+                        0o100_000 => {
+                            // regular file
+                            let mut buf = Vec::with_capacity(entry.file_size() as usize);
+                            reader
+                                .read_to_end(&mut buf)
+                                .context("reading CPIO entry contents")?;
+                            result.add_with_mode(&name, buf, mode & 0o7777);
+                            true
+                        }
+                        0o040_000 => {
+                            result.add_dir(&name, mode & 0o7777);
+                            true
+                        }
+                        0o120_000 => {
+                            let mut target = String::new();
+                            reader
+                                .read_to_string(&mut target)
+                                .with_context(|| format!("reading target of symlink {name}"))?;
+                            result.add_symlink(&name, &target);
+                            true
+                        }
+                        // device nodes, FIFOs, etc. aren't supported
+                        _ => false,
+                    };
+                    if known {
+                        result.set_owner(&name, uid, gid);
+                    }
                 }
                 decompressor = reader.finish().context("finishing reading CPIO entry")?;
             }
@@ -165,15 +345,88 @@ impl Initrd {
     }
 
     pub fn add(&mut self, path: &str, contents: Vec<u8>) {
+        self.add_with_mode(path, contents, DEFAULT_FILE_MODE);
+    }
+
+    /// Add a file with the specified permission bits.
+    pub fn add_with_mode(&mut self, path: &str, contents: Vec<u8>, mode: u32) {
+        self.remove(path);
         self.members.insert(path.into(), contents);
+        if mode != DEFAULT_FILE_MODE {
+            self.modes.insert(path.into(), mode);
+        }
+    }
+
+    /// Add a directory with the specified permission bits.  Directories
+    /// only need to be added explicitly if they're empty or need
+    /// non-default metadata.
+    pub fn add_dir(&mut self, path: &str, mode: u32) {
+        self.remove(path);
+        self.dirs.insert(path.into(), mode);
+    }
+
+    /// Add a symbolic link pointing to the specified target.
+    pub fn add_symlink(&mut self, path: &str, target: &str) {
+        self.remove(path);
+        self.symlinks.insert(path.into(), target.into());
+    }
+
+    /// Set the owner of an existing member.  Members are owned by root
+    /// by default.
+    pub fn set_owner(&mut self, path: &str, uid: u32, gid: u32) {
+        if uid == 0 && gid == 0 {
+            self.owners.remove(path);
+        } else {
+            self.owners.insert(path.into(), (uid, gid));
+        }
+    }
+
+    /// Get the metadata of a member, if present.
+    pub fn metadata(&self, path: &str) -> Option<InitrdMetadata> {
+        let (kind, mode) = if self.members.contains_key(path) {
+            (
+                InitrdEntryKind::File,
+                self.modes.get(path).copied().unwrap_or(DEFAULT_FILE_MODE),
+            )
+        } else if let Some(mode) = self.dirs.get(path) {
+            (InitrdEntryKind::Directory, *mode)
+        } else if let Some(target) = self.symlinks.get(path) {
+            (InitrdEntryKind::Symlink(target.clone()), 0o777)
+        } else {
+            return None;
+        };
+        let (uid, gid) = self.owners.get(path).copied().unwrap_or_default();
+        Some(InitrdMetadata {
+            kind,
+            mode,
+            uid,
+            gid,
+        })
+    }
+
+    /// List all explicitly added members, including directories and
+    /// symlinks, with their metadata.
+    pub fn entries(&self) -> BTreeMap<&str, InitrdMetadata> {
+        self.members
+            .keys()
+            .chain(self.dirs.keys())
+            .chain(self.symlinks.keys())
+            .map(|p| (p.as_str(), self.metadata(p).expect("member exists")))
+            .collect()
     }
 
     pub fn remove(&mut self, path: &str) {
         self.members.remove(path);
+        self.modes.remove(path);
+        self.dirs.remove(path);
+        self.symlinks.remove(path);
+        self.owners.remove(path);
     }
 
+    /// Whether the initrd has no files or symlinks.  Directories alone
+    /// don't count.
     pub fn is_empty(&self) -> bool {
-        self.members.is_empty()
+        self.members.is_empty() && self.symlinks.is_empty()
     }
 }
 
@@ -216,6 +469,131 @@ mod tests {
         );
     }
 
+    #[test]
+    fn compression_formats() {
+        let mut initrd = Initrd::default();
+        initrd.add("a/b", b"contents".to_vec());
+        initrd.add("a/a", b"more contents".to_vec());
+        // same members, added in a different order
+        let mut other = Initrd::default();
+        other.add("a/a", b"more contents".to_vec());
+        other.add("a/b", b"contents".to_vec());
+        for (spec, magic) in [
+            ("gzip", &b"\x1f\x8b"[..]),
+            ("gzip:1", b"\x1f\x8b"),
+            ("xz:0", b"\xfd7zXZ\x00"),
+            ("zstd:19", b"\x28\xb5\x2f\xfd"),
+        ] {
+            initrd.set_compression(spec.parse().unwrap());
+            let data = initrd.to_bytes().unwrap();
+            assert!(data.starts_with(magic), "{spec}");
+            other.set_compression(spec.parse().unwrap());
+            assert_eq!(data, other.to_bytes().unwrap(), "{spec}");
+            assert_eq!(
+                Initrd::from_reader(&*data).unwrap().get("a/b").unwrap(),
+                b"contents",
+                "{spec}"
+            );
+        }
+        assert_eq!(InitrdCompression::default().to_string(), "xz:9");
+        assert_eq!(
+            "zstd".parse::<InitrdCompression>().unwrap(),
+            InitrdCompression::Zstd(19)
+        );
+        assert_eq!(
+            "gzip:6".parse::<InitrdCompression>().unwrap().to_string(),
+            "gzip:6"
+        );
+        for bad in ["", "lz4", "xz:", "xz:10", "zstd:0", "zstd:20", "gzip:-1"] {
+            bad.parse::<InitrdCompression>().unwrap_err();
+        }
+    }
+
+    #[test]
+    fn modes() {
+        let mut initrd = Initrd::default();
+        initrd.add("a", vec![]);
+        initrd.add_with_mode("b", vec![], 0o755);
+        initrd.add_with_mode("c", vec![], 0o644);
+        initrd.add("c", vec![]);
+        let initrd = Initrd::from_reader(&*initrd.to_bytes().unwrap()).unwrap();
+        assert_eq!(initrd.modes, btreemap! {"b".into() => 0o755});
+    }
+
+    #[test]
+    fn metadata() {
+        let mut initrd = Initrd::default();
+        initrd.add_with_mode("usr/bin/hook", b"#!/bin/sh\n".to_vec(), 0o755);
+        initrd.add_symlink("usr/bin/hook-link", "hook");
+        initrd.add_dir("var/empty", 0o700);
+        initrd.add("var/lib/x", vec![]);
+        initrd.add_dir("var/lib", 0o750);
+        initrd.set_owner("var/lib", 1000, 1001);
+        initrd.set_owner("var/lib/x", 0, 5);
+        // replacing a member resets its metadata
+        initrd.add_with_mode("etc/replaced", vec![], 0o644);
+        initrd.set_owner("etc/replaced", 1, 1);
+        initrd.add_symlink("etc/replaced", "/dev/null");
+
+        let result = Initrd::from_reader(&*initrd.to_bytes().unwrap()).unwrap();
+        let entries = result.entries();
+        let meta = |kind, mode, uid, gid| InitrdMetadata {
+            kind,
+            mode,
+            uid,
+            gid,
+        };
+        assert_eq!(
+            entries["usr/bin/hook"],
+            meta(InitrdEntryKind::File, 0o755, 0, 0)
+        );
+        assert_eq!(result.get("usr/bin/hook").unwrap(), b"#!/bin/sh\n");
+        assert_eq!(
+            entries["usr/bin/hook-link"],
+            meta(InitrdEntryKind::Symlink("hook".into()), 0o777, 0, 0)
+        );
+        assert_eq!(result.get("usr/bin/hook-link"), None);
+        assert_eq!(
+            entries["var/empty"],
+            meta(InitrdEntryKind::Directory, 0o700, 0, 0)
+        );
+        assert_eq!(
+            entries["var/lib"],
+            meta(InitrdEntryKind::Directory, 0o750, 1000, 1001)
+        );
+        assert_eq!(
+            entries["var/lib/x"],
+            meta(InitrdEntryKind::File, 0o600, 0, 5)
+        );
+        assert_eq!(
+            entries["etc/replaced"],
+            meta(InitrdEntryKind::Symlink("/dev/null".into()), 0o777, 0, 0)
+        );
+        // implied parent directories
+        assert_eq!(
+            entries["usr/bin"],
+            meta(InitrdEntryKind::Directory, 0o755, 0, 0)
+        );
+        assert_eq!(entries.len(), 10);
+        // and again
+        assert_eq!(
+            Initrd::from_reader(&*result.to_bytes().unwrap())
+                .unwrap()
+                .entries(),
+            entries
+        );
+
+        // directories alone don't make an initrd non-empty
+        let mut initrd = Initrd::default();
+        initrd.add_dir("a", 0o755);
+        assert!(initrd.is_empty());
+        initrd.add_symlink("a/b", "c");
+        assert!(!initrd.is_empty());
+        initrd.remove("a/b");
+        assert!(initrd.is_empty());
+        assert_eq!(initrd.metadata("a/b"), None);
+    }
+
     #[test]
     fn compression() {
         let mut archive: Vec<u8> = Vec::new();
@@ -13,6 +13,10 @@
 // limitations under the License.
 
 use std::io::{self, Read, Write};
+use std::num::NonZeroU64;
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 
 pub struct LimitReader<R: Read> {
     source: R,
@@ -101,10 +105,106 @@ impl<W: Write> Write for LimitWriter<W> {
     }
 }
 
+/// Token bucket holding transfers to an average number of bytes per
+/// second, with bursts of up to one second's worth.  Clones share the
+/// bucket, so several streams can be held to one combined rate.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    rate: NonZeroU64,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Bytes that can be transferred without waiting; negative if
+    /// transfers are ahead of the rate
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(rate: NonZeroU64) -> Self {
+        Self {
+            rate,
+            bucket: Arc::new(Mutex::new(Bucket {
+                tokens: rate.get() as f64,
+                updated: Instant::now(),
+            })),
+        }
+    }
+
+    /// Largest transfer that should be made at once, so a single transfer
+    /// can't exceed the burst size.
+    fn max_transfer(&self, len: usize) -> usize {
+        len.min(self.rate.get().try_into().unwrap_or(usize::MAX))
+    }
+
+    /// Account for a transfer of the specified size, and sleep until the
+    /// transfer is within the rate.
+    fn consume(&self, count: usize) {
+        let rate = self.rate.get() as f64;
+        let wait = {
+            let mut bucket = self.bucket.lock().expect("locking rate limiter");
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.updated).as_secs_f64() * rate;
+            bucket.tokens = (bucket.tokens + refill).min(rate) - count as f64;
+            bucket.updated = now;
+            -bucket.tokens / rate
+        };
+        if wait > 0.0 {
+            sleep(Duration::from_secs_f64(wait));
+        }
+    }
+}
+
+pub struct RateLimitReader<R: Read> {
+    source: R,
+    limiter: RateLimiter,
+}
+
+impl<R: Read> RateLimitReader<R> {
+    pub fn new(source: R, limiter: RateLimiter) -> Self {
+        Self { source, limiter }
+    }
+}
+
+impl<R: Read> Read for RateLimitReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.limiter.max_transfer(buf.len());
+        let count = self.source.read(&mut buf[..len])?;
+        self.limiter.consume(count);
+        Ok(count)
+    }
+}
+
+pub struct RateLimitWriter<W: Write> {
+    sink: W,
+    limiter: RateLimiter,
+}
+
+impl<W: Write> RateLimitWriter<W> {
+    pub fn new(sink: W, limiter: RateLimiter) -> Self {
+        Self { sink, limiter }
+    }
+}
+
+impl<W: Write> Write for RateLimitWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.limiter.max_transfer(buf.len());
+        let count = self.sink.write(&buf[..len])?;
+        self.limiter.consume(count);
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Cursor;
+    use std::io::{copy, Cursor};
 
     #[test]
     fn limit_reader_test() {
@@ -205,4 +305,28 @@ mod tests {
         assert_eq!(lim.write(&data[0..0]).unwrap(), 0);
         assert_eq!(&data[0..90], &outbuf);
     }
+
+    #[test]
+    fn rate_limit_test() {
+        let data = vec![7u8; 300_000];
+        let limiter = RateLimiter::new(NonZeroU64::new(200_000).unwrap());
+
+        // the first second's worth is a burst; the rest is limited
+        let start = Instant::now();
+        let mut outbuf: Vec<u8> = Vec::new();
+        let mut reader = RateLimitReader::new(&data[..], limiter.clone());
+        assert_eq!(copy(&mut reader, &mut outbuf).unwrap(), 300_000);
+        assert_eq!(data, outbuf);
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(450), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(5), "{elapsed:?}");
+
+        // a writer sharing the limiter finds the bucket empty
+        let start = Instant::now();
+        let mut writer = RateLimitWriter::new(Vec::new(), limiter);
+        assert_eq!(writer.write(&data).unwrap(), 200_000);
+        writer.flush().unwrap();
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(950), "{elapsed:?}");
+    }
 }
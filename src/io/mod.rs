@@ -18,11 +18,14 @@ use std::io::{ErrorKind, Read, Write};
 
 mod bls;
 mod compress;
+mod direct;
 mod hash;
 mod ignition;
 mod initrd;
 mod limit;
+mod pe;
 mod peek;
+mod sync;
 mod tee;
 mod verify;
 mod xz;
@@ -30,11 +33,14 @@ mod zstd;
 
 pub use self::bls::*;
 pub use self::compress::*;
+pub use self::direct::*;
 pub use self::hash::*;
 pub use self::ignition::*;
 pub use self::initrd::*;
 pub use self::limit::*;
+pub use self::pe::*;
 pub use self::peek::*;
+pub use self::sync::*;
 pub use self::tee::*;
 pub use self::verify::*;
 pub use self::xz::*;
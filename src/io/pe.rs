@@ -0,0 +1,134 @@
+// Copyright 2024 Red Hat, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Authenticode digests of PE images, as checked by UEFI Secure Boot.
+
+use anyhow::{bail, Context, Result};
+use openssl::sha;
+
+/// Compute the SHA-256 Authenticode digest of a PE/COFF image: the digest
+/// of the file, skipping the header checksum, the certificate table
+/// directory entry, and the certificate table itself.  This is the linear
+/// form of the digest, which matches the per-section form for images with
+/// contiguous sections, such as shim and GRUB.
+pub fn authenticode_sha256(data: &[u8]) -> Result<[u8; 32]> {
+    let le16 = |off: usize| -> Result<usize> {
+        data.get(off..off + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+            .context("truncated PE image")
+    };
+    let le32 = |off: usize| -> Result<usize> {
+        data.get(off..off + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+            .context("truncated PE image")
+    };
+
+    if data.get(0..2) != Some(b"MZ") {
+        bail!("not a PE image: missing MZ signature");
+    }
+    let pe = le32(0x3c)?;
+    if data.get(pe..pe + 4) != Some(b"PE\0\0") {
+        bail!("not a PE image: missing PE signature");
+    }
+    // optional header follows the signature and the 20-byte COFF header
+    let opt = pe + 24;
+    let checksum = opt + 64;
+    let (num_dirs, dirs) = match le16(opt)? {
+        0x10b => (le32(opt + 92)?, opt + 96),
+        0x20b => (le32(opt + 108)?, opt + 112),
+        magic => bail!("unknown PE optional header magic {magic:#x}"),
+    };
+    // the certificate table is data directory 4
+    if num_dirs < 5 {
+        bail!("PE image has no certificate table directory entry");
+    }
+    let cert_dir = dirs + 4 * 8;
+    let (cert_start, cert_end) = match (le32(cert_dir)?, le32(cert_dir + 4)?) {
+        (_, 0) => (data.len(), data.len()),
+        (start, size) => (start, start + size),
+    };
+    if cert_start < cert_dir + 8 || cert_end > data.len() {
+        bail!("PE certificate table is out of bounds");
+    }
+
+    let mut hasher = sha::Sha256::new();
+    hasher.update(&data[..checksum]);
+    hasher.update(&data[checksum + 4..cert_dir]);
+    hasher.update(&data[cert_dir + 8..cert_start]);
+    hasher.update(&data[cert_end..]);
+    Ok(hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a minimal PE32+ image with a certificate table at the end.
+    fn make_image() -> Vec<u8> {
+        let mut data = vec![0u8; 0x400];
+        data[0..2].copy_from_slice(b"MZ");
+        data[0x3c..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        data[0x80..0x84].copy_from_slice(b"PE\0\0");
+        let opt = 0x80 + 24;
+        data[opt..opt + 2].copy_from_slice(&0x20bu16.to_le_bytes());
+        data[opt + 64..opt + 68].copy_from_slice(&0x1234u32.to_le_bytes());
+        data[opt + 108..opt + 112].copy_from_slice(&16u32.to_le_bytes());
+        let cert_dir = opt + 112 + 4 * 8;
+        data[cert_dir..cert_dir + 4].copy_from_slice(&0x3f0u32.to_le_bytes());
+        data[cert_dir + 4..cert_dir + 8].copy_from_slice(&0x10u32.to_le_bytes());
+        for (i, b) in data[0x200..0x3f0].iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        data[0x3f0..].fill(0xaa);
+        data
+    }
+
+    #[test]
+    fn test_authenticode_sha256() {
+        let data = make_image();
+        let digest = authenticode_sha256(&data).unwrap();
+        let opt = 0x80 + 24;
+        let cert_dir = opt + 112 + 4 * 8;
+
+        // checksum, certificate table entry, and certificate table are
+        // excluded
+        let mut modified = data.clone();
+        modified[opt + 64] ^= 0xff;
+        assert_eq!(authenticode_sha256(&modified).unwrap(), digest);
+        let mut modified = data.clone();
+        modified[0x3f8] ^= 0xff;
+        assert_eq!(authenticode_sha256(&modified).unwrap(), digest);
+        let mut modified = data[..0x3f0].to_vec();
+        modified[cert_dir..cert_dir + 8].fill(0);
+        assert_eq!(authenticode_sha256(&modified).unwrap(), digest);
+
+        // everything else is covered
+        let mut modified = data.clone();
+        modified[0x300] ^= 0xff;
+        assert_ne!(authenticode_sha256(&modified).unwrap(), digest);
+        let mut modified = data.clone();
+        modified[opt + 4] ^= 0xff;
+        assert_ne!(authenticode_sha256(&modified).unwrap(), digest);
+
+        // invalid images
+        authenticode_sha256(b"MZ").unwrap_err();
+        authenticode_sha256(&data[..0x100]).unwrap_err();
+        let mut modified = data.clone();
+        modified[0x80] = b'X';
+        authenticode_sha256(&modified).unwrap_err();
+        let mut modified = data;
+        modified[cert_dir + 4..cert_dir + 8].copy_from_slice(&0x20u32.to_le_bytes());
+        authenticode_sha256(&modified).unwrap_err();
+    }
+}
@@ -0,0 +1,92 @@
+// Copyright 2024 Red Hat, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::num::NonZeroU64;
+
+/// Reader that flushes a destination file to stable storage each time
+/// the specified number of bytes has passed through it.  For copies that
+/// write data as fast as they read it, this bounds the amount of dirty
+/// data in the page cache, give or take the copy's write buffer.
+pub struct SyncReader<R: Read> {
+    source: R,
+    dest: File,
+    interval: NonZeroU64,
+    unsynced: u64,
+    syncs: u64,
+}
+
+impl<R: Read> SyncReader<R> {
+    pub fn new(source: R, dest: File, interval: NonZeroU64) -> Self {
+        Self {
+            source,
+            dest,
+            interval,
+            unsynced: 0,
+            syncs: 0,
+        }
+    }
+
+    /// Number of times the destination has been synced.
+    pub fn syncs(&self) -> u64 {
+        self.syncs
+    }
+}
+
+impl<R: Read> Read for SyncReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.source.read(buf)?;
+        self.unsynced += count as u64;
+        if self.unsynced >= self.interval.get() {
+            self.dest.sync_data()?;
+            self.unsynced = 0;
+            self.syncs += 1;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::copy;
+
+    #[test]
+    fn sync_reader() {
+        let data = vec![7u8; 1000];
+        let dest = tempfile::tempfile().unwrap();
+        let mut reader = SyncReader::new(
+            &data[..],
+            dest.try_clone().unwrap(),
+            NonZeroU64::new(250).unwrap(),
+        );
+        let mut buf = [0u8; 100];
+        let mut out = Vec::new();
+        loop {
+            let count = reader.read(&mut buf).unwrap();
+            if count == 0 {
+                break;
+            }
+            out.extend(&buf[..count]);
+        }
+        assert_eq!(out, data);
+        // 100-byte reads reach the interval at 300, 600, and 900 bytes
+        assert_eq!(reader.syncs(), 3);
+
+        let mut reader = SyncReader::new(&data[..], dest, NonZeroU64::new(u64::MAX).unwrap());
+        copy(&mut reader, &mut io::sink()).unwrap();
+        assert_eq!(reader.syncs(), 0);
+    }
+}
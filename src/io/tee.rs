@@ -14,7 +14,13 @@
 
 //! Wrappers for splitting I/O streams
 
-use std::io::{self, Read, Write};
+use std::io::{self, ErrorKind, Read, Write};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::Arc;
+
+/// Number of writes that can be queued for each fan-out reader before
+/// the writer blocks
+const FANOUT_QUEUE_DEPTH: usize = 4;
 
 /// Reader wrapper that copies data to a writer as a side effect
 pub struct TeeReader<R: Read, W: Write> {
@@ -43,6 +49,121 @@ impl<R: Read, W: Write> Read for TeeReader<R, W> {
     }
 }
 
+enum FanoutMessage {
+    Data(Arc<Vec<u8>>),
+    End,
+}
+
+/// Create a writer that copies its input to `count` readers, each of which
+/// should be consumed on its own thread.  The readers only report EOF
+/// after `FanoutWriter::finish()` is called; if the writer is dropped
+/// without finishing, the readers fail instead, so a consumer can't
+/// mistake a truncated stream for a complete one.
+pub fn fanout(count: usize) -> (FanoutWriter, Vec<FanoutReader>) {
+    let (senders, receivers): (Vec<_>, Vec<_>) =
+        (0..count).map(|_| sync_channel(FANOUT_QUEUE_DEPTH)).unzip();
+    let writer = FanoutWriter {
+        senders: senders.into_iter().map(Some).collect(),
+    };
+    let readers = receivers
+        .into_iter()
+        .map(|receiver| FanoutReader {
+            receiver,
+            buf: None,
+            pos: 0,
+            finished: false,
+        })
+        .collect();
+    (writer, readers)
+}
+
+/// Writer half of a fan-out.  Readers that go away are dropped from the
+/// fan-out; writes only fail once no readers remain.
+pub struct FanoutWriter {
+    senders: Vec<Option<SyncSender<FanoutMessage>>>,
+}
+
+impl FanoutWriter {
+    /// Signal EOF to the remaining readers.
+    pub fn finish(self) {
+        for sender in self.senders.into_iter().flatten() {
+            // ignore readers that have already gone away
+            let _ = sender.send(FanoutMessage::End);
+        }
+    }
+}
+
+impl Write for FanoutWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let data = Arc::new(buf.to_vec());
+        for slot in self.senders.iter_mut() {
+            let gone = slot
+                .as_ref()
+                .is_some_and(|sender| sender.send(FanoutMessage::Data(data.clone())).is_err());
+            if gone {
+                *slot = None;
+            }
+        }
+        if self.senders.iter().all(Option::is_none) {
+            return Err(io::Error::new(
+                ErrorKind::BrokenPipe,
+                "all fan-out readers have exited",
+            ));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reader half of a fan-out
+pub struct FanoutReader {
+    receiver: Receiver<FanoutMessage>,
+    buf: Option<Arc<Vec<u8>>>,
+    pos: usize,
+    finished: bool,
+}
+
+impl Read for FanoutReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if let Some(data) = &self.buf {
+                if self.pos < data.len() {
+                    let count = buf.len().min(data.len() - self.pos);
+                    buf[..count].copy_from_slice(&data[self.pos..self.pos + count]);
+                    self.pos += count;
+                    return Ok(count);
+                }
+                self.buf = None;
+            }
+            if self.finished {
+                return Ok(0);
+            }
+            match self.receiver.recv() {
+                Ok(FanoutMessage::Data(data)) => {
+                    self.buf = Some(data);
+                    self.pos = 0;
+                }
+                Ok(FanoutMessage::End) => self.finished = true,
+                Err(_) => {
+                    return Err(io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "fan-out writer exited without finishing",
+                    ))
+                }
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -67,4 +188,50 @@ mod tests {
         let (_, dest) = tee.into_inner();
         assert_eq!(src, dest);
     }
+
+    /// Copy data through a fan-out to several threads, check that each
+    /// copy is correct, and check that an unfinished writer causes the
+    /// readers to fail
+    #[test]
+    fn fanout_reader() {
+        const COUNT: usize = 100_000;
+        let src: Vec<u8> = (0..COUNT).map(|i| i as u8).collect();
+
+        let (mut writer, readers) = fanout(3);
+        let outputs = std::thread::scope(|s| {
+            let handles: Vec<_> = readers
+                .into_iter()
+                .map(|mut reader| {
+                    s.spawn(move || {
+                        let mut out = Vec::new();
+                        reader.read_to_end(&mut out).map(|_| out)
+                    })
+                })
+                .collect();
+            for chunk in src.chunks(777) {
+                writer.write_all(chunk).unwrap();
+            }
+            writer.finish();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        for output in outputs {
+            assert_eq!(output.unwrap(), src);
+        }
+
+        let (mut writer, mut readers) = fanout(1);
+        writer.write_all(&src[..10]).unwrap();
+        drop(writer);
+        let mut out = Vec::new();
+        let err = readers[0].read_to_end(&mut out).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(out, src[..10]);
+
+        // writes fail once all readers have exited
+        let (mut writer, readers) = fanout(2);
+        drop(readers);
+        writer.write_all(&src[..10]).unwrap_err();
+    }
 }
@@ -20,10 +20,12 @@ use std::process::{Child, Command, Stdio};
 use std::thread::{self, JoinHandle};
 use tempfile::{self, TempDir};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum VerifyKeys {
     /// Production keys
     Production,
+    /// User-specified keys, each an armored or binary public key or keyring
+    Custom(Vec<Vec<u8>>),
     /// Snake oil key
     #[cfg(test)]
     InsecureTest,
@@ -88,6 +90,14 @@ impl<R: Read> Read for VerifyReader<R> {
     }
 }
 
+/// Check a detached signature over an in-memory buffer, reporting the
+/// result to stderr.
+pub fn verify_detached(data: &[u8], signature: &[u8], keys: VerifyKeys) -> Result<()> {
+    let mut reader = VerifyReader::new(data, Some(signature), keys)?;
+    io::copy(&mut reader, &mut io::sink()).context("reading signed data")?;
+    reader.verify()
+}
+
 struct GpgReader<R: Read> {
     _gpgdir: TempDir,
     source: R,
@@ -109,30 +119,33 @@ impl<R: Read> GpgReader<R> {
             .context("setting mode for temporary directory")?;
 
         // import public keys
-        let keys = match keys {
-            VerifyKeys::Production => &include_bytes!("../signing-keys.asc")[..],
+        let keys: Vec<&[u8]> = match &keys {
+            VerifyKeys::Production => vec![&include_bytes!("../signing-keys.asc")[..]],
+            VerifyKeys::Custom(keys) => keys.iter().map(|k| k.as_slice()).collect(),
             #[cfg(test)]
             VerifyKeys::InsecureTest => {
-                &include_bytes!("../../fixtures/verify/test-key.pub.asc")[..]
+                vec![&include_bytes!("../../fixtures/verify/test-key.pub.asc")[..]]
             }
         };
-        let mut import = Command::new("gpg")
-            .arg("--homedir")
-            .arg(gpgdir.path())
-            .arg("--batch")
-            .arg("--quiet")
-            .arg("--import")
-            .stdin(Stdio::piped())
-            .spawn()
-            .context("running gpg --import")?;
-        import
-            .stdin
-            .as_mut()
-            .unwrap()
-            .write_all(keys)
-            .context("importing GPG keys")?;
-        if !import.wait().context("waiting for gpg --import")?.success() {
-            bail!("gpg --import failed");
+        for key in keys {
+            let mut import = Command::new("gpg")
+                .arg("--homedir")
+                .arg(gpgdir.path())
+                .arg("--batch")
+                .arg("--quiet")
+                .arg("--import")
+                .stdin(Stdio::piped())
+                .spawn()
+                .context("running gpg --import")?;
+            import
+                .stdin
+                .as_mut()
+                .unwrap()
+                .write_all(key)
+                .context("importing GPG keys")?;
+            if !import.wait().context("waiting for gpg --import")?.success() {
+                bail!("gpg --import failed");
+            }
         }
 
         // list the public keys we just imported
@@ -356,6 +369,38 @@ mod tests {
         assert_eq!(&buf[..], &data[..1000]);
     }
 
+    /// Read data with a user-specified key
+    #[test]
+    fn test_custom_keys() {
+        let data = include_bytes!("../../fixtures/verify/test-key.priv.asc");
+        let sig = include_bytes!("../../fixtures/verify/test-key.priv.asc.sig");
+        let key = include_bytes!("../../fixtures/verify/test-key.pub.asc");
+
+        let keys = VerifyKeys::Custom(vec![key.to_vec()]);
+        let mut reader = VerifyReader::new(&data[..], Some(&sig[..]), keys).unwrap();
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).unwrap();
+        reader.verify().unwrap();
+
+        // custom keys replace the default ones
+        let mut reader =
+            VerifyReader::new(&data[..], Some(&sig[..]), VerifyKeys::Custom(Vec::new())).unwrap();
+        buf.clear();
+        reader.read_to_end(&mut buf).unwrap();
+        reader.verify().unwrap_err();
+    }
+
+    /// Verify a detached signature over a buffer
+    #[test]
+    fn test_verify_detached() {
+        let mut data = *include_bytes!("../../fixtures/verify/test-key.priv.asc");
+        let sig = include_bytes!("../../fixtures/verify/test-key.priv.asc.sig");
+
+        verify_detached(&data, sig, VerifyKeys::InsecureTest).unwrap();
+        data[data.len() - 1] = b'!';
+        verify_detached(&data, sig, VerifyKeys::InsecureTest).unwrap_err();
+    }
+
     /// Read data with signing key not in keyring
     #[test]
     fn test_no_pubkey() {
@@ -21,8 +21,10 @@
 //
 // https://github.com/alexcrichton/xz2-rs/pull/86
 
+use anyhow::{bail, Context, Result};
 use bytes::{Buf, BufMut, BytesMut};
 use std::io::{self, BufRead, Read};
+use std::ops::Range;
 use xz2::write::XzDecoder;
 
 use crate::io::*;
@@ -80,6 +82,86 @@ impl<R: BufRead> Read for XzStreamDecoder<R> {
     }
 }
 
+/// Size of the header at the start of an xz stream.
+const XZ_HEADER_SIZE: u64 = 12;
+/// Size of the footer at the end of an xz stream.
+const XZ_FOOTER_SIZE: u64 = 12;
+/// Sanity limit on the size of an xz index we're willing to read.
+const MAX_XZ_INDEX_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Return the uncompressed size of a single-stream xz file of the
+/// specified length, without decompressing it.  read_range is called to
+/// read the footer and index at the end of the file, and should return
+/// the bytes in the specified range.
+pub fn xz_uncompressed_size(
+    length: u64,
+    mut read_range: impl FnMut(Range<u64>) -> Result<Vec<u8>>,
+) -> Result<u64> {
+    let index_end = length
+        .checked_sub(XZ_FOOTER_SIZE)
+        .context("file is too short to be an xz file")?;
+    let index_size = xz_index_size(&read_range(index_end..length)?)?;
+    if index_size > MAX_XZ_INDEX_SIZE {
+        bail!("xz index is unreasonably large ({index_size} bytes)");
+    }
+    let index_start = index_end
+        .checked_sub(index_size)
+        .context("xz index is larger than the file")?;
+    xz_index_uncompressed_size(&read_range(index_start..index_end)?, length)
+}
+
+/// Parse the footer at the end of an xz file and return the size of the
+/// stream index preceding it.
+fn xz_index_size(footer: &[u8]) -> Result<u64> {
+    if footer.len() as u64 != XZ_FOOTER_SIZE || &footer[10..] != b"YZ" {
+        bail!("couldn't find xz stream footer");
+    }
+    let backward_size = u32::from_le_bytes(footer[4..8].try_into().expect("4 bytes"));
+    Ok((u64::from(backward_size) + 1) * 4)
+}
+
+/// Parse the index of a single-stream xz file of the specified length
+/// and return the uncompressed size of the stream.  Fail if the file
+/// contains multiple streams or stream padding, since then the index
+/// only describes part of it.
+fn xz_index_uncompressed_size(index: &[u8], file_length: u64) -> Result<u64> {
+    if index.first() != Some(&0) {
+        bail!("couldn't find xz index");
+    }
+    let mut buf = &index[1..];
+    let mut vli = || -> Result<u64> {
+        let mut value = 0u64;
+        for i in 0..9 {
+            let (&byte, rest) = buf.split_first().context("truncated xz index")?;
+            buf = rest;
+            value |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("invalid integer in xz index");
+    };
+    let records = vli()?;
+    let mut compressed = 0u64;
+    let mut uncompressed = 0u64;
+    for _ in 0..records {
+        // blocks are padded to a multiple of 4 bytes
+        compressed = compressed
+            .checked_add((vli()? + 3) & !3)
+            .context("xz block sizes overflow")?;
+        uncompressed = uncompressed
+            .checked_add(vli()?)
+            .context("xz block sizes overflow")?;
+    }
+    // stream header, blocks, index, stream footer
+    if compressed.checked_add(XZ_HEADER_SIZE + index.len() as u64 + XZ_FOOTER_SIZE)
+        != Some(file_length)
+    {
+        bail!("xz file doesn't contain exactly one stream");
+    }
+    Ok(uncompressed)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -111,4 +193,27 @@ mod tests {
         d.into_inner().read_to_end(&mut remainder).unwrap();
         assert_eq!(&remainder, b"abcdefg");
     }
+
+    #[test]
+    fn uncompressed_size() {
+        let mut data = include_bytes!("../../fixtures/verify/1M.xz").to_vec();
+        let size = |data: &[u8]| {
+            xz_uncompressed_size(data.len() as u64, |range| {
+                Ok(data[range.start as usize..range.end as usize].to_vec())
+            })
+        };
+        assert_eq!(size(&data).unwrap(), 1048576);
+        // truncated
+        size(&data[..8]).unwrap_err();
+        // concatenated streams
+        let single = data.clone();
+        data.extend(&single);
+        size(&data).unwrap_err();
+        // stream padding
+        data.truncate(single.len());
+        data.extend([0; 4]);
+        size(&data).unwrap_err();
+        // truncated index
+        xz_index_uncompressed_size(&[0, 1, 0x80], single.len() as u64).unwrap_err();
+    }
 }
@@ -26,12 +26,13 @@
 // Many magic numbers corresponding to offsets and lengths have not been const-ified. It should be
 // straightforward to see to what they correspond using the referenced linked above.
 
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
 use std::io::{BufReader, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 
 use anyhow::{anyhow, bail, Context, Result};
-use bytes::{Buf, Bytes};
+use bytes::{Buf, BufMut, Bytes};
 use serde::{Deserialize, Serialize};
 
 use crate::io::*;
@@ -39,16 +40,102 @@ use crate::io::*;
 // technically the standard supports others, but this is the only one we support
 const ISO9660_SECTOR_SIZE: usize = 2048;
 
+const ISO9660_VOLUME_DESCRIPTORS: Address = Address(0x10);
+
+/// Changes to the files of an ISO 9660 filesystem, planned against the
+/// image without modifying it.  The changes can be written to the image
+/// with [`IsoFs::apply_changes`], or overlaid on a copy of it using the
+/// byte ranges from [`IsoFsChanges::writes`].
+#[derive(Debug, Default)]
+pub struct IsoFsChanges {
+    /// New contents of modified directory extents, by sector
+    dirs: BTreeMap<u32, Vec<u8>>,
+    /// Contents of added files and zeroes over the contents of removed
+    /// files, by sector; always a multiple of the sector size
+    data: BTreeMap<u32, Vec<u8>>,
+}
+
+impl IsoFsChanges {
+    pub fn is_empty(&self) -> bool {
+        self.dirs.is_empty() && self.data.is_empty()
+    }
+
+    /// Returns the modified byte ranges of the image as offsets and
+    /// contents, sorted by offset.
+    pub fn writes(&self) -> Vec<(u64, &[u8])> {
+        let mut writes: Vec<(u64, &[u8])> = self
+            .dirs
+            .iter()
+            .chain(self.data.iter())
+            .filter(|(_, data)| !data.is_empty())
+            .map(|(sector, data)| (Address(*sector).as_offset(), data.as_slice()))
+            .collect();
+        writes.sort_unstable_by_key(|(offset, _)| *offset);
+        writes
+    }
+
+    /// Sets the contents of the sectors starting at `sector`, replacing
+    /// any overlapping data from earlier changes.
+    fn put_data(&mut self, sector: u32, data: Vec<u8>) {
+        if data.is_empty() {
+            return;
+        }
+        let sectors = |data: &[u8]| (data.len() / ISO9660_SECTOR_SIZE) as u32;
+        let end = sector + sectors(&data);
+        let overlapping: Vec<u32> = self
+            .data
+            .range(..end)
+            .filter(|(start, old)| *start + sectors(old) > sector)
+            .map(|(start, _)| *start)
+            .collect();
+        for start in overlapping {
+            let old = self.data.remove(&start).expect("overlapping data vanished");
+            let old_end = start + sectors(&old);
+            if start < sector {
+                let keep = (sector - start) as usize * ISO9660_SECTOR_SIZE;
+                self.data.insert(start, old[..keep].to_vec());
+            }
+            if old_end > end {
+                let skip = (end - start) as usize * ISO9660_SECTOR_SIZE;
+                self.data.insert(end, old[skip..].to_vec());
+            }
+        }
+        self.data.insert(sector, data);
+    }
+}
+
+/// An ISO 9660 filesystem read from a file or any other seekable stream.
 #[derive(Debug, Serialize)]
-pub struct IsoFs {
+pub struct IsoFs<R = fs::File> {
     descriptors: Vec<VolumeDescriptor>,
     #[serde(skip_serializing)]
-    file: fs::File,
+    file: R,
+}
+
+impl IsoFs<fs::File> {
+    pub fn from_file(file: fs::File) -> Result<Self> {
+        Self::new(file)
+    }
 }
 
-impl IsoFs {
-    pub fn from_file(mut file: fs::File) -> Result<Self> {
-        let length = file.metadata()?.len();
+impl<R: Read + Seek> IsoFs<R> {
+    /// Parses the volume descriptors of an ISO image.  The image can be
+    /// backed by anything seekable, such as a `std::io::Cursor` over an
+    /// in-memory copy.
+    ///
+    /// ```no_run
+    /// use libcoreinst::iso9660::IsoFs;
+    /// use std::io::Cursor;
+    ///
+    /// let data = std::fs::read("live.iso")?;
+    /// let iso = IsoFs::new(Cursor::new(data))?;
+    /// println!("{}", iso.get_volume_id()?);
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn new(mut file: R) -> Result<Self> {
+        let length = file
+            .seek(SeekFrom::End(0))
+            .context("seeking to end of ISO")?;
         let descriptors = get_volume_descriptors(&mut file)?;
         let iso_fs = Self { descriptors, file };
         let primary = iso_fs.get_primary_volume_descriptor()?;
@@ -59,11 +146,17 @@ impl IsoFs {
         Ok(iso_fs)
     }
 
-    pub fn as_file(&mut self) -> Result<&mut fs::File> {
+    /// Returns the underlying stream, rewound to the start of the image.
+    pub fn as_file(&mut self) -> Result<&mut R> {
         self.file.rewind().context("seeking to start of ISO")?;
         Ok(&mut self.file)
     }
 
+    /// Consumes the filesystem and returns the underlying stream.
+    pub fn into_inner(self) -> R {
+        self.file
+    }
+
     pub fn get_root_directory(&self) -> Result<Directory> {
         let primary = self
             .get_primary_volume_descriptor()
@@ -71,7 +164,21 @@ impl IsoFs {
         Ok(primary.root.clone())
     }
 
-    pub fn walk(&mut self) -> Result<IsoFsWalkIterator> {
+    /// Returns an iterator over every record in the filesystem, depth
+    /// first, along with its path.
+    ///
+    /// ```no_run
+    /// use libcoreinst::iso9660::{DirectoryRecord, IsoFs};
+    ///
+    /// let mut iso = IsoFs::from_file(std::fs::File::open("live.iso")?)?;
+    /// for entry in iso.walk()? {
+    ///     if let (path, DirectoryRecord::File(file)) = entry? {
+    ///         println!("{path}: {} bytes", file.length);
+    ///     }
+    /// }
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn walk(&mut self) -> Result<IsoFsWalkIterator<'_, R>> {
         let root_dir = self.get_root_directory()?;
         let buf = self.list_dir(&root_dir)?;
         Ok(IsoFsWalkIterator {
@@ -82,6 +189,18 @@ impl IsoFs {
         })
     }
 
+    /// Returns all the files in the filesystem, by path.
+    pub fn files(&mut self) -> Result<BTreeMap<String, File>> {
+        self.walk()?
+            .filter_map(|r| match r {
+                Err(e) => Some(Err(e)),
+                Ok((s, DirectoryRecord::File(f))) => Some(Ok((s, f))),
+                Ok(_) => None,
+            })
+            .collect::<Result<BTreeMap<String, File>>>()
+            .context("while walking ISO filesystem")
+    }
+
     /// Returns an iterator over the records of a directory.
     pub fn list_dir(&mut self, dir: &Directory) -> Result<IsoFsIterator> {
         IsoFsIterator::new(&mut self.file, dir)
@@ -132,16 +251,354 @@ impl IsoFs {
     }
 
     /// Returns a reader for a file record.
+    ///
+    /// ```no_run
+    /// use libcoreinst::iso9660::IsoFs;
+    /// use std::io::Read;
+    ///
+    /// let mut iso = IsoFs::from_file(std::fs::File::open("live.iso")?)?;
+    /// let file = iso.get_path("coreos/features.json")?.try_into_file()?;
+    /// let mut features = String::new();
+    /// iso.read_file(&file)?.read_to_string(&mut features)?;
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
     pub fn read_file(&mut self, file: &File) -> Result<impl Read + '_> {
         self.file
             .seek(SeekFrom::Start(file.address.as_offset()))
             .with_context(|| format!("seeking to file {}", file.name))?;
         Ok(BufReader::with_capacity(
             BUFFER_SIZE,
-            (&self.file).take(file.length as u64),
+            (&mut self.file).take(file.length as u64),
         ))
     }
 
+    /// Returns the first sector after everything referenced by the volume
+    /// descriptors: path tables, El Torito boot catalog and boot images,
+    /// directory trees (including Joliet), file extents, and Rock Ridge
+    /// continuation areas.  Directories are read with `changes` applied.
+    fn get_used_space_end(&mut self, changes: &IsoFsChanges) -> Result<u32> {
+        const TYPE_PRIMARY: u8 = 1;
+        const TYPE_SUPPLEMENTARY: u8 = 2;
+
+        let sectors = |length: u64| length.div_ceil(ISO9660_SECTOR_SIZE as u64) as u32;
+        // volume descriptors, including the terminator
+        let mut end = 0x10 + self.descriptors.len() as u32 + 1;
+        let mut dirs = Vec::new();
+        let mut buf = vec![0; ISO9660_SECTOR_SIZE];
+        for sector in 0x10..0x10 + self.descriptors.len() as u32 {
+            self.read_sector(sector, &mut buf)
+                .context("reading volume descriptor")?;
+            let mut desc = Bytes::copy_from_slice(&buf);
+            match desc[0] {
+                TYPE_PRIMARY | TYPE_SUPPLEMENTARY => {
+                    let path_table_size = sectors(eat(&mut desc, 132).get_u32_le() as u64);
+                    eat(&mut desc, 4);
+                    let path_tables = [
+                        desc.get_u32_le(),
+                        desc.get_u32_le(),
+                        desc.get_u32(),
+                        desc.get_u32(),
+                    ];
+                    for location in path_tables.into_iter().filter(|l| *l != 0) {
+                        end = end.max(location + path_table_size);
+                    }
+                    let address = eat(&mut desc, 2).get_u32_le();
+                    let length = eat(&mut desc, 4).get_u32_le();
+                    dirs.push((address, length));
+                }
+                _ => (),
+            }
+        }
+
+        if let Some(catalog) = self.get_boot_catalog_address() {
+            end = end.max(catalog.as_sector() + 1);
+            for entry in self.get_boot_catalog()?.into_iter().flat_map(|c| c.entries) {
+                // sector count is in 512-byte virtual sectors
+                let length = sectors(entry.sector_count as u64 * 512).max(1);
+                end = end.max(entry.load_address.as_sector() + length);
+            }
+        }
+
+        let mut visited = HashSet::new();
+        while let Some((address, length)) = dirs.pop() {
+            if !visited.insert(address) {
+                continue;
+            }
+            end = end.max(address + sectors(length as u64));
+            let buf = self.read_dir_extent(changes, Address(address), length)?;
+            for (_, record) in iter_raw_records(&buf) {
+                let mut record = Bytes::copy_from_slice(record);
+                let address = eat(&mut record, 2).get_u32_le();
+                let length = eat(&mut record, 4).get_u32_le();
+                let flags = eat(&mut record, 25 - 14).get_u8();
+                let name_length = eat(&mut record, 32 - 26).get_u8() as usize;
+                if flags & 2 > 0 {
+                    // skip "." and ".."
+                    if name_length != 1 || record[0] > 1 {
+                        dirs.push((address, length));
+                    }
+                } else {
+                    end = end.max(address + sectors(length as u64));
+                }
+                // Rock Ridge continuation areas, in the System Use field
+                // after the name and its padding byte
+                let name_field_length = name_length + (1 - name_length % 2);
+                if name_field_length > record.remaining() {
+                    continue;
+                }
+                record.advance(name_field_length);
+                while record.remaining() >= 4 {
+                    let signature = record.copy_to_bytes(2);
+                    let entry_length = record.get_u8() as usize;
+                    if entry_length < 4 || entry_length - 3 > record.remaining() {
+                        break;
+                    }
+                    let mut entry = record.copy_to_bytes(entry_length - 3);
+                    if &signature[..] == b"CE" && entry_length >= 28 {
+                        let block = eat(&mut entry, 1).get_u32_le();
+                        let offset = eat(&mut entry, 4).get_u32_le();
+                        let length = eat(&mut entry, 4).get_u32_le();
+                        end = end.max(block + sectors(offset as u64 + length as u64));
+                    }
+                }
+            }
+        }
+        Ok(end)
+    }
+
+    /// Returns the El Torito boot catalog, if the image has one.
+    pub fn get_boot_catalog(&mut self) -> Result<Option<BootCatalog>> {
+        let address = match self.get_boot_catalog_address() {
+            Some(address) => address,
+            None => return Ok(None),
+        };
+        let mut buf = vec![0; ISO9660_SECTOR_SIZE];
+        self.read_sector(address.as_sector(), &mut buf)
+            .context("reading El Torito boot catalog")?;
+        BootCatalog::parse(&mut Bytes::from(buf))
+            .context("parsing El Torito boot catalog")
+            .map(Some)
+    }
+
+    fn get_boot_catalog_address(&self) -> Option<Address> {
+        self.descriptors.iter().find_map(|d| match d {
+            VolumeDescriptor::Boot(b) => b.boot_catalog,
+            _ => None,
+        })
+    }
+
+    /// Plans adding a new file at the specified path, on top of the
+    /// changes already in `changes`, and returns its record.
+    ///
+    /// Write support is deliberately constrained: neither the volume nor
+    /// any directory is grown.  The file contents are placed in unused
+    /// space after the last sector referenced by the filesystem (usually
+    /// the padding at the end of the volume), and the new directory record
+    /// is appended to unused space at the end of the parent directory's
+    /// extent.  The parent directory must already exist.  Joliet and Rock
+    /// Ridge metadata are not updated, so the file is only visible under
+    /// its ISO 9660 name.
+    pub fn plan_add_file(
+        &mut self,
+        changes: &mut IsoFsChanges,
+        path: &str,
+        contents: &[u8],
+    ) -> Result<File> {
+        let (dir, name) = self.get_parent_dir(path)?;
+        let record_name = file_record_name(name)?;
+        let mut buf = self.read_dir_extent(changes, dir.address, dir.length)?;
+        if find_raw_record(&buf, name).is_some() {
+            bail!("{path} already exists");
+        }
+        let length: u32 = contents
+            .len()
+            .try_into()
+            .with_context(|| format!("{path} is too large"))?;
+
+        // find space for the contents
+        let sectors = length.div_ceil(ISO9660_SECTOR_SIZE as u32);
+        let start = self.get_used_space_end(changes)?;
+        let available = self.get_free_space_limit(start)?.saturating_sub(start);
+        if sectors > available {
+            bail!(
+                "not enough unused space in ISO image for {path}: need {sectors} sectors, have {available}"
+            );
+        }
+        let address = Address(start);
+
+        // find space for the directory record
+        // copy the timestamp from the directory's "." record
+        let record = build_file_record(&record_name, address, length, &buf[18..25]);
+        let mut offset = get_dir_records_end(&buf);
+        if offset / ISO9660_SECTOR_SIZE != (offset + record.len() - 1) / ISO9660_SECTOR_SIZE {
+            // records can't cross sector boundaries
+            offset = offset.next_multiple_of(ISO9660_SECTOR_SIZE);
+        }
+        if offset + record.len() > buf.len() {
+            bail!(
+                "not enough unused space in directory {} for {path}",
+                dir.name
+            );
+        }
+        buf[offset..offset + record.len()].copy_from_slice(&record);
+        changes.dirs.insert(dir.address.as_sector(), buf);
+
+        // zero-fill the rest of the last sector
+        let mut data = contents.to_vec();
+        data.resize(sectors as usize * ISO9660_SECTOR_SIZE, 0);
+        changes.put_data(start, data);
+
+        Ok(File {
+            name: name.to_string(),
+            address,
+            length,
+        })
+    }
+
+    /// Plans removing the file at the specified path, on top of the
+    /// changes already in `changes`.  The directory record is dropped and
+    /// the file contents are zeroed.
+    pub fn plan_remove_file(&mut self, changes: &mut IsoFsChanges, path: &str) -> Result<()> {
+        let (dir, name) = self.get_parent_dir(path)?;
+        let mut buf = self.read_dir_extent(changes, dir.address, dir.length)?;
+        let record = find_raw_record(&buf, name)
+            .filter(|r| !r.is_dir)
+            .ok_or_else(|| NotFound(format!("file {path} not found")))?;
+
+        // shift the following records in the sector down over the record
+        let sector_end = (record.offset / ISO9660_SECTOR_SIZE + 1) * ISO9660_SECTOR_SIZE;
+        buf.copy_within(record.offset + record.len..sector_end, record.offset);
+        buf[sector_end - record.len..sector_end].fill(0);
+        changes.dirs.insert(dir.address.as_sector(), buf);
+
+        let sectors = record.length.div_ceil(ISO9660_SECTOR_SIZE as u32);
+        changes.put_data(
+            record.address,
+            vec![0; sectors as usize * ISO9660_SECTOR_SIZE],
+        );
+        Ok(())
+    }
+
+    /// Returns the parent directory and filename of a path.
+    fn get_parent_dir<'a>(&mut self, path: &'a str) -> Result<(Directory, &'a str)> {
+        let mut components = path_components(path);
+        let name = components
+            .pop()
+            .with_context(|| format!("no filename in path {path}"))?;
+        let dir = self
+            .get_path(&components.join("/"))
+            .and_then(|r| r.try_into_dir())
+            .with_context(|| format!("looking up parent directory of {path}"))?;
+        Ok((dir, name))
+    }
+
+    /// Reads a directory extent, with any planned changes applied.
+    fn read_dir_extent(
+        &mut self,
+        changes: &IsoFsChanges,
+        address: Address,
+        length: u32,
+    ) -> Result<Vec<u8>> {
+        if let Some(buf) = changes.dirs.get(&address.as_sector()) {
+            return Ok(buf.clone());
+        }
+        let mut buf = vec![0; length as usize];
+        self.file
+            .seek(SeekFrom::Start(address.as_offset()))
+            .context("seeking to directory")?;
+        self.file
+            .read_exact(&mut buf)
+            .context("reading directory")?;
+        Ok(buf)
+    }
+
+    /// Returns the end of the space available for new file contents
+    /// starting at sector `start`: the end of the volume or of the image,
+    /// whichever comes first, or the start of anything placed after
+    /// `start` by the partition tables of a hybrid image, such as an
+    /// appended partition or the backup GPT.
+    fn get_free_space_limit(&mut self, start: u32) -> Result<u32> {
+        const LBA_SIZE: u64 = 512;
+
+        let image_end = self
+            .file
+            .seek(SeekFrom::End(0))
+            .context("seeking to end of ISO")?
+            / ISO9660_SECTOR_SIZE as u64;
+        let volume_end = self.get_primary_volume_descriptor()?.volume_space_size;
+        let mut limit = volume_end.min(image_end);
+        let mut bound = |lba: u64| {
+            // round down to the sector containing the LBA
+            let sector = lba * LBA_SIZE / ISO9660_SECTOR_SIZE as u64;
+            if sector >= start as u64 {
+                limit = limit.min(sector);
+            }
+        };
+
+        let mut buf = vec![0; ISO9660_SECTOR_SIZE];
+        self.read_sector(0, &mut buf)
+            .context("reading system area")?;
+        if buf[510..512] == [0x55, 0xaa] {
+            for entry in buf[446..510].chunks_exact(16) {
+                // skip unused and GPT protective entries
+                if !matches!(entry[4], 0x00 | 0xee) {
+                    bound(u32::from_le_bytes(entry[8..12].try_into().unwrap()) as u64);
+                }
+            }
+        }
+        let header = &buf[LBA_SIZE as usize..];
+        if header.starts_with(b"EFI PART") {
+            let backup_lba = u64::from_le_bytes(header[32..40].try_into().unwrap());
+            let entries_lba = u64::from_le_bytes(header[72..80].try_into().unwrap());
+            let entry_count = u32::from_le_bytes(header[80..84].try_into().unwrap()) as u64;
+            let entry_size = u32::from_le_bytes(header[84..88].try_into().unwrap()) as u64;
+            let entries_len = entry_count * entry_size;
+            // the backup partition entries precede the backup header
+            bound(backup_lba.saturating_sub(entries_len.div_ceil(LBA_SIZE)));
+            if entry_size >= 128 && entries_len <= 1 << 20 {
+                let mut entries = vec![0; entries_len as usize];
+                self.file
+                    .seek(SeekFrom::Start(entries_lba * LBA_SIZE))
+                    .context("seeking to GPT partition entries")?;
+                self.file
+                    .read_exact(&mut entries)
+                    .context("reading GPT partition entries")?;
+                for entry in entries.chunks_exact(entry_size as usize) {
+                    // skip unused entries, which have a zero type GUID
+                    if entry[..16].iter().any(|b| *b != 0) {
+                        bound(u64::from_le_bytes(entry[32..40].try_into().unwrap()));
+                    }
+                }
+            }
+        }
+        Ok(limit as u32)
+    }
+
+    fn read_sector(&mut self, sector: u32, buf: &mut [u8]) -> Result<()> {
+        self.file
+            .seek(SeekFrom::Start(Address(sector).as_offset()))
+            .with_context(|| format!("seeking to sector {sector}"))?;
+        self.file
+            .read_exact(buf)
+            .with_context(|| format!("reading sector {sector}"))
+    }
+
+    /// Return the volume ID from the primary volume descriptor.
+    pub fn get_volume_id(&self) -> Result<&str> {
+        Ok(&self.get_primary_volume_descriptor()?.volume_id)
+    }
+
+    fn get_primary_volume_descriptor(&self) -> Result<&PrimaryVolumeDescriptor> {
+        for d in &self.descriptors {
+            if let VolumeDescriptor::Primary(p) = d {
+                return Ok(p);
+            }
+        }
+        Err(anyhow!("no primary volume descriptor found in ISO"))
+    }
+}
+
+impl<R: Read + Write + Seek> IsoFs<R> {
     /// Returns a writer for a file record.
     pub fn overwrite_file(&mut self, file: &File) -> Result<impl Write + '_> {
         self.file
@@ -154,13 +611,72 @@ impl IsoFs {
         ))
     }
 
-    fn get_primary_volume_descriptor(&self) -> Result<&PrimaryVolumeDescriptor> {
-        for d in &self.descriptors {
-            if let VolumeDescriptor::Primary(p) = d {
-                return Ok(p);
+    /// Adds a new file at the specified path and returns its record.  See
+    /// [`IsoFs::plan_add_file`] for the constraints.
+    pub fn add_file(&mut self, path: &str, contents: &[u8]) -> Result<File> {
+        let mut changes = IsoFsChanges::default();
+        let file = self.plan_add_file(&mut changes, path, contents)?;
+        self.apply_changes(&changes)?;
+        Ok(file)
+    }
+
+    /// Removes the file at the specified path, zeroing its contents.
+    pub fn remove_file(&mut self, path: &str) -> Result<()> {
+        let mut changes = IsoFsChanges::default();
+        self.plan_remove_file(&mut changes, path)?;
+        self.apply_changes(&changes)
+    }
+
+    /// Writes planned changes to the image.
+    pub fn apply_changes(&mut self, changes: &IsoFsChanges) -> Result<()> {
+        for (offset, data) in changes.writes() {
+            self.file
+                .seek(SeekFrom::Start(offset))
+                .with_context(|| format!("seeking to offset {offset}"))?;
+            self.file
+                .write_all(data)
+                .with_context(|| format!("writing {} bytes at {offset}", data.len()))?;
+        }
+        self.file.flush().context("flushing ISO")
+    }
+
+    /// Set the volume ID in the primary volume descriptor, and in the
+    /// Joliet supplementary volume descriptor if there is one.  Joliet
+    /// volume IDs are limited to 16 characters, so the Joliet ID may be
+    /// truncated.
+    pub fn set_volume_id(&mut self, volume_id: &str) -> Result<()> {
+        validate_volume_id(volume_id)?;
+        for (i, descriptor) in self.descriptors.iter_mut().enumerate() {
+            let sector = Address(ISO9660_VOLUME_DESCRIPTORS.as_sector() + i as u32);
+            let field: [u8; 32] = match descriptor {
+                VolumeDescriptor::Primary(_) => {
+                    let mut field = [b' '; 32];
+                    field[..volume_id.len()].copy_from_slice(volume_id.as_bytes());
+                    field
+                }
+                VolumeDescriptor::Supplementary
+                    if is_joliet_descriptor(&mut self.file, sector)? =>
+                {
+                    // UCS-2 big-endian, padded with spaces
+                    let mut field = [0u8, b' '].repeat(16);
+                    for (c, b) in field.chunks_mut(2).zip(volume_id.bytes()) {
+                        c.copy_from_slice(&[0, b]);
+                    }
+                    field.try_into().expect("wrong Joliet volume ID length")
+                }
+                _ => continue,
+            };
+            // the volume ID follows the descriptor header and system ID
+            self.file
+                .seek(SeekFrom::Start(sector.as_offset() + 40))
+                .context("seeking to volume ID")?;
+            self.file.write_all(&field).context("writing volume ID")?;
+            if let VolumeDescriptor::Primary(p) = descriptor {
+                p.volume_id = volume_id.to_string();
             }
         }
-        Err(anyhow!("no primary volume descriptor found in ISO"))
+        self.file.flush().context("flushing volume ID")?;
+        Ok(())
     }
 }
 
@@ -177,6 +693,8 @@ enum VolumeDescriptor {
 struct BootVolumeDescriptor {
     boot_system_id: String,
     boot_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    boot_catalog: Option<Address>,
 }
 
 #[derive(Debug, Serialize)]
@@ -237,14 +755,146 @@ impl Address {
     }
 }
 
+/// Files in two ISO images, matched by path and sorted by path.
+#[derive(Debug, Default)]
+pub struct FileMatches {
+    /// Files present in both images, as (path, first, second)
+    pub common: Vec<(String, File, File)>,
+    /// Files only present in the first image
+    pub first_only: Vec<(String, File)>,
+    /// Files only present in the second image
+    pub second_only: Vec<(String, File)>,
+}
+
+impl FileMatches {
+    pub fn new(first: &mut IsoFs, second: &mut IsoFs) -> Result<Self> {
+        let mut first_files = first.files().context("collecting files from first ISO")?;
+        let second_files = second.files().context("collecting files from second ISO")?;
+        let mut matches = Self::default();
+        for (path, second_file) in second_files {
+            match first_files.remove(&path) {
+                Some(first_file) => matches.common.push((path, first_file, second_file)),
+                None => matches.second_only.push((path, second_file)),
+            }
+        }
+        matches.first_only = first_files.into_iter().collect();
+        Ok(matches)
+    }
+
+    pub fn first_len(&self) -> usize {
+        self.common.len() + self.first_only.len()
+    }
+
+    pub fn second_len(&self) -> usize {
+        self.common.len() + self.second_only.len()
+    }
+}
+
+/// El Torito boot catalog.
+#[derive(Debug, Serialize)]
+pub struct BootCatalog {
+    pub id: String,
+    pub entries: Vec<BootEntry>,
+}
+
+/// Boot catalog initial/default entry or section entry.
+#[derive(Debug, Serialize)]
+pub struct BootEntry {
+    pub platform: BootPlatform,
+    pub bootable: bool,
+    pub media: BootMedia,
+    pub load_segment: u16,
+    pub system_type: u8,
+    /// Length of the boot image in 512-byte virtual sectors
+    pub sector_count: u16,
+    pub load_address: Address,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BootPlatform {
+    X86,
+    PowerPC,
+    Mac,
+    Efi,
+    Unknown(u8),
+}
+
+impl From<u8> for BootPlatform {
+    fn from(id: u8) -> Self {
+        match id {
+            0x00 => Self::X86,
+            0x01 => Self::PowerPC,
+            0x02 => Self::Mac,
+            0xef => Self::Efi,
+            id => Self::Unknown(id),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BootMedia {
+    NoEmulation,
+    #[serde(rename = "floppy-1.2M")]
+    Floppy1_2M,
+    #[serde(rename = "floppy-1.44M")]
+    Floppy1_44M,
+    #[serde(rename = "floppy-2.88M")]
+    Floppy2_88M,
+    HardDisk,
+    Unknown(u8),
+}
+
+impl From<u8> for BootMedia {
+    fn from(media: u8) -> Self {
+        match media {
+            0 => Self::NoEmulation,
+            1 => Self::Floppy1_2M,
+            2 => Self::Floppy1_44M,
+            3 => Self::Floppy2_88M,
+            4 => Self::HardDisk,
+            media => Self::Unknown(media),
+        }
+    }
+}
+
 /// Requested path was not found.
 #[derive(Debug, thiserror::Error)]
 #[error("{0}")]
 pub struct NotFound(String);
 
+/// Checks that a volume ID fits in the primary volume descriptor and
+/// only uses characters that are safe in a kernel command line.
+pub fn validate_volume_id(volume_id: &str) -> Result<()> {
+    if volume_id.is_empty() || volume_id.len() > 32 {
+        bail!("volume ID must be between 1 and 32 characters");
+    }
+    if !volume_id
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b"-._".contains(&b))
+    {
+        bail!(
+            "volume ID '{}' may only contain letters, digits, '-', '.', and '_'",
+            volume_id
+        );
+    }
+    Ok(())
+}
+
+/// Checks the escape sequences of the supplementary volume descriptor at
+/// the specified sector for one of the Joliet UCS-2 levels.
+fn is_joliet_descriptor(f: &mut (impl Read + Seek), sector: Address) -> Result<bool> {
+    let mut escapes = [0u8; 3];
+    f.seek(SeekFrom::Start(sector.as_offset() + 88))
+        .context("seeking to escape sequences")?;
+    f.read_exact(&mut escapes)
+        .context("reading escape sequences")?;
+    Ok(matches!(escapes, [b'%', b'/', b'@' | b'C' | b'E']))
+}
+
 /// Reads all the volume descriptors.
-fn get_volume_descriptors(f: &mut fs::File) -> Result<Vec<VolumeDescriptor>> {
-    const ISO9660_VOLUME_DESCRIPTORS: Address = Address(0x10);
+fn get_volume_descriptors(f: &mut (impl Read + Seek)) -> Result<Vec<VolumeDescriptor>> {
     f.seek(SeekFrom::Start(ISO9660_VOLUME_DESCRIPTORS.as_offset()))
         .context("seeking to volume descriptors")?;
 
@@ -259,7 +909,7 @@ fn get_volume_descriptors(f: &mut fs::File) -> Result<Vec<VolumeDescriptor>> {
 }
 
 /// Reads the volume descriptor at cursor and advances to the next one.
-fn get_next_volume_descriptor(f: &mut fs::File) -> Result<Option<VolumeDescriptor>> {
+fn get_next_volume_descriptor(f: &mut impl Read) -> Result<Option<VolumeDescriptor>> {
     const TYPE_BOOT: u8 = 0;
     const TYPE_PRIMARY: u8 = 1;
     const TYPE_SUPPLEMENTARY: u8 = 2;
@@ -284,15 +934,109 @@ fn get_next_volume_descriptor(f: &mut fs::File) -> Result<Option<VolumeDescripto
 impl BootVolumeDescriptor {
     /// Parses boot descriptor at cursor after type field.
     fn parse(buf: &mut Bytes) -> Result<Self> {
+        const EL_TORITO_ID: &str = "EL TORITO SPECIFICATION";
+
         verify_descriptor_header(buf).context("parsing boot descriptor")?;
+        let boot_system_id =
+            parse_iso9660_string(buf, 32, IsoString::StrA).context("parsing boot system ID")?;
+        let boot_id = parse_iso9660_string(buf, 32, IsoString::StrA).context("parsing boot ID")?;
+        let boot_catalog = if boot_system_id == EL_TORITO_ID {
+            Some(Address(buf.get_u32_le()))
+        } else {
+            None
+        };
         Ok(Self {
-            boot_system_id: parse_iso9660_string(buf, 32, IsoString::StrA)
-                .context("parsing boot system ID")?,
-            boot_id: parse_iso9660_string(buf, 32, IsoString::StrA).context("parsing boot ID")?,
+            boot_system_id,
+            boot_id,
+            boot_catalog,
         })
     }
 }
 
+impl BootCatalog {
+    /// Parses an El Torito boot catalog at cursor.
+    fn parse(buf: &mut Bytes) -> Result<Self> {
+        const HEADER_VALIDATION: u8 = 0x01;
+        const HEADER_SECTION: u8 = 0x90;
+        const HEADER_SECTION_FINAL: u8 = 0x91;
+        const ENTRY_EXTENSION: u8 = 0x44;
+
+        // validation entry
+        let mut validation = buf.copy_to_bytes(32);
+        let checksum = validation.chunks_exact(2).fold(0u16, |sum, w| {
+            sum.wrapping_add(u16::from_le_bytes([w[0], w[1]]))
+        });
+        if validation[0] != HEADER_VALIDATION || validation[30..] != [0x55, 0xaa] {
+            bail!("invalid validation entry");
+        }
+        if checksum != 0 {
+            bail!("bad validation entry checksum");
+        }
+        let mut platform = BootPlatform::from(eat(&mut validation, 1).get_u8());
+        let id = parse_catalog_id(&eat(&mut validation, 2).copy_to_bytes(24));
+
+        // initial/default entry
+        let mut entries = vec![BootEntry::parse(&mut buf.copy_to_bytes(32), platform)];
+
+        // sections
+        while buf.remaining() >= 32 {
+            let mut header = buf.copy_to_bytes(32);
+            let header_type = header.get_u8();
+            if header_type != HEADER_SECTION && header_type != HEADER_SECTION_FINAL {
+                break;
+            }
+            platform = BootPlatform::from(header.get_u8());
+            let count = header.get_u16_le();
+            let mut found = 0;
+            while found < count {
+                if buf.remaining() < 32 {
+                    bail!("boot catalog section truncated");
+                }
+                let mut entry = buf.copy_to_bytes(32);
+                if entry[0] == ENTRY_EXTENSION {
+                    continue;
+                }
+                entries.push(BootEntry::parse(&mut entry, platform));
+                found += 1;
+            }
+            if header_type == HEADER_SECTION_FINAL {
+                break;
+            }
+        }
+        Ok(Self { id, entries })
+    }
+}
+
+impl BootEntry {
+    /// Parses an initial/default or section entry at cursor.
+    fn parse(buf: &mut Bytes, platform: BootPlatform) -> Self {
+        const BOOTABLE: u8 = 0x88;
+
+        let bootable = buf.get_u8() == BOOTABLE;
+        let media = BootMedia::from(buf.get_u8() & 0x0f);
+        let load_segment = buf.get_u16_le();
+        let system_type = buf.get_u8();
+        let sector_count = eat(buf, 1).get_u16_le();
+        let load_address = Address(buf.get_u32_le());
+        Self {
+            platform,
+            bootable,
+            media,
+            load_segment,
+            system_type,
+            sector_count,
+            load_address,
+        }
+    }
+}
+
+/// Reads a boot catalog ID string.  These are free-form, so be lenient.
+fn parse_catalog_id(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .trim_end_matches(['\0', ' '])
+        .to_string()
+}
+
 impl PrimaryVolumeDescriptor {
     /// Parses primary descriptor at cursor after type field.
     fn parse(buf: &mut Bytes) -> Result<Self> {
@@ -340,7 +1084,7 @@ pub struct IsoFsIterator {
 }
 
 impl IsoFsIterator {
-    fn new(iso: &mut fs::File, dir: &Directory) -> Result<Self> {
+    fn new(iso: &mut (impl Read + Seek), dir: &Directory) -> Result<Self> {
         iso.seek(SeekFrom::Start(dir.address.as_offset()))
             .with_context(|| format!("seeking to directory {}", dir.name))?;
 
@@ -364,21 +1108,21 @@ impl Iterator for IsoFsIterator {
     }
 }
 
-pub struct IsoFsWalkIterator<'a> {
-    iso: &'a mut fs::File,
+pub struct IsoFsWalkIterator<'a, R = fs::File> {
+    iso: &'a mut R,
     parent_dirs: Vec<IsoFsIterator>,
     current_dir: Option<IsoFsIterator>,
     dirpath: PathBuf,
 }
 
-impl<'a> Iterator for IsoFsWalkIterator<'a> {
+impl<'a, R: Read + Seek> Iterator for IsoFsWalkIterator<'a, R> {
     type Item = Result<(String, DirectoryRecord)>;
     fn next(&mut self) -> Option<Self::Item> {
         self.walk_iterator_next().transpose()
     }
 }
 
-impl<'a> IsoFsWalkIterator<'a> {
+impl<'a, R: Read + Seek> IsoFsWalkIterator<'a, R> {
     // This is simply split out of next() above for easier error-handling
     fn walk_iterator_next(&mut self) -> Result<Option<(String, DirectoryRecord)>> {
         while let Some(ref mut current_dir) = self.current_dir {
@@ -531,6 +1275,123 @@ fn parse_iso9660_string(buf: &mut Bytes, len: usize, kind: IsoString) -> Result<
     Ok(s)
 }
 
+/// Returns the offsets and contents of the raw directory records in a
+/// directory extent, including "." and "..".
+fn iter_raw_records(buf: &[u8]) -> impl Iterator<Item = (usize, &[u8])> {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        while pos < buf.len() {
+            let len = buf[pos] as usize;
+            if len == 0 {
+                // skip padding at end of sector
+                pos = (pos + 1).next_multiple_of(ISO9660_SECTOR_SIZE);
+                continue;
+            }
+            // records are at least 34 bytes; ignore truncated ones
+            if len < 34 || pos + len > buf.len() {
+                return None;
+            }
+            let offset = pos;
+            pos += len;
+            return Some((offset, &buf[offset..pos]));
+        }
+        None
+    })
+}
+
+/// A raw directory record found in a directory extent.
+struct RawRecord {
+    /// Offset of the record in the extent
+    offset: usize,
+    /// Length of the record
+    len: usize,
+    /// Location of the file or directory contents
+    address: Address,
+    /// Length of the file or directory contents
+    length: u32,
+    is_dir: bool,
+}
+
+/// Finds the record with the specified ISO 9660 name in a directory
+/// extent, ignoring any version suffix and the trailing period of names
+/// without an extension.
+fn find_raw_record(buf: &[u8], name: &str) -> Option<RawRecord> {
+    iter_raw_records(buf)
+        .filter(|(_, record)| {
+            let name_length = record[32] as usize;
+            // skip "." and ".."
+            !(name_length == 1 && record[33] <= 1) && 33 + name_length <= record.len()
+        })
+        .find(|(_, record)| {
+            let raw_name = &record[33..33 + record[32] as usize];
+            let raw_name = raw_name.split(|c| *c == b';').next().unwrap_or_default();
+            let raw_name = raw_name.strip_suffix(b".").unwrap_or(raw_name);
+            raw_name == name.as_bytes()
+        })
+        .map(|(offset, record)| {
+            let mut fields = Bytes::copy_from_slice(record);
+            let address = Address(eat(&mut fields, 2).get_u32_le());
+            let length = eat(&mut fields, 4).get_u32_le();
+            let flags = eat(&mut fields, 25 - 14).get_u8();
+            RawRecord {
+                offset,
+                len: record.len(),
+                address,
+                length,
+                is_dir: flags & 2 > 0,
+            }
+        })
+}
+
+/// Returns the offset just past the last record in a directory extent.
+fn get_dir_records_end(buf: &[u8]) -> usize {
+    iter_raw_records(buf)
+        .last()
+        .map(|(offset, record)| offset + record.len())
+        .unwrap_or(0)
+}
+
+/// Validates a filename for a new file and returns its on-disk form.
+fn file_record_name(name: &str) -> Result<String> {
+    // We only create names consisting of d-characters, with at most one
+    // separator.  The version suffix counts toward the 31-byte limit.
+    if name.is_empty()
+        || name.len() > 29
+        || name.matches('.').count() > 1
+        || !name
+            .bytes()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == b'_' || c == b'.')
+    {
+        bail!(
+            "invalid ISO 9660 filename {name:?}; must be at most 29 characters of A-Z, 0-9, and _, with at most one period"
+        );
+    }
+    Ok(format!("{name};1"))
+}
+
+/// Builds a directory record for a regular file.
+fn build_file_record(name: &str, address: Address, length: u32, timestamp: &[u8]) -> Vec<u8> {
+    // record length must be even
+    let record_length = 33 + name.len() + (1 - name.len() % 2);
+    let mut record = Vec::with_capacity(record_length);
+    record.put_u8(record_length as u8);
+    record.put_u8(0); // extended attribute record length
+    record.put_u32_le(address.as_sector());
+    record.put_u32(address.as_sector());
+    record.put_u32_le(length);
+    record.put_u32(length);
+    record.put_slice(timestamp);
+    record.put_u8(0); // flags
+    record.put_u8(0); // file unit size
+    record.put_u8(0); // interleave gap size
+    record.put_u16_le(1); // volume sequence number
+    record.put_u16(1);
+    record.put_u8(name.len() as u8);
+    record.put_slice(name.as_bytes());
+    record.resize(record_length, 0);
+    record
+}
+
 fn eat(buf: &mut Bytes, n: usize) -> &mut Bytes {
     buf.advance(n);
     buf
@@ -596,6 +1457,24 @@ mod tests {
         assert_eq!(desc.volume_id, "volume-ID-string");
         assert_eq!(desc.root.name, ".");
         assert_eq!(desc.volume_space_size, 338);
+        assert_eq!(iso.get_volume_id().unwrap(), "volume-ID-string");
+    }
+
+    #[test]
+    fn test_set_volume_id() {
+        let mut iso = open_iso();
+        iso.set_volume_id("new-label_1.0").unwrap();
+        assert_eq!(iso.get_volume_id().unwrap(), "new-label_1.0");
+        // reopen and read back
+        let iso = IsoFs::from_file(iso.file).unwrap();
+        assert_eq!(iso.get_volume_id().unwrap(), "new-label_1.0");
+
+        let mut iso = iso;
+        iso.set_volume_id("").unwrap_err();
+        iso.set_volume_id(&"x".repeat(33)).unwrap_err();
+        iso.set_volume_id("has space").unwrap_err();
+        iso.set_volume_id(&"x".repeat(32)).unwrap();
+        assert_eq!(iso.get_volume_id().unwrap(), "x".repeat(32));
     }
 
     #[test]
@@ -668,6 +1547,45 @@ mod tests {
         assert_eq!(data.as_str(), "foo\n");
     }
 
+    #[test]
+    fn test_in_memory() {
+        let iso_bytes: &[u8] = include_bytes!("../fixtures/iso/synthetic.iso.xz");
+        let mut data = Vec::new();
+        XzDecoder::new(iso_bytes).read_to_end(&mut data).unwrap();
+        let mut iso = IsoFs::new(std::io::Cursor::new(data.clone())).unwrap();
+        assert_eq!(iso.get_volume_id().unwrap(), "volume-ID-string");
+        assert_eq!(
+            iso.walk().unwrap().count(),
+            open_iso().walk().unwrap().count()
+        );
+        let file = iso
+            .get_path("REALLY/VERY/DEEPLY/NESTED/FILE.TXT")
+            .unwrap()
+            .try_into_file()
+            .unwrap();
+        let mut contents = String::new();
+        iso.read_file(&file)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "foo\n");
+
+        // writable backends support modification
+        iso.set_volume_id("NEW").unwrap();
+        let iso = IsoFs::new(iso.into_inner()).unwrap();
+        assert_eq!(iso.get_volume_id().unwrap(), "NEW");
+
+        // truncated
+        data.truncate(data.len() / 2);
+        assert_eq!(
+            IsoFs::new(std::io::Cursor::new(data))
+                .err()
+                .map(|e| e.to_string())
+                .unwrap_or_default(),
+            "ISO image is incomplete"
+        );
+    }
+
     #[test]
     fn test_walk() {
         let expected = vec![
@@ -716,6 +1634,213 @@ mod tests {
         assert_eq!(names, expected);
     }
 
+    #[test]
+    fn test_add_file() {
+        let mut iso = open_iso();
+        assert_eq!(
+            iso.get_used_space_end(&IsoFsChanges::default()).unwrap(),
+            188
+        );
+
+        // add to top-level and nested directories
+        let file = iso.add_file("NEW.TXT", b"new\n").unwrap();
+        assert_eq!(file.address.as_sector(), 188);
+        let large = vec![7; 3 * ISO9660_SECTOR_SIZE + 1];
+        let file = iso.add_file("/CONTENT/DIR/LARGE", &large).unwrap();
+        assert_eq!(file.address.as_sector(), 189);
+        assert_eq!(
+            iso.get_used_space_end(&IsoFsChanges::default()).unwrap(),
+            193
+        );
+
+        // reopen and read back
+        let mut iso = IsoFs::from_file(iso.file).unwrap();
+        let file = iso.get_path("NEW.TXT").unwrap().try_into_file().unwrap();
+        let mut data = String::new();
+        iso.read_file(&file)
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, "new\n");
+        let file = iso
+            .get_path("CONTENT/DIR/LARGE")
+            .unwrap()
+            .try_into_file()
+            .unwrap();
+        let mut data = Vec::new();
+        iso.read_file(&file)
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, large);
+        assert_eq!(iso.walk().unwrap().count(), 177);
+
+        // errors
+        iso.add_file("NEW.TXT", b"").unwrap_err();
+        iso.add_file("MISSING/NEW.TXT", b"").unwrap_err();
+        iso.add_file("NEW.TXT/NEW.TXT", b"").unwrap_err();
+        iso.add_file("/", b"").unwrap_err();
+        iso.add_file("lower.txt", b"").unwrap_err();
+        iso.add_file("A.B.C", b"").unwrap_err();
+        iso.add_file("TOO_BIG", &vec![0; 146 * ISO9660_SECTOR_SIZE])
+            .unwrap_err();
+        // fill up the root directory
+        let mut i = 0;
+        let err = loop {
+            match iso.add_file(&format!("FILE_{i:023}"), b"") {
+                Ok(_) => i += 1,
+                Err(e) => break e,
+            }
+        };
+        assert!(i > 0);
+        assert!(err
+            .to_string()
+            .contains("not enough unused space in directory"));
+    }
+
+    #[test]
+    fn test_remove_file() {
+        let mut iso = open_iso();
+        let read_image = |iso: &mut IsoFs| {
+            let mut data = Vec::new();
+            iso.as_file().unwrap().read_to_end(&mut data).unwrap();
+            data
+        };
+        let orig = read_image(&mut iso);
+
+        // removing added files restores the original image
+        iso.add_file("NEW.TXT", b"new\n").unwrap();
+        iso.add_file("CONTENT/DIR/LARGE", &[7; 5000]).unwrap();
+        iso.add_file("CONTENT/DIR/SMALL", b"small").unwrap();
+        iso.remove_file("CONTENT/DIR/LARGE").unwrap();
+        iso.get_path("CONTENT/DIR/SMALL").unwrap();
+        iso.remove_file("CONTENT/DIR/SMALL").unwrap();
+        iso.remove_file("NEW.TXT").unwrap();
+        assert_eq!(read_image(&mut iso), orig);
+
+        // replacing a file in one set of changes reuses its space
+        iso.add_file("NEW.TXT", &[1; 5000]).unwrap();
+        let mut changes = IsoFsChanges::default();
+        iso.plan_remove_file(&mut changes, "NEW.TXT").unwrap();
+        let file = iso.plan_add_file(&mut changes, "NEW.TXT", b"new").unwrap();
+        assert_eq!(file.address.as_sector(), 188);
+        // the stale tail of the old contents is zeroed
+        assert_eq!(
+            changes
+                .writes()
+                .iter()
+                .filter(|(offset, _)| *offset >= Address(188).as_offset())
+                .map(|(offset, data)| (*offset, data.len()))
+                .collect::<Vec<_>>(),
+            [
+                (Address(188).as_offset(), ISO9660_SECTOR_SIZE),
+                (Address(189).as_offset(), 2 * ISO9660_SECTOR_SIZE)
+            ]
+        );
+        iso.apply_changes(&changes).unwrap();
+        iso.remove_file("NEW.TXT").unwrap();
+        assert_eq!(read_image(&mut iso), orig);
+
+        // errors
+        let err = iso.remove_file("NEW.TXT").unwrap_err();
+        assert!(err.is::<NotFound>());
+        iso.remove_file("CONTENT/DIR").unwrap_err();
+        iso.remove_file("MISSING/FILE.TXT").unwrap_err();
+    }
+
+    #[test]
+    fn test_free_space_limit() {
+        let mut iso = open_iso();
+        assert_eq!(iso.get_free_space_limit(188).unwrap(), 338);
+
+        // hybrid MBR and GPT, with the backup GPT at the end of the volume
+        let mut system_area = vec![0; 16 * ISO9660_SECTOR_SIZE];
+        system_area[446 + 4] = 0xee;
+        system_area[446 + 8..446 + 12].copy_from_slice(&1u32.to_le_bytes());
+        system_area[510..512].copy_from_slice(&[0x55, 0xaa]);
+        let header = &mut system_area[512..1024];
+        header[..8].copy_from_slice(b"EFI PART");
+        header[32..40].copy_from_slice(&(338u64 * 4 - 1).to_le_bytes());
+        header[72..80].copy_from_slice(&2u64.to_le_bytes());
+        header[80..84].copy_from_slice(&128u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        let write_system_area = |iso: &mut IsoFs, data: &[u8]| {
+            iso.as_file().unwrap().write_all(data).unwrap();
+        };
+        write_system_area(&mut iso, &system_area);
+        // 32 LBAs of backup entries precede the backup header
+        assert_eq!(iso.get_free_space_limit(188).unwrap(), 329);
+        iso.add_file("TOO_BIG", &vec![0; 142 * ISO9660_SECTOR_SIZE])
+            .unwrap_err();
+
+        // a partition after the used space, but not one before it
+        let entries = &mut system_area[1024..];
+        entries[0] = 1;
+        entries[32..40].copy_from_slice(&(250u64 * 4 + 2).to_le_bytes());
+        entries[128] = 1;
+        entries[128 + 32..128 + 40].copy_from_slice(&64u64.to_le_bytes());
+        write_system_area(&mut iso, &system_area);
+        assert_eq!(iso.get_free_space_limit(188).unwrap(), 250);
+        iso.add_file("TOO_BIG", &vec![0; 63 * ISO9660_SECTOR_SIZE])
+            .unwrap_err();
+        iso.add_file("BIG", &vec![0; 62 * ISO9660_SECTOR_SIZE])
+            .unwrap();
+    }
+
+    #[test]
+    fn test_file_matches() {
+        let mut first = open_iso();
+        let mut second = open_iso();
+        second.add_file("NEW.TXT", b"new\n").unwrap();
+        let matches = FileMatches::new(&mut first, &mut second).unwrap();
+        assert_eq!(matches.common.len(), 167);
+        assert!(matches.first_only.is_empty());
+        assert_eq!(matches.second_only.len(), 1);
+        assert_eq!(matches.second_only[0].0, "NEW.TXT");
+        assert_eq!(matches.first_len(), 167);
+        assert_eq!(matches.second_len(), 168);
+    }
+
+    #[test]
+    fn test_boot_catalog() {
+        // no boot record
+        assert!(open_iso().get_boot_catalog().unwrap().is_none());
+
+        let mut catalog = vec![0; ISO9660_SECTOR_SIZE];
+        // validation entry
+        catalog[0] = 1;
+        catalog[4..8].copy_from_slice(b"TEST");
+        catalog[30..32].copy_from_slice(&[0x55, 0xaa]);
+        let sum = catalog[..32].chunks_exact(2).fold(0u16, |sum, w| {
+            sum.wrapping_add(u16::from_le_bytes([w[0], w[1]]))
+        });
+        catalog[28..30].copy_from_slice(&0u16.wrapping_sub(sum).to_le_bytes());
+        // initial entry
+        catalog[32..44].copy_from_slice(&[0x88, 0, 0, 0, 0, 0, 4, 0, 30, 0, 0, 0]);
+        // final section header with an entry and an extension
+        catalog[64..68].copy_from_slice(&[0x91, 0xef, 1, 0]);
+        catalog[96..108].copy_from_slice(&[0x88, 0x20, 0, 0x7c, 0, 0, 0, 0, 40, 0, 0, 0]);
+        catalog[128] = 0x44;
+        let parsed = BootCatalog::parse(&mut Bytes::from(catalog.clone())).unwrap();
+        assert_eq!(parsed.id, "TEST");
+        assert_eq!(parsed.entries.len(), 2);
+        let e = &parsed.entries[0];
+        assert_eq!(e.platform, BootPlatform::X86);
+        assert!(e.bootable);
+        assert_eq!(e.media, BootMedia::NoEmulation);
+        assert_eq!(e.sector_count, 4);
+        assert_eq!(e.load_address.as_sector(), 30);
+        let e = &parsed.entries[1];
+        assert_eq!(e.platform, BootPlatform::Efi);
+        assert_eq!(e.media, BootMedia::NoEmulation);
+        assert_eq!(e.load_segment, 0x7c00);
+        assert_eq!(e.load_address.as_sector(), 40);
+
+        // bad checksum
+        catalog[4] = b'X';
+        BootCatalog::parse(&mut Bytes::from(catalog)).unwrap_err();
+    }
+
     #[test]
     fn test_path_components() {
         // basic
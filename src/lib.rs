@@ -12,7 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod api;
 pub mod blockdev;
+pub mod cache;
 pub mod cmdline;
 pub mod download;
 pub mod install;
@@ -21,7 +23,14 @@ pub mod iso9660;
 pub mod live;
 pub mod miniso;
 pub mod osmet;
+#[cfg(target_arch = "powerpc64")]
+pub mod ppc64le;
+pub mod preflight;
 #[cfg(target_arch = "s390x")]
 pub mod s390x;
+pub mod selector;
+pub mod signal;
 pub mod source;
+#[cfg(test)]
+mod testing;
 pub mod util;
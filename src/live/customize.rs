@@ -16,54 +16,292 @@
 
 use anyhow::{bail, Context, Result};
 use nmstate::NetworkState;
-use serde::Deserialize;
-use serde_json;
-use std::fs::read;
-use std::path::Path;
+use reqwest::Url;
+use std::fmt;
+use std::fs::{metadata, read};
+use std::io::{Read, Seek};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path};
 
 use crate::cmdline::*;
+use crate::download::download_to_tempfile;
 use crate::io::*;
-use crate::iso9660::{self, IsoFs};
+use crate::selector::DeviceSelector;
+use crate::source::RetryPolicy;
 
 use super::embed::{INITRD_IGNITION_PATH, INITRD_NETWORK_DIR};
-use super::util::filename;
-
-pub(super) const INITRD_FEATURES_PATH: &str = "etc/coreos/features.json";
-
-const COREOS_ISO_FEATURES_PATH: &str = "COREOS/FEATURES.JSO";
-
-/// CoreOS feature flags in /etc/coreos/features.json in the live initramfs
-/// and /coreos/features.json in the live ISO.  Written by
-/// cosa buildextend-live.
-#[derive(Default, Deserialize)]
-#[serde(default, rename_all = "kebab-case")]
-pub(super) struct OsFeatures {
-    /// Installer reads config files from /etc/coreos/installer.d
-    pub installer_config: bool,
-    /// Directives supported in installer config files
-    pub installer_config_directives: InstallerDirectives,
-    /// Live initrd reads NM keyfiles from /etc/coreos-firstboot-network
-    pub live_initrd_network: bool,
+use super::features::{Feature, OsFeatures};
+use super::util::{filename, validate_keyfile};
+
+/// Fetch an Ignition config fragment, verifying its digest if specified.
+fn fetch_ignition(url: &PinnedUrl) -> Result<Vec<u8>> {
+    match url.url.scheme() {
+        "https" => (),
+        "http" if url.hash.is_some() => (),
+        "http" => bail!(
+            "refusing to fetch Ignition config over HTTP without a digest: {}",
+            url.url
+        ),
+        _ => bail!("unknown protocol for URL '{}'", url.url),
+    }
+    let mut f = download_to_tempfile(&url.url, RetryPolicy::default())
+        .with_context(|| format!("downloading Ignition config {}", url.url))?;
+    if let Some(hash) = &url.hash {
+        hash.validate(&mut f)
+            .with_context(|| format!("validating digest of Ignition config {}", url.url))?;
+        f.rewind().context("rewinding Ignition config")?;
+    }
+    let mut data = Vec::new();
+    f.read_to_end(&mut data)
+        .with_context(|| format!("reading Ignition config {}", url.url))?;
+    Ok(data)
 }
 
-#[derive(Default, Deserialize)]
-#[serde(default, rename_all = "kebab-case")]
-pub(super) struct InstallerDirectives {
-    pub console: bool,
+/// Read a systemd unit file to be enabled via an Ignition config, returning
+/// its name and contents.
+fn read_unit(path: &str) -> Result<(String, String)> {
+    let name = filename(path)?;
+    let contents = String::from_utf8(read(path).with_context(|| format!("reading {path}"))?)
+        .with_context(|| format!("unit file {path} isn't UTF-8"))?;
+    check_unit(&name, &contents)?;
+    Ok((name, contents))
 }
 
-impl OsFeatures {
-    pub fn for_iso(iso: &mut IsoFs) -> Result<Self> {
-        match iso.get_path(COREOS_ISO_FEATURES_PATH) {
-            Ok(record) => serde_json::from_reader(
-                iso.read_file(&record.try_into_file()?)
-                    .context("reading OS features")?,
-            )
-            .context("parsing OS features"),
-            Err(e) if e.is::<iso9660::NotFound>() => Ok(Self::default()),
-            Err(e) => Err(e).context("looking up OS features"),
+fn check_unit(name: &str, contents: &str) -> Result<()> {
+    const UNIT_TYPES: [&str; 11] = [
+        "service",
+        "socket",
+        "device",
+        "mount",
+        "automount",
+        "swap",
+        "target",
+        "path",
+        "timer",
+        "slice",
+        "scope",
+    ];
+    match name.rsplit_once('.') {
+        Some((base, typ)) if !base.is_empty() && UNIT_TYPES.contains(&typ) => (),
+        _ => bail!("unit filename {name} doesn't have a valid unit type suffix"),
+    }
+    if !contents.lines().any(|l| l.trim() == "[Install]") {
+        bail!("unit {name} has no [Install] section, so it can't be enabled");
+    }
+    Ok(())
+}
+
+/// Convert a templated kernel argument into a double-quoted shell word
+/// which calls the script functions for each placeholder.
+fn karg_template_to_shell(template: &str) -> Result<String> {
+    if template.matches('@').count() % 2 != 0 {
+        bail!("unterminated placeholder");
+    }
+    let mut word = String::from("\"");
+    for (i, part) in template.split('@').enumerate() {
+        if i % 2 == 0 {
+            for c in part.chars() {
+                if matches!(c, '\\' | '"' | '$' | '`') {
+                    word.push('\\');
+                }
+                word.push(c);
+            }
+        } else {
+            let func = match part {
+                "primary-serial" => "primary_serial",
+                "primary-nic" => "primary_nic",
+                _ => bail!("unknown placeholder '@{part}@'"),
+            };
+            word.push_str(&format!("$({func})"));
         }
     }
+    word.push('"');
+    Ok(word)
+}
+
+/// Generate a script that resolves templated dest kargs and writes them
+/// to an installer config file.
+fn karg_template_script(words: &[String]) -> String {
+    format!(
+        r#"#!/bin/bash
+# Generated by coreos-installer {{iso|pxe}} customize
+
+set -euo pipefail
+
+primary_serial() {{
+    local dev
+    for dev in /sys/class/tty/*; do
+        # ports without a UART have type 0
+        if [ -e "${{dev}}/type" ] && [ "$(<"${{dev}}/type")" != 0 ]; then
+            echo "${{dev##*/}}"
+            return
+        fi
+    done
+    echo "No serial port found" >&2
+    exit 1
+}}
+
+primary_nic() {{
+    local dev
+    for dev in /sys/class/net/*; do
+        if [ -e "${{dev}}/device" ]; then
+            echo "${{dev##*/}}"
+            return
+        fi
+    done
+    echo "No network interface found" >&2
+    exit 1
+}}
+
+kargs=(
+{kargs}
+)
+
+q="'"
+{{
+    echo "append-karg:"
+    for karg in "${{kargs[@]}}"; do
+        echo "Appending destination kernel argument: ${{karg}}" >&2
+        echo "  - '${{karg//$q/$q$q}}'"
+    done
+}} > /etc/coreos/installer.d/9999-dest-karg-template.yaml
+"#,
+        kargs = words
+            .iter()
+            .map(|w| format!("    {w}"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    )
+}
+
+/// Generate an iPXE script booting the live kernel with a list of
+/// (name, URL) initramfs images, loaded in order.  iPXE rejoins the
+/// words of the kernel line with single spaces, so quoted kargs survive
+/// unless they contain runs of whitespace; kargs that iPXE would
+/// otherwise change are refused.
+pub(super) fn ipxe_script(
+    kernel_url: &Url,
+    initrds: &[(&str, &Url)],
+    rootfs_url: &Url,
+    kargs: &[String],
+) -> Result<String> {
+    for karg in kargs {
+        if karg.contains("${") {
+            bail!("kernel argument '{karg}' would be expanded as an iPXE setting");
+        }
+        if karg.chars().any(|c| c.is_control()) || karg.contains("  ") {
+            bail!("kernel argument '{karg}' can't be represented in an iPXE script");
+        }
+    }
+    // initrd= is needed for UEFI boot
+    let mut kernel_args: Vec<String> = initrds
+        .iter()
+        .map(|(name, _)| format!("initrd={name}"))
+        .collect();
+    kernel_args.push(format!("coreos.live.rootfs_url={rootfs_url}"));
+    kernel_args.extend(kargs.iter().cloned());
+    let initrd_lines: String = initrds
+        .iter()
+        .map(|(name, url)| format!("initrd --name {name} {url}\n"))
+        .collect();
+    Ok(format!(
+        "#!ipxe
+# Generated by coreos-installer
+kernel {kernel_url} {kernel_args}
+{initrd_lines}boot
+",
+        kernel_args = kernel_args.join(" ")
+    ))
+}
+
+/// Keyfile fields that hold a secret, as (group, key).  NM writes these
+/// to the keyfile in plaintext unless the secret's `-flags` field says
+/// otherwise.
+const KEYFILE_SECRET_FIELDS: &[(&str, &str)] = &[
+    ("wifi-security", "psk"),
+    ("wifi-security", "leap-password"),
+    ("wifi-security", "wep-key0"),
+    ("wifi-security", "wep-key1"),
+    ("wifi-security", "wep-key2"),
+    ("wifi-security", "wep-key3"),
+    ("802-1x", "password"),
+    ("802-1x", "password-raw"),
+    ("802-1x", "pin"),
+    ("802-1x", "private-key-password"),
+    ("802-1x", "phase2-private-key-password"),
+    ("gsm", "password"),
+    ("gsm", "pin"),
+    ("cdma", "password"),
+    ("pppoe", "password"),
+    ("macsec", "mka-cak"),
+    ("wireguard", "private-key"),
+];
+
+/// A keyfile field containing a plaintext secret.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) struct KeyfileSecret {
+    pub keyfile: String,
+    pub group: String,
+    pub key: String,
+}
+
+impl fmt::Display for KeyfileSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: [{}] {}", self.keyfile, self.group, self.key)
+    }
+}
+
+/// Read and validate NetworkManager keyfiles, returning the fields that
+/// embed secrets in plaintext.
+pub(super) fn keyfile_secrets(paths: &[String]) -> Result<Vec<KeyfileSecret>> {
+    let mut secrets = Vec::new();
+    for path in paths {
+        let data = read(path).with_context(|| format!("reading {path}"))?;
+        let name = filename(path)?;
+        validate_keyfile(&data).with_context(|| format!("validating keyfile {name}"))?;
+        // validate_keyfile() checked the encoding
+        let contents = std::str::from_utf8(&data).expect("keyfile is UTF-8");
+        secrets.extend(find_keyfile_secrets(&name, contents));
+    }
+    Ok(secrets)
+}
+
+/// Find secret fields with non-empty values in a validated keyfile.
+fn find_keyfile_secrets(name: &str, contents: &str) -> Vec<KeyfileSecret> {
+    let mut secrets = Vec::new();
+    let mut group = "";
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            group = header.trim();
+            continue;
+        }
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => continue,
+        };
+        if value.is_empty() {
+            continue;
+        }
+        // the reader also accepts full setting names
+        let setting = match group {
+            "802-11-wireless-security" => "wifi-security",
+            group => group,
+        };
+        let secret = setting == "vpn-secrets"
+            || (setting.starts_with("wireguard-peer.") && key == "preshared-key")
+            || KEYFILE_SECRET_FIELDS.contains(&(setting, key));
+        if secret {
+            secrets.push(KeyfileSecret {
+                keyfile: name.to_string(),
+                group: group.to_string(),
+                key: key.to_string(),
+            });
+        }
+    }
+    secrets
 }
 
 #[derive(Default)]
@@ -75,38 +313,70 @@ pub(super) struct LiveInitrd {
     initrd: Initrd,
     /// The Ignition config for the live system
     live: Option<Ignition>,
-    /// The Ignition config for the destination system
-    dest: Option<Ignition>,
-    /// User-supplied Ignition configs for the dest system, which might be
-    /// merged into the dest config or might become the dest config
-    user_dest: Vec<ignition_config::Config>,
+    /// The Ignition config for the destination system, including
+    /// user-supplied configs which might be merged into our own dest config
+    /// or might become the dest config
+    dest: IgnitionBuilder,
     /// The coreos-installer config for our own parameters, excluding custom
     /// configs supplied by the user
     installer: Option<InstallConfig>,
     /// Have the installer copy network configs, if we are running it
     installer_copy_network: bool,
+    /// Templated dest kargs, as shell words to be expanded at boot
+    dest_karg_templates: Vec<String>,
     /// Ignition CAs for the dest system, if it has an Ignition config
     dest_ca: Vec<Vec<u8>>,
 
     /// Prefix for installer config filenames
     installer_serial: u32,
+    /// First installer config prefix not used by the base config
+    first_installer_serial: u32,
+    /// Existing live Ignition config that our config is layered on
+    base_live: Option<Vec<u8>>,
 }
 
 impl LiveInitrd {
     pub fn from_common(common: &CommonCustomizeConfig, features: OsFeatures) -> Result<Self> {
+        Self {
+            features,
+            ..Default::default()
+        }
+        .apply_common(common)
+    }
+
+    /// Layer customizations on top of the live Ignition config of an
+    /// already-customized initrd.  New configs take precedence over the
+    /// base config, and new installer configs are applied after existing
+    /// ones.
+    pub fn from_common_merged(
+        common: &CommonCustomizeConfig,
+        features: OsFeatures,
+        base_live: Option<&[u8]>,
+    ) -> Result<Self> {
         let mut conf = Self {
             features,
             ..Default::default()
         };
+        if let Some(base) = base_live {
+            conf.installer_serial = next_installer_serial(base)?;
+            conf.first_installer_serial = conf.installer_serial;
+            conf.base_live = Some(base.to_vec());
+        }
+        conf.apply_common(common)
+    }
 
+    fn apply_common(mut self, common: &CommonCustomizeConfig) -> Result<Self> {
         for path in &common.dest_ignition {
-            conf.dest_ignition(path)?;
+            self.dest_ignition(path)?;
+        }
+        for url in &common.dest_ignition_url {
+            self.dest_ignition_url(url)?;
         }
         if let Some(path) = &common.dest_device {
-            conf.dest_device(path)?;
+            self.dest_device(path)?;
         }
         for arg in &common.dest_console {
-            conf.dest_console(arg)?;
+            self.dest_console(arg)?;
         }
         Console::maybe_warn_on_kargs(
             &common.dest_karg_append,
@@ -114,58 +384,92 @@ impl LiveInitrd {
             "--dest-console",
         );
         for arg in &common.dest_karg_append {
-            conf.dest_karg_append(arg);
+            self.dest_karg_append(arg);
         }
         for arg in &common.dest_karg_delete {
-            conf.dest_karg_delete(arg);
+            self.dest_karg_delete(arg);
+        }
+        for arg in &common.dest_karg_template {
+            self.dest_karg_template(arg)?;
+        }
+        if common.dest_multipath {
+            self.dest_multipath()?;
+        }
+        for path in &common.dest_unit {
+            self.dest_unit(path)?;
         }
         for path in &common.network_keyfile {
-            conf.network_keyfile(path)?;
+            self.network_keyfile(path)?;
         }
         for path in &common.network_nmstate {
-            conf.network_nmstate(path)?;
+            self.network_nmstate(path)?;
         }
+        self.network_generated(
+            &common.network_bond,
+            &common.network_vlan,
+            &common.network_static,
+        )?;
         for path in &common.ignition_ca {
-            conf.ignition_ca(path)?;
+            self.ignition_ca(path)?;
         }
         for path in &common.pre_install {
-            conf.pre_install(path)?;
+            self.pre_install(path)?;
         }
         for path in &common.post_install {
-            conf.post_install(path)?;
+            self.post_install(path)?;
         }
         for path in &common.installer_config {
-            conf.installer_config(path)?;
+            self.installer_config(path)?;
         }
         for path in &common.live_ignition {
-            conf.live_config(path)?;
+            self.live_config(path)?;
+        }
+        for url in &common.live_ignition_url {
+            self.live_config_url(url)?;
+        }
+        for path in &common.live_unit {
+            self.live_unit(path)?;
+        }
+        for arg in &common.live_file {
+            self.live_file(arg)?;
         }
 
-        Ok(conf)
+        Ok(self)
     }
 
     pub fn dest_ignition(&mut self, path: &str) -> Result<()> {
         let data = read(path).with_context(|| format!("reading {path}"))?;
-        let (config, warnings) = ignition_config::Config::parse_slice(&data)
-            .with_context(|| format!("parsing Ignition config {path}"))?;
+        self.dest_ignition_bytes(path, &data)
+    }
+
+    pub fn dest_ignition_url(&mut self, url: &PinnedUrl) -> Result<()> {
+        let data = fetch_ignition(url)?;
+        self.dest_ignition_bytes(url.url.as_str(), &data)
+    }
+
+    fn dest_ignition_bytes(&mut self, name: &str, data: &[u8]) -> Result<()> {
+        let (config, warnings) = ignition_config::Config::parse_slice(data)
+            .with_context(|| format!("parsing Ignition config {name}"))?;
         for warning in warnings {
-            eprintln!("Warning parsing {path}: {warning}");
+            eprintln!("Warning parsing {name}: {warning}");
         }
-        self.user_dest.push(config);
-        Ok(())
+        self.dest.add_child(&config)
     }
 
     pub fn dest_device(&mut self, device: &str) -> Result<()> {
+        // check the syntax now; selectors are resolved at install time
+        device.parse::<DeviceSelector>()?;
         self.installer
             .get_or_insert_with(Default::default)
-            .dest_device = Some(device.into());
+            .dest_device = vec![device.into()];
         Ok(())
     }
 
     pub fn dest_console(&mut self, console: &Console) -> Result<()> {
-        if !self.features.installer_config_directives.console {
-            bail!("This OS image does not support customizing the destination console.");
-        }
+        self.features.require(
+            Feature::InstallerConsole,
+            "customizing the destination console",
+        )?;
         self.installer
             .get_or_insert_with(Default::default)
             .console
@@ -187,9 +491,64 @@ impl LiveInitrd {
             .push(arg.into());
     }
 
+    pub fn dest_karg_template(&mut self, arg: &str) -> Result<()> {
+        self.features
+            .require(Feature::InstallerConfig, "templated kernel arguments")?;
+        let word = karg_template_to_shell(arg)
+            .with_context(|| format!("parsing kernel argument template '{arg}'"))?;
+        self.dest_karg_templates.push(word);
+        // make sure the installer runs
+        self.installer.get_or_insert_with(Default::default);
+        Ok(())
+    }
+
+    pub fn dest_multipath(&mut self) -> Result<()> {
+        for arg in [
+            "rd.multipath=default",
+            "root=/dev/disk/by-label/dm-mpath-root",
+            "rw",
+        ] {
+            self.dest_karg_append(arg);
+        }
+        // the multipath device needs to exist before the installer runs
+        self.live.get_or_insert_with(Default::default).add_unit(
+            "coreos-installer-multipath.service".into(),
+            "# Generated by coreos-installer {iso|pxe} customize
+
+[Unit]
+Description=Enable Multipath for Installation
+Documentation=https://coreos.github.io/coreos-installer/customizing-install/
+After=coreos-installer-pre.target
+Before=coreos-installer.service
+
+[Service]
+Type=oneshot
+ExecStart=/usr/sbin/mpathconf --enable --with_multipathd y
+ExecStart=/usr/bin/udevadm settle
+RemainAfterExit=true
+StandardOutput=kmsg+console
+StandardError=kmsg+console
+
+[Install]
+RequiredBy=coreos-installer.service"
+                .into(),
+            true,
+        )
+    }
+
+    pub fn dest_unit(&mut self, path: &str) -> Result<()> {
+        let (name, contents) = read_unit(path)?;
+        self.dest.config().add_unit(name, contents, true)
+    }
+
     pub fn network_keyfile(&mut self, path: &str) -> Result<()> {
-        if !self.features.live_initrd_network {
-            bail!("This OS image does not support customizing network settings.");
+        self.features
+            .require(Feature::LiveInitrdNetwork, "customizing network settings")?;
+        // the image is often copied widely, so secrets in it aren't secret
+        for secret in keyfile_secrets(&[path.to_string()])? {
+            eprintln!(
+                "Warning: {secret} is a plaintext secret; anyone with a copy of the image can read it"
+            );
         }
         let data = read(path).with_context(|| format!("reading {path}"))?;
         let name = filename(path)?;
@@ -203,9 +562,8 @@ impl LiveInitrd {
     }
 
     pub fn network_nmstate(&mut self, path: &str) -> Result<()> {
-        if !self.features.live_initrd_network {
-            bail!("This OS image does not support customizing network settings.");
-        }
+        self.features
+            .require(Feature::LiveInitrdNetwork, "customizing network settings")?;
         let net_state_reader = std::fs::File::open(path).context("opening nmstate file")?;
         // Despite of the name the serde_yaml is able to parse JSON too.
         let net_state: NetworkState =
@@ -231,6 +589,30 @@ impl LiveInitrd {
         Ok(())
     }
 
+    pub fn network_generated(
+        &mut self,
+        bonds: &[NetworkBond],
+        vlans: &[NetworkVlan],
+        statics: &[NetworkStatic],
+    ) -> Result<()> {
+        if bonds.is_empty() && vlans.is_empty() && statics.is_empty() {
+            return Ok(());
+        }
+        self.features
+            .require(Feature::LiveInitrdNetwork, "customizing network settings")?;
+        for (name, contents) in network_keyfiles(bonds, vlans, statics)? {
+            validate_keyfile(contents.as_bytes())
+                .with_context(|| format!("validating generated keyfile {name}"))?;
+            let path = format!("{INITRD_NETWORK_DIR}/{name}");
+            if self.initrd.get(&path).is_some() {
+                bail!("config already specifies keyfile {}", name);
+            }
+            self.initrd.add(&path, contents.into_bytes());
+        }
+        self.installer_copy_network = true;
+        Ok(())
+    }
+
     pub fn ignition_ca(&mut self, path: &str) -> Result<()> {
         let data = read(path).with_context(|| format!("reading {path}"))?;
         self.live
@@ -302,15 +684,16 @@ RequiredBy={install_target}",
     pub fn installer_config(&mut self, path: &str) -> Result<()> {
         let data = read(path).with_context(|| format!("reading {path}"))?;
         // we don't validate but at least we parse
-        serde_yaml::from_slice::<InstallConfig>(&data)
+        InstallConfig::from_config_file_data(&data)
             .with_context(|| format!("parsing installer config {path}"))?;
         self.installer_config_bytes(&filename(path)?, &data)
     }
 
     fn installer_config_bytes(&mut self, filename: &str, data: &[u8]) -> Result<()> {
-        if !self.features.installer_config {
-            bail!("This OS image does not support customizing installer configuration.");
-        }
+        self.features.require(
+            Feature::InstallerConfig,
+            "customizing installer configuration",
+        )?;
         self.live.get_or_insert_with(Default::default).add_file(
             format!(
                 "/etc/coreos/installer.d/{:04}-{}",
@@ -325,43 +708,85 @@ RequiredBy={install_target}",
 
     pub fn live_config(&mut self, path: &str) -> Result<()> {
         let data = read(path).with_context(|| format!("reading {path}"))?;
+        self.live_config_bytes(path, &data)
+    }
+
+    pub fn live_config_url(&mut self, url: &PinnedUrl) -> Result<()> {
+        let data = fetch_ignition(url)?;
+        self.live_config_bytes(url.url.as_str(), &data)
+    }
+
+    fn live_config_bytes(&mut self, name: &str, data: &[u8]) -> Result<()> {
         // we don't validate but at least we parse
-        let (config, warnings) = ignition_config::Config::parse_slice(&data)
-            .with_context(|| format!("parsing Ignition config {path}"))?;
+        let (config, warnings) = ignition_config::Config::parse_slice(data)
+            .with_context(|| format!("parsing Ignition config {name}"))?;
         for warning in warnings {
-            eprintln!("Warning parsing {path}: {warning}");
+            eprintln!("Warning parsing {name}: {warning}");
         }
         self.live
             .get_or_insert_with(Default::default)
             .merge_config(&config)
-            .with_context(|| format!("merging Ignition config {path}"))
+            .with_context(|| format!("merging Ignition config {name}"))
+    }
+
+    pub fn live_unit(&mut self, path: &str) -> Result<()> {
+        let (name, contents) = read_unit(path)?;
+        self.live
+            .get_or_insert_with(Default::default)
+            .add_unit(name, contents, true)
+    }
+
+    pub fn live_file(&mut self, arg: &str) -> Result<()> {
+        let (src, dest) = arg
+            .split_once(':')
+            .with_context(|| format!("live file '{arg}' must be in the form SRC:DEST"))?;
+        let dest = Path::new(dest);
+        if !dest.is_absolute() {
+            bail!("live file destination {} must be absolute", dest.display());
+        }
+        if dest
+            .components()
+            .any(|c| !matches!(c, Component::RootDir | Component::Normal(_)))
+        {
+            bail!(
+                "live file destination {} must be a normalized path",
+                dest.display()
+            );
+        }
+        let path = dest
+            .strip_prefix("/")
+            .unwrap()
+            .to_str()
+            .context("converting live file destination to UTF-8")?;
+        if path.is_empty() {
+            bail!("live file destination must be a file");
+        }
+        if path == INITRD_IGNITION_PATH {
+            bail!("use --live-ignition to customize the live Ignition config");
+        }
+        if self.initrd.get(path).is_some() {
+            bail!("config already specifies live file {}", dest.display());
+        }
+        let mode = metadata(src)
+            .with_context(|| format!("getting metadata for {src}"))?
+            .permissions()
+            .mode()
+            & 0o7777;
+        let data = read(src).with_context(|| format!("reading {src}"))?;
+        self.initrd.add_with_mode(path, data, mode);
+        Ok(())
     }
 
     pub fn into_initrd(mut self) -> Result<Initrd> {
-        if self.dest.is_some() || !self.user_dest.is_empty() {
+        if !self.dest.is_empty() {
             // Embed dest config in live and installer configs
 
             // We now know we'll have a dest config, so add CAs to it
             for ca in self.dest_ca.drain(..) {
-                self.dest.get_or_insert_with(Default::default).add_ca(&ca)?;
+                self.dest.config().add_ca(&ca)?;
             }
 
-            let data = if self.dest.is_none() && self.user_dest.len() == 1 {
-                // Special case: the user supplied exactly one dest config
-                // and we didn't add any dest config directives of our own.
-                // Avoid another level of wrapping by embedding the user's
-                // dest config directly.
-                let mut buf = serde_json::to_vec(&self.user_dest.pop().unwrap())
-                    .context("serializing dest Ignition config")?;
-                buf.push(b'\n');
-                buf
-            } else {
-                let dest = self.dest.get_or_insert_with(Default::default);
-                for user_dest in self.user_dest.drain(..) {
-                    dest.merge_config(&user_dest)?;
-                }
-                dest.to_bytes()?
-            };
+            let data = std::mem::take(&mut self.dest).to_bytes()?;
             let conf = self.installer.get_or_insert_with(Default::default);
             assert!(conf.ignition_file.is_none());
             let dest_path = "/etc/coreos/dest.ign";
@@ -373,9 +798,9 @@ RequiredBy={install_target}",
             conf.ignition_file = Some(dest_path.into());
         }
 
-        if self.installer_serial > 0 || self.installer.is_some() {
+        if self.installer_serial > self.first_installer_serial || self.installer.is_some() {
             // The installer will run; apply deferred settings
-            if let Some(device) = self.installer.as_ref().and_then(|c| c.dest_device.as_ref()) {
+            if let Some(device) = self.installer.as_ref().and_then(|c| c.dest_device.first()) {
                 eprintln!(
                     "Boot media will automatically install to {device} without confirmation."
                 );
@@ -389,6 +814,39 @@ RequiredBy={install_target}",
             }
         }
 
+        if !self.dest_karg_templates.is_empty() {
+            // Resolve templated kargs in the live system and pass them to
+            // the installer via a config file
+            let live = self.live.get_or_insert_with(Default::default);
+            live.add_file(
+                "/usr/local/bin/dest-karg-template".into(),
+                karg_template_script(&self.dest_karg_templates).as_bytes(),
+                0o700,
+            )?;
+            live.add_unit(
+                "dest-karg-template.service".into(),
+                "# Generated by coreos-installer {iso|pxe} customize
+
+[Unit]
+Description=Resolve Templated Destination Kernel Arguments
+Documentation=https://coreos.github.io/coreos-installer/customizing-install/
+After=coreos-installer-pre.target
+Before=coreos-installer.service
+
+[Service]
+Type=oneshot
+ExecStart=/usr/local/bin/dest-karg-template
+RemainAfterExit=true
+StandardOutput=kmsg+console
+StandardError=kmsg+console
+
+[Install]
+RequiredBy=coreos-installer.service"
+                    .into(),
+                true,
+            )?;
+        }
+
         if let Some(conf) = self.installer.take() {
             // Embed installer config in live config
             self.installer_config_bytes(
@@ -402,8 +860,166 @@ RequiredBy={install_target}",
         // Embed live config, if we have one, in the initrd.  Avoid embedding
         // an empty config because the ISO disables autologin if it sees one.
         if let Some(live) = self.live {
-            self.initrd.add(INITRD_IGNITION_PATH, live.to_bytes()?);
+            let data = match self.base_live {
+                // merge as siblings so our config overrides the base
+                Some(base) => {
+                    let mut merged = Ignition::default();
+                    merged.merge_bytes(&base)?;
+                    merged.merge_bytes(&live.to_bytes()?)?;
+                    merged.to_bytes()?
+                }
+                None => live.to_bytes()?,
+            };
+            self.initrd.add(INITRD_IGNITION_PATH, data);
         }
         Ok(self.initrd)
     }
 }
+
+/// Find the first installer config prefix not used by a live Ignition
+/// config.
+fn next_installer_serial(live: &[u8]) -> Result<u32> {
+    Ok(config_file_paths(live)
+        .context("reading existing live Ignition config")?
+        .iter()
+        .filter_map(|path| path.strip_prefix("/etc/coreos/installer.d/"))
+        .filter_map(|name| name.split_once('-')?.0.parse::<u32>().ok())
+        .map(|serial| serial + 1)
+        .max()
+        .unwrap_or(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_installer_serial() {
+        let mut child = Ignition::default();
+        child
+            .add_file("/etc/coreos/installer.d/0003-b.yaml".into(), b"", 0o600)
+            .unwrap();
+        let mut live = Ignition::default();
+        live.add_file("/etc/coreos/installer.d/0000-a.yaml".into(), b"", 0o600)
+            .unwrap();
+        live.add_file("/etc/coreos/dest.ign".into(), b"", 0o600)
+            .unwrap();
+        assert_eq!(next_installer_serial(&live.to_bytes().unwrap()).unwrap(), 1);
+        live.merge_bytes(&child.to_bytes().unwrap()).unwrap();
+        assert_eq!(next_installer_serial(&live.to_bytes().unwrap()).unwrap(), 4);
+        assert_eq!(
+            next_installer_serial(&Ignition::default().to_bytes().unwrap()).unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_find_keyfile_secrets() {
+        let keyfile = "[connection]\nid=lab\ntype=wifi\n\n[wifi-security]\nkey-mgmt=wpa-psk\npsk = hunter22\n\n[802-1x]\neap=peap;\nidentity=admin\npassword=\nprivate-key-password-flags=1\n\n[vpn-secrets]\ncert-pass=x\n\n[wireguard-peer.cGVlcg==]\npreshared-key=a2V5\nendpoint=192.0.2.1:51820\n";
+        let secrets: Vec<String> = find_keyfile_secrets("lab.nmconnection", keyfile)
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(
+            secrets,
+            [
+                "lab.nmconnection: [wifi-security] psk",
+                "lab.nmconnection: [vpn-secrets] cert-pass",
+                "lab.nmconnection: [wireguard-peer.cGVlcg==] preshared-key",
+            ]
+        );
+        let keyfile = "[802-11-wireless-security]\nwep-key0=abcde\n";
+        assert_eq!(
+            find_keyfile_secrets("wep.nmconnection", keyfile),
+            [KeyfileSecret {
+                keyfile: "wep.nmconnection".into(),
+                group: "802-11-wireless-security".into(),
+                key: "wep-key0".into(),
+            }]
+        );
+        assert!(find_keyfile_secrets("eth.nmconnection", "[ipv4]\nmethod=auto\n").is_empty());
+    }
+
+    #[test]
+    fn test_ipxe_script() {
+        let url = |path: &str| Url::parse(&format!("http://192.0.2.1:8080/{path}")).unwrap();
+        let (kernel, live, custom, rootfs) = (
+            url("vmlinuz"),
+            url("initrd.img"),
+            url("custom.img"),
+            url("rootfs.img"),
+        );
+        let script = ipxe_script(
+            &kernel,
+            &[("main", &live), ("custom", &custom)],
+            &rootfs,
+            &[
+                "ignition.firstboot".into(),
+                r#"foo="a b""#.into(),
+                "bar='c'".into(),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            script,
+            r#"#!ipxe
+# Generated by coreos-installer
+kernel http://192.0.2.1:8080/vmlinuz initrd=main initrd=custom coreos.live.rootfs_url=http://192.0.2.1:8080/rootfs.img ignition.firstboot foo="a b" bar='c'
+initrd --name main http://192.0.2.1:8080/initrd.img
+initrd --name custom http://192.0.2.1:8080/custom.img
+boot
+"#
+        );
+
+        // no kargs, one initrd
+        assert_eq!(
+            ipxe_script(&kernel, &[("main", &live)], &rootfs, &[]).unwrap(),
+            "#!ipxe
+# Generated by coreos-installer
+kernel http://192.0.2.1:8080/vmlinuz initrd=main coreos.live.rootfs_url=http://192.0.2.1:8080/rootfs.img
+initrd --name main http://192.0.2.1:8080/initrd.img
+boot
+"
+        );
+
+        // kargs iPXE would mangle
+        for bad in ["foo=${net0/mac}", "foo=\"a  b\"", "foo=a\nboot", "foo=\ta"] {
+            ipxe_script(&kernel, &[("main", &live)], &rootfs, &[bad.into()]).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_karg_template_to_shell() {
+        assert_eq!(
+            karg_template_to_shell("console=@primary-serial@,115200n8").unwrap(),
+            r#""console=$(primary_serial),115200n8""#
+        );
+        assert_eq!(
+            karg_template_to_shell("ip=@primary-nic@:dhcp").unwrap(),
+            r#""ip=$(primary_nic):dhcp""#
+        );
+        assert_eq!(
+            karg_template_to_shell(r#"foo="$x`y`\""#).unwrap(),
+            r#""foo=\"\$x\`y\`\\\"""#
+        );
+        karg_template_to_shell("console=@primary-serial").unwrap_err();
+        karg_template_to_shell("console=@foo@").unwrap_err();
+    }
+
+    #[test]
+    fn test_check_unit() {
+        let unit = "[Unit]\nDescription=Inventory\n\n[Service]\nType=oneshot\nExecStart=/usr/bin/true\n\n[Install]\nWantedBy=multi-user.target\n";
+        check_unit("inventory.service", unit).unwrap();
+        check_unit("inventory.timer", unit).unwrap();
+        // bad suffix
+        check_unit("inventory", unit).unwrap_err();
+        check_unit("inventory.conf", unit).unwrap_err();
+        check_unit(".service", unit).unwrap_err();
+        // no [Install] section
+        check_unit(
+            "inventory.service",
+            "[Unit]\nDescription=Inventory\n\n[Service]\nExecStart=/usr/bin/true\n",
+        )
+        .unwrap_err();
+    }
+}
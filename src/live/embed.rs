@@ -23,10 +23,13 @@ use std::io::{copy, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::iter::repeat;
 
 use crate::io::*;
-use crate::iso9660::{self, IsoFs};
+use crate::iso9660::{self, IsoFs, IsoFsChanges};
 
 pub(super) const INITRD_IGNITION_PATH: &str = "config.ign";
 pub(super) const INITRD_NETWORK_DIR: &str = "etc/coreos-firstboot-network";
+/// JSON list of the files added to the ISO9660 filesystem, so they can be
+/// removed again when the customizations are reset
+const INITRD_ISO_FILES_PATH: &str = "etc/coreos/iso-files.json";
 
 lazy_static! {
     pub(super) static ref INITRD_IGNITION_GLOB: GlobMatcher =
@@ -44,9 +47,16 @@ const COREOS_KARG_EMBED_AREA_HEADER_MAX_OFFSETS: usize = 6;
 const COREOS_KARG_EMBED_AREA_MAX_SIZE: usize = 2048;
 const COREOS_KARG_EMBED_INFO_PATH: &str = "COREOS/KARGS.JSO";
 
-pub(super) struct IsoConfig {
+/// The customizable areas of a CoreOS live ISO image: the embedded
+/// initrd containing the Ignition config and network settings, and the
+/// kernel argument embed areas, if any.  Also tracks files to be added to
+/// or removed from the ISO9660 filesystem and changes to the volume ID.
+pub struct IsoConfig {
     initrd: InitrdEmbedArea,
     kargs: Option<KargEmbedAreas>,
+    file_changes: Vec<FileChange>,
+    orig_volume_id: String,
+    volume_id: String,
 }
 
 impl IsoConfig {
@@ -60,6 +70,9 @@ impl IsoConfig {
         Ok(Self {
             initrd: InitrdEmbedArea::for_iso(iso).context("Unrecognized CoreOS ISO image.")?,
             kargs: KargEmbedAreas::for_iso(iso)?,
+            file_changes: Vec::new(),
+            orig_volume_id: iso.get_volume_id()?.to_string(),
+            volume_id: iso.get_volume_id()?.to_string(),
         })
     }
 
@@ -115,6 +128,10 @@ impl IsoConfig {
         self.kargs.is_some()
     }
 
+    pub fn validate_kargs(&self) -> Result<()> {
+        self.unwrap_kargs()?.validate()
+    }
+
     // for debugging
     pub fn kargs_header_json(&self) -> Result<Vec<u8>> {
         let mut ret =
@@ -123,6 +140,15 @@ impl IsoConfig {
         Ok(ret)
     }
 
+    pub fn kargs_header_value(&self) -> Result<serde_json::Value> {
+        serde_json::to_value(self.unwrap_kargs()?).context("failed to serialize kargs header")
+    }
+
+    /// Maximum length of the kernel arguments, in bytes.
+    pub fn kargs_max_len(&self) -> Result<usize> {
+        Ok(self.unwrap_kargs()?.length - 1)
+    }
+
     fn unwrap_kargs(&self) -> Result<&KargEmbedAreas> {
         self.kargs
             .as_ref()
@@ -135,24 +161,212 @@ impl IsoConfig {
             .context("No karg embed areas found; old or corrupted CoreOS ISO image.")
     }
 
+    /// Add a new file to the ISO9660 filesystem when writing.  The
+    /// parent directory must already exist.  The path is recorded in the
+    /// initrd so [`IsoConfig::remove_added_files`] can find it later.
+    pub fn add_file(&mut self, path: &str, contents: Vec<u8>) -> Result<()> {
+        let mut paths = self.added_files()?;
+        paths.push(path.to_string());
+        let list = serde_json::to_vec(&paths).context("serializing added ISO files")?;
+        self.initrd_mut().add(INITRD_ISO_FILES_PATH, list);
+        self.file_changes
+            .push(FileChange::Add(path.to_string(), contents));
+        Ok(())
+    }
+
+    pub fn have_added_files(&self) -> bool {
+        self.initrd().get(INITRD_ISO_FILES_PATH).is_some()
+    }
+
+    /// Remove the files added to the ISO9660 filesystem by earlier
+    /// customizations when writing.
+    pub fn remove_added_files(&mut self) -> Result<()> {
+        for path in self.added_files()? {
+            self.file_changes.push(FileChange::Remove(path));
+        }
+        self.initrd_mut().remove(INITRD_ISO_FILES_PATH);
+        Ok(())
+    }
+
+    fn added_files(&self) -> Result<Vec<String>> {
+        match self.initrd().get(INITRD_ISO_FILES_PATH) {
+            Some(list) => serde_json::from_slice(list).context("decoding added ISO files"),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Work out the filesystem changes for the added and removed files
+    /// without writing them.
+    fn plan_file_changes(&self, iso: &mut IsoFs) -> Result<IsoFsChanges> {
+        let mut changes = IsoFsChanges::default();
+        for change in &self.file_changes {
+            match change {
+                FileChange::Add(path, contents) => {
+                    iso.plan_add_file(&mut changes, path, contents)
+                        .with_context(|| format!("adding {path} to ISO image"))?;
+                }
+                FileChange::Remove(path) => {
+                    match iso.plan_remove_file(&mut changes, path) {
+                        // already removed by hand
+                        Err(e) if e.is::<iso9660::NotFound>() => (),
+                        r => r.with_context(|| format!("removing {path} from ISO image"))?,
+                    }
+                }
+            }
+        }
+        Ok(changes)
+    }
+
+    pub fn volume_id(&self) -> &str {
+        &self.volume_id
+    }
+
+    /// Change the volume ID when writing.  The `coreos.liveiso` karg, which
+    /// the live initramfs uses to find the ISO, is updated to match, in
+    /// both the current and default kargs.
+    pub fn set_volume_id(&mut self, volume_id: &str) -> Result<()> {
+        iso9660::validate_volume_id(volume_id)?;
+        if let Some(kargs) = &mut self.kargs {
+            let replace = [format!("coreos.liveiso={}={}", self.volume_id, volume_id)];
+            let args = KargsEditor::new()
+                .replace(&replace)
+                .apply_to(kargs.kargs())?;
+            kargs.set_kargs(&args)?;
+            kargs.default = KargsEditor::new()
+                .replace(&replace)
+                .apply_to(&kargs.default)?;
+        }
+        self.volume_id = volume_id.to_string();
+        Ok(())
+    }
+
+    /// Write the new volume ID to the volume descriptors, kargs.json, and
+    /// any bootloader configs that search for the volume by label,
+    /// including the one inside the EFI boot image.  Those configs are
+    /// rewritten in place, so a shorter ID is padded with spaces, and a
+    /// longer ID must fit in padding left by an earlier change.
+    fn write_volume_id(&self, iso: &mut IsoFs) -> Result<()> {
+        let mut configs = Vec::new();
+        for (path, file) in iso.files()? {
+            let new = if path.eq_ignore_ascii_case(super::COREOS_ISO_EFIBOOT_IMG) {
+                // the FAT image has its own grub.cfg, which is small
+                // enough to be stored contiguously, so patch the image
+                // directly
+                let mut contents = Vec::new();
+                iso.read_file(&file)?
+                    .read_to_end(&mut contents)
+                    .with_context(|| format!("reading {path}"))?;
+                replace_quoted_label_bytes(&contents, &self.orig_volume_id, &self.volume_id)
+                    .map(|new| (new != contents).then_some(new))
+            } else if path.to_lowercase().ends_with(".cfg") {
+                let mut contents = String::new();
+                if iso.read_file(&file)?.read_to_string(&mut contents).is_err() {
+                    continue;
+                }
+                replace_quoted_label(&contents, &self.orig_volume_id, &self.volume_id)
+                    .map(|new| (new != contents).then_some(new.into_bytes()))
+            } else {
+                continue;
+            };
+            match new {
+                Some(None) => continue,
+                Some(Some(new)) => configs.push((path, file, new)),
+                None => bail!(
+                    "{} refers to volume ID {}; new volume ID is too long",
+                    path,
+                    self.orig_volume_id
+                ),
+            }
+        }
+
+        if let Some(mut info) = KargEmbedInfo::for_iso(iso)? {
+            let default = self.kargs_default()?;
+            if info.default != default {
+                info.default = default.to_string();
+                info.update_iso(iso)?;
+            }
+        }
+        for (path, file, contents) in configs {
+            let mut w = iso.overwrite_file(&file)?;
+            w.write_all(&contents)
+                .with_context(|| format!("updating volume ID in {path}"))?;
+            w.flush().context("flushing ISO")?;
+        }
+        iso.set_volume_id(&self.volume_id)
+    }
+
     pub fn write(&self, file: &mut File) -> Result<()> {
+        if self.volume_id != self.orig_volume_id {
+            let mut iso = IsoFs::from_file(file.try_clone().context("cloning file")?)
+                .context("parsing ISO9660 image")?;
+            self.write_volume_id(&mut iso)?;
+        }
         self.initrd.write(file)?;
         if let Some(kargs) = &self.kargs {
             kargs.write(file)?;
         }
+        if !self.file_changes.is_empty() {
+            let mut iso = IsoFs::from_file(file.try_clone().context("cloning file")?)
+                .context("parsing ISO9660 image")?;
+            let changes = self.plan_file_changes(&mut iso)?;
+            iso.apply_changes(&changes)?;
+        }
         Ok(())
     }
 
     pub fn stream(&self, input: &mut File, writer: &mut (impl Write + ?Sized)) -> Result<()> {
+        if self.volume_id != self.orig_volume_id {
+            // a volume ID change touches files outside the embed areas,
+            // so build the modified image in a temporary file
+            let mut tempfile = tempfile::tempfile().context("creating temporary file")?;
+            input.rewind().context("seeking to start")?;
+            copy(input, &mut tempfile).context("copying input to temporary file")?;
+            self.write(&mut tempfile)?;
+            tempfile.rewind().context("seeking temporary file")?;
+            let mut write_buf = BufWriter::with_capacity(BUFFER_SIZE, writer);
+            copy(
+                &mut BufReader::with_capacity(BUFFER_SIZE, tempfile),
+                &mut write_buf,
+            )
+            .context("copying temporary file")?;
+            write_buf.flush().context("flushing output")?;
+            return Ok(());
+        }
         let initrd_region = self.initrd.region()?;
         let mut regions = vec![&initrd_region];
         if let Some(kargs) = &self.kargs {
             regions.extend(kargs.regions.iter())
         }
+        // file changes only touch directory extents and free space, so
+        // they can be overlaid on the embed areas
+        let file_regions: Vec<Region> = if !self.file_changes.is_empty() {
+            let mut iso = IsoFs::from_file(input.try_clone().context("cloning file")?)
+                .context("parsing ISO9660 image")?;
+            self.plan_file_changes(&mut iso)?
+                .writes()
+                .into_iter()
+                .map(|(offset, contents)| Region {
+                    offset,
+                    length: contents.len(),
+                    contents: contents.to_vec(),
+                    modified: true,
+                    pad: None,
+                    end: None,
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        regions.extend(file_regions.iter());
         regions.stream(input, writer)
     }
 }
 
+enum FileChange {
+    Add(String, Vec<u8>),
+    Remove(String),
+}
+
 #[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd, Serialize)]
 struct Region {
     // sort order is derived from field order
@@ -272,6 +486,7 @@ impl Stream for [&Region] {
 
 #[derive(Serialize)]
 struct KargEmbedAreas {
+    /// Length of the smallest area, which limits the length of the kargs
     length: usize,
     default: String,
 
@@ -285,7 +500,9 @@ struct KargEmbedAreas {
 struct KargEmbedInfo {
     default: String,
     files: Vec<KargEmbedLocation>,
-    size: usize,
+    /// Length of areas that don't specify their own
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<usize>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -293,6 +510,8 @@ struct KargEmbedLocation {
     path: String,
     offset: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pad: Option<char>,
     #[serde(skip_serializing_if = "Option::is_none")]
     end: Option<char>,
@@ -347,25 +566,30 @@ impl KargEmbedAreas {
             None => return Self::for_file_via_system_area(iso.as_file()?),
         };
 
-        // sanity-check size against a reasonable limit
-        if info.size > COREOS_KARG_EMBED_AREA_MAX_SIZE {
-            bail!(
-                "karg embed area size larger than {} (found {})",
-                COREOS_KARG_EMBED_AREA_MAX_SIZE,
-                info.size
-            );
-        }
-        if info.default.len() > info.size {
-            bail!(
-                "default kargs size {} larger than embed areas ({})",
-                info.default.len(),
-                info.size
-            );
-        }
-
         // writable regions
         let mut regions = Vec::new();
         for loc in info.files {
+            let size = loc
+                .size
+                .or(info.size)
+                .with_context(|| format!("no karg embed area size for '{}'", loc.path))?;
+            // sanity-check size against a reasonable limit
+            if size > COREOS_KARG_EMBED_AREA_MAX_SIZE {
+                bail!(
+                    "karg embed area size larger than {} (found {} in '{}')",
+                    COREOS_KARG_EMBED_AREA_MAX_SIZE,
+                    size,
+                    loc.path
+                );
+            }
+            if info.default.len() > size {
+                bail!(
+                    "default kargs size {} larger than embed area in '{}' ({})",
+                    info.default.len(),
+                    loc.path,
+                    size
+                );
+            }
             let iso_file = iso
                 .get_path(&loc.path.to_uppercase())
                 .with_context(|| format!("looking up '{}'", loc.path))?
@@ -376,7 +600,7 @@ impl KargEmbedAreas {
                 Region::read(
                     iso.as_file()?,
                     iso_file.address.as_offset() + loc.offset,
-                    info.size,
+                    size,
                     loc.pad,
                     loc.end,
                 )
@@ -385,7 +609,7 @@ impl KargEmbedAreas {
         }
         regions.sort_unstable_by_key(|r| r.offset);
 
-        Some(Self::build(info.size, info.default, regions)).transpose()
+        Some(Self::build(info.default, regions)).transpose()
     }
 
     fn for_file_via_system_area(file: &mut File) -> Result<Option<Self>> {
@@ -444,14 +668,15 @@ impl KargEmbedAreas {
             );
         }
 
-        Some(Self::build(length, default, regions)).transpose()
+        Some(Self::build(default, regions)).transpose()
     }
 
-    fn build(length: usize, default: String, regions: Vec<Region>) -> Result<Self> {
+    fn build(default: String, regions: Vec<Region>) -> Result<Self> {
         // we expect at least one region
-        if regions.is_empty() {
-            bail!("No karg embed areas found; corrupted CoreOS ISO image.");
-        }
+        let length = match regions.iter().map(|r| r.length).min() {
+            Some(length) => length,
+            None => bail!("No karg embed areas found; corrupted CoreOS ISO image."),
+        };
 
         // parse kargs and verify that all the offsets have the same arguments
         let args = Self::parse(&regions[0])?;
@@ -506,7 +731,7 @@ impl KargEmbedAreas {
             let mut formatted = unformatted.to_string();
             formatted.push(region.end.unwrap_or('\n'));
             let pad = region.pad.unwrap_or('#');
-            let mut contents = vec![pad as u8; self.length];
+            let mut contents = vec![pad as u8; region.length];
             contents[..formatted.len()].copy_from_slice(formatted.as_bytes());
             region.contents = contents.clone();
             region.modified = true;
@@ -515,6 +740,40 @@ impl KargEmbedAreas {
         Ok(())
     }
 
+    /// Check that the current kargs fit in the embed areas and that every
+    /// area contains the kargs followed by the expected padding.
+    pub fn validate(&self) -> Result<()> {
+        if self.args.len() >= self.length {
+            bail!(
+                "kargs too large for area: {} vs {}",
+                self.args.len() + 1,
+                self.length
+            );
+        }
+        for region in &self.regions {
+            region.validate()?;
+            let pad = region.pad.unwrap_or('#') as u8;
+            let end = region.end.unwrap_or('\n') as u8;
+            // skip the kargs and any whitespace around them
+            let trailer = region
+                .contents
+                .iter()
+                .skip_while(|c| c.is_ascii_whitespace())
+                .skip(self.args.len())
+                .skip_while(|c| **c == b' ');
+            if trailer
+                .skip_while(|c| **c == end)
+                .any(|c| *c != pad && *c != end)
+            {
+                bail!(
+                    "unexpected data after kargs in embed area at offset {}",
+                    region.offset
+                );
+            }
+        }
+        Ok(())
+    }
+
     pub fn write(&self, file: &mut File) -> Result<()> {
         for region in &self.regions {
             region.write(file)?;
@@ -615,6 +874,40 @@ impl InitrdEmbedArea {
     }
 }
 
+/// Replace every double-quoted occurrence of a label in a config file
+/// without changing the file length.  Spaces following the closing quote
+/// (except the last one) are treated as room for a longer label, and a
+/// shorter label is padded with spaces.  Returns None if the new label
+/// doesn't fit.
+fn replace_quoted_label(contents: &str, old: &str, new: &str) -> Option<String> {
+    let old = format!("\"{old}\"");
+    let mut ret = String::with_capacity(contents.len());
+    let mut rest = contents;
+    while let Some(start) = rest.find(&old) {
+        ret.push_str(&rest[..start]);
+        rest = &rest[start + old.len()..];
+        let spaces = rest.len() - rest.trim_start_matches(' ').len();
+        let room = old.len() + spaces.saturating_sub(1);
+        let quoted = format!("\"{new}\"");
+        if quoted.len() > room {
+            return None;
+        }
+        ret.push_str(&format!("{quoted:room$}"));
+        rest = &rest[spaces.saturating_sub(1)..];
+    }
+    ret.push_str(rest);
+    Some(ret)
+}
+
+/// Like replace_quoted_label(), but for binary data, such as a filesystem
+/// image containing a config file.
+fn replace_quoted_label_bytes(contents: &[u8], old: &str, new: &str) -> Option<Vec<u8>> {
+    // map each byte to one char so that offsets are preserved
+    let text: String = contents.iter().map(|&b| char::from(b)).collect();
+    let replaced = replace_quoted_label(&text, old, new)?;
+    Some(replaced.chars().map(|c| c as u8).collect())
+}
+
 // only for miniso generation
 pub(super) fn set_default_kargs(iso: &mut IsoFs, default: String) -> Result<()> {
     let mut kargs_info = KargEmbedInfo::for_iso(iso)?.context(
@@ -682,6 +975,130 @@ mod tests {
         assert!(KargEmbedAreas::for_iso(&mut iso).unwrap().is_none());
     }
 
+    #[test]
+    fn test_karg_embed_area_sizes() {
+        let default = "mitigations=auto,nosmt coreos.liveiso=fedora-coreos-34.20210921.dev.0 ignition.firstboot ignition.platform.id=metal";
+        let mut iso_file = open_iso_file();
+        // GRUB area shorter than the default size
+        write_karg_embed_info(
+            &mut iso_file,
+            &format!(
+                r#"{{"default":"{default}","files":[{{"path":"EFI/fedora/grub.cfg","offset":970,"size":1000}},{{"path":"isolinux/isolinux.cfg","offset":1870}}],"size":1139}}"#
+            ),
+        );
+        let mut iso = IsoFs::from_file(iso_file.try_clone().unwrap()).unwrap();
+        let mut areas = KargEmbedAreas::for_iso(&mut iso).unwrap().unwrap();
+        assert_eq!(areas.length, 1000);
+        assert_eq!(areas.regions[0].length, 1000);
+        assert_eq!(areas.regions[1].length, 1139);
+        assert_eq!(areas.kargs(), default);
+        areas.set_kargs(&"a".repeat(1000)).unwrap_err();
+        areas.set_kargs(&"a".repeat(999)).unwrap();
+        assert_eq!(areas.regions[0].contents.len(), 1000);
+        assert_eq!(areas.regions[1].contents.len(), 1139);
+        areas.validate().unwrap();
+
+        // GRUB area only, with no default size
+        write_karg_embed_info(
+            &mut iso_file,
+            &format!(
+                r#"{{"default":"{default}","files":[{{"path":"EFI/fedora/grub.cfg","offset":970,"size":1139}}]}}"#
+            ),
+        );
+        let mut iso = IsoFs::from_file(iso_file.try_clone().unwrap()).unwrap();
+        let areas = KargEmbedAreas::for_iso(&mut iso).unwrap().unwrap();
+        assert_eq!(areas.length, 1139);
+        assert_eq!(areas.regions.len(), 1);
+        assert_eq!(areas.regions[0].offset, 98126);
+
+        // no size for an area
+        write_karg_embed_info(
+            &mut iso_file,
+            &format!(
+                r#"{{"default":"{default}","files":[{{"path":"EFI/fedora/grub.cfg","offset":970}}]}}"#
+            ),
+        );
+        let mut iso = IsoFs::from_file(iso_file.try_clone().unwrap()).unwrap();
+        KargEmbedAreas::for_iso(&mut iso).unwrap_err();
+
+        // area too small for the default kargs
+        write_karg_embed_info(
+            &mut iso_file,
+            &format!(
+                r#"{{"default":"{default}","files":[{{"path":"EFI/fedora/grub.cfg","offset":970,"size":100}}]}}"#
+            ),
+        );
+        let mut iso = IsoFs::from_file(iso_file).unwrap();
+        KargEmbedAreas::for_iso(&mut iso).unwrap_err();
+    }
+
+    #[test]
+    fn test_volume_id() {
+        let mut iso_file = open_iso_file();
+        let mut config = IsoConfig::for_file(&mut iso_file).unwrap();
+        assert_eq!(config.volume_id(), "fedora-coreos-34.20210921.dev.0");
+        config.set_volume_id("bad label").unwrap_err();
+        config.set_volume_id("relabeled").unwrap();
+        let expected = "mitigations=auto,nosmt coreos.liveiso=relabeled ignition.firstboot ignition.platform.id=metal";
+        assert_eq!(config.kargs().unwrap(), expected);
+        assert_eq!(config.kargs_default().unwrap(), expected);
+        config.write(&mut iso_file).unwrap();
+
+        let config = IsoConfig::for_file(&mut iso_file).unwrap();
+        assert_eq!(config.volume_id(), "relabeled");
+        assert_eq!(config.kargs().unwrap(), expected);
+        assert_eq!(config.kargs_default().unwrap(), expected);
+        config.validate_kargs().unwrap();
+    }
+
+    #[test]
+    fn test_replace_quoted_label() {
+        let cfg =
+            "search --label \"old-label\" --set root\nlinux /vmlinuz coreos.liveiso=old-label\n";
+        let shorter = replace_quoted_label(cfg, "old-label", "new").unwrap();
+        assert_eq!(
+            shorter,
+            "search --label \"new\"       --set root\nlinux /vmlinuz coreos.liveiso=old-label\n"
+        );
+        // padding is reused for a longer label
+        assert_eq!(
+            replace_quoted_label(&shorter, "new", "old-label").unwrap(),
+            cfg
+        );
+        assert_eq!(replace_quoted_label(&shorter, "new", "old-label-x"), None);
+        assert_eq!(replace_quoted_label(cfg, "old-label", "old-label-x"), None);
+        assert_eq!(replace_quoted_label(cfg, "other", "x").unwrap(), cfg);
+
+        // binary data around the label is preserved
+        let mut image = vec![0xebu8, 0x3c, 0x90, 0xff, 0];
+        image.extend(cfg.as_bytes());
+        image.extend([0xffu8, 0]);
+        let relabeled = replace_quoted_label_bytes(&image, "old-label", "new").unwrap();
+        assert_eq!(relabeled.len(), image.len());
+        assert_eq!(&relabeled[..5], &image[..5]);
+        assert_eq!(&relabeled[5..relabeled.len() - 2], shorter.as_bytes());
+        assert_eq!(&relabeled[relabeled.len() - 2..], &image[image.len() - 2..]);
+        assert_eq!(
+            replace_quoted_label_bytes(&image, "old-label", "old-label-x"),
+            None
+        );
+    }
+
+    /// Replace kargs.json, padding with spaces to its original length.
+    fn write_karg_embed_info(iso_file: &mut File, json: &str) {
+        let mut iso = IsoFs::from_file(iso_file.try_clone().unwrap()).unwrap();
+        let file = iso
+            .get_path(COREOS_KARG_EMBED_INFO_PATH)
+            .unwrap()
+            .try_into_file()
+            .unwrap();
+        let mut contents = vec![b' '; file.length as usize];
+        contents[..json.len()].copy_from_slice(json.as_bytes());
+        let mut w = iso.overwrite_file(&file).unwrap();
+        w.write_all(&contents).unwrap();
+        w.flush().unwrap();
+    }
+
     fn check_karg_embed_areas(iso_file: &mut File) {
         let iso_file = iso_file.try_clone().unwrap();
         let mut iso = IsoFs::from_file(iso_file).unwrap();
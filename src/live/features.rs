@@ -0,0 +1,173 @@
+// Copyright 2024 Red Hat, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Capabilities of a CoreOS live image, as advertised by the image build.
+//!
+//! An image declares a feature level, which implies every feature
+//! introduced at or below that level, plus any features it supports
+//! individually by name.  Older images instead set a boolean for each
+//! feature; those are still honored.  Customizations check for the
+//! features they need through `OsFeatures::require()`, so an unsupported
+//! image fails with a consistent message naming the required level.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::io::Initrd;
+use crate::iso9660::{self, IsoFs};
+
+pub(super) const INITRD_FEATURES_PATH: &str = "etc/coreos/features.json";
+
+const COREOS_ISO_FEATURES_PATH: &str = "COREOS/FEATURES.JSO";
+
+/// A capability of the live image that some customizations depend on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Feature {
+    /// Installer reads config files from /etc/coreos/installer.d
+    InstallerConfig,
+    /// Live initrd reads NM keyfiles from /etc/coreos-firstboot-network
+    LiveInitrdNetwork,
+    /// Installer config files support the console directive
+    InstallerConsole,
+}
+
+impl Feature {
+    /// Name of the feature in the features list
+    fn name(&self) -> &'static str {
+        match self {
+            Self::InstallerConfig => "installer-config",
+            Self::LiveInitrdNetwork => "live-initrd-network",
+            Self::InstallerConsole => "installer-console",
+        }
+    }
+
+    /// Feature level at which all images support the feature
+    fn level(&self) -> u32 {
+        match self {
+            Self::InstallerConfig | Self::LiveInitrdNetwork => 1,
+            Self::InstallerConsole => 2,
+        }
+    }
+}
+
+/// CoreOS feature flags in /etc/coreos/features.json in the live initramfs
+/// and /coreos/features.json in the live ISO.  Written by
+/// cosa buildextend-live.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub(super) struct OsFeatures {
+    /// Feature level of the image
+    pub feature_level: u32,
+    /// Features supported beyond the feature level
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub features: Vec<String>,
+
+    // legacy flags, from images that predate feature levels
+    /// Installer reads config files from /etc/coreos/installer.d
+    pub installer_config: bool,
+    /// Directives supported in installer config files
+    pub installer_config_directives: InstallerDirectives,
+    /// Live initrd reads NM keyfiles from /etc/coreos-firstboot-network
+    pub live_initrd_network: bool,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub(super) struct InstallerDirectives {
+    pub console: bool,
+}
+
+impl OsFeatures {
+    pub fn for_iso(iso: &mut IsoFs) -> Result<Self> {
+        match iso.get_path(COREOS_ISO_FEATURES_PATH) {
+            Ok(record) => serde_json::from_reader(
+                iso.read_file(&record.try_into_file()?)
+                    .context("reading OS features")?,
+            )
+            .context("parsing OS features"),
+            Err(e) if e.is::<iso9660::NotFound>() => Ok(Self::default()),
+            Err(e) => Err(e).context("looking up OS features"),
+        }
+    }
+
+    pub fn for_initrd(initrd: &Initrd) -> Result<Self> {
+        match initrd.get(INITRD_FEATURES_PATH) {
+            Some(json) => serde_json::from_slice(json).context("parsing OS features"),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn has(&self, feature: Feature) -> bool {
+        let legacy = match feature {
+            Feature::InstallerConfig => self.installer_config,
+            Feature::LiveInitrdNetwork => self.live_initrd_network,
+            Feature::InstallerConsole => self.installer_config_directives.console,
+        };
+        legacy
+            || self.feature_level >= feature.level()
+            || self.features.iter().any(|f| f == feature.name())
+    }
+
+    /// Fail if the image doesn't support the feature needed for the
+    /// specified customization.
+    pub fn require(&self, feature: Feature, what: &str) -> Result<()> {
+        if !self.has(feature) {
+            bail!(
+                "This OS image does not support {}; requires OS image feature level >= {} or the \"{}\" feature.",
+                what,
+                feature.level(),
+                feature.name()
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_os_features() {
+        let parse = |json: &str| serde_json::from_str::<OsFeatures>(json).unwrap();
+
+        let features = parse("{}");
+        assert!(!features.has(Feature::InstallerConfig));
+        let err = features
+            .require(Feature::LiveInitrdNetwork, "customizing network settings")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("does not support customizing network settings"));
+        assert!(err.contains("feature level >= 1"));
+
+        let features = parse(
+            r#"{"installer-config": true, "installer-config-directives": {"console": true}}"#,
+        );
+        assert!(features.has(Feature::InstallerConfig));
+        assert!(features.has(Feature::InstallerConsole));
+        assert!(!features.has(Feature::LiveInitrdNetwork));
+
+        let features = parse(r#"{"feature-level": 1, "features": ["installer-console"]}"#);
+        assert!(features.has(Feature::LiveInitrdNetwork));
+        assert!(features.has(Feature::InstallerConsole));
+
+        let features = parse(r#"{"feature-level": 2, "features": ["future-thing"]}"#);
+        features
+            .require(
+                Feature::InstallerConsole,
+                "customizing the destination console",
+            )
+            .unwrap();
+    }
+}
@@ -14,12 +14,16 @@
 
 use anyhow::{bail, Context, Result};
 use lazy_static::lazy_static;
+use openssl::sha;
+use reqwest::Url;
 use serde::Serialize;
-use std::collections::HashMap;
-use std::fs::{create_dir_all, read, File, OpenOptions};
+use std::fs::{create_dir_all, read, write, File, OpenOptions};
 use std::io::{self, copy, BufReader, BufWriter, Read, Seek, Write};
+use std::os::unix::io::AsRawFd;
 use std::path::{Component, Path, PathBuf};
+use std::process::Command;
 
+use crate::blockdev::{flush_buffer_cache, get_block_device_size};
 use crate::cmdline::*;
 use crate::io::*;
 use crate::iso9660::{self, IsoFs};
@@ -28,16 +32,26 @@ use crate::util::set_die_on_sigpipe;
 
 mod customize;
 mod embed;
+mod features;
+mod manifest;
+mod serve;
 mod util;
 
 use self::customize::*;
 use self::embed::*;
+use self::features::*;
+use self::manifest::*;
 use self::util::*;
 
+pub use self::embed::IsoConfig;
+pub use self::serve::dev_serve_iso;
+
 const INITRD_LIVE_STAMP_PATH: &str = "etc/coreos-live-initramfs";
 const COREOS_ISO_PXEBOOT_DIR: &str = "IMAGES/PXEBOOT";
 const COREOS_ISO_ROOTFS_IMG: &str = "IMAGES/PXEBOOT/ROOTFS.IMG";
+const COREOS_ISO_INITRD_IMG: &str = "IMAGES/PXEBOOT/INITRD.IMG";
 const COREOS_ISO_MINISO_FILE: &str = "COREOS/MINISO.DAT";
+const COREOS_ISO_EFIBOOT_IMG: &str = "IMAGES/EFIBOOT.IMG";
 
 lazy_static! {
     static ref ALL_GLOB: GlobMatcher = GlobMatcher::new(&["*"]).unwrap();
@@ -47,7 +61,8 @@ pub fn iso_embed(config: IsoEmbedConfig) -> Result<()> {
     eprintln!("`iso embed` is deprecated; use `iso ignition embed`.  Continuing.");
     iso_ignition_embed(IsoIgnitionEmbedConfig {
         force: config.force,
-        ignition_file: config.config,
+        ignition_file: config.config.into_iter().collect(),
+        insecure: false,
         output: config.output,
         input: config.input,
     })
@@ -56,6 +71,8 @@ pub fn iso_embed(config: IsoEmbedConfig) -> Result<()> {
 pub fn iso_show(config: IsoShowConfig) -> Result<()> {
     eprintln!("`iso show` is deprecated; use `iso ignition show`.  Continuing.");
     iso_ignition_show(IsoIgnitionShowConfig {
+        pretty: false,
+        validate: false,
         input: config.input,
     })
 }
@@ -69,11 +86,8 @@ pub fn iso_remove(config: IsoRemoveConfig) -> Result<()> {
 }
 
 pub fn iso_ignition_embed(config: IsoIgnitionEmbedConfig) -> Result<()> {
-    let ignition = match &config.ignition_file {
-        Some(ignition_path) => {
-            read(ignition_path).with_context(|| format!("reading {ignition_path}"))?
-        }
-        None => {
+    let ignition = match config.ignition_file.as_slice() {
+        [] => {
             let mut data = Vec::new();
             io::stdin()
                 .lock()
@@ -81,9 +95,29 @@ pub fn iso_ignition_embed(config: IsoIgnitionEmbedConfig) -> Result<()> {
                 .context("reading stdin")?;
             data
         }
+        [ignition_path] => {
+            read(ignition_path).with_context(|| format!("reading {ignition_path}"))?
+        }
+        ignition_paths => {
+            // merge all configs into a wrapper, in the order specified
+            let mut wrapper = Ignition::default();
+            for path in ignition_paths {
+                let data = read(path).with_context(|| format!("reading {path}"))?;
+                let (config, warnings) = ignition_config::Config::parse_slice(&data)
+                    .with_context(|| format!("parsing Ignition config {path}"))?;
+                for warning in warnings {
+                    eprintln!("Warning parsing {path}: {warning}");
+                }
+                wrapper
+                    .merge_config(&config)
+                    .with_context(|| format!("merging Ignition config {path}"))?;
+            }
+            wrapper.to_bytes()?
+        }
     };
 
-    let mut iso_file = open_live_iso(&config.input, Some(config.output.as_ref()))?;
+    let mut iso_file =
+        open_live_iso_or_url(&config.input, config.output.as_ref(), config.insecure)?;
     let mut iso = IsoConfig::for_file(&mut iso_file)?;
 
     if !config.force && iso.have_ignition() {
@@ -102,18 +136,48 @@ pub fn iso_ignition_show(config: IsoIgnitionShowConfig) -> Result<()> {
     if !iso.have_ignition() {
         bail!("No embedded Ignition config.");
     }
+    let data = iso
+        .initrd()
+        .get(INITRD_IGNITION_PATH)
+        .context("couldn't find Ignition config in archive")?;
+    if config.validate {
+        validate_ignition(data).context("validating embedded Ignition config")?;
+    }
     let stdout = io::stdout();
     let mut out = stdout.lock();
-    out.write_all(
-        iso.initrd()
-            .get(INITRD_IGNITION_PATH)
-            .context("couldn't find Ignition config in archive")?,
-    )
-    .context("writing output")?;
+    if config.pretty {
+        let value: serde_json::Value =
+            serde_json::from_slice(data).context("parsing embedded Ignition config")?;
+        serde_json::to_writer_pretty(&mut out, &value).context("writing output")?;
+        out.write_all(b"\n").context("writing output")?;
+    } else {
+        out.write_all(data).context("writing output")?;
+    }
     out.flush().context("flushing output")?;
     Ok(())
 }
 
+/// Parse an Ignition config as Ignition would, and report its spec version
+/// and any warnings on stderr.
+fn validate_ignition(data: &[u8]) -> Result<()> {
+    let (_, warnings) =
+        ignition_config::Config::parse_slice(data).context("parsing Ignition config")?;
+    for warning in &warnings {
+        eprintln!("Warning: {warning}");
+    }
+    let value: serde_json::Value =
+        serde_json::from_slice(data).context("parsing Ignition config")?;
+    let version = value
+        .pointer("/ignition/version")
+        .and_then(|v| v.as_str())
+        .unwrap_or("unknown");
+    eprintln!(
+        "Ignition config: OK (spec version {version}, {} warning(s))",
+        warnings.len()
+    );
+    Ok(())
+}
+
 pub fn iso_ignition_remove(config: IsoIgnitionRemoveConfig) -> Result<()> {
     let mut iso_file = open_live_iso(&config.input, Some(config.output.as_ref()))?;
     let mut iso = IsoConfig::for_file(&mut iso_file)?;
@@ -123,15 +187,116 @@ pub fn iso_ignition_remove(config: IsoIgnitionRemoveConfig) -> Result<()> {
     write_live_iso(&iso, &mut iso_file, config.output.as_ref())
 }
 
-pub fn iso_network_embed(config: IsoNetworkEmbedConfig) -> Result<()> {
+pub fn iso_ignition_edit(config: IsoIgnitionEditConfig) -> Result<()> {
+    if config.output.as_deref() == Some("-") {
+        bail!("cannot write ISO to stdout while running an editor");
+    }
     let mut iso_file = open_live_iso(&config.input, Some(config.output.as_ref()))?;
+    let mut iso = IsoConfig::for_file(&mut iso_file)?;
+    let orig = iso
+        .initrd()
+        .get(INITRD_IGNITION_PATH)
+        .context("No embedded Ignition config; use `iso ignition embed` to add one.")?
+        .to_vec();
+
+    let mut file = tempfile::Builder::new()
+        .prefix("coreos-installer-")
+        .suffix(".ign")
+        .tempfile()
+        .context("creating temporary file")?;
+    file.write_all(&orig).context("writing temporary file")?;
+    file.flush().context("flushing temporary file")?;
+    let edited = loop {
+        run_editor(file.path())?;
+        let data = read(file.path()).context("reading edited config")?;
+        if data == orig {
+            eprintln!("Ignition config unchanged.");
+            return Ok(());
+        }
+        match validate_ignition(&data) {
+            Ok(()) => break data,
+            Err(e) => {
+                eprintln!("Error: {e:?}");
+                if !prompt_yes_no("Edit again?")? {
+                    bail!("edited Ignition config is invalid; ISO image not modified");
+                }
+            }
+        }
+    };
+
+    iso.initrd_mut().add(INITRD_IGNITION_PATH, edited);
+    write_live_iso(&iso, &mut iso_file, config.output.as_ref())
+}
+
+/// Run the user's editor on the specified file, choosing it as virsh edit
+/// does.  Empty variables are treated as unset.
+fn run_editor(path: &Path) -> Result<()> {
+    let editor = ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.trim().is_empty())
+        .unwrap_or_else(|| "vi".into());
+    // run via the shell, since the editor may include arguments
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$1\""))
+        .arg("sh")
+        .arg(path)
+        .status()
+        .with_context(|| format!("running editor {editor}"))?;
+    if !status.success() {
+        bail!("editor {} failed with {}", editor, status);
+    }
+    Ok(())
+}
+
+/// Ask a yes/no question on the terminal, defaulting to no.
+fn prompt_yes_no(question: &str) -> Result<bool> {
+    eprint!("{question} [y/N] ");
+    io::stderr().flush().context("flushing stderr")?;
+    let mut answer = String::new();
+    io::stdin()
+        .read_line(&mut answer)
+        .context("reading answer")?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+pub fn iso_network_embed(config: IsoNetworkEmbedConfig) -> Result<()> {
+    // the ISO is often copied widely, so secrets in it aren't secret
+    let secrets = keyfile_secrets(&config.keyfile)?;
+    if config.redact_check {
+        for secret in &secrets {
+            println!("{secret}");
+        }
+        return Ok(());
+    }
+    if config.strict && !secrets.is_empty() {
+        bail!(
+            "Keyfiles contain plaintext secrets:\n  {}\nRemove them, or set the secrets' -flags fields to have NetworkManager ask for them.",
+            secrets
+                .iter()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+                .join("\n  ")
+        );
+    }
+    for secret in &secrets {
+        eprintln!(
+            "Warning: {secret} is a plaintext secret; anyone with a copy of the ISO can read it"
+        );
+    }
+
+    let input = config
+        .input
+        .as_ref()
+        .expect("ISO is required unless --redact-check is specified");
+    let mut iso_file = open_live_iso(input, Some(config.output.as_ref()))?;
     let mut iso_fs = IsoFs::from_file(iso_file.try_clone().context("cloning file")?)
         .context("parsing ISO9660 image")?;
     let mut iso = IsoConfig::for_iso(&mut iso_fs)?;
 
-    if !OsFeatures::for_iso(&mut iso_fs)?.live_initrd_network {
-        bail!("This OS image does not support customizing network settings.");
-    }
+    OsFeatures::for_iso(&mut iso_fs)?
+        .require(Feature::LiveInitrdNetwork, "customizing network settings")?;
     if !config.force && iso.have_network() {
         bail!("This ISO image already has embedded network settings; use -f to force.");
     }
@@ -287,11 +452,15 @@ pub fn iso_kargs_modify(config: IsoKargsModifyConfig) -> Result<()> {
     let mut iso_file = open_live_iso(&config.input, Some(config.output.as_ref()))?;
     let mut iso = IsoConfig::for_file(&mut iso_file)?;
 
-    let kargs = KargsEditor::new()
+    let mut editor = KargsEditor::new();
+    editor
         .append(&config.append)
         .replace(&config.replace)
-        .delete(&config.delete)
-        .apply_to(iso.kargs()?)?;
+        .delete(&config.delete);
+    if config.dedup {
+        editor.dedup();
+    }
+    let kargs = editor.apply_to(iso.kargs()?)?;
     iso.set_kargs(&kargs)?;
 
     write_live_iso(&iso, &mut iso_file, config.output.as_ref())
@@ -307,21 +476,107 @@ pub fn iso_kargs_reset(config: IsoKargsResetConfig) -> Result<()> {
     write_live_iso(&iso, &mut iso_file, config.output.as_ref())
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct IsoKargsShowOutput<'a> {
+    kargs: &'a str,
+    default: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    architecture: Option<&'static str>,
+    max_length: usize,
+    remaining: usize,
+    embed_area: serde_json::Value,
+}
+
 pub fn iso_kargs_show(config: IsoKargsShowConfig) -> Result<()> {
     set_die_on_sigpipe()?;
-    let mut iso_file = open_live_iso(&config.input, None)?;
-    let iso = IsoConfig::for_file(&mut iso_file)?;
-    let kargs = if config.default {
-        iso.kargs_default()?
-    } else {
-        iso.kargs()?
-    };
+    let mut iso_fs =
+        IsoFs::from_file(open_live_iso(&config.input, None)?).context("parsing ISO9660 image")?;
+    let iso = IsoConfig::for_iso(&mut iso_fs)?;
+    match config.format {
+        OutputFormat::Json => {
+            if config.default {
+                bail!("--default can't be used with JSON output, which includes the default kargs");
+            }
+            let kargs = iso.kargs()?;
+            let max_length = iso.kargs_max_len()?;
+            let output = IsoKargsShowOutput {
+                kargs,
+                default: iso.kargs_default()?,
+                architecture: iso_architecture(&mut iso_fs)?,
+                max_length,
+                remaining: max_length.saturating_sub(kargs.trim().len()),
+                embed_area: iso.kargs_header_value()?,
+            };
+            let stdout = io::stdout();
+            let mut out = stdout.lock();
+            serde_json::to_writer_pretty(&mut out, &output).context("failed to serialize kargs")?;
+            out.write_all(b"\n").context("failed to write newline")?;
+        }
+        OutputFormat::Text => {
+            let kargs = if config.default {
+                iso.kargs_default()?
+            } else {
+                iso.kargs()?
+            };
+            println!("{kargs}");
+        }
+    }
+    Ok(())
+}
+
+pub fn pxe_kargs_modify(config: PxeKargsModifyConfig) -> Result<()> {
+    set_die_on_sigpipe()?;
+    let kargs = pxe_kargs_for_iso(&config.input, config.rootfs_url.as_ref())?;
+    let kargs = KargsEditor::new()
+        .append(&config.append)
+        .replace(&config.replace)
+        .delete(&config.delete)
+        .apply_to(&kargs)?;
+    println!("{kargs}");
+    Ok(())
+}
+
+pub fn pxe_kargs_show(config: PxeKargsShowConfig) -> Result<()> {
+    set_die_on_sigpipe()?;
+    let kargs = pxe_kargs_for_iso(&config.input, config.rootfs_url.as_ref())?;
     println!("{kargs}");
     Ok(())
 }
 
+fn pxe_kargs_for_iso(path: &str, rootfs_url: Option<&Url>) -> Result<String> {
+    let mut iso = IsoFs::from_file(open_live_iso(path, None)?)?;
+    let kargs = pxe_default_kargs(&mut iso)?;
+    match rootfs_url {
+        Some(url) => KargsEditor::new()
+            .append(&[format!("coreos.live.rootfs_url={url}")])
+            .apply_to(&kargs),
+        None => Ok(kargs),
+    }
+}
+
+/// Default kargs for PXE booting the artifacts of a live ISO: the ISO's
+/// default kargs from kargs.json, without coreos.liveiso.
+fn pxe_default_kargs(iso: &mut IsoFs) -> Result<String> {
+    let kargs = KargEmbedAreas::for_iso(iso)?
+        .context("This ISO image doesn't record its default kernel arguments.")?;
+    without_liveiso_karg(kargs.kargs_default())
+}
+
+/// Drop the coreos.liveiso karg, since PXE boots don't have an ISO
+/// volume to look for.
+fn without_liveiso_karg(kargs: &str) -> Result<String> {
+    let liveiso: Vec<String> = kargs
+        .split_ascii_whitespace()
+        .filter(|karg| karg.starts_with("coreos.liveiso="))
+        .map(String::from)
+        .collect();
+    KargsEditor::new().delete(&liveiso).apply_to(kargs)
+}
+
 pub fn iso_customize(config: IsoCustomizeConfig) -> Result<()> {
-    let mut iso_file = open_live_iso(&config.input, Some(config.output.as_ref()))?;
+    let mut iso_file =
+        open_live_iso_or_url(&config.input, config.output.as_ref(), config.insecure)?;
     let mut iso_fs = IsoFs::from_file(iso_file.try_clone().context("cloning file")?)
         .context("parsing ISO9660 image")?;
     let mut iso = IsoConfig::for_iso(&mut iso_fs)?;
@@ -329,13 +584,25 @@ pub fn iso_customize(config: IsoCustomizeConfig) -> Result<()> {
     if !config.force
         && (iso.have_ignition()
             || iso.have_network()
+            || iso.have_added_files()
             || (iso.kargs_supported() && iso.kargs()? != iso.kargs_default()?))
     {
         bail!("This ISO image is already customized; use -f to force.");
     }
 
+    // the list of added files lives in the initrd we're about to replace
+    iso.remove_added_files()?;
     let live = LiveInitrd::from_common(&config.common, OsFeatures::for_iso(&mut iso_fs)?)?;
     *iso.initrd_mut() = live.into_initrd()?;
+    if let Some(compression) = config.initrd_compression {
+        iso.initrd_mut().set_compression(compression);
+    }
+    let mut customized = !iso.initrd().is_empty();
+
+    // before karg edits, which start from the updated default kargs
+    if let Some(volume_id) = &config.volume_id {
+        iso.set_volume_id(volume_id)?;
+    }
 
     if [
         &config.live_karg_append,
@@ -354,17 +621,107 @@ pub fn iso_customize(config: IsoCustomizeConfig) -> Result<()> {
             .delete(&config.live_karg_delete)
             .apply_to(iso.kargs_default()?)?;
         iso.set_kargs(&kargs)?;
+        customized = true;
+    }
+
+    if !config.iso_file.is_empty() {
+        customized = true;
+    }
+
+    // before recording the manifest and adding ISO files, neither of
+    // which "pxe customize" would write
+    if let Some(path) = &config.pxe_output {
+        write_pxe_initrd(&mut iso_fs, iso.initrd(), &config.live_karg_append, path)?;
+    }
+
+    // only record customizations that `iso reset` undoes, so that
+    // e.g. relabeling produces the same output as `iso set-label`
+    if customized {
+        iso.initrd_mut().add(
+            INITRD_MANIFEST_PATH,
+            CustomizeManifest::for_iso_customize(&config)?.to_bytes()?,
+        );
+    }
+
+    for arg in &config.iso_file {
+        let (src, dest) = arg
+            .split_once(':')
+            .with_context(|| format!("ISO file '{arg}' must be in the form SRC:DEST"))?;
+        let contents = read(src).with_context(|| format!("reading {src}"))?;
+        iso.add_file(dest, contents)?;
     }
 
     write_live_iso(&iso, &mut iso_file, config.output.as_ref())
 }
 
+/// Write the PXE initramfs from the ISO with the customizations appended,
+/// so the ISO and PXE outputs of a single `iso customize` can't diverge.
+fn write_pxe_initrd(
+    iso_fs: &mut IsoFs,
+    customizations: &Initrd,
+    live_karg_append: &[String],
+    path: &str,
+) -> Result<()> {
+    let file = iso_fs
+        .get_path(COREOS_ISO_INITRD_IMG)
+        .context("Unrecognized CoreOS ISO image.")?
+        .try_into_file()?;
+    let dir = Path::new(path)
+        .parent()
+        .with_context(|| format!("no parent directory of {path}"))?;
+    let mut tempfile = tempfile::Builder::new()
+        .prefix(".coreos-installer-temp-")
+        .tempfile_in(dir)
+        .context("creating temporary file")?;
+    {
+        let mut buf = BufWriter::with_capacity(BUFFER_SIZE, tempfile.as_file_mut());
+        copy(&mut iso_fs.read_file(&file)?, &mut buf).context("copying PXE initramfs")?;
+        buf.write_all(&customizations.to_bytes()?)
+            .context("writing initrd")?;
+        buf.flush().context("flushing initrd")?;
+    }
+    tempfile
+        .persist_noclobber(path)
+        .map_err(|e| e.error)
+        .with_context(|| format!("persisting output file to {path}"))?;
+
+    let mut kargs = Vec::new();
+    if customizations.get(INITRD_IGNITION_PATH).is_some() {
+        kargs.push("ignition.firstboot".to_string());
+        kargs.push("ignition.platform.id=metal".to_string());
+    }
+    kargs.extend(live_karg_append.iter().cloned());
+    if !kargs.is_empty() {
+        eprintln!(
+            "PXE configuration must include kernel arguments:\n\t{}",
+            kargs.join(" ")
+        );
+    }
+    Ok(())
+}
+
 pub fn iso_reset(config: IsoResetConfig) -> Result<()> {
     let mut iso_file = open_live_iso(&config.input, Some(config.output.as_ref()))?;
     let mut iso = IsoConfig::for_file(&mut iso_file)?;
 
-    *iso.initrd_mut() = Initrd::default();
-    if iso.kargs_supported() {
+    let selective = config.ignition || config.network || config.kargs;
+    if !selective {
+        iso.remove_added_files()?;
+        *iso.initrd_mut() = Initrd::default();
+    }
+    if config.ignition {
+        iso.initrd_mut().remove(INITRD_IGNITION_PATH);
+    }
+    if config.network {
+        iso.remove_network();
+    }
+    // whatever's left no longer matches the recorded customizations
+    iso.initrd_mut().remove(INITRD_MANIFEST_PATH);
+    if config.kargs && !iso.kargs_supported() {
+        bail!("This OS image does not support customizing live kernel arguments.");
+    }
+    let reset_kargs = config.kargs || !(selective || config.keep_kargs);
+    if reset_kargs && iso.kargs_supported() {
         #[allow(clippy::unnecessary_to_owned)]
         iso.set_kargs(&iso.kargs_default()?.to_string())?;
     };
@@ -372,6 +729,207 @@ pub fn iso_reset(config: IsoResetConfig) -> Result<()> {
     write_live_iso(&iso, &mut iso_file, config.output.as_ref())
 }
 
+pub fn iso_set_label(config: IsoSetLabelConfig) -> Result<()> {
+    let mut iso_file = open_live_iso(&config.input, Some(config.output.as_ref()))?;
+    let mut iso = IsoConfig::for_file(&mut iso_file)?;
+
+    iso.set_volume_id(&config.label)?;
+    write_live_iso(&iso, &mut iso_file, config.output.as_ref())
+}
+
+pub fn iso_write(config: IsoWriteConfig) -> Result<()> {
+    let ignition = config
+        .ignition_file
+        .as_ref()
+        .map(|path| read(path).with_context(|| format!("reading {path}")))
+        .transpose()?;
+
+    let mut iso_file = open_live_iso(&config.input, None)?;
+    // check that the customizations apply before touching the device
+    let iso = IsoConfig::for_file(&mut iso_file)?;
+    if ignition.is_some() && iso.have_ignition() {
+        bail!("This ISO image already has an embedded Ignition config.");
+    }
+    if !config.append.is_empty() && !iso.kargs_supported() {
+        bail!("This OS image does not support customizing live kernel arguments.");
+    }
+    let iso_len = iso_file
+        .metadata()
+        .with_context(|| format!("getting metadata for {}", config.input))?
+        .len();
+
+    let device = &config.device;
+    let mut dest = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(device)
+        .with_context(|| format!("opening {device}"))?;
+    if !is_block_device(&dest, device)? {
+        bail!("{device} is not a block device");
+    }
+    check_device_not_active(device)?;
+    let size = get_block_device_size(&dest)
+        .with_context(|| format!("getting size of {device}"))?
+        .get();
+    if size < iso_len {
+        bail!("{size}-byte device {device} is smaller than the {iso_len}-byte ISO image");
+    }
+
+    eprintln!("Writing {} to {}", config.input, device);
+    copy_verified(&mut iso_file, iso_len, &mut dest, device)?;
+    if ignition.is_some() || !config.append.is_empty() {
+        eprintln!("Customizing {device}");
+        customize_in_place(&mut dest, device, ignition, &config.append)?;
+    }
+    eprintln!("Wrote {} to {}", config.input, device);
+    Ok(())
+}
+
+/// Copy the first `len` bytes of `src` to `dest` unmodified, then read
+/// them back and check that they match.
+fn copy_verified(src: &mut File, len: u64, dest: &mut File, dest_path: &str) -> Result<()> {
+    src.rewind().context("seeking input")?;
+    let mut reader =
+        DigestReader::new(BufReader::with_capacity(BUFFER_SIZE, (&mut *src).take(len)));
+    let mut writer = BufWriter::with_capacity(BUFFER_SIZE, &mut *dest);
+    copy(&mut reader, &mut writer).with_context(|| format!("copying ISO image to {dest_path}"))?;
+    writer
+        .flush()
+        .with_context(|| format!("flushing {dest_path}"))?;
+    drop(writer);
+    let expected = reader.finish();
+    dest.sync_all().context("syncing data to disk")?;
+
+    eprintln!("Verifying {dest_path}");
+    check_read_back(dest, dest_path, &expected)
+}
+
+/// Read back the start of `dest` and check that it has the expected
+/// digest and length.
+fn check_read_back(dest: &mut File, dest_path: &str, expected: &(IgnitionHash, u64)) -> Result<()> {
+    rewind_uncached(dest, dest_path)?;
+    let mut reader = DigestReader::new(BufReader::with_capacity(
+        BUFFER_SIZE,
+        (&mut *dest).take(expected.1),
+    ));
+    copy(&mut reader, &mut io::sink()).with_context(|| format!("reading back {dest_path}"))?;
+    if &reader.finish() != expected {
+        bail!("data read back from {dest_path} doesn't match the ISO image");
+    }
+    Ok(())
+}
+
+/// Apply customizations to a written ISO image in place, the same way
+/// the `iso` modification subcommands would, and check that they read
+/// back correctly.
+fn customize_in_place(
+    dest: &mut File,
+    dest_path: &str,
+    ignition: Option<Vec<u8>>,
+    append: &[String],
+) -> Result<()> {
+    let mut iso = IsoConfig::for_file(dest)?;
+    if let Some(ignition) = ignition {
+        iso.initrd_mut().add(INITRD_IGNITION_PATH, ignition);
+    }
+    if !append.is_empty() {
+        let kargs = KargsEditor::new().append(append).apply_to(iso.kargs()?)?;
+        iso.set_kargs(&kargs)?;
+    }
+    iso.write(dest)?;
+    dest.sync_all().context("syncing data to disk")?;
+
+    check_customizations(dest, dest_path, &iso)
+}
+
+/// Read back the embed areas of `dest` and check that its Ignition config
+/// and kargs match `expected`.
+fn check_customizations(dest: &mut File, dest_path: &str, expected: &IsoConfig) -> Result<()> {
+    rewind_uncached(dest, dest_path)?;
+    let written = IsoConfig::for_file(dest)
+        .with_context(|| format!("reading embed areas from {dest_path}"))?;
+    if written.initrd().get(INITRD_IGNITION_PATH) != expected.initrd().get(INITRD_IGNITION_PATH)
+        || (expected.kargs_supported() && written.kargs()? != expected.kargs()?)
+    {
+        bail!("customizations read back from {dest_path} don't match");
+    }
+    Ok(())
+}
+
+/// Seek to the start of `file`, dropping cached data so subsequent reads
+/// come from the device, or from storage for regular files.  The data
+/// must have been synced, since dirty pages aren't dropped.
+fn rewind_uncached(file: &mut File, path: &str) -> Result<()> {
+    if is_block_device(file, path)? {
+        flush_buffer_cache(file).with_context(|| format!("flushing cache of {path}"))?;
+    } else {
+        // returns the error number rather than setting errno
+        match unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) } {
+            0 => (),
+            err => {
+                return Err(io::Error::from_raw_os_error(err))
+                    .with_context(|| format!("dropping cache of {path}"))
+            }
+        }
+    }
+    file.rewind().with_context(|| format!("seeking {path}"))?;
+    Ok(())
+}
+
+pub fn iso_verify(config: IsoVerifyConfig) -> Result<()> {
+    let mut iso_fs =
+        IsoFs::from_file(open_live_iso(&config.input, None)?).context("parsing ISO9660 image")?;
+    // reading the embed areas also decodes the initrd archive and checks
+    // that all the karg embed areas agree
+    let iso = IsoConfig::for_iso(&mut iso_fs)?;
+
+    if let Some(data) = iso.initrd().get(INITRD_IGNITION_PATH) {
+        let (_, warnings) = ignition_config::Config::parse_slice(data)
+            .context("parsing embedded Ignition config")?;
+        for warning in warnings {
+            eprintln!("Warning parsing embedded Ignition config: {warning}");
+        }
+        println!("Ignition config: OK");
+    }
+
+    for (path, contents) in iso.initrd().find(&INITRD_NETWORK_GLOB) {
+        let name = filename(path)?;
+        validate_keyfile(contents).with_context(|| format!("validating keyfile {name}"))?;
+        println!("Network keyfile {name}: OK");
+    }
+
+    if iso.kargs_supported() {
+        iso.validate_kargs()
+            .context("validating kernel arguments")?;
+        println!("Kernel arguments: OK");
+    }
+
+    match iso_fs.get_path(COREOS_ISO_MINISO_FILE) {
+        Ok(record) => {
+            // the minimal ISO is rebuilt from the unmodified full ISO, so
+            // the digest can't match if there are embedded customizations
+            if !iso.initrd().is_empty()
+                || (iso.kargs_supported() && iso.kargs()? != iso.kargs_default()?)
+            {
+                println!("Minimal ISO data: skipped; ISO has embedded customizations");
+            } else {
+                let miniso_data_file = record.try_into_file()?;
+                let data = {
+                    let mut f = iso_fs.read_file(&miniso_data_file)?;
+                    miniso::Data::deserialize(&mut f).context("reading miniso data file")?
+                };
+                data.unxzpack(iso_fs.as_file()?, io::sink())
+                    .context("verifying miniso data")?;
+                println!("Minimal ISO data: OK");
+            }
+        }
+        Err(e) if e.is::<iso9660::NotFound>() => (),
+        Err(e) => return Err(e).with_context(|| format!("looking up '{COREOS_ISO_MINISO_FILE}'")),
+    }
+
+    Ok(())
+}
+
 pub fn pxe_customize(config: PxeCustomizeConfig) -> Result<()> {
     // open input and set up output
     let mut input = BufReader::with_capacity(
@@ -381,7 +939,13 @@ pub fn pxe_customize(config: PxeCustomizeConfig) -> Result<()> {
             .open(&config.input)
             .with_context(|| format!("opening {}", &config.input))?,
     );
-    let mut tempfile = match &*config.output {
+    // clap ensures exactly one is present
+    let (output, separate) = match (&config.output, &config.separate_output) {
+        (Some(path), None) => (path.as_str(), false),
+        (None, Some(path)) => (path.as_str(), true),
+        _ => unreachable!(),
+    };
+    let mut tempfile = match output {
         "-" => {
             verify_stdout_not_tty()?;
             None
@@ -406,7 +970,11 @@ pub fn pxe_customize(config: PxeCustomizeConfig) -> Result<()> {
         &format!("{INITRD_NETWORK_DIR}/*"),
     ])
     .unwrap();
-    let base_initrd = match &*config.output {
+    let base_initrd = match output {
+        // the input is left alone and only the customizations are written
+        _ if separate => {
+            Initrd::from_reader_filtered(&mut input, &filter).context("reading input initrd")?
+        }
         "-" => {
             Initrd::from_reader_filtered(TeeReader::new(&mut input, io::stdout().lock()), &filter)
                 .context("reading/copying input initrd")?
@@ -420,23 +988,52 @@ pub fn pxe_customize(config: PxeCustomizeConfig) -> Result<()> {
     if base_initrd.get(INITRD_LIVE_STAMP_PATH).is_none() {
         bail!("not a CoreOS live initramfs image");
     }
-    if base_initrd.get(INITRD_IGNITION_PATH).is_some()
-        || !base_initrd.find(&INITRD_NETWORK_GLOB).is_empty()
+    if !config.merge
+        && (base_initrd.get(INITRD_IGNITION_PATH).is_some()
+            || !base_initrd.find(&INITRD_NETWORK_GLOB).is_empty())
     {
-        bail!("input is already customized");
+        bail!("input is already customized; use --merge to add customizations");
     }
-    let features = match base_initrd.get(INITRD_FEATURES_PATH) {
-        Some(json) => serde_json::from_slice::<OsFeatures>(json).context("parsing OS features")?,
-        None => OsFeatures::default(),
-    };
+    let features = OsFeatures::for_initrd(&base_initrd)?;
 
-    let live = LiveInitrd::from_common(&config.common, features)?;
+    let base_live = base_initrd.get(INITRD_IGNITION_PATH);
+    let live = match config.merge {
+        true => LiveInitrd::from_common_merged(&config.common, features, base_live)?,
+        false => LiveInitrd::from_common(&config.common, features)?,
+    };
     let initrd = live.into_initrd()?;
-    if initrd.get(INITRD_IGNITION_PATH).is_some() {
+    if separate && initrd.is_empty() {
+        bail!("no customizations specified");
+    }
+    let mut kargs = Vec::new();
+    if initrd.get(INITRD_IGNITION_PATH).is_some() || base_live.is_some() {
+        kargs.push("ignition.firstboot".to_string());
+        kargs.push("ignition.platform.id=metal".to_string());
+    }
+    kargs.extend(config.live_karg_append.iter().cloned());
+    if config.ipxe_script.is_none() && !kargs.is_empty() {
         eprintln!(
-            "PXE configuration must include kernel arguments:\n\tignition.firstboot ignition.platform.id=metal"
+            "PXE configuration must include kernel arguments:\n\t{}",
+            kargs.join(" ")
         );
     }
+    // generate the script before writing anything, in case the kargs
+    // can't be expressed in it; clap ensures the URLs are present
+    let script = match &config.ipxe_script {
+        Some(path) => Some((
+            path,
+            ipxe_script(
+                config.ipxe_kernel_url.as_ref().unwrap(),
+                &[
+                    ("main", config.ipxe_initrd_url.as_ref().unwrap()),
+                    ("custom", config.ipxe_custom_initrd_url.as_ref().unwrap()),
+                ],
+                config.ipxe_rootfs_url.as_ref().unwrap(),
+                &kargs,
+            )?,
+        )),
+        None => None,
+    };
 
     // append customizations to output
     let do_write = |writer: &mut dyn Write| -> Result<()> {
@@ -445,8 +1042,8 @@ pub fn pxe_customize(config: PxeCustomizeConfig) -> Result<()> {
             .context("writing initrd")?;
         buf.flush().context("flushing initrd")
     };
-    match &*config.output {
-        "-" => do_write(&mut io::stdout().lock()),
+    match output {
+        "-" => do_write(&mut io::stdout().lock())?,
         path => {
             let mut tempfile = tempfile.unwrap();
             do_write(tempfile.as_file_mut())?;
@@ -454,14 +1051,114 @@ pub fn pxe_customize(config: PxeCustomizeConfig) -> Result<()> {
                 .persist_noclobber(path)
                 .map_err(|e| e.error)
                 .with_context(|| format!("persisting output file to {path}"))?;
-            Ok(())
         }
     }
+
+    if let Some((path, script)) = script {
+        write(path, script).with_context(|| format!("writing iPXE script {path}"))?;
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct IsoInfoOutput {
+    volume_id: String,
+    #[serde(flatten)]
+    os: OsVersion,
+    features: OsFeatures,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    customizations: Option<CustomizeManifest>,
+}
+
+pub fn iso_info(config: IsoInfoConfig) -> Result<()> {
+    let mut iso_fs =
+        IsoFs::from_file(open_live_iso(&config.input, None)?).context("parsing ISO9660 image")?;
+    let volume_id = iso_fs.get_volume_id()?.to_string();
+    let features = OsFeatures::for_iso(&mut iso_fs)?;
+    // images without an initrd embed area can't have been customized
+    let customizations = match IsoConfig::for_iso(&mut iso_fs) {
+        Ok(iso) => CustomizeManifest::from_initrd(iso.initrd())?,
+        Err(_) => None,
+    };
+    let info = IsoInfoOutput {
+        os: OsVersion::from_volume_id(&volume_id),
+        volume_id,
+        features,
+        customizations,
+    };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match config.format {
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, &info).context("failed to serialize info")?;
+            out.write_all(b"\n").context("failed to write newline")?;
+        }
+        OutputFormat::Text => {
+            let unknown = "unknown".to_string();
+            writeln!(out, "Volume ID: {}", info.volume_id)?;
+            writeln!(out, "OS: {}", info.os.name.as_ref().unwrap_or(&unknown))?;
+            writeln!(
+                out,
+                "Version: {}",
+                info.os.version.as_ref().unwrap_or(&unknown)
+            )?;
+            if let Some(stream) = &info.os.stream {
+                writeln!(out, "Stream: {stream}")?;
+            }
+            writeln!(out, "Features:")?;
+            let features =
+                serde_json::to_value(&info.features).context("failed to serialize features")?;
+            write_features(&mut out, "", &features)?;
+            if let Some(manifest) = &info.customizations {
+                writeln!(out, "Customizations:")?;
+                writeln!(out, "  Tool version: {}", manifest.tool_version)?;
+                writeln!(
+                    out,
+                    "  Timestamp: {} (seconds since epoch)",
+                    manifest.timestamp
+                )?;
+                for option in &manifest.options {
+                    writeln!(out, "  Option: {option}")?;
+                }
+                for input in &manifest.inputs {
+                    let dest = match &input.dest {
+                        Some(dest) => format!(":{dest}"),
+                        None => String::new(),
+                    };
+                    writeln!(
+                        out,
+                        "  Input: --{} {}{} (sha256 {})",
+                        input.option, input.path, dest, input.sha256
+                    )?;
+                }
+            }
+        }
+    }
+    out.flush().context("flushing output")?;
+    Ok(())
+}
+
+/// Write one "name: value" line for each leaf of a features object.
+fn write_features(out: &mut impl Write, prefix: &str, value: &serde_json::Value) -> Result<()> {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map {
+                write_features(out, &format!("{prefix}{key}."), value)?;
+            }
+        }
+        _ => writeln!(out, "  {}: {value}", prefix.trim_end_matches('.'))?,
+    }
+    Ok(())
 }
 
 #[derive(Serialize)]
 struct DevShowIsoOutput {
     header: IsoFs,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    boot_catalog: Option<iso9660::BootCatalog>,
     records: Vec<String>,
 }
 
@@ -485,8 +1182,10 @@ pub fn dev_show_iso(config: DevShowIsoConfig) -> Result<()> {
             .map(|r| r.map(|(path, _)| path))
             .collect::<Result<Vec<String>>>()
             .context("while walking ISO filesystem")?;
+        let boot_catalog = iso.get_boot_catalog()?;
         let info = DevShowIsoOutput {
             header: iso,
+            boot_catalog,
             records,
         };
 
@@ -497,15 +1196,177 @@ pub fn dev_show_iso(config: DevShowIsoConfig) -> Result<()> {
     Ok(())
 }
 
+#[derive(Serialize)]
+struct DevShowIsoDiffOutput {
+    added: Vec<String>,
+    removed: Vec<String>,
+    changed: Vec<IsoFileDiff>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    embed: Option<IsoEmbedDiff>,
+}
+
+#[derive(Serialize)]
+struct IsoFileDiff {
+    path: String,
+    old_size: u32,
+    new_size: u32,
+    old_sha256: String,
+    new_sha256: String,
+}
+
+#[derive(Default, Serialize)]
+struct IsoEmbedDiff {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kargs: Option<(String, String)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kargs_default: Option<(String, String)>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    initrd_added: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    initrd_removed: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    initrd_changed: Vec<String>,
+}
+
+pub fn dev_show_iso_diff(config: DevShowIsoDiffConfig) -> Result<()> {
+    set_die_on_sigpipe()?;
+    let mut old = IsoFs::from_file(open_live_iso(&config.input, None)?)?;
+    let mut new = IsoFs::from_file(open_live_iso(&config.other, None)?)?;
+
+    let files = iso9660::FileMatches::new(&mut old, &mut new)
+        .with_context(|| format!("comparing {} and {}", &config.input, &config.other))?;
+    let mut changed = Vec::new();
+    for (path, old_file, new_file) in &files.common {
+        let old_digest = Sha256Digest::from_reader(&mut old.read_file(old_file)?)
+            .with_context(|| format!("hashing {path} in {}", &config.input))?;
+        let new_digest = Sha256Digest::from_reader(&mut new.read_file(new_file)?)
+            .with_context(|| format!("hashing {path} in {}", &config.other))?;
+        if old_file.length != new_file.length || old_digest != new_digest {
+            changed.push(IsoFileDiff {
+                path: path.clone(),
+                old_size: old_file.length,
+                new_size: new_file.length,
+                old_sha256: old_digest.to_hex_string()?,
+                new_sha256: new_digest.to_hex_string()?,
+            });
+        }
+    }
+
+    let info = DevShowIsoDiffOutput {
+        added: files
+            .second_only
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect(),
+        removed: files.first_only.into_iter().map(|(path, _)| path).collect(),
+        changed,
+        embed: diff_embed_areas(&mut old, &mut new)?,
+    };
+    let mut out = io::stdout().lock();
+    serde_json::to_writer_pretty(&mut out, &info).context("failed to serialize ISO diff")?;
+    out.write_all(b"\n").context("failed to write newline")?;
+    Ok(())
+}
+
+/// Compare the embed areas of two CoreOS live ISOs.  Returns None if
+/// either image isn't a CoreOS live ISO.
+fn diff_embed_areas(old: &mut IsoFs, new: &mut IsoFs) -> Result<Option<IsoEmbedDiff>> {
+    let (old, new) = match (IsoConfig::for_iso(old), IsoConfig::for_iso(new)) {
+        (Ok(old), Ok(new)) => (old, new),
+        _ => return Ok(None),
+    };
+    let mut diff = IsoEmbedDiff::default();
+    if old.kargs_supported() && new.kargs_supported() {
+        if old.kargs()? != new.kargs()? {
+            diff.kargs = Some((old.kargs()?.to_string(), new.kargs()?.to_string()));
+        }
+        if old.kargs_default()? != new.kargs_default()? {
+            diff.kargs_default = Some((
+                old.kargs_default()?.to_string(),
+                new.kargs_default()?.to_string(),
+            ));
+        }
+    }
+    let old_files = old.initrd().find(&ALL_GLOB);
+    let new_files = new.initrd().find(&ALL_GLOB);
+    for (path, contents) in &new_files {
+        match old_files.get(path) {
+            None => diff.initrd_added.push(path.to_string()),
+            Some(old_contents) if old_contents != contents => {
+                diff.initrd_changed.push(path.to_string())
+            }
+            Some(_) => (),
+        }
+    }
+    diff.initrd_removed = old_files
+        .keys()
+        .filter(|path| !new_files.contains_key(*path))
+        .map(|path| path.to_string())
+        .collect();
+    Ok(Some(diff))
+}
+
 pub fn dev_show_initrd(config: DevShowInitrdConfig) -> Result<()> {
     set_die_on_sigpipe()?;
     let initrd = read_initrd(&config.input, &config.filter)?;
-    for path in initrd.find(&ALL_GLOB).keys() {
-        println!("{path}");
+    match config.format {
+        OutputFormat::Json => print_initrd_listing(&initrd)?,
+        OutputFormat::Text => {
+            for path in initrd.find(&ALL_GLOB).keys() {
+                println!("{path}");
+            }
+        }
     }
     Ok(())
 }
 
+/// Entry in the JSON listing of an initrd
+#[derive(Serialize)]
+struct InitrdListEntry<'a> {
+    path: &'a str,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    /// Permission bits, in octal
+    mode: String,
+    uid: u32,
+    gid: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<String>,
+}
+
+fn print_initrd_listing(initrd: &Initrd) -> Result<()> {
+    let entries: Vec<InitrdListEntry> = initrd
+        .entries()
+        .into_iter()
+        .map(|(path, meta)| {
+            let contents = initrd.get(path);
+            let (kind, target) = match meta.kind {
+                InitrdEntryKind::File => ("file", None),
+                InitrdEntryKind::Directory => ("directory", None),
+                InitrdEntryKind::Symlink(target) => ("symlink", Some(target)),
+            };
+            InitrdListEntry {
+                path,
+                kind,
+                mode: format!("{:04o}", meta.mode),
+                uid: meta.uid,
+                gid: meta.gid,
+                size: contents.map(|c| c.len() as u64),
+                sha256: contents.map(|c| hex::encode(sha::sha256(c))),
+                target,
+            }
+        })
+        .collect();
+    let mut out = io::stdout().lock();
+    serde_json::to_writer_pretty(&mut out, &entries).context("serializing initrd listing")?;
+    out.write_all(b"\n").context("writing output")?;
+    Ok(())
+}
+
 pub fn dev_extract_initrd(config: DevExtractInitrdConfig) -> Result<()> {
     let initrd = read_initrd(&config.input, &config.filter)?;
     let base_path = Path::new(&config.directory);
@@ -532,6 +1393,9 @@ pub fn dev_extract_initrd(config: DevExtractInitrdConfig) -> Result<()> {
             .write_all(contents)
             .with_context(|| format!("writing {}", out_path.display()))?;
     }
+    if config.list_json {
+        print_initrd_listing(&initrd)?;
+    }
     Ok(())
 }
 
@@ -563,13 +1427,19 @@ pub fn iso_extract_pxe(config: IsoExtractPxeConfig) -> Result<()> {
         .try_into_dir()?;
     create_dir_all(&config.output_dir)?;
 
+    // this can't be None since we successfully opened the live ISO at the location
+    let stem = Path::new(&config.input).file_stem().unwrap();
     let base = {
-        // this can't be None since we successfully opened the live ISO at the location
-        let mut s = Path::new(&config.input).file_stem().unwrap().to_os_string();
+        let mut s = stem.to_os_string();
         s.push("-");
         s
     };
+    let write_kargs = config.kargs
+        || !config.karg_append.is_empty()
+        || !config.karg_delete.is_empty()
+        || !config.karg_replace.is_empty();
 
+    let mut artifacts = Vec::new();
     for record in iso.list_dir(&pxeboot)? {
         match record? {
             iso9660::DirectoryRecord::Directory(_) => continue,
@@ -579,12 +1449,106 @@ pub fn iso_extract_pxe(config: IsoExtractPxeConfig) -> Result<()> {
                     s.push(file.name.to_lowercase());
                     s
                 };
-                let path = Path::new(&config.output_dir).join(filename);
+                let path = Path::new(&config.output_dir).join(&filename);
                 println!("{}", path.display());
                 copy_file_from_iso(&mut iso, &file, &path)?;
+                if config.manifest.is_some() {
+                    artifacts.push(PxeManifestArtifact {
+                        filename: filename.to_string_lossy().into_owned(),
+                        size: file.length as u64,
+                        sha256: Sha256Digest::from_path(&path)?.to_hex_string()?,
+                    });
+                }
             }
         }
     }
+
+    if config.manifest.is_none() && !write_kargs {
+        return Ok(());
+    }
+    let kargs = extracted_pxe_kargs(&mut iso, &config)?;
+
+    if write_kargs {
+        let filename = {
+            let mut s = stem.to_os_string();
+            s.push(".kargs");
+            s
+        };
+        let path = Path::new(&config.output_dir).join(filename);
+        println!("{}", path.display());
+        write(&path, format!("{kargs}\n"))
+            .with_context(|| format!("writing {}", path.display()))?;
+    }
+
+    if let Some(manifest_path) = &config.manifest {
+        let kargs = KargsEditor::new()
+            .append(&[format!("coreos.live.rootfs_url={PXE_MANIFEST_ROOTFS_URL}")])
+            .apply_to(&kargs)?;
+        let manifest = PxeManifest { artifacts, kargs };
+        let mut json = serde_json::to_vec_pretty(&manifest).context("serializing manifest")?;
+        json.push(b'\n');
+        write(manifest_path, json).with_context(|| format!("writing {manifest_path}"))?;
+    }
+    Ok(())
+}
+
+/// Default kargs for PXE booting the artifacts extracted from an ISO,
+/// with the requested edits applied.
+fn extracted_pxe_kargs(iso: &mut IsoFs, config: &IsoExtractPxeConfig) -> Result<String> {
+    let kargs = pxe_default_kargs(iso)?;
+    KargsEditor::new()
+        .append(&config.karg_append)
+        .replace(&config.karg_replace)
+        .delete(&config.karg_delete)
+        .apply_to(&kargs)
+}
+
+/// Placeholder for the rootfs URL in the kargs of a PXE manifest, to be
+/// replaced by the provisioning tooling.
+const PXE_MANIFEST_ROOTFS_URL: &str = "<ROOTFS_URL>";
+
+/// Manifest written by `iso extract pxe --manifest`.
+#[derive(Serialize)]
+struct PxeManifest {
+    artifacts: Vec<PxeManifestArtifact>,
+    kargs: String,
+}
+
+#[derive(Serialize)]
+struct PxeManifestArtifact {
+    filename: String,
+    size: u64,
+    sha256: String,
+}
+
+pub fn iso_extract_file(config: IsoExtractFileConfig) -> Result<()> {
+    let mut iso = IsoFs::from_file(open_live_iso(&config.input, None)?)?;
+    let files = config
+        .paths
+        .iter()
+        .map(|path| {
+            iso.get_path(path)
+                .and_then(|record| record.try_into_file())
+                .with_context(|| format!("looking up '{path}'"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if config.output_dir == "-" {
+        verify_stdout_not_tty()?;
+        let mut out = io::stdout().lock();
+        for file in &files {
+            copy(&mut iso.read_file(file)?, &mut out)
+                .with_context(|| format!("writing {} to stdout", file.name))?;
+        }
+        out.flush().context("flushing stdout")?;
+    } else {
+        create_dir_all(&config.output_dir)?;
+        for file in &files {
+            let path = Path::new(&config.output_dir).join(&file.name);
+            println!("{}", path.display());
+            copy_file_from_iso(&mut iso, file, &path)?;
+        }
+    }
     Ok(())
 }
 
@@ -641,6 +1605,16 @@ pub fn iso_extract_minimal_iso(config: IsoExtractMinimalIsoConfig) -> Result<()>
     modify_miniso_kargs(outf.as_file_mut(), config.rootfs_url.as_ref())
         .context("modifying miniso kernel args")?;
 
+    if config.verify_signatures {
+        let mut minimal_iso = IsoFs::from_file(
+            outf.as_file()
+                .try_clone()
+                .context("duplicating miniso file descriptor")?,
+        )?;
+        verify_miniso_efi_files(&mut full_iso, &mut minimal_iso)
+            .context("verifying minimal ISO EFI boot files")?;
+    }
+
     if &config.output == "-" {
         outf.rewind()
             .context("seeking back to start of miniso tempfile")?;
@@ -653,25 +1627,74 @@ pub fn iso_extract_minimal_iso(config: IsoExtractMinimalIsoConfig) -> Result<()>
     Ok(())
 }
 
+/// Check that the EFI binaries in the minimal ISO have the same
+/// Authenticode digests as in the full ISO, and that the EFI boot image is
+/// identical, so Secure Boot will accept the minimal ISO.
+fn verify_miniso_efi_files(full_iso: &mut IsoFs, minimal_iso: &mut IsoFs) -> Result<()> {
+    let full_files = full_iso.files()?;
+    let minimal_files = minimal_iso.files()?;
+    let mut count = 0;
+    for (path, full_file) in &full_files {
+        let upper = path.to_uppercase();
+        let is_efi_binary = upper.starts_with("EFI/") && upper.ends_with(".EFI");
+        if !is_efi_binary && upper != COREOS_ISO_EFIBOOT_IMG {
+            continue;
+        }
+        let minimal_file = minimal_files
+            .get(path)
+            .with_context(|| format!("{path} missing from minimal ISO"))?;
+        let full_data = read_iso_file(full_iso, full_file)?;
+        let minimal_data = read_iso_file(minimal_iso, minimal_file)?;
+        if is_efi_binary {
+            let full_digest = authenticode_sha256(&full_data)
+                .with_context(|| format!("computing Authenticode digest of {path} in full ISO"))?;
+            let minimal_digest = authenticode_sha256(&minimal_data).with_context(|| {
+                format!("computing Authenticode digest of {path} in minimal ISO")
+            })?;
+            if full_digest != minimal_digest {
+                bail!("Authenticode digest of {path} doesn't match full ISO");
+            }
+        } else if full_data != minimal_data {
+            bail!("{path} doesn't match full ISO");
+        }
+        count += 1;
+    }
+    if count == 0 {
+        eprintln!("No EFI boot files found to verify");
+    } else {
+        eprintln!("Verified {count} EFI boot files against full ISO");
+    }
+    Ok(())
+}
+
+fn read_iso_file(iso: &mut IsoFs, file: &iso9660::File) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(file.length as usize);
+    iso.read_file(file)?
+        .read_to_end(&mut data)
+        .with_context(|| format!("reading {}", file.name))?;
+    Ok(data)
+}
+
 pub fn pack_minimal_iso(config: PackMinimalIsoConfig) -> Result<()> {
     let mut full_iso = IsoFs::from_file(open_live_iso(&config.full, Some(None))?)?;
     let mut minimal_iso = IsoFs::from_file(open_live_iso(&config.minimal, None)?)?;
 
-    let full_files = collect_iso_files(&mut full_iso)
-        .with_context(|| format!("collecting files from {}", &config.full))?;
-    let minimal_files = collect_iso_files(&mut minimal_iso)
-        .with_context(|| format!("collecting files from {}", &config.minimal))?;
-    if full_files.is_empty() {
+    let files = iso9660::FileMatches::new(&mut full_iso, &mut minimal_iso).with_context(|| {
+        format!(
+            "collecting files from {} and {}",
+            &config.full, &config.minimal
+        )
+    })?;
+    if files.first_len() == 0 {
         bail!("No files found in {}", &config.full);
-    } else if minimal_files.is_empty() {
+    } else if files.second_len() == 0 {
         bail!("No files found in {}", &config.minimal);
     }
 
     eprintln!("Packing minimal ISO");
     let (data, matches, skipped, written, written_compressed) =
-        miniso::Data::xzpack(minimal_iso.as_file()?, &full_files, &minimal_files)
-            .context("packing miniso")?;
-    eprintln!("Matched {} files of {}", matches, minimal_files.len());
+        miniso::Data::xzpack(minimal_iso.as_file()?, &files).context("packing miniso")?;
+    eprintln!("Matched {} files of {}", matches, files.second_len());
 
     eprintln!("Total bytes skipped: {skipped}");
     eprintln!("Total bytes written: {written}");
@@ -698,17 +1721,6 @@ pub fn pack_minimal_iso(config: PackMinimalIsoConfig) -> Result<()> {
     Ok(())
 }
 
-fn collect_iso_files(iso: &mut IsoFs) -> Result<HashMap<String, iso9660::File>> {
-    iso.walk()?
-        .filter_map(|r| match r {
-            Err(e) => Some(Err(e)),
-            Ok((s, iso9660::DirectoryRecord::File(f))) => Some(Ok((s, f))),
-            Ok(_) => None,
-        })
-        .collect::<Result<HashMap<String, iso9660::File>>>()
-        .context("while walking ISO filesystem")
-}
-
 fn modify_miniso_kargs(f: &mut File, rootfs_url: Option<&String>) -> Result<()> {
     let mut iso = IsoFs::from_file(f.try_clone().context("cloning a file")?)?;
     let mut cfg = IsoConfig::for_file(f)?;
@@ -743,3 +1755,142 @@ fn modify_miniso_kargs(f: &mut File, rootfs_url: Option<&String>) -> Result<()>
     // re-add `coreos.liveiso`
     set_default_kargs(&mut iso, new_default_kargs)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempfile;
+    use xz2::read::XzDecoder;
+
+    fn open_iso_file() -> File {
+        let iso_bytes: &[u8] = include_bytes!("../../fixtures/iso/embed-areas-2021-09.iso.xz");
+        let mut decoder = XzDecoder::new(iso_bytes);
+        let mut iso_file = tempfile().unwrap();
+        copy(&mut decoder, &mut iso_file).unwrap();
+        iso_file
+    }
+
+    fn read_all(file: &mut File) -> Vec<u8> {
+        let mut data = Vec::new();
+        file.rewind().unwrap();
+        file.read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_iso_write() {
+        // customized with a non-default initrd compression, which must
+        // be copied verbatim
+        let mut iso_file = open_iso_file();
+        let mut iso = IsoConfig::for_file(&mut iso_file).unwrap();
+        iso.initrd_mut().add("etc/hello", b"hello".to_vec());
+        iso.initrd_mut().set_compression("gzip:1".parse().unwrap());
+        iso.write(&mut iso_file).unwrap();
+        let iso_len = iso_file.metadata().unwrap().len();
+
+        // unmodified copy
+        let mut dest = tempfile().unwrap();
+        copy_verified(&mut iso_file, iso_len, &mut dest, "dest").unwrap();
+        assert_eq!(read_all(&mut dest), read_all(&mut iso_file));
+
+        // customized copy
+        let mut dest = tempfile().unwrap();
+        copy_verified(&mut iso_file, iso_len, &mut dest, "dest").unwrap();
+        customize_in_place(
+            &mut dest,
+            "dest",
+            Some(b"{}".to_vec()),
+            &["foo=bar".to_string()],
+        )
+        .unwrap();
+        let written = IsoConfig::for_file(&mut dest).unwrap();
+        assert_eq!(written.initrd().get(INITRD_IGNITION_PATH), Some(&b"{}"[..]));
+        assert_eq!(written.initrd().get("etc/hello"), Some(&b"hello"[..]));
+        assert!(written.kargs().unwrap().ends_with(" foo=bar"));
+        // the embed areas stayed where they were
+        let orig = IsoConfig::for_file(&mut iso_file).unwrap();
+        assert_eq!(
+            written.initrd_header_json().unwrap(),
+            orig.initrd_header_json().unwrap()
+        );
+        assert_eq!(
+            written.kargs_header_json().unwrap(),
+            orig.kargs_header_json().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_iso_write_read_back() {
+        let mut iso_file = open_iso_file();
+        let iso_len = iso_file.metadata().unwrap().len();
+        let mut dest = tempfile::NamedTempFile::new().unwrap();
+        let dest_path = dest.path().to_str().unwrap().to_string();
+        copy_verified(&mut iso_file, iso_len, dest.as_file_mut(), &dest_path).unwrap();
+        iso_file.rewind().unwrap();
+        let expected = {
+            let mut reader = DigestReader::new(&mut iso_file);
+            copy(&mut reader, &mut io::sink()).unwrap();
+            reader.finish()
+        };
+        check_read_back(dest.as_file_mut(), &dest_path, &expected).unwrap();
+
+        // corrupt a byte behind the file handle's back
+        let mut other = OpenOptions::new().write(true).open(dest.path()).unwrap();
+        other.seek(io::SeekFrom::Start(iso_len / 2)).unwrap();
+        other.write_all(b"x").unwrap();
+        other.sync_all().unwrap();
+        let err = check_read_back(dest.as_file_mut(), &dest_path, &expected).unwrap_err();
+        assert!(
+            err.to_string().contains("doesn't match the ISO image"),
+            "{err:#}"
+        );
+        // truncated
+        dest.as_file().set_len(iso_len - 1).unwrap();
+        check_read_back(dest.as_file_mut(), &dest_path, &expected).unwrap_err();
+    }
+
+    #[test]
+    fn test_iso_write_customizations() {
+        let mut iso_file = open_iso_file();
+        let iso_len = iso_file.metadata().unwrap().len();
+        let mut dest = tempfile::NamedTempFile::new().unwrap();
+        let dest_path = dest.path().to_str().unwrap().to_string();
+        copy_verified(&mut iso_file, iso_len, dest.as_file_mut(), &dest_path).unwrap();
+        customize_in_place(
+            dest.as_file_mut(),
+            &dest_path,
+            Some(b"{}".to_vec()),
+            &["foo=bar".to_string()],
+        )
+        .unwrap();
+
+        // the written embed areas match what was requested
+        let mut expected = IsoConfig::for_file(&mut iso_file).unwrap();
+        expected
+            .initrd_mut()
+            .add(INITRD_IGNITION_PATH, b"{}".to_vec());
+        let kargs = KargsEditor::new()
+            .append(&["foo=bar".to_string()])
+            .apply_to(expected.kargs().unwrap())
+            .unwrap();
+        expected.set_kargs(&kargs).unwrap();
+        check_customizations(dest.as_file_mut(), &dest_path, &expected).unwrap();
+
+        // a different Ignition config doesn't match
+        let mut other = IsoConfig::for_file(&mut iso_file).unwrap();
+        other
+            .initrd_mut()
+            .add(INITRD_IGNITION_PATH, b"{ }".to_vec());
+        other.set_kargs(&kargs).unwrap();
+        check_customizations(dest.as_file_mut(), &dest_path, &other).unwrap_err();
+        // nor do different kargs
+        let mut other = IsoConfig::for_file(&mut iso_file).unwrap();
+        other.initrd_mut().add(INITRD_IGNITION_PATH, b"{}".to_vec());
+        check_customizations(dest.as_file_mut(), &dest_path, &other).unwrap_err();
+        // nor does a missing Ignition config
+        let mut other = IsoConfig::for_file(&mut iso_file).unwrap();
+        other.set_kargs(&kargs).unwrap();
+        check_customizations(dest.as_file_mut(), &dest_path, &other).unwrap_err();
+    }
+}
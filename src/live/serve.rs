@@ -0,0 +1,339 @@
+// Copyright 2024 Red Hat, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Minimal HTTP server for PXE booting the contents of a live ISO in a
+//! lab, without extracting the artifacts first.  It only handles GET and
+//! HEAD of a few fixed paths and isn't meant to be exposed to untrusted
+//! networks.
+
+use anyhow::{bail, Context, Result};
+use reqwest::Url;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{copy, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::cmdline::*;
+use crate::io::*;
+use crate::iso9660::{self, IsoFs};
+
+use super::customize::ipxe_script;
+use super::util::open_live_iso;
+use super::{pxe_default_kargs, IsoConfig, COREOS_ISO_PXEBOOT_DIR};
+
+const IPXE_SCRIPT_PATH: &str = "/boot.ipxe";
+const KERNEL_PATH: &str = "/vmlinuz";
+const INITRD_PATH: &str = "/initrd.img";
+const CUSTOM_INITRD_PATH: &str = "/custom.img";
+const ROOTFS_PATH: &str = "/rootfs.img";
+
+/// Upper bound on the request line and headers.
+const MAX_REQUEST_SIZE: u64 = 8192;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct ServeState {
+    iso_path: String,
+    /// URL path -> (offset, length) of the file in the ISO
+    files: BTreeMap<String, (u64, u64)>,
+    /// Customizations embedded in the ISO, as a separate initramfs
+    custom_initrd: Option<Vec<u8>>,
+    kargs: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Request {
+    method: String,
+    path: String,
+    host: Option<String>,
+}
+
+pub fn dev_serve_iso(config: DevServeIsoConfig) -> Result<()> {
+    let mut iso = IsoFs::from_file(open_live_iso(&config.input, None)?)?;
+    let pxeboot = iso
+        .get_path(COREOS_ISO_PXEBOOT_DIR)
+        .context("Unrecognized CoreOS ISO image.")?
+        .try_into_dir()?;
+    let mut files = BTreeMap::new();
+    for record in iso.list_dir(&pxeboot)? {
+        if let iso9660::DirectoryRecord::File(file) = record? {
+            files.insert(
+                format!("/{}", file.name.to_lowercase()),
+                (file.address.as_offset(), file.length as u64),
+            );
+        }
+    }
+    for path in [KERNEL_PATH, INITRD_PATH, ROOTFS_PATH] {
+        if !files.contains_key(path) {
+            bail!("couldn't find {} in ISO image", &path[1..]);
+        }
+    }
+
+    // the ISO boots its embedded customizations as a second initramfs;
+    // serve them the same way
+    let iso_config = IsoConfig::for_iso(&mut iso)?;
+    let custom_initrd = match iso_config.initrd().is_empty() {
+        true => None,
+        false => Some(iso_config.initrd().to_bytes()?),
+    };
+
+    let kargs = KargsEditor::new()
+        .append(&config.karg_append)
+        .apply_to(&pxe_default_kargs(&mut iso)?)?;
+    let state = Arc::new(ServeState {
+        iso_path: config.input.clone(),
+        files,
+        custom_initrd,
+        kargs: kargs.split_ascii_whitespace().map(String::from).collect(),
+    });
+    // fail now rather than on every request
+    boot_script(&state, "localhost")?;
+
+    let listener = TcpListener::bind((config.address, config.port))
+        .with_context(|| format!("listening on {}:{}", config.address, config.port))?;
+    let local = listener.local_addr().context("getting listening address")?;
+    eprintln!(
+        "Serving {} at http://{}{}",
+        config.input, local, IPXE_SCRIPT_PATH
+    );
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Accepting connection: {e}");
+                continue;
+            }
+        };
+        let state = state.clone();
+        thread::spawn(move || {
+            let peer = stream
+                .peer_addr()
+                .map(|a| a.to_string())
+                .unwrap_or_else(|_| "unknown client".into());
+            if let Err(e) = handle_connection(&state, stream) {
+                eprintln!("{peer}: {e:#}");
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle_connection(state: &ServeState, mut stream: TcpStream) -> Result<()> {
+    stream
+        .set_read_timeout(Some(REQUEST_TIMEOUT))
+        .context("setting read timeout")?;
+    let request = match read_request(&mut stream) {
+        Ok(request) => request,
+        Err(e) => {
+            respond_error(&mut stream, "400 Bad Request")?;
+            return Err(e);
+        }
+    };
+    let peer = stream.peer_addr().context("getting peer address")?;
+    eprintln!("{peer}: {} {}", request.method, request.path);
+    let head = match request.method.as_str() {
+        "GET" => false,
+        "HEAD" => true,
+        _ => return respond_error(&mut stream, "405 Method Not Allowed"),
+    };
+
+    if request.path == IPXE_SCRIPT_PATH {
+        // point the script back at whatever address the client used to
+        // reach us
+        let host = match request.host {
+            Some(host) => host,
+            None => stream
+                .local_addr()
+                .context("getting local address")?
+                .to_string(),
+        };
+        let script = boot_script(state, &host)?;
+        write_headers(&mut stream, "200 OK", "text/plain", script.len() as u64)?;
+        if !head {
+            stream
+                .write_all(script.as_bytes())
+                .context("writing response")?;
+        }
+    } else if let (CUSTOM_INITRD_PATH, Some(data)) = (request.path.as_str(), &state.custom_initrd) {
+        write_headers(
+            &mut stream,
+            "200 OK",
+            "application/octet-stream",
+            data.len() as u64,
+        )?;
+        if !head {
+            stream.write_all(data).context("writing response")?;
+        }
+    } else if let Some(&(offset, length)) = state.files.get(&request.path) {
+        let mut iso =
+            File::open(&state.iso_path).with_context(|| format!("opening {}", state.iso_path))?;
+        iso.seek(SeekFrom::Start(offset))
+            .with_context(|| format!("seeking {}", state.iso_path))?;
+        write_headers(&mut stream, "200 OK", "application/octet-stream", length)?;
+        if !head {
+            copy(&mut iso.take(length), &mut stream)
+                .with_context(|| format!("sending {}", request.path))?;
+        }
+    } else {
+        return respond_error(&mut stream, "404 Not Found");
+    }
+    stream.flush().context("writing response")
+}
+
+/// Generate the iPXE script for a client that reached us at `host`.
+fn boot_script(state: &ServeState, host: &str) -> Result<String> {
+    let url = |path: &str| {
+        Url::parse(&format!("http://{host}{path}"))
+            .with_context(|| format!("building URL for host '{host}'"))
+    };
+    let (initrd_url, custom_url) = (url(INITRD_PATH)?, url(CUSTOM_INITRD_PATH)?);
+    let mut initrds = vec![("main", &initrd_url)];
+    if state.custom_initrd.is_some() {
+        initrds.push(("custom", &custom_url));
+    }
+    ipxe_script(
+        &url(KERNEL_PATH)?,
+        &initrds,
+        &url(ROOTFS_PATH)?,
+        &state.kargs,
+    )
+}
+
+/// Parse the request line and the Host header, discarding other headers.
+fn read_request(reader: impl Read) -> Result<Request> {
+    let mut reader = BufReader::new(reader.take(MAX_REQUEST_SIZE));
+    let mut line = String::new();
+    reader
+        .read_line(&mut line)
+        .context("reading request line")?;
+    let mut fields = line.split_ascii_whitespace();
+    let (method, path) = match (fields.next(), fields.next(), fields.next()) {
+        (Some(method), Some(path), Some(version)) if version.starts_with("HTTP/") => {
+            (method.to_string(), path.to_string())
+        }
+        _ => bail!("malformed request line '{}'", line.trim_end()),
+    };
+    // ignore any query string
+    let path = path.split('?').next().unwrap_or_default().to_string();
+
+    let mut host = None;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).context("reading headers")? == 0 {
+            bail!("truncated request headers");
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("host") {
+                host = Some(value.trim().to_string());
+            }
+        }
+    }
+    Ok(Request { method, path, host })
+}
+
+fn write_headers(
+    stream: &mut impl Write,
+    status: &str,
+    content_type: &str,
+    length: u64,
+) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.0 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {length}\r\nConnection: close\r\n\r\n"
+    )
+    .context("writing response headers")
+}
+
+fn respond_error(stream: &mut impl Write, status: &str) -> Result<()> {
+    let body = format!("{status}\n");
+    write_headers(stream, status, "text/plain", body.len() as u64)?;
+    stream
+        .write_all(body.as_bytes())
+        .context("writing response")?;
+    stream.flush().context("writing response")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boot_script() {
+        let mut state = ServeState {
+            iso_path: "/dev/null".into(),
+            files: BTreeMap::new(),
+            custom_initrd: None,
+            kargs: vec!["console=ttyS0".into()],
+        };
+        let script = boot_script(&state, "192.0.2.1:8080").unwrap();
+        assert!(script.contains("initrd --name main http://192.0.2.1:8080/initrd.img\n"));
+        assert!(!script.contains("custom.img"));
+
+        // embedded customizations are loaded after the live initramfs
+        state.custom_initrd = Some(vec![0; 512]);
+        let script = boot_script(&state, "192.0.2.1:8080").unwrap();
+        assert!(script.contains(" initrd=main initrd=custom "), "{script}");
+        assert!(script.contains(
+            "initrd --name main http://192.0.2.1:8080/initrd.img
+initrd --name custom http://192.0.2.1:8080/custom.img
+boot
+"
+        ));
+
+        state.kargs.push("foo=${bar}".into());
+        boot_script(&state, "192.0.2.1:8080").unwrap_err();
+    }
+
+    #[test]
+    fn test_read_request() {
+        assert_eq!(
+            read_request(
+                &b"GET /boot.ipxe?mac=52:54:00:12:34:56 HTTP/1.1\r\nUser-Agent: iPXE/1.21\r\nhost: 192.0.2.1:8080\r\n\r\n"[..]
+            )
+            .unwrap(),
+            Request {
+                method: "GET".into(),
+                path: "/boot.ipxe".into(),
+                host: Some("192.0.2.1:8080".into()),
+            }
+        );
+        assert_eq!(
+            read_request(&b"HEAD /rootfs.img HTTP/1.0\r\n\r\n"[..]).unwrap(),
+            Request {
+                method: "HEAD".into(),
+                path: "/rootfs.img".into(),
+                host: None,
+            }
+        );
+        for bad in [
+            &b""[..],
+            b"GET /\r\n\r\n",
+            b"GET / FTP/1.0\r\n\r\n",
+            b"GET / HTTP/1.1\r\nHost: a\r\n",
+        ] {
+            read_request(bad).unwrap_err();
+        }
+        // oversized headers
+        let mut big = b"GET / HTTP/1.1\r\n".to_vec();
+        big.extend(vec![b'a'; MAX_REQUEST_SIZE as usize]);
+        big.extend(b"\r\n\r\n");
+        read_request(&big[..]).unwrap_err();
+    }
+}
@@ -14,13 +14,19 @@
 
 use anyhow::{bail, Context, Result};
 use nix::unistd::isatty;
+use reqwest::Url;
+use serde::Serialize;
 use std::fs::{write, File, OpenOptions};
 use std::io::{self, copy, BufWriter, Seek, Write};
+use std::os::unix::fs::FileTypeExt;
 use std::os::unix::io::AsRawFd;
 use std::path::Path;
 
+use crate::blockdev::find_active_mounts;
+use crate::download::{image_copy_default, write_image};
 use crate::io::*;
 use crate::iso9660::{self, IsoFs};
+use crate::source::{ImageLocation, RetryPolicy, UrlLocation};
 
 use super::embed::IsoConfig;
 
@@ -31,11 +37,84 @@ pub(super) fn open_live_iso(
 ) -> Result<File> {
     // if output_path is Some(None), we're modifying in place, so we need to
     // open for writing
-    OpenOptions::new()
+    let in_place = matches!(output_path, Some(None));
+    let file = OpenOptions::new()
         .read(true)
-        .write(matches!(output_path, Some(None)))
+        .write(in_place)
         .open(input_path)
-        .with_context(|| format!("opening {}", &input_path))
+        .with_context(|| format!("opening {}", &input_path))?;
+    // a USB stick written from the ISO can be edited in place.  The
+    // ISO9660 parser checks that the whole image fits on the device.
+    if in_place && is_block_device(&file, input_path)? {
+        check_device_not_active(input_path)?;
+    }
+    Ok(file)
+}
+
+pub(super) fn is_block_device(file: &File, path: &str) -> Result<bool> {
+    Ok(file
+        .metadata()
+        .with_context(|| format!("getting metadata for {path}"))?
+        .file_type()
+        .is_block_device())
+}
+
+/// Refuse to modify a device backing the running system, such as the
+/// USB stick we booted from.
+pub(super) fn check_device_not_active(device: &str) -> Result<()> {
+    let mounts = find_active_mounts(device)
+        .with_context(|| format!("checking whether {device} is in use by the running system"))?;
+    if !mounts.is_empty() {
+        bail!(
+            "{} backs {} of the running system",
+            device,
+            mounts.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Like open_live_iso() with an output path, but also accept an http or
+/// https URL, which is fetched into a temporary file.  The ISO must be
+/// signed unless insecure is set.
+pub(super) fn open_live_iso_or_url(
+    input: &str,
+    output_path: Option<&String>,
+    insecure: bool,
+) -> Result<File> {
+    let url = match Url::parse(input) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => url,
+        _ => return open_live_iso(input, Some(output_path)),
+    };
+    if output_path.is_none() {
+        bail!("--output is required when the ISO image is a URL");
+    }
+
+    let location = UrlLocation::new(&url, RetryPolicy::default());
+    eprintln!("{location}");
+    let mut source = location.sources()?.pop().context("no artifacts found")?;
+    if source.signature.is_none() {
+        if insecure {
+            eprintln!("Signature not found; skipping verification as requested");
+        } else {
+            bail!("--insecure not specified and signature not found");
+        }
+    }
+    source.artifact_type = "iso".into();
+    let mut file = tempfile::tempfile().context("creating temporary file")?;
+    let path = Path::new(&source.filename).to_path_buf();
+    write_image(
+        &mut source,
+        &mut file,
+        &path,
+        image_copy_default,
+        false,
+        None,
+        None,
+        VerifyKeys::Production,
+    )
+    .with_context(|| format!("fetching {url}"))?;
+    Ok(file)
 }
 
 pub(super) fn write_live_iso(
@@ -47,11 +126,23 @@ pub(super) fn write_live_iso(
         None => {
             // open_live_iso() opened input for writing
             iso.write(input)?;
+            if is_block_device(input, "ISO image")? {
+                // detect any latent write errors
+                input.sync_all().context("syncing data to disk")?;
+            }
         }
         Some("-") => {
             verify_stdout_not_tty()?;
             iso.stream(input, &mut io::stdout().lock())?;
         }
+        Some(output_path)
+            if Path::new(output_path)
+                .metadata()
+                .is_ok_and(|m| m.file_type().is_block_device()) =>
+        {
+            // the output would be renamed over the device node
+            bail!("Refusing to replace block device {output_path}; use `coreos-installer iso write` to write the ISO to it, then modify it in place");
+        }
         Some(output_path) => {
             let output_dir = Path::new(output_path)
                 .parent()
@@ -118,3 +209,155 @@ pub(super) fn filename(path: &str) -> Result<String> {
         .to_string_lossy()
         .into_owned())
 }
+
+/// Check that a NetworkManager keyfile is syntactically valid.  This only
+/// checks the key file structure, not the keys and values themselves.
+pub(super) fn validate_keyfile(contents: &[u8]) -> Result<()> {
+    let contents = std::str::from_utf8(contents).context("invalid UTF-8")?;
+    let mut have_group = false;
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            if !line.ends_with(']') || line.len() < 3 {
+                bail!("line {}: malformed group header", i + 1);
+            }
+            have_group = true;
+        } else {
+            match line.split_once('=') {
+                Some((key, _)) if !key.trim().is_empty() => (),
+                _ => bail!("line {}: expected key=value", i + 1),
+            }
+            if !have_group {
+                bail!("line {}: key outside of any group", i + 1);
+            }
+        }
+    }
+    if !have_group {
+        bail!("no groups found");
+    }
+    Ok(())
+}
+
+/// Boot files that identify the architecture of a CoreOS live ISO.
+const ISO_ARCHITECTURE_FILES: &[(&str, &str)] = &[
+    ("EFI/BOOT/BOOTX64.EFI", "x86_64"),
+    ("EFI/BOOT/BOOTAA64.EFI", "aarch64"),
+    ("PPC/BOOTINFO.TXT", "ppc64le"),
+    ("GENERIC.INS", "s390x"),
+];
+
+/// Infer the architecture of a live ISO from its boot files.  Returns
+/// None if the architecture isn't recognized.
+pub(super) fn iso_architecture(iso: &mut IsoFs) -> Result<Option<&'static str>> {
+    for &(path, arch) in ISO_ARCHITECTURE_FILES {
+        match iso.get_path(path) {
+            Ok(_) => return Ok(Some(arch)),
+            Err(e) if e.is::<iso9660::NotFound>() => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(None)
+}
+
+/// OS name and version parsed from the volume ID of a CoreOS live ISO,
+/// e.g. "fedora-coreos-39.20231101.3.0" or "rhcos-415.92.202311241643-0".
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(super) struct OsVersion {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Fedora CoreOS stream, if it can be inferred from the version
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<String>,
+}
+
+impl OsVersion {
+    pub fn from_volume_id(volume_id: &str) -> Self {
+        // the version starts at the first dash followed by a digit
+        let split = volume_id
+            .match_indices('-')
+            .map(|(i, _)| i)
+            .find(|&i| volume_id[i + 1..].starts_with(|c: char| c.is_ascii_digit()));
+        let Some(i) = split else {
+            return Self::default();
+        };
+        let (name, version) = (&volume_id[..i], &volume_id[i + 1..]);
+        let stream = if name == "fedora-coreos" {
+            // X.YYYYMMDD.S.N, where S identifies the stream
+            let components: Vec<&str> = version.split('.').collect();
+            match components.as_slice() {
+                [_, _, stream, _] => match *stream {
+                    "1" => Some("next"),
+                    "2" => Some("testing"),
+                    "3" => Some("stable"),
+                    "10" => Some("next-devel"),
+                    "20" => Some("testing-devel"),
+                    "91" => Some("rawhide"),
+                    _ => None,
+                },
+                _ => None,
+            }
+        } else {
+            None
+        };
+        Self {
+            name: Some(name.into()),
+            version: Some(version.into()),
+            stream: stream.map(String::from),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_os_version_from_volume_id() {
+        assert_eq!(
+            OsVersion::from_volume_id("fedora-coreos-39.20231101.3.0"),
+            OsVersion {
+                name: Some("fedora-coreos".into()),
+                version: Some("39.20231101.3.0".into()),
+                stream: Some("stable".into()),
+            }
+        );
+        assert_eq!(
+            OsVersion::from_volume_id("fedora-coreos-40.20240301.20.1"),
+            OsVersion {
+                name: Some("fedora-coreos".into()),
+                version: Some("40.20240301.20.1".into()),
+                stream: Some("testing-devel".into()),
+            }
+        );
+        assert_eq!(
+            OsVersion::from_volume_id("rhcos-415.92.202311241643-0"),
+            OsVersion {
+                name: Some("rhcos".into()),
+                version: Some("415.92.202311241643-0".into()),
+                stream: None,
+            }
+        );
+        assert_eq!(
+            OsVersion::from_volume_id("volume-ID-string"),
+            OsVersion::default()
+        );
+    }
+
+    #[test]
+    fn test_validate_keyfile() {
+        validate_keyfile(b"[connection]\nid=foo\n# comment\n\n[ipv4]\nmethod=auto\n").unwrap();
+        validate_keyfile(b"").unwrap_err();
+        validate_keyfile(b"id=foo\n[connection]\n").unwrap_err();
+        validate_keyfile(b"[connection\nid=foo\n").unwrap_err();
+        validate_keyfile(b"[]\n").unwrap_err();
+        validate_keyfile(b"[connection]\nid\n").unwrap_err();
+        validate_keyfile(b"[connection]\n=foo\n").unwrap_err();
+        validate_keyfile(b"[connection]\nid=\xff\n").unwrap_err();
+    }
+}
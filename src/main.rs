@@ -15,15 +15,31 @@
 use anyhow::Result;
 use clap::Parser;
 
-use libcoreinst::{cmdline, download, install, live, osmet, source};
+use libcoreinst::{cache, cmdline, download, install, live, osmet, signal, source};
 
 use cmdline::*;
 
 fn main() -> Result<()> {
-    match Cmd::parse() {
+    let result = run(Cmd::parse());
+    if let Err(e) = &result {
+        if signal::cancel_signal().is_some() {
+            // exit the way the shell expects after an interrupt
+            eprintln!("Error: {e:?}");
+            signal::reraise_cancel_signal();
+        }
+    }
+    result
+}
+
+fn run(cmd: Cmd) -> Result<()> {
+    match cmd {
         Cmd::Download(c) => download::download(c),
         Cmd::Install(c) => install::install(c),
         Cmd::ListStream(c) => source::list_stream(c),
+        Cmd::Cache(c) => match c {
+            CacheCmd::List(c) => cache::cache_list(c),
+            CacheCmd::Prune(c) => cache::cache_prune(c),
+        },
         Cmd::Iso(c) => match c {
             IsoCmd::Customize(c) => live::iso_customize(c),
             IsoCmd::Embed(c) => live::iso_embed(c),
@@ -33,6 +49,7 @@ fn main() -> Result<()> {
                 IsoIgnitionCmd::Embed(c) => live::iso_ignition_embed(c),
                 IsoIgnitionCmd::Show(c) => live::iso_ignition_show(c),
                 IsoIgnitionCmd::Remove(c) => live::iso_ignition_remove(c),
+                IsoIgnitionCmd::Edit(c) => live::iso_ignition_edit(c),
             },
             IsoCmd::Network(c) => match c {
                 IsoNetworkCmd::Embed(c) => live::iso_network_embed(c),
@@ -47,8 +64,13 @@ fn main() -> Result<()> {
             IsoCmd::Extract(c) => match c {
                 IsoExtractCmd::Pxe(c) => live::iso_extract_pxe(c),
                 IsoExtractCmd::MinimalIso(c) => live::iso_extract_minimal_iso(c),
+                IsoExtractCmd::File(c) => live::iso_extract_file(c),
             },
             IsoCmd::Reset(c) => live::iso_reset(c),
+            IsoCmd::Verify(c) => live::iso_verify(c),
+            IsoCmd::Info(c) => live::iso_info(c),
+            IsoCmd::Write(c) => live::iso_write(c),
+            IsoCmd::SetLabel(c) => live::iso_set_label(c),
         },
         Cmd::Pxe(c) => match c {
             PxeCmd::Customize(c) => live::pxe_customize(c),
@@ -60,6 +82,10 @@ fn main() -> Result<()> {
                 PxeNetworkCmd::Wrap(c) => live::pxe_network_wrap(c),
                 PxeNetworkCmd::Unwrap(c) => live::pxe_network_unwrap(c),
             },
+            PxeCmd::Kargs(c) => match c {
+                PxeKargsCmd::Modify(c) => live::pxe_kargs_modify(c),
+                PxeKargsCmd::Show(c) => live::pxe_kargs_show(c),
+            },
         },
         Cmd::Pack(c) => match c {
             PackCmd::Osmet(c) => osmet::pack_osmet(c),
@@ -72,13 +98,23 @@ fn main() -> Result<()> {
         Cmd::Dev(c) => match c {
             DevCmd::Show(c) => match c {
                 DevShowCmd::Iso(c) => live::dev_show_iso(c),
+                DevShowCmd::IsoDiff(c) => live::dev_show_iso_diff(c),
                 DevShowCmd::Initrd(c) => live::dev_show_initrd(c),
                 DevShowCmd::Fiemap(c) => osmet::dev_show_fiemap(c),
+                DevShowCmd::Platforms(c) => install::dev_show_platforms(c),
+                DevShowCmd::InstallRecord(c) => install::dev_show_install_record(c),
+                DevShowCmd::Disk(c) => install::dev_show_disk(c),
             },
             DevCmd::Extract(c) => match c {
                 DevExtractCmd::Osmet(c) => osmet::dev_extract_osmet(c),
                 DevExtractCmd::Initrd(c) => live::dev_extract_initrd(c),
             },
+            DevCmd::Verify(c) => match c {
+                DevVerifyCmd::Disk(c) => install::dev_verify_disk(c),
+                DevVerifyCmd::Osmet(c) => osmet::dev_verify_osmet(c),
+            },
+            DevCmd::Wipe(c) => install::dev_wipe(c),
+            DevCmd::ServeIso(c) => live::dev_serve_iso(c),
         },
     }
 }
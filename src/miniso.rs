@@ -12,7 +12,6 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::HashMap;
 use std::fs::File;
 use std::io::{copy, Read, Seek, SeekFrom, Write};
 
@@ -43,15 +42,12 @@ struct Table {
 }
 
 impl Table {
-    fn new(
-        full_files: &HashMap<String, iso9660::File>,
-        minimal_files: &HashMap<String, iso9660::File>,
-    ) -> Result<(Self, usize)> {
+    fn new(files: &iso9660::FileMatches) -> Result<(Self, usize)> {
+        if let Some((path, _)) = files.second_only.first() {
+            bail!("missing minimal file {path} in full ISO");
+        }
         let mut entries: Vec<TableEntry> = Vec::new();
-        for (path, minimal_entry) in minimal_files {
-            let full_entry = full_files
-                .get(path)
-                .with_context(|| format!("missing minimal file {path} in full ISO"))?;
+        for (path, full_entry, minimal_entry) in &files.common {
             if full_entry.length != minimal_entry.length {
                 bail!("File {path} has different lengths in full and minimal ISOs");
             }
@@ -146,12 +142,13 @@ pub struct Data {
 }
 
 impl Data {
+    /// Pack the minimal ISO against the full ISO.  `files` must match the
+    /// full ISO against the minimal one, in that order.
     pub fn xzpack(
         miniso: &mut File,
-        full_files: &HashMap<String, iso9660::File>,
-        minimal_files: &HashMap<String, iso9660::File>,
+        files: &iso9660::FileMatches,
     ) -> Result<(Self, usize, u64, u64, u64)> {
-        let (table, extraneous) = Table::new(full_files, minimal_files)?;
+        let (table, extraneous) = Table::new(files)?;
 
         // A `ReadHasher` here would let us wrap the miniso so we calculate the digest as we read.
         let digest = Sha256Digest::from_file(miniso)?;
@@ -12,14 +12,21 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
 use std::ffi::OsStr;
-use std::fs::OpenOptions;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
 use std::os::unix::io::AsRawFd;
 use std::os::unix::io::RawFd;
 
 use anyhow::{bail, Context, Result};
+use openssl::sha::sha256;
 use serde::{Deserialize, Serialize};
 
+use crate::blockdev::Mount;
+use crate::io::{Sha256Digest, BUFFER_SIZE};
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub(super) struct Extent {
     pub logical: u64,
@@ -37,6 +44,179 @@ pub(super) fn fiemap_path(path: &OsStr) -> Result<Vec<Extent>> {
     fiemap(fd).with_context(|| format!("mapping {path:?}"))
 }
 
+/// Strategy for mapping the contents of files on a mounted partition to physical offsets in the
+/// partition.
+pub(super) enum ExtentMapper {
+    /// Ask the filesystem via FIEMAP.
+    Fiemap,
+    /// Find the data ranges of each file with SEEK_DATA/SEEK_HOLE, then locate each block of
+    /// data on the partition by its content. Slower, but works where FIEMAP isn't supported.
+    BlockHash(BlockIndex),
+}
+
+impl ExtentMapper {
+    pub fn new(mount: &Mount, no_fiemap: bool) -> Result<Self> {
+        if no_fiemap {
+            Ok(Self::BlockHash(BlockIndex::new(mount)?))
+        } else {
+            Ok(Self::Fiemap)
+        }
+    }
+
+    pub fn map_path(&self, path: &OsStr) -> Result<Vec<Extent>> {
+        match self {
+            Self::Fiemap => fiemap_path(path),
+            Self::BlockHash(index) => index.map_path(path),
+        }
+    }
+}
+
+/// Index of the blocks of a partition by content digest.
+pub(super) struct BlockIndex {
+    block_size: u64,
+    /// Physical offsets relative to the partition start, sorted.
+    blocks: HashMap<Sha256Digest, Vec<u64>>,
+}
+
+impl BlockIndex {
+    fn new(mount: &Mount) -> Result<Self> {
+        // the preferred I/O size is the filesystem block size on the filesystems we care about
+        let block_size = mount
+            .mountpoint()
+            .metadata()
+            .with_context(|| format!("getting metadata for {:?}", mount.mountpoint()))?
+            .blksize();
+        eprintln!(
+            "Indexing {}-byte blocks of {} by content",
+            block_size,
+            mount.device()
+        );
+        let device = OpenOptions::new()
+            .read(true)
+            .open(mount.device())
+            .with_context(|| format!("opening {}", mount.device()))?;
+        let blocks = Self::index(device, block_size)
+            .with_context(|| format!("indexing blocks of {}", mount.device()))?;
+        Ok(Self { block_size, blocks })
+    }
+
+    fn index(source: impl Read, block_size: u64) -> Result<HashMap<Sha256Digest, Vec<u64>>> {
+        let mut source = BufReader::with_capacity(BUFFER_SIZE.max(block_size as usize), source);
+        let mut blocks: HashMap<Sha256Digest, Vec<u64>> = HashMap::new();
+        let mut buf = vec![0u8; block_size as usize];
+        let mut offset = 0;
+        // a trailing partial block can't hold file data we'd map
+        while read_block(&mut source, &mut buf)? == buf.len() {
+            blocks
+                .entry(Sha256Digest(sha256(&buf)))
+                .or_default()
+                .push(offset);
+            offset += block_size;
+        }
+        Ok(blocks)
+    }
+
+    fn map_path(&self, path: &OsStr) -> Result<Vec<Extent>> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .open(path)
+            .with_context(|| format!("opening {path:?}"))?;
+        self.map_file(&mut file)
+            .with_context(|| format!("mapping {path:?}"))
+    }
+
+    fn map_file(&self, file: &mut File) -> Result<Vec<Extent>> {
+        let bs = self.block_size;
+        let len = file.metadata().context("getting metadata")?.len();
+        let mut extents: Vec<Extent> = Vec::new();
+        let mut buf = vec![0u8; bs as usize];
+        let mut offset = 0;
+        while offset < len {
+            let (data_start, data_end) = match seek_data(file, offset)? {
+                Some(range) => range,
+                None => break,
+            };
+            // data ranges are reported at block granularity, but be defensive
+            let mut logical = data_start - data_start % bs;
+            while logical < data_end {
+                file.seek(SeekFrom::Start(logical))
+                    .context("seeking to block")?;
+                let n = read_block(file, &mut buf)?;
+                // the tail of the last block is zero-filled on disk
+                buf[n..].fill(0);
+                let digest = Sha256Digest(sha256(&buf));
+                if let Some(physical) = self.lookup(&digest, extents.last(), logical) {
+                    match extents.last_mut() {
+                        Some(last)
+                            if last.logical + last.length == logical
+                                && last.physical + last.length == physical =>
+                        {
+                            last.length += bs
+                        }
+                        _ => extents.push(Extent {
+                            logical,
+                            physical,
+                            length: bs,
+                        }),
+                    }
+                }
+                // blocks we can't find are left in the packed image
+                logical += bs;
+            }
+            offset = data_end;
+        }
+        Ok(extents)
+    }
+
+    /// Find a block with the specified digest, preferring one that continues the previous
+    /// extent.
+    fn lookup(&self, digest: &Sha256Digest, last: Option<&Extent>, logical: u64) -> Option<u64> {
+        let candidates = self.blocks.get(digest)?;
+        if let Some(last) = last {
+            if last.logical + last.length == logical {
+                let next = last.physical + last.length;
+                if candidates.binary_search(&next).is_ok() {
+                    return Some(next);
+                }
+            }
+        }
+        candidates.first().copied()
+    }
+}
+
+/// Returns the next range of data at or after `offset`, or None if there's no more data.
+fn seek_data(file: &File, offset: u64) -> Result<Option<(u64, u64)>> {
+    let fd = file.as_raw_fd();
+    let offset: libc::off_t = offset.try_into().context("offset too large")?;
+    let start = unsafe { libc::lseek(fd, offset, libc::SEEK_DATA) };
+    if start < 0 {
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() == Some(libc::ENXIO) {
+            return Ok(None);
+        }
+        return Err(err).context("lseek(SEEK_DATA)");
+    }
+    let end = unsafe { libc::lseek(fd, start, libc::SEEK_HOLE) };
+    if end < 0 {
+        return Err(io::Error::last_os_error()).context("lseek(SEEK_HOLE)");
+    }
+    Ok(Some((start as u64, end as u64)))
+}
+
+/// Reads until the buffer is full or EOF, returning the number of bytes read.
+fn read_block(r: &mut impl Read, buf: &mut [u8]) -> Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match r.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(count) => n += count,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e).context("reading block"),
+        }
+    }
+    Ok(n)
+}
+
 /// Returns the `Extent`s associated with the given file. Note that the physical offsets are
 /// relative to the partition start on which the file resides.
 fn fiemap(fd: RawFd) -> Result<Vec<Extent>> {
@@ -174,3 +354,61 @@ mod ffi {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn block_index() {
+        let bs = 512;
+        let block = |c: u8, n: usize| {
+            let mut v = vec![c; n];
+            v.resize(bs, 0);
+            v
+        };
+        let partition = [
+            block(b'a', bs),
+            block(0, 0),
+            block(b'b', bs),
+            block(b'c', 300),
+            block(0, 0),
+        ]
+        .concat();
+        let index = BlockIndex {
+            block_size: bs as u64,
+            blocks: BlockIndex::index(partition.as_slice(), bs as u64).unwrap(),
+        };
+        assert_eq!(index.blocks.len(), 4);
+
+        let map = |contents: &[u8]| {
+            let mut file = tempfile::tempfile().unwrap();
+            file.write_all(contents).unwrap();
+            index.map_file(&mut file).unwrap()
+        };
+        let extent = |logical, physical, length| Extent {
+            logical,
+            physical,
+            length,
+        };
+
+        // contiguous, with a partial tail block
+        assert_eq!(
+            map(&[vec![b'b'; bs], vec![b'c'; 300]].concat()),
+            vec![extent(0, 1024, 1024)]
+        );
+        // unknown block in the middle
+        assert_eq!(
+            map(&[vec![b'a'; bs], vec![b'x'; bs], vec![b'b'; bs]].concat()),
+            vec![extent(0, 0, 512), extent(1024, 1024, 512)]
+        );
+        // prefer the zero block that continues the extent
+        assert_eq!(
+            map(&[vec![b'c'; 300], vec![0; 212], vec![0; bs]].concat()),
+            vec![extent(0, 1536, 1024)]
+        );
+        // empty file
+        assert_eq!(map(&[]), Vec::new());
+    }
+}
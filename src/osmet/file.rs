@@ -13,12 +13,12 @@
 // limitations under the License.
 
 use std::fs::{File, OpenOptions};
-use std::io::{BufReader, BufWriter, Read};
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
 use std::path::Path;
 
 use anyhow::{bail, Context, Result};
 use bincode::Options;
-use clap::crate_version;
+use clap::{crate_version, ValueEnum};
 use serde::{Deserialize, Serialize};
 use xz2::bufread::XzDecoder;
 
@@ -31,7 +31,38 @@ const OSMET_FILE_HEADER_MAGIC: [u8; 8] = *b"OSMET\0\0\0";
 
 /// Basic versioning. Used as a safety check that we're unpacking something we understand. Bump
 /// this when making changes to the format.
-const OSMET_FILE_VERSION: u32 = 1;
+const OSMET_FILE_VERSION: u32 = 2;
+
+/// Magic header value for osmet container, which bundles complete osmet files for multiple
+/// sector sizes.
+const OSMET_CONTAINER_HEADER_MAGIC: [u8; 8] = *b"OSMETSET";
+
+/// Versioning for the container format. Bump this when making changes to it.
+const OSMET_CONTAINER_VERSION: u32 = 1;
+
+/// Compression format of the packed image in an osmet file.
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OsmetCompression {
+    #[default]
+    Xz,
+    Zstd,
+}
+
+impl OsmetCompression {
+    /// Wrap a reader of the compressed packed image in a decompressor.
+    pub(super) fn decoder<'a>(
+        self,
+        source: impl BufRead + Send + 'a,
+    ) -> Result<Box<dyn Read + Send + 'a>> {
+        Ok(match self {
+            Self::Xz => Box::new(XzDecoder::new(source)),
+            Self::Zstd => Box::new(
+                zstd::stream::read::Decoder::with_buffer(source)
+                    .context("creating zstd decoder")?,
+            ),
+        })
+    }
+}
 
 /// We currently use bincode for serialization. Note bincode does not support backwards compatible
 /// changes well. However we do not currently care about backcompatibility. If that changes, we
@@ -46,10 +77,16 @@ pub(super) struct OsmetFileHeader {
     pub(super) sector_size: u32,
     pub(super) os_description: String,
     pub(super) os_architecture: String,
+    /// Compression format of the packed image following the osmet.
+    pub(super) compression: OsmetCompression,
 }
 
 impl OsmetFileHeader {
-    pub(super) fn new(sector_size: u32, os_description: &str) -> Result<Self> {
+    pub(super) fn new(
+        sector_size: u32,
+        os_description: &str,
+        compression: OsmetCompression,
+    ) -> Result<Self> {
         Ok(Self {
             magic: OSMET_FILE_HEADER_MAGIC,
             version: OSMET_FILE_VERSION,
@@ -68,18 +105,109 @@ impl OsmetFileHeader {
                 .to_str()
                 .context("OS architecture is invalid Unicode")?
                 .into(),
+            compression,
         })
     }
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+struct OsmetContainerHeader {
+    magic: [u8; 8],
+    version: u32,
+    /// For informational purposes only.
+    app_version: String,
+    /// Bundled osmet files, in file order.
+    images: Vec<OsmetContainerEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct OsmetContainerEntry {
+    /// Sector size of the bundled osmet file, duplicated from its header.
+    sector_size: u32,
+    /// Offset of the bundled osmet file, relative to the end of the container header.
+    offset: u64,
+    /// Length of the bundled osmet file.
+    length: u64,
+}
+
+/// Write the osmet file to the specified path, or to stdout if the path
+/// is "-".
 pub(super) fn osmet_file_write(
     path: &Path,
     header: OsmetFileHeader,
     osmet: Osmet,
-    mut xzpacked_image: File,
+    compressed_image: File,
 ) -> Result<()> {
     validate_osmet(&osmet).context("validating before writing")?;
 
+    write_output(path, |f| {
+        osmet_file_write_to(f, header, osmet, compressed_image)
+    })
+}
+
+/// Write an osmet container bundling an osmet file for each image to the specified path, or to
+/// stdout if the path is "-".
+pub(super) fn osmet_container_write(
+    path: &Path,
+    images: Vec<(OsmetFileHeader, Osmet, File)>,
+) -> Result<()> {
+    // serialize each osmet file to a tempfile first so we know its length
+    let mut entries: Vec<OsmetContainerEntry> = Vec::new();
+    let mut files: Vec<File> = Vec::new();
+    let mut offset = 0;
+    for (header, osmet, compressed_image) in images {
+        validate_osmet(&osmet).context("validating before writing")?;
+        if entries.iter().any(|e| e.sector_size == header.sector_size) {
+            bail!("duplicate images for sector size {}", header.sector_size);
+        }
+        let sector_size = header.sector_size;
+        let mut f = BufWriter::with_capacity(
+            BUFFER_SIZE,
+            tempfile::tempfile().context("allocating osmet tempfile")?,
+        );
+        osmet_file_write_to(&mut f, header, osmet, compressed_image)?;
+        let mut f = f.into_inner().context("failed to flush write buffer")?;
+        let length = f
+            .stream_position()
+            .context("getting length of osmet tempfile")?;
+        f.rewind()
+            .context("seeking back to start of osmet tempfile")?;
+        entries.push(OsmetContainerEntry {
+            sector_size,
+            offset,
+            length,
+        });
+        files.push(f);
+        offset += length;
+    }
+
+    let header = OsmetContainerHeader {
+        magic: OSMET_CONTAINER_HEADER_MAGIC,
+        version: OSMET_CONTAINER_VERSION,
+        app_version: crate_version!().into(),
+        images: entries,
+    };
+    write_output(path, |f| {
+        bincoder()
+            .serialize_into(&mut *f, &header)
+            .context("failed to serialize osmet container header")?;
+        for mut file in files {
+            copy(&mut file, f).context("copying osmet file into container")?;
+        }
+        Ok(())
+    })
+}
+
+/// Atomically write output to the specified path, or to stdout if the path is "-".
+fn write_output(path: &Path, write: impl FnOnce(&mut dyn Write) -> Result<()>) -> Result<()> {
+    if path == Path::new("-") {
+        let stdout = io::stdout();
+        let mut f = BufWriter::with_capacity(BUFFER_SIZE, stdout.lock());
+        write(&mut f)?;
+        f.flush().context("flushing stdout")?;
+        return Ok(());
+    }
+
     // would be nice to opportunistically do open(O_TMPFILE) then linkat here, but the tempfile API
     // doesn't provide that API: https://github.com/Stebalien/tempfile/pull/31
     let mut f = BufWriter::with_capacity(
@@ -90,21 +218,32 @@ pub(super) fn osmet_file_write(
             .tempfile_in(path.parent().unwrap())?,
     );
 
+    write(&mut f)?;
+
+    f.into_inner()
+        .context("failed to flush write buffer")?
+        .persist(path)
+        .with_context(|| format!("failed to persist tempfile to {path:?}"))?;
+
+    Ok(())
+}
+
+fn osmet_file_write_to(
+    f: &mut (impl Write + ?Sized),
+    header: OsmetFileHeader,
+    osmet: Osmet,
+    mut compressed_image: File,
+) -> Result<()> {
     let coder = &mut bincoder();
     coder
-        .serialize_into(&mut f, &header)
+        .serialize_into(&mut *f, &header)
         .context("failed to serialize osmet file header")?;
     coder
-        .serialize_into(&mut f, &osmet)
+        .serialize_into(&mut *f, &osmet)
         .context("failed to serialize osmet")?;
 
-    // and followed by the xz-compressed packed image
-    copy(&mut xzpacked_image, &mut f)?;
-
-    f.into_inner()
-        .context("failed to flush write buffer")?
-        .persist(path)
-        .with_context(|| format!("failed to persist tempfile to {path:?}"))?;
+    // and followed by the compressed packed image
+    copy(&mut compressed_image, f)?;
 
     Ok(())
 }
@@ -124,34 +263,139 @@ fn read_and_check_header(f: &mut impl Read) -> Result<OsmetFileHeader> {
     Ok(header)
 }
 
-pub(super) fn osmet_file_read_header(path: &Path) -> Result<OsmetFileHeader> {
-    let mut f = BufReader::with_capacity(
-        BUFFER_SIZE,
-        OpenOptions::new()
-            .read(true)
-            .open(path)
-            .with_context(|| format!("opening {path:?}"))?,
-    );
+/// Reads in the container header, and does some basic sanity checking.
+fn read_and_check_container_header(f: &mut impl Read) -> Result<OsmetContainerHeader> {
+    let header: OsmetContainerHeader = bincoder()
+        .deserialize_from(f)
+        .context("failed to deserialize osmet container")?;
+    if header.magic != OSMET_CONTAINER_HEADER_MAGIC {
+        bail!("not an OSMET container!");
+    }
+    if header.version != OSMET_CONTAINER_VERSION {
+        bail!("incompatible OSMET container version {}", header.version);
+    }
 
-    read_and_check_header(&mut f)
+    Ok(header)
 }
 
-pub(super) fn osmet_file_read(path: &Path) -> Result<(OsmetFileHeader, Osmet, impl Read + Send)> {
-    let mut f = BufReader::with_capacity(
-        BUFFER_SIZE,
-        OpenOptions::new()
-            .read(true)
-            .open(path)
-            .with_context(|| format!("opening {path:?}"))?,
-    );
+/// Picks the container entry for the specified sector size, or the only entry if no sector size
+/// is specified.
+fn select_container_entry(
+    header: &OsmetContainerHeader,
+    sector_size: Option<u32>,
+) -> Result<&OsmetContainerEntry> {
+    match (sector_size, header.images.as_slice()) {
+        (Some(sector_size), images) => images
+            .iter()
+            .find(|e| e.sector_size == sector_size)
+            .with_context(|| format!("no image for sector size {sector_size} in osmet container")),
+        (None, [entry]) => Ok(entry),
+        (None, _) => bail!("osmet container has multiple images; sector size must be specified"),
+    }
+}
+
+fn read_magic(f: &mut impl Read) -> Result<[u8; 8]> {
+    let mut magic = [0u8; 8];
+    f.read_exact(&mut magic)
+        .context("failed to read osmet file magic")?;
+    Ok(magic)
+}
+
+/// Opens the osmet file at the specified path, or stdin if the path is "-". If the file is an
+/// osmet container, returns a reader for the bundled osmet file for the specified sector size.
+fn open_osmet_file(path: &Path, sector_size: Option<u32>) -> Result<Box<dyn Read + Send>> {
+    if path == Path::new("-") {
+        let mut stdin = io::stdin();
+        let magic = read_magic(&mut stdin)?;
+        let mut f = Cursor::new(magic).chain(stdin);
+        if magic != OSMET_CONTAINER_HEADER_MAGIC {
+            return Ok(Box::new(f));
+        }
+        let header = read_and_check_container_header(&mut f)?;
+        let entry = select_container_entry(&header, sector_size)?;
+        copy(&mut (&mut f).take(entry.offset), &mut io::sink())
+            .context("skipping to image in osmet container")?;
+        return Ok(Box::new(f.take(entry.length)));
+    }
+
+    let mut f = OpenOptions::new()
+        .read(true)
+        .open(path)
+        .with_context(|| format!("opening {path:?}"))?;
+    let magic = read_magic(&mut f)?;
+    f.rewind().with_context(|| format!("seeking {path:?}"))?;
+    if magic != OSMET_CONTAINER_HEADER_MAGIC {
+        return Ok(Box::new(f));
+    }
+    let header = read_and_check_container_header(&mut f)?;
+    let entry = select_container_entry(&header, sector_size)?;
+    f.seek(SeekFrom::Current(
+        entry.offset.try_into().context("offset too large")?,
+    ))
+    .with_context(|| format!("seeking {path:?}"))?;
+    Ok(Box::new(f.take(entry.length)))
+}
+
+/// Reads the headers of all the osmet files at the specified path: either the single header of an
+/// osmet file, or the headers of all the osmet files bundled in an osmet container.
+pub(super) fn osmet_file_read_headers(path: &Path) -> Result<Vec<OsmetFileHeader>> {
+    let mut f = OpenOptions::new()
+        .read(true)
+        .open(path)
+        .with_context(|| format!("opening {path:?}"))?;
+    let magic = read_magic(&mut f)?;
+    f.rewind().with_context(|| format!("seeking {path:?}"))?;
+    if magic != OSMET_CONTAINER_HEADER_MAGIC {
+        return Ok(vec![read_and_check_header(&mut BufReader::new(f))?]);
+    }
+
+    let header = read_and_check_container_header(&mut f)?;
+    let start = f
+        .stream_position()
+        .with_context(|| format!("seeking {path:?}"))?;
+    let mut headers = Vec::new();
+    for entry in &header.images {
+        f.seek(SeekFrom::Start(start + entry.offset))
+            .with_context(|| format!("seeking {path:?}"))?;
+        let image_header = read_and_check_header(&mut BufReader::new(&mut f))?;
+        if image_header.sector_size != entry.sector_size {
+            bail!(
+                "osmet container entry has sector size {} but image has {}",
+                entry.sector_size,
+                image_header.sector_size
+            );
+        }
+        headers.push(image_header);
+    }
+    Ok(headers)
+}
+
+/// Read the osmet file from the specified path, or from stdin if the path
+/// is "-". If the file is an osmet container, read the bundled osmet file
+/// for the specified sector size.
+pub(super) fn osmet_file_read(
+    path: &Path,
+    sector_size: Option<u32>,
+) -> Result<(OsmetFileHeader, Osmet, impl Read + Send)> {
+    let mut f = BufReader::with_capacity(BUFFER_SIZE, open_osmet_file(path, sector_size)?);
 
     let header = read_and_check_header(&mut f)?;
+    if let Some(sector_size) = sector_size {
+        if header.sector_size != sector_size {
+            bail!(
+                "osmet file has sector size {} but expected {}",
+                header.sector_size,
+                sector_size
+            );
+        }
+    }
     let osmet: Osmet = bincoder()
         .deserialize_from(&mut f)
         .context("failed to deserialize osmet file")?;
 
     validate_osmet(&osmet).context("validating after reading")?;
-    Ok((header, osmet, XzDecoder::new(f)))
+    let packed_image = header.compression.decoder(f)?;
+    Ok((header, osmet, packed_image))
 }
 
 fn validate_osmet(osmet: &Osmet) -> Result<()> {
@@ -206,3 +450,62 @@ fn verify_canonical(mappings: &[Mapping]) -> Result<u64> {
 
     Ok(cursor)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_image(sector_size: u32, size: u64) -> (OsmetFileHeader, Osmet, File) {
+        let header = OsmetFileHeader::new(sector_size, "test OS", OsmetCompression::Xz).unwrap();
+        let osmet = Osmet {
+            partitions: vec![OsmetPartition {
+                start_offset: 0,
+                end_offset: size,
+                mappings: Vec::new(),
+            }],
+            checksum: Sha256Digest::default(),
+            size,
+        };
+        let mut image = tempfile::tempfile().unwrap();
+        image.write_all(b"packed").unwrap();
+        image.rewind().unwrap();
+        (header, osmet, image)
+    }
+
+    #[test]
+    fn test_osmet_container() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // plain osmet file
+        let path = dir.path().join("plain.osmet");
+        let (header, osmet, image) = test_image(512, 1024);
+        osmet_file_write(&path, header, osmet, image).unwrap();
+        let headers = osmet_file_read_headers(&path).unwrap();
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers[0].sector_size, 512);
+        osmet_file_read(&path, None).unwrap();
+        osmet_file_read(&path, Some(512)).unwrap();
+        assert!(osmet_file_read(&path, Some(4096)).is_err());
+
+        // container
+        let path = dir.path().join("combined.osmet");
+        osmet_container_write(&path, vec![test_image(512, 1024), test_image(4096, 2048)]).unwrap();
+        let headers = osmet_file_read_headers(&path).unwrap();
+        assert_eq!(
+            headers.iter().map(|h| h.sector_size).collect::<Vec<_>>(),
+            vec![512, 4096]
+        );
+        let (header, osmet, _) = osmet_file_read(&path, Some(512)).unwrap();
+        assert_eq!(header.sector_size, 512);
+        assert_eq!(osmet.size, 1024);
+        let (header, osmet, _) = osmet_file_read(&path, Some(4096)).unwrap();
+        assert_eq!(header.sector_size, 4096);
+        assert_eq!(osmet.size, 2048);
+        assert!(osmet_file_read(&path, None).is_err());
+        assert!(osmet_file_read(&path, Some(1024)).is_err());
+
+        // duplicate sector sizes
+        osmet_container_write(&path, vec![test_image(512, 1024), test_image(512, 1024)])
+            .unwrap_err();
+    }
+}
@@ -18,12 +18,12 @@
 // - extents for which we already have a mapping are "skipped"
 // - the "packed" image refers to the metal image with all the extents for which we already have a
 //   mapping skipped
-// - the "xzpacked" image is the packed image compressed with xz
+// - the "compressed" image is the packed image compressed with xz or zstd
 
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::{File, OpenOptions};
-use std::io::{copy, Seek, SeekFrom, Write};
+use std::io::{self, copy, BufWriter, Read, Seek, SeekFrom, Write};
 use std::os::unix::fs::FileTypeExt;
 use std::path::{Path, PathBuf};
 
@@ -31,11 +31,13 @@ use anyhow::{bail, Context, Result};
 use nix::mount;
 use serde::{Deserialize, Serialize};
 use walkdir::WalkDir;
+use xz2::stream::{Check, MtStreamBuilder};
 use xz2::write::XzEncoder;
 
 use crate::blockdev::*;
 use crate::cmdline::*;
 use crate::io::*;
+use crate::util::set_die_on_sigpipe;
 
 mod fiemap;
 mod file;
@@ -48,8 +50,15 @@ use crate::osmet::io_helpers::*;
 use crate::osmet::unpacker::*;
 
 // just re-export OsmetUnpacker
+pub use crate::osmet::file::OsmetCompression;
 pub use crate::osmet::unpacker::OsmetUnpacker;
 
+/// Files at or below this size aren't worth matching up with OSTree objects. The 1024 is chosen
+/// semi-arbitrarily; really, as long as the file is larger than the size of one serialized
+/// `Mapping` (assuming no fragmentation), which is 56 bytes, we save space. But we're not
+/// guaranteed an OSTree object match, and incur more overhead for diminishing returns.
+const MIN_MATCHED_FILE_SIZE: u64 = 1024;
+
 #[derive(Serialize, Deserialize, Debug)]
 struct Mapping {
     extent: Extent,
@@ -90,35 +99,89 @@ pub fn dev_show_fiemap(config: DevShowFiemapConfig) -> Result<()> {
 }
 
 pub fn pack_osmet(config: PackOsmetConfig) -> Result<()> {
+    let output = Path::new(&config.output);
+    let repo = config.repo.as_deref().map(Path::new);
+
+    if let (Some(device_512), Some(device_4k)) = (&config.device_512, &config.device_4k) {
+        // pack both sector sizes into a single osmet container
+        let mut images = Vec::new();
+        for (device, checksum, expected_sector_size) in [
+            (device_512, &config.checksum, 512),
+            (device_4k, config.checksum_4k.as_ref().unwrap(), 4096),
+        ] {
+            let sector_size = get_sector_size_for_path(Path::new(device))?.get();
+            if sector_size != expected_sector_size {
+                bail!(
+                    "{} has {}-byte sectors; expected {}",
+                    device,
+                    sector_size,
+                    expected_sector_size
+                );
+            }
+            eprintln!("Packing {sector_size}-byte sector image from {device}");
+            let source = open_pack_source(device, None)?;
+            images.push(pack_osmet_image(device, source, checksum, repo, config)?);
+        }
+        osmet_container_write(output, images)?;
+    } else {
+        let device = config
+            .device
+            .as_deref()
+            .context("source device is required")?;
+        let source = open_pack_source(config.image_file.as_deref().unwrap_or(device), config.size)?;
+        let (header, osmet, compressed_image) =
+            pack_osmet_image(device, source, &config.checksum, repo, config)?;
+        osmet_file_write(output, header, osmet, compressed_image)?;
+    }
+    eprintln!("Packing successful!");
+
+    Ok(())
+}
+
+/// Generate the osmet for the specified device, and the packed image from the image contents.
+fn pack_osmet_image(
+    device: &str,
+    (mut source, size): (PackSource, u64),
+    expected_checksum: &str,
+    repo: Option<&Path>,
+    config: &PackOsmetConfig,
+) -> Result<(OsmetFileHeader, Osmet, File)> {
     // First, mount the two main partitions we want to suck out data from: / and /boot. Note
     // MS_RDONLY; this also ensures that the partition isn't already mounted rw elsewhere.
-    let disk = Disk::new(&config.device)?;
+    let disk = Disk::new(device)?;
     let boot = disk.mount_partition_by_label("boot", mount::MsFlags::MS_RDONLY)?;
     let root = disk.mount_partition_by_label("root", mount::MsFlags::MS_RDONLY)?;
+    let boot_mapper = ExtentMapper::new(&boot, config.no_fiemap)?;
+    let root_mapper = ExtentMapper::new(&root, config.no_fiemap)?;
 
     // now, we do a first scan of the boot partition and pick up files over a certain size
     let boot_files = prescan_boot_partition(&boot)?;
 
     // generate the primary OSTree object <--> disk block mappings, and also try to match up boot
     // files with OSTree objects
-    let (root_partition, mapped_boot_files) = scan_root_partition(&root, boot_files)?;
+    let (root_partition, mapped_boot_files) =
+        scan_root_partition(&root, &root_mapper, boot_files, repo)?;
 
-    let boot_partition = scan_boot_partition(&boot, mapped_boot_files)?;
+    let boot_partition = scan_boot_partition(&boot, &boot_mapper, mapped_boot_files)?;
 
     let partitions = vec![boot_partition, root_partition];
 
     // create a first tempfile to store the packed image
     eprintln!("Packing image");
-    let (mut xzpacked_image, size) =
-        write_xzpacked_image_to_file(Path::new(&config.device), &partitions, config.fast)?;
+    let mut compressed_image =
+        write_compressed_image_to_file(&mut source, size, &partitions, config)?;
 
     // verify that re-packing will yield the expected checksum
     eprintln!("Verifying that repacked image matches digest");
-    let (checksum, unpacked_size) =
-        get_unpacked_image_digest(&mut xzpacked_image, &partitions, &root)?;
-    xzpacked_image
+    let (checksum, unpacked_size) = get_unpacked_image_digest(
+        &mut compressed_image,
+        config.compression,
+        &partitions,
+        &root,
+    )?;
+    compressed_image
         .rewind()
-        .context("seeking back to start of xzpacked image")?;
+        .context("seeking back to start of compressed image")?;
 
     if unpacked_size != size {
         bail!(
@@ -129,16 +192,16 @@ pub fn pack_osmet(config: PackOsmetConfig) -> Result<()> {
     }
 
     let checksum_str = checksum.to_hex_string()?;
-    if checksum_str != config.checksum {
+    if checksum_str != expected_checksum {
         bail!(
             "unpacking test: got checksum {} but expected {}",
             checksum_str,
-            &config.checksum
+            expected_checksum
         );
     }
 
-    let sector_size = get_sector_size_for_path(Path::new(&config.device))?.get();
-    let header = OsmetFileHeader::new(sector_size, &config.description)?;
+    let sector_size = get_sector_size_for_path(Path::new(device))?.get();
+    let header = OsmetFileHeader::new(sector_size, &config.description, config.compression)?;
 
     // create final Osmet object to serialize
     let osmet = Osmet {
@@ -147,32 +210,148 @@ pub fn pack_osmet(config: PackOsmetConfig) -> Result<()> {
         size,
     };
 
-    osmet_file_write(Path::new(&config.output), header, osmet, xzpacked_image)?;
-    eprintln!("Packing successful!");
+    Ok((header, osmet, compressed_image))
+}
 
-    Ok(())
+/// Source of the raw disk image contents to pack.
+enum PackSource {
+    /// A seekable file or block device.
+    File(File),
+    /// A non-seekable stream, such as stdin.
+    Stream(Box<dyn Read>),
+}
+
+impl PackSource {
+    /// Skip over the next `n` bytes of the image.
+    fn skip(&mut self, n: u64, buf: &mut [u8]) -> Result<()> {
+        match self {
+            Self::File(f) => {
+                f.seek(SeekFrom::Current(n.try_into().context("skip too large")?))
+                    .context("seeking over extent")?;
+            }
+            Self::Stream(r) => {
+                copy_exactly_n(r, &mut io::sink(), n, buf).context("reading over extent")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Read for PackSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::File(f) => f.read(buf),
+            Self::Stream(r) => r.read(buf),
+        }
+    }
+}
+
+/// Open the image contents to pack, and determine their size.  These are
+/// usually read from the source device, but can also be read from a file
+/// or stdin with identical contents.
+fn open_pack_source(path: &str, size: Option<u64>) -> Result<(PackSource, u64)> {
+    if path == "-" {
+        let size = size.context("--size is required when reading image from stdin")?;
+        return Ok((PackSource::Stream(Box::new(io::stdin())), size));
+    }
+    let f = OpenOptions::new()
+        .read(true)
+        .open(path)
+        .with_context(|| format!("opening {path:?}"))?;
+    let size = match size {
+        Some(size) => size,
+        None => {
+            let metadata = f
+                .metadata()
+                .with_context(|| format!("getting metadata for {path:?}"))?;
+            if metadata.file_type().is_block_device() {
+                get_block_device_size(&f)
+                    .with_context(|| format!("querying block device size of {path:?}"))?
+                    .get()
+            } else {
+                metadata.len()
+            }
+        }
+    };
+    Ok((PackSource::File(f), size))
 }
 
 pub fn dev_extract_osmet(config: DevExtractOsmetConfig) -> Result<()> {
-    // open output device for writing
-    let mut dev = OpenOptions::new()
-        .write(true)
-        .open(Path::new(&config.device))
-        .with_context(|| format!("opening {:?}", &config.device))?;
-
-    if !dev
-        .metadata()
-        .with_context(|| format!("getting metadata for {:?}", &config.device))?
-        .file_type()
-        .is_block_device()
-    {
-        bail!("{:?} is not a block device", &config.device);
+    let osmet = Path::new(&config.osmet);
+    let repo = Path::new(&config.repo);
+
+    match (&config.output, &config.device) {
+        (Some(output), _) if output == "-" => {
+            // we're writing to a pipe; exit quietly if the reader goes away
+            set_die_on_sigpipe()?;
+            let stdout = io::stdout();
+            let mut out = BufWriter::with_capacity(BUFFER_SIZE, stdout.lock());
+            OsmetUnpacker::unpack_to_writer(osmet, repo, config.sector_size, &mut out)?;
+            out.flush().context("flushing stdout")?;
+        }
+        (Some(output), _) => {
+            let mut out = BufWriter::with_capacity(
+                BUFFER_SIZE,
+                OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(output)
+                    .with_context(|| format!("opening {output:?}"))?,
+            );
+            let result = OsmetUnpacker::unpack_to_writer(osmet, repo, config.sector_size, &mut out)
+                .and_then(|_| out.flush().context("flushing output file"));
+            if result.is_err() {
+                drop(out);
+                let _ = std::fs::remove_file(output);
+            }
+            result.with_context(|| format!("unpacking to {output}"))?;
+        }
+        (None, Some(device)) => {
+            // open output device for writing
+            let mut dev = OpenOptions::new()
+                .write(true)
+                .open(Path::new(device))
+                .with_context(|| format!("opening {device:?}"))?;
+
+            if !dev
+                .metadata()
+                .with_context(|| format!("getting metadata for {device:?}"))?
+                .file_type()
+                .is_block_device()
+            {
+                bail!("{:?} is not a block device", device);
+            }
+
+            OsmetUnpacker::unpack_to_writer(osmet, repo, config.sector_size, &mut dev)
+                .with_context(|| format!("copying to block device {device}"))?;
+        }
+        (None, None) => bail!("either --output or a destination device is required"),
     }
 
-    let mut unpacker = OsmetUnpacker::new(Path::new(&config.osmet), Path::new(&config.repo))?;
-    copy(&mut unpacker, &mut dev)
-        .with_context(|| format!("copying to block device {}", &config.device))?;
+    Ok(())
+}
+
+pub fn dev_verify_osmet(config: DevVerifyOsmetConfig) -> Result<()> {
+    let path = Path::new(&config.osmet);
+    let repo = Path::new(&config.repo);
 
+    // check all the headers, even if we're only verifying one image
+    let headers = osmet_file_read_headers(path)?;
+    let sector_sizes = match config.sector_size {
+        Some(sector_size) => vec![sector_size],
+        None => headers.iter().map(|h| h.sector_size).collect(),
+    };
+    for sector_size in sector_sizes {
+        let (header, osmet, packed_image) = osmet_file_read(path, Some(sector_size))?;
+        eprintln!(
+            "Verifying {} image for {}-byte sectors ({} bytes)",
+            header.os_description, sector_size, osmet.size
+        );
+        verify_osmet(osmet, packed_image, repo)
+            .with_context(|| format!("verifying image for {sector_size}-byte sectors"))?;
+    }
+    eprintln!("Verification succeeded.");
     Ok(())
 }
 
@@ -188,9 +367,11 @@ pub fn find_matching_osmet_in_dir(
             continue;
         }
 
-        let header = osmet_file_read_header(entry.path())?;
-        if header.os_architecture == architecture && header.sector_size == sector_size {
-            return Ok(Some((entry.into_path(), header.os_description)));
+        // osmet containers bundle images for multiple sector sizes
+        for header in osmet_file_read_headers(entry.path())? {
+            if header.os_architecture == architecture && header.sector_size == sector_size {
+                return Ok(Some((entry.into_path(), header.os_description)));
+            }
         }
     }
 
@@ -199,7 +380,9 @@ pub fn find_matching_osmet_in_dir(
 
 fn scan_root_partition(
     root: &Mount,
+    mapper: &ExtentMapper,
     mut boot_files: HashMap<u64, PathBuf>,
+    repo: Option<&Path>,
 ) -> Result<(OsmetPartition, HashMap<PathBuf, Sha256Digest>)> {
     // query the trivial stuff first
     let (start_offset, end_offset) = root.get_partition_offsets()?;
@@ -213,6 +396,16 @@ fn scan_root_partition(
 
     let objects_dir = root.mountpoint().join("ostree/repo/objects");
 
+    // If the image will be unpacked from a different repo, objects that repo doesn't have can
+    // only be mapped by matching their contents against its objects, so index them by size.
+    let reference = repo
+        .map(|repo| index_repo_objects(&repo.join("objects")))
+        .transpose()?;
+    let reference_objects: Option<HashSet<&Sha256Digest>> = reference
+        .as_ref()
+        .map(|index| index.values().flatten().map(|(_, object)| object).collect());
+    let mut unmatched_objects: Vec<PathBuf> = Vec::new();
+
     let mut mappings: Vec<Mapping> = vec![];
     let mut mapped_file_count = 0;
     let mut empty_file_count = 0;
@@ -227,7 +420,7 @@ fn scan_root_partition(
             continue;
         }
 
-        let extents = fiemap_path(entry.path().as_os_str())?;
+        let extents = mapper.map_path(entry.path().as_os_str())?;
         if extents.is_empty() {
             empty_file_count += 1;
             continue;
@@ -236,6 +429,13 @@ fn scan_root_partition(
         let object = object_path_to_checksum(entry.path())
             .with_context(|| format!("invalid object path {:?}", entry.path()))?;
 
+        if let Some(reference_objects) = &reference_objects {
+            if !reference_objects.contains(&object) {
+                unmatched_objects.push(entry.into_path());
+                continue;
+            }
+        }
+
         for extent in extents {
             mappings.push(Mapping {
                 extent,
@@ -243,11 +443,12 @@ fn scan_root_partition(
             });
         }
 
-        // and check if this matches a boot file
         let len = entry
             .metadata()
             .with_context(|| format!("getting metadata for {:?}", entry.path()))?
             .len();
+
+        // and check if this matches a boot file
         if let Entry::Occupied(boot_entry) = boot_files.entry(len) {
             // we can't use Entry::or_insert_with() here because from_path() is fallible
             let boot_file_digest = match cached_boot_files_digests.entry(len) {
@@ -274,6 +475,24 @@ fn scan_root_partition(
         mapped_boot_files.len()
     );
 
+    if let (Some(repo), Some(index)) = (repo, &reference) {
+        let matched = match_objects_by_content(&unmatched_objects, index)?;
+        eprintln!(
+            "Total OSTree objects missing from {}: {} ({} matched by content)",
+            repo.display(),
+            unmatched_objects.len(),
+            matched.len()
+        );
+        for (path, object) in matched {
+            for extent in mapper.map_path(path.as_os_str())? {
+                mappings.push(Mapping {
+                    extent,
+                    object: object.clone(),
+                });
+            }
+        }
+    }
+
     canonicalize(&mut mappings);
 
     // would be cool to detect and report fragmented vs sparse files here too
@@ -289,6 +508,73 @@ fn scan_root_partition(
     ))
 }
 
+/// Index the file objects in an OSTree repo by size, for matching up with objects by content.
+fn index_repo_objects(objects_dir: &Path) -> Result<HashMap<u64, Vec<(PathBuf, Sha256Digest)>>> {
+    let mut index: HashMap<u64, Vec<(PathBuf, Sha256Digest)>> = HashMap::new();
+    for entry in WalkDir::new(objects_dir) {
+        let entry = entry.with_context(|| format!("walking {objects_dir:?}"))?;
+
+        if !entry.file_type().is_file() || entry.path().extension() != Some("file".as_ref()) {
+            continue;
+        }
+
+        // index everything, even objects too small to be worth matching; the index also
+        // tells us which objects the repo has
+        let len = entry
+            .metadata()
+            .with_context(|| format!("getting metadata for {:?}", entry.path()))?
+            .len();
+        let object = object_path_to_checksum(entry.path())
+            .with_context(|| format!("invalid object path {:?}", entry.path()))?;
+        index
+            .entry(len)
+            .or_default()
+            .push((entry.into_path(), object));
+    }
+    Ok(index)
+}
+
+/// Find an object in the index with the same contents as each file, and return the files that
+/// have one along with the matching object. This is essentially the same matching we do for boot
+/// files, except that there can be multiple candidate objects of the same size.
+fn match_objects_by_content(
+    files: &[PathBuf],
+    objects_by_size: &HashMap<u64, Vec<(PathBuf, Sha256Digest)>>,
+) -> Result<Vec<(PathBuf, Sha256Digest)>> {
+    // content digests of indexed objects, computed only when there's a potential match
+    let mut cached_object_digests: HashMap<&Path, Sha256Digest> = HashMap::new();
+
+    let mut matched = Vec::new();
+    for file in files {
+        let len = file
+            .metadata()
+            .with_context(|| format!("getting metadata for {file:?}"))?
+            .len();
+        if len <= MIN_MATCHED_FILE_SIZE {
+            // leave it as packed data
+            continue;
+        }
+        let candidates = match objects_by_size.get(&len) {
+            Some(candidates) => candidates,
+            None => continue,
+        };
+
+        let digest = Sha256Digest::from_path(file)?;
+        for (path, object) in candidates {
+            // we can't use Entry::or_insert_with() here because from_path() is fallible
+            let object_digest = match cached_object_digests.entry(path) {
+                Entry::Vacant(e) => e.insert(Sha256Digest::from_path(path)?),
+                Entry::Occupied(e) => e.into_mut(),
+            };
+            if *object_digest == digest {
+                matched.push((file.clone(), object.clone()));
+                break;
+            }
+        }
+    }
+    Ok(matched)
+}
+
 fn prescan_boot_partition(boot: &Mount) -> Result<HashMap<u64, PathBuf>> {
     let mut files: HashMap<u64, PathBuf> = HashMap::new();
 
@@ -304,11 +590,7 @@ fn prescan_boot_partition(boot: &Mount) -> Result<HashMap<u64, PathBuf>> {
             .with_context(|| format!("getting metadata for {:?}", entry.path()))?
             .len();
 
-        // The 1024 is chosen semi-arbitrarily; really, as long as the file is larger than the size
-        // of one serialized `Mapping` (assuming no fragmentation), which is 56 bytes, we save
-        // space. But we're not guaranteed an OSTree object match, and incur more overhead for
-        // diminishing returns.
-        if len > 1024 {
+        if len > MIN_MATCHED_FILE_SIZE {
             files.entry(len).or_insert_with(|| entry.into_path());
         }
     }
@@ -318,6 +600,7 @@ fn prescan_boot_partition(boot: &Mount) -> Result<HashMap<u64, PathBuf>> {
 
 fn scan_boot_partition(
     boot: &Mount,
+    mapper: &ExtentMapper,
     mut boot_files: HashMap<PathBuf, Sha256Digest>,
 ) -> Result<OsmetPartition> {
     // query the trivial stuff first
@@ -326,7 +609,7 @@ fn scan_boot_partition(
     let mut mappings: Vec<Mapping> = vec![];
 
     for (path, object) in boot_files.drain() {
-        for extent in fiemap_path(path.as_path().as_os_str())? {
+        for extent in mapper.map_path(path.as_path().as_os_str())? {
             mappings.push(Mapping {
                 extent,
                 object: object.clone(),
@@ -346,82 +629,109 @@ fn scan_boot_partition(
 }
 
 /// Writes the compressed disk image, with the extents for which we have mappings for skipped.
-fn write_xzpacked_image_to_file(
-    block_device: &Path,
+fn write_compressed_image_to_file(
+    source: &mut PackSource,
+    size: u64,
     partitions: &[OsmetPartition],
-    fast: bool,
-) -> Result<(File, u64)> {
-    let mut xz_tmpf = XzEncoder::new(
-        // ideally this would use O_TMPFILE, but since tempfile *needs* to create a named tempfile,
-        // let's give it a descriptive name and extension
-        tempfile::Builder::new()
-            .prefix("coreos-installer-xzpacked")
-            .suffix(".raw.xz")
-            .tempfile()
-            .context("allocating packed image tempfile")?
-            // and here we delete it on disk so we just have an fd to it
-            .into_file(),
-        if fast { 0 } else { 9 },
-    );
-
-    let mut dev = OpenOptions::new()
-        .read(true)
-        .open(block_device)
-        .with_context(|| format!("opening {block_device:?}"))?;
-
-    let total_bytes_skipped = write_packed_image(&mut dev, &mut xz_tmpf, partitions)?;
-
-    xz_tmpf.try_finish().context("trying to finish xz stream")?;
+    config: &PackOsmetConfig,
+) -> Result<File> {
+    // ideally this would use O_TMPFILE, but since tempfile *needs* to create a named tempfile,
+    // let's give it a descriptive name
+    let mut tmpf = tempfile::Builder::new()
+        .prefix("coreos-installer-packed")
+        .suffix(".raw")
+        .tempfile()
+        .context("allocating packed image tempfile")?
+        // and here we delete it on disk so we just have an fd to it
+        .into_file();
+
+    let (total_bytes_written, total_bytes_skipped) = match config.compression {
+        OsmetCompression::Xz => {
+            let threads = match config.threads {
+                Some(threads) => threads.get(),
+                None => std::thread::available_parallelism()
+                    .map(|n| n.get() as u32)
+                    .unwrap_or(1),
+            };
+            // the multi-threaded encoder splits the stream into independently
+            // compressed blocks; the decompressor handles those transparently
+            let stream = MtStreamBuilder::new()
+                .threads(threads)
+                .preset(if config.fast { 0 } else { 9 })
+                .check(Check::Crc64)
+                .encoder()
+                .context("creating XZ encoder")?;
+            let mut encoder = XzEncoder::new_stream(&mut tmpf, stream);
+            let totals = write_packed_image(source, &mut encoder, partitions)?;
+            encoder.finish().context("finishing xz stream")?;
+            totals
+        }
+        OsmetCompression::Zstd => {
+            let mut encoder =
+                zstd::stream::write::Encoder::new(&mut tmpf, if config.fast { 1 } else { 19 })
+                    .context("creating zstd encoder")?;
+            encoder
+                .include_checksum(true)
+                .context("enabling zstd checksum")?;
+            let totals = write_packed_image(source, &mut encoder, partitions)?;
+            encoder.finish().context("finishing zstd stream")?;
+            totals
+        }
+    };
 
-    // sanity check that the number of bytes written + packed match up with block device size
-    let blksize = get_block_device_size(&dev)
-        .with_context(|| format!("querying block device size of {block_device:?}"))?;
-    let total_bytes_written = xz_tmpf.total_in();
-    if total_bytes_written + total_bytes_skipped != blksize.get() {
+    // sanity check that the number of bytes written + packed match up with image size
+    if total_bytes_written + total_bytes_skipped != size {
         bail!(
-            "bytes written + bytes skipped != block device size: {} + {} vs {}",
+            "bytes written + bytes skipped != image size: {} + {} vs {}",
             total_bytes_written,
             total_bytes_skipped,
-            blksize
+            size
         );
     }
 
     eprintln!("Total bytes skipped: {total_bytes_skipped}");
     eprintln!("Total bytes written: {total_bytes_written}");
-    eprintln!("Total bytes written (compressed): {}", xz_tmpf.total_out());
+    eprintln!(
+        "Total bytes written (compressed): {}",
+        tmpf.stream_position()
+            .context("getting size of packed image tempfile")?
+    );
 
-    let mut tmpf = xz_tmpf.finish().context("finishing xz stream")?;
     tmpf.rewind().context("seeking back to start of tempfile")?;
 
-    Ok((tmpf, blksize.get()))
+    Ok(tmpf)
 }
 
+/// Returns the number of bytes written and skipped.
 fn write_packed_image(
-    dev: &mut File,
+    dev: &mut PackSource,
     w: &mut impl Write,
     partitions: &[OsmetPartition],
-) -> Result<u64> {
+) -> Result<(u64, u64)> {
     let mut buf = [0u8; 8192];
 
     let mut cursor: u64 = 0;
+    let mut total_bytes_written: u64 = 0;
     let mut total_bytes_skipped: u64 = 0;
     for (i, partition) in partitions.iter().enumerate() {
         // first copy everything up to the start of the partition
         assert!(partition.start_offset >= cursor);
-        copy_exactly_n(dev, w, partition.start_offset - cursor, &mut buf)?;
-        total_bytes_skipped += write_packed_image_partition(dev, w, partition, &mut buf)
+        total_bytes_written += copy_exactly_n(dev, w, partition.start_offset - cursor, &mut buf)?;
+        let skipped = write_packed_image_partition(dev, w, partition, &mut buf)
             .with_context(|| format!("packing partition {i}"))?;
+        total_bytes_written += partition.end_offset - partition.start_offset - skipped;
+        total_bytes_skipped += skipped;
         cursor = partition.end_offset;
     }
 
     // and finally write out the remainder of the disk
-    copy(dev, w).context("copying remainder of disk")?;
+    total_bytes_written += copy(dev, w).context("copying remainder of disk")?;
 
-    Ok(total_bytes_skipped)
+    Ok((total_bytes_written, total_bytes_skipped))
 }
 
 fn write_packed_image_partition(
-    dev: &mut File,
+    dev: &mut PackSource,
     w: &mut impl Write,
     partition: &OsmetPartition,
     buf: &mut [u8],
@@ -440,7 +750,7 @@ fn write_packed_image_partition(
         }
 
         // this is the crucial space-saving step; we skip over the extent we have a mapping for
-        dev.seek(SeekFrom::Current(mapping.extent.length.try_into().unwrap()))
+        dev.skip(mapping.extent.length, buf)
             .with_context(|| format!("while skipping extent: {:?}", mapping.extent))?;
         total_bytes_skipped += mapping.extent.length;
         cursor += mapping.extent.length;
@@ -608,4 +918,96 @@ mod tests {
             }
         );
     }
+
+    /// Write an object named by `checksum` into a fake repo objects dir.
+    fn write_object(objects_dir: &Path, checksum: &str, contents: &[u8]) -> PathBuf {
+        let dir = objects_dir.join(&checksum[..2]);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{}.file", &checksum[2..]));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_index_repo_objects() {
+        let dir = tempfile::tempdir().unwrap();
+        let objects = dir.path().join("objects");
+        let a = "aa".repeat(32);
+        let b = "bb".repeat(32);
+        let c = "cc".repeat(32);
+        let d = "dd".repeat(32);
+        let a_path = write_object(&objects, &a, &[1; 2048]);
+        let b_path = write_object(&objects, &b, &[2; 2048]);
+        let c_path = write_object(&objects, &c, &[3; 4096]);
+        // small objects are indexed too
+        let d_path = write_object(&objects, &d, &[4; 1024]);
+        // not a file object
+        std::fs::write(
+            objects.join("dd").join(format!("{}.dirtree", &d[2..])),
+            [5; 2048],
+        )
+        .unwrap();
+
+        let mut index = index_repo_objects(&objects).unwrap();
+        assert_eq!(index.len(), 3);
+        let mut same_size = index.remove(&2048).unwrap();
+        same_size.sort_by(|x, y| x.0.cmp(&y.0));
+        assert_eq!(
+            same_size,
+            vec![
+                (a_path, Sha256Digest([0xaa; 32])),
+                (b_path, Sha256Digest([0xbb; 32])),
+            ]
+        );
+        assert_eq!(
+            index.remove(&4096).unwrap(),
+            vec![(c_path, Sha256Digest([0xcc; 32]))]
+        );
+        assert_eq!(
+            index.remove(&1024).unwrap(),
+            vec![(d_path, Sha256Digest([0xdd; 32]))]
+        );
+
+        // invalid object name
+        write_object(&objects, &"ee".repeat(16), &[6; 2048]);
+        index_repo_objects(&objects).unwrap_err();
+    }
+
+    #[test]
+    fn test_match_objects_by_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let objects = dir.path().join("objects");
+        write_object(&objects, &"aa".repeat(32), &[1; 2048]);
+        write_object(&objects, &"bb".repeat(32), &[2; 2048]);
+        write_object(&objects, &"cc".repeat(32), &[3; 1024]);
+        let index = index_repo_objects(&objects).unwrap();
+
+        let file = |name: &str, contents: &[u8]| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, contents).unwrap();
+            path
+        };
+        // same size as both candidates, matching the second
+        let second = file("second", &[2; 2048]);
+        // same size, different contents
+        let different = file("different", &[3; 2048]);
+        // no candidates of this size
+        let other_size = file("other-size", &[1; 4096]);
+        let first = file("first", &[1; 2048]);
+        // identical to an object, but too small to be worth matching
+        let small = file("small", &[3; 1024]);
+
+        let matched = match_objects_by_content(
+            &[second.clone(), different, other_size, first.clone(), small],
+            &index,
+        )
+        .unwrap();
+        assert_eq!(
+            matched,
+            vec![
+                (second, Sha256Digest([0xbb; 32])),
+                (first, Sha256Digest([0xaa; 32])),
+            ]
+        );
+    }
 }
@@ -14,17 +14,16 @@
 
 use std::ffi::OsStr;
 use std::fs::{File, OpenOptions};
-use std::io::{self, copy, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::io::{self, copy, BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
 use std::os::unix::ffi::{OsStrExt, OsStringExt};
 use std::path::{Path, PathBuf};
 use std::thread;
 
 use anyhow::{bail, Context, Result};
 use openssl::hash::{Hasher, MessageDigest};
-use xz2::read::XzDecoder;
 
 use super::*;
-use crate::io::WriteHasher;
+use crate::io::{WriteHasher, BUFFER_SIZE};
 
 /// Path to OSTree repo of sysroot.
 const SYSROOT_OSTREE_REPO: &str = "/sysroot/ostree/repo";
@@ -37,15 +36,16 @@ pub struct OsmetUnpacker {
 
 impl OsmetUnpacker {
     pub fn new(osmet: &Path, repo: &Path) -> Result<Self> {
-        let (_, osmet, xzpacked_image) = osmet_file_read(osmet)?;
-        Ok(Self::new_impl(osmet, xzpacked_image, repo))
+        let (_, osmet, compressed_image) = osmet_file_read(osmet, None)?;
+        Ok(Self::new_impl(osmet, compressed_image, repo))
     }
 
-    pub fn new_from_sysroot(osmet: &Path) -> Result<Self> {
-        let (_, osmet, xzpacked_image) = osmet_file_read(osmet)?;
+    /// Unpack the image for the specified sector size, using the OSTree repo of the sysroot.
+    pub fn new_from_sysroot(osmet: &Path, sector_size: u32) -> Result<Self> {
+        let (_, osmet, compressed_image) = osmet_file_read(osmet, Some(sector_size))?;
         Ok(Self::new_impl(
             osmet,
-            xzpacked_image,
+            compressed_image,
             Path::new(SYSROOT_OSTREE_REPO),
         ))
     }
@@ -69,6 +69,21 @@ impl OsmetUnpacker {
     pub fn length(&self) -> u64 {
         self.length
     }
+
+    /// Unpack an osmet file directly into a writer, without a separate
+    /// unpacking thread.  If the osmet file is a container, the sector size
+    /// selects the image to unpack.  Returns the number of bytes written.
+    pub fn unpack_to_writer(
+        osmet: &Path,
+        repo: &Path,
+        sector_size: Option<u32>,
+        writer: impl Write,
+    ) -> Result<u64> {
+        let (_, osmet, compressed_image) = osmet_file_read(osmet, sector_size)?;
+        let length = osmet.size;
+        osmet_unpack_to_writer(osmet, compressed_image, repo.to_owned(), writer)?;
+        Ok(length)
+    }
 }
 
 impl Read for OsmetUnpacker {
@@ -90,17 +105,62 @@ impl Read for OsmetUnpacker {
 }
 
 pub(super) fn get_unpacked_image_digest(
-    xzpacked_image: &mut File,
+    compressed_image: &mut File,
+    compression: OsmetCompression,
     partitions: &[OsmetPartition],
     root: &Mount,
 ) -> Result<(Sha256Digest, u64)> {
     let mut hasher = Hasher::new(MessageDigest::sha256()).context("creating SHA256 hasher")?;
     let repo = root.mountpoint().join("ostree/repo");
-    let mut packed_image = XzDecoder::new(xzpacked_image);
+    let mut packed_image =
+        compression.decoder(BufReader::with_capacity(BUFFER_SIZE, compressed_image))?;
     let n = write_unpacked_image(&mut packed_image, &mut hasher, partitions, &repo)?;
     Ok((hasher.try_into()?, n))
 }
 
+/// Check that all the objects referenced by the osmet are in the repo,
+/// then unpack the image to check its length and digest.
+pub(super) fn verify_osmet(osmet: Osmet, packed_image: impl Read, repo: &Path) -> Result<()> {
+    let missing = find_missing_objects(&osmet.partitions, repo)?;
+    if let Some(first) = missing.first() {
+        bail!(
+            "{} mapped objects missing from OSTree repo, including {}",
+            missing.len(),
+            first.display()
+        );
+    }
+    osmet_unpack_to_writer(osmet, packed_image, repo.to_owned(), io::sink())
+}
+
+/// Largest filesystem block size.  Extents are block-aligned, so the last
+/// extent of an object can extend past its end by less than a block; the
+/// unpacker fills the rest with zeroes, as the filesystem does.
+const MAX_BLOCK_SIZE: u64 = 65536;
+
+/// Return the paths of objects referenced by the partitions which are
+/// missing from the repo or too short for their mapped extents.
+fn find_missing_objects(partitions: &[OsmetPartition], repo: &Path) -> Result<Vec<PathBuf>> {
+    let objects = repo.join("objects");
+    let mut missing = Vec::new();
+    for mapping in partitions.iter().flat_map(|p| p.mappings.iter()) {
+        let mut buf = Vec::new();
+        checksum_to_object_path(&mapping.object, &mut buf)?;
+        let path = objects.join(OsStr::from_bytes(&buf));
+        let extent = &mapping.extent;
+        let covers_extent = |len: u64| {
+            let end = extent.logical + extent.length;
+            len >= end || (len > extent.logical && end - len < MAX_BLOCK_SIZE)
+        };
+        match path.metadata() {
+            Ok(meta) if meta.is_file() && covers_extent(meta.len()) => (),
+            Ok(_) => missing.push(path),
+            Err(e) if e.kind() == ErrorKind::NotFound => missing.push(path),
+            Err(e) => return Err(e).with_context(|| format!("getting metadata for {path:?}")),
+        }
+    }
+    Ok(missing)
+}
+
 fn osmet_unpack_to_writer(
     osmet: Osmet,
     mut packed_image: impl Read,
@@ -224,3 +284,61 @@ fn write_partition_mapping(
 
     Ok(n)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_missing_objects() {
+        let repo = tempfile::tempdir().unwrap();
+        let present = Sha256Digest([0xab; 32]);
+        let absent = Sha256Digest([0xcd; 32]);
+        let mut buf = Vec::new();
+        checksum_to_object_path(&present, &mut buf).unwrap();
+        let path = repo.path().join("objects").join(OsStr::from_bytes(&buf));
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, [0u8; 100]).unwrap();
+
+        let mapping = |object: &Sha256Digest, logical, length| Mapping {
+            extent: Extent {
+                logical,
+                physical: 0,
+                length,
+            },
+            object: object.clone(),
+        };
+        let partitions = vec![OsmetPartition {
+            start_offset: 0,
+            end_offset: 4096,
+            mappings: vec![
+                mapping(&present, 0, 10),
+                mapping(&present, 50, 10),
+                // zero-filled tail of the last block
+                mapping(&present, 0, 4096),
+            ],
+        }];
+        assert!(find_missing_objects(&partitions, repo.path())
+            .unwrap()
+            .is_empty());
+
+        let partitions = vec![OsmetPartition {
+            start_offset: 0,
+            end_offset: 4096,
+            mappings: vec![
+                mapping(&present, 0, 10),
+                mapping(&absent, 0, 10),
+                // past the end of the object
+                mapping(&present, 100, 10),
+                // object is shorter than the extent by more than a block
+                mapping(&present, 0, 2 * MAX_BLOCK_SIZE),
+            ],
+        }];
+        let missing = find_missing_objects(&partitions, repo.path()).unwrap();
+        assert_eq!(missing.len(), 3);
+        assert!(missing[0]
+            .ends_with("cd/cdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcd.file"));
+        assert_eq!(missing[1], path);
+        assert_eq!(missing[2], path);
+    }
+}
@@ -0,0 +1,97 @@
+// Copyright 2024 Red Hat, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! PowerPC PReP boot partition handling.  Open Firmware loads the GRUB
+//! core image directly from the PReP partition, so the partition has no
+//! filesystem; grub2-install writes core.elf to the start of it.
+
+use anyhow::{bail, Context, Result};
+use std::fs::{read, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::blockdev::Disk;
+use crate::status;
+
+/// Partition label of the PReP partition in CoreOS images
+const PREP_PARTLABEL: &str = "PowerPC-PReP-boot";
+
+/// GRUB core image installed by grub2-install, relative to the boot
+/// filesystem
+const CORE_ELF_PATH: &str = "grub2/powerpc-ieee1275/core.elf";
+
+/// Rewrite the PReP partition of `device` with the GRUB core image from
+/// the boot filesystem mounted at `boot_mount`, as grub2-install would.
+pub fn refresh_prep(device: &str, boot_mount: &Path) -> Result<()> {
+    let core_path = boot_mount.join(CORE_ELF_PATH);
+    let core = read(&core_path).with_context(|| format!("reading {}", core_path.display()))?;
+    let prep = Disk::new(device)?
+        .get_partitions()?
+        .into_iter()
+        .find(|p| p.partlabel.as_deref() == Some(PREP_PARTLABEL))
+        .with_context(|| format!("couldn't find PReP partition on {device}"))?;
+    status!("Updating PReP partition {}", prep.path);
+    let mut f = OpenOptions::new()
+        .write(true)
+        .open(&prep.path)
+        .with_context(|| format!("opening {}", prep.path))?;
+    write_prep(&mut f, &core).with_context(|| format!("writing {}", prep.path))
+}
+
+/// Write the core image to the start of the partition and zero the rest,
+/// so no stale code from a previous image remains.
+fn write_prep(f: &mut File, core: &[u8]) -> Result<()> {
+    let size = f.seek(SeekFrom::End(0)).context("getting partition size")?;
+    if core.len() as u64 > size {
+        bail!(
+            "GRUB core image ({} bytes) doesn't fit in PReP partition ({} bytes)",
+            core.len(),
+            size
+        );
+    }
+    f.rewind().context("seeking to start of partition")?;
+    f.write_all(core).context("writing GRUB core image")?;
+    let zeroes = vec![0u8; 64 * 1024];
+    let mut remaining = size - core.len() as u64;
+    while remaining > 0 {
+        let count = remaining.min(zeroes.len() as u64);
+        f.write_all(&zeroes[..count as usize])
+            .context("clearing rest of partition")?;
+        remaining -= count;
+    }
+    f.sync_all().context("syncing partition")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_write_prep() {
+        let mut f = tempfile::tempfile().unwrap();
+        f.write_all(&[0xaa; 200 * 1024]).unwrap();
+        write_prep(&mut f, b"core image").unwrap();
+        let mut data = Vec::new();
+        f.rewind().unwrap();
+        f.read_to_end(&mut data).unwrap();
+        assert_eq!(data.len(), 200 * 1024);
+        assert_eq!(&data[..10], b"core image");
+        assert!(data[10..].iter().all(|b| *b == 0));
+
+        // too large
+        write_prep(&mut f, &vec![1; 201 * 1024]).unwrap_err();
+    }
+}
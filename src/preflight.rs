@@ -0,0 +1,368 @@
+// Copyright 2024 Red Hat, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Hardware checks for `install --check-hardware`, run before anything is
+//! written so that an install which can't succeed fails with an
+//! explanation rather than partway through.
+
+use anyhow::{bail, Context, Result};
+use std::fs::{read_to_string, OpenOptions};
+use std::path::Path;
+
+use crate::blockdev::*;
+use crate::cmdline::*;
+use crate::io::ClevisPin;
+#[cfg(target_arch = "s390x")]
+use crate::s390x;
+use crate::source::ImageSource;
+use crate::status;
+
+const EFI_SYSFS_DIR: &str = "/sys/firmware/efi";
+const TPM_VERSION_PATH: &str = "/sys/class/tpm/tpm0/tpm_version_major";
+const ENTROPY_AVAIL_PATH: &str = "/proc/sys/kernel/random/entropy_avail";
+/// Entropy estimate at which the kernel considers itself fully seeded.
+const MIN_ENTROPY_BITS: u32 = 256;
+
+#[derive(Debug, Default)]
+pub struct HardwareReport {
+    checks: Vec<HardwareCheck>,
+}
+
+impl HardwareReport {
+    fn add(
+        &mut self,
+        check: &str,
+        device: Option<&str>,
+        status: CheckStatus,
+        message: impl Into<String>,
+    ) {
+        self.checks.push(HardwareCheck {
+            check: check.into(),
+            device: device.map(String::from),
+            status,
+            message: message.into(),
+        });
+    }
+
+    pub fn has_failures(&self) -> bool {
+        self.checks.iter().any(|c| c.status == CheckStatus::Fail)
+    }
+
+    /// Print the results so far, add them to the command report, and fail
+    /// if any check failed.
+    pub fn finish(&mut self) -> Result<()> {
+        let checks = std::mem::take(&mut self.checks);
+        let failures = checks
+            .iter()
+            .filter(|c| c.status == CheckStatus::Fail)
+            .count();
+        status!("Hardware checks:");
+        for c in &checks {
+            let subject = match &c.device {
+                Some(device) => format!("{} ({})", c.check, device),
+                None => c.check.clone(),
+            };
+            match c.status {
+                // always show failures, even with --quiet
+                CheckStatus::Fail => eprintln!("  FAILED   {}: {}", subject, c.message),
+                CheckStatus::Warn => status!("  warning  {}: {}", subject, c.message),
+                CheckStatus::Pass => status!("  ok       {}: {}", subject, c.message),
+                CheckStatus::Skip => status!("  skipped  {}: {}", subject, c.message),
+            }
+        }
+        report(|r| r.hardware_checks.extend(checks));
+        match failures {
+            0 => Ok(()),
+            1 => bail!("1 hardware check failed"),
+            n => bail!("{} hardware checks failed", n),
+        }
+    }
+}
+
+/// Checks that don't depend on the image.  Run before any DASD is
+/// low-level formatted, since that can take a long time.
+pub fn check_devices(
+    report: &mut HardwareReport,
+    config: &InstallConfig,
+    block_devices: &[&str],
+    sector_size: u32,
+) -> Result<()> {
+    check_firmware(report, config, block_devices, sector_size);
+
+    #[cfg(target_arch = "s390x")]
+    for &device in block_devices {
+        if is_dasd(device, None)? {
+            // only unformatted ECKD DASDs have an expected sector size
+            let message = match s390x::dasd_try_get_sector_size(device)? {
+                Some(_) => "unformatted; will be low-level formatted, which can take a long time",
+                None => "formatted",
+            };
+            report.add("dasd-format", Some(device), CheckStatus::Pass, message);
+        }
+    }
+
+    if config.encrypt_root.contains(&ClevisPin::Tpm2) {
+        check_tpm(report, config);
+    }
+    if !config.encrypt_root.is_empty() {
+        check_entropy(report);
+    }
+    Ok(())
+}
+
+fn check_firmware(
+    report: &mut HardwareReport,
+    config: &InstallConfig,
+    block_devices: &[&str],
+    sector_size: u32,
+) {
+    let arch = config.architecture.as_str();
+    let host_arch = <Architecture as DefaultString>::default();
+    if block_devices.is_empty() {
+        report.add(
+            "firmware",
+            None,
+            CheckStatus::Skip,
+            "writing a disk image file",
+        );
+        return;
+    }
+    if arch != host_arch {
+        report.add(
+            "firmware",
+            None,
+            CheckStatus::Skip,
+            format!("installing {arch} image on {host_arch} system"),
+        );
+        return;
+    }
+    let uefi = Path::new(EFI_SYSFS_DIR).exists();
+    let (status, message) = match (arch, uefi) {
+        ("x86_64", true) => (CheckStatus::Pass, "UEFI"),
+        ("x86_64", false) if sector_size == 4096 => (
+            CheckStatus::Warn,
+            "BIOS firmware usually can't boot from disks with 4096-byte sectors; boot the system in UEFI mode if possible",
+        ),
+        ("x86_64", false) => (CheckStatus::Pass, "BIOS"),
+        ("aarch64", true) => (CheckStatus::Pass, "UEFI"),
+        ("aarch64", false) => (
+            CheckStatus::Fail,
+            "aarch64 images require UEFI firmware, but the system didn't boot with UEFI",
+        ),
+        _ => (CheckStatus::Skip, "no firmware requirements on this architecture"),
+    };
+    report.add("firmware", None, status, message);
+}
+
+fn check_tpm(report: &mut HardwareReport, config: &InstallConfig) {
+    let (status, message) = if config.dest_image_file.is_some() {
+        (
+            CheckStatus::Skip,
+            "can't check the TPM of the system that will boot the image file".to_string(),
+        )
+    } else {
+        match read_to_string(TPM_VERSION_PATH).map(|v| v.trim().to_string()) {
+            Ok(version) if version == "2" => (CheckStatus::Pass, "TPM 2.0 present".to_string()),
+            Ok(version) => (
+                CheckStatus::Fail,
+                format!("found TPM {version}, but --encrypt-root tpm2 requires TPM 2.0"),
+            ),
+            Err(_) => (
+                CheckStatus::Fail,
+                "--encrypt-root tpm2 requires a TPM 2.0 device, but none was found; enable the TPM in the firmware settings or use a Tang pin".to_string(),
+            ),
+        }
+    };
+    report.add("tpm", None, status, message);
+}
+
+fn check_entropy(report: &mut HardwareReport) {
+    let entropy = read_to_string(ENTROPY_AVAIL_PATH)
+        .ok()
+        .and_then(|s| s.trim().parse::<u32>().ok());
+    let (status, message) = match entropy {
+        Some(bits) if bits < MIN_ENTROPY_BITS => (
+            CheckStatus::Warn,
+            format!("only {bits} bits of entropy available; generating encryption keys may stall on first boot"),
+        ),
+        Some(bits) => (CheckStatus::Pass, format!("{bits} bits available")),
+        None => (
+            CheckStatus::Skip,
+            format!("couldn't read {ENTROPY_AVAIL_PATH}"),
+        ),
+    };
+    report.add("entropy", None, status, message);
+}
+
+/// Checks that depend on the image.
+pub fn check_image(
+    report: &mut HardwareReport,
+    config: &InstallConfig,
+    block_devices: &[&str],
+    sector_size: u32,
+    source: &ImageSource,
+    save_partitions: &[PartitionFilter],
+) -> Result<()> {
+    let image_size = source.image_size();
+
+    // CoreOS metal images are named by sector size
+    let image_sector_size = if source.filename.contains("metal4k") {
+        Some(4096)
+    } else if source.filename.contains("metal") {
+        Some(512)
+    } else {
+        None
+    };
+    let (status, message) = match image_sector_size {
+        Some(size) if size == sector_size => {
+            (CheckStatus::Pass, format!("{sector_size}-byte sectors"))
+        }
+        // 512-byte images may work on disks with unusual sector sizes
+        Some(512) if sector_size != 4096 => (
+            CheckStatus::Warn,
+            format!("non-standard sector size {sector_size}; assuming 512-byte compatible"),
+        ),
+        Some(size) => (
+            CheckStatus::Fail,
+            format!(
+                "image {} has {}-byte sectors but the destination has {}-byte sectors; use the {} image",
+                source.filename,
+                size,
+                sector_size,
+                if sector_size == 4096 { "metal4k" } else { "metal" }
+            ),
+        ),
+        None => (
+            CheckStatus::Skip,
+            format!("can't determine the sector size of {}", source.filename),
+        ),
+    };
+    report.add("sector-size", None, status, message);
+
+    if let Some(path) = &config.dest_image_file {
+        let (status, message) = match (image_size, config.image_size) {
+            (Some(image), Some(size)) if image > size.0 => (
+                CheckStatus::Fail,
+                format!("--image-size {size} is smaller than the {image}-byte image"),
+            ),
+            (Some(image), Some(size)) => (
+                CheckStatus::Pass,
+                format!("{} bytes, image needs {}", size.0, image),
+            ),
+            _ => (CheckStatus::Skip, "image file size not yet known".into()),
+        };
+        report.add("size", Some(path), status, message);
+    }
+    for &device in block_devices {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .open(device)
+            .with_context(|| format!("opening {device}"))?;
+        let size = get_block_device_size(&file)
+            .with_context(|| format!("getting size of {device}"))?
+            .get();
+        let saved = SavedPartitions::new_from_disk(&mut file, save_partitions)
+            .with_context(|| format!("reading partitions from {device}"))?;
+        let (status, message) = match (image_size, saved.get_offset()?) {
+            (Some(image), _) if image > size => (
+                CheckStatus::Fail,
+                format!("{size}-byte disk is smaller than the {image}-byte image"),
+            ),
+            (Some(image), Some((offset, desc))) if offset < image => (
+                CheckStatus::Fail,
+                format!(
+                    "saved {desc} starts at offset {offset}, inside the {image}-byte image; move or remove it, or don't save it"
+                ),
+            ),
+            (Some(image), _) => (
+                CheckStatus::Pass,
+                format!("{size} bytes, image needs {image}"),
+            ),
+            (None, Some((offset, desc))) => (
+                CheckStatus::Skip,
+                format!("image size unknown until decompressed; saved {desc} leaves {offset} bytes for it"),
+            ),
+            (None, None) => (
+                CheckStatus::Skip,
+                format!("{size} bytes; image size unknown until decompressed"),
+            ),
+        };
+        report.add("size", Some(device), status, message);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmdline::CheckStatus::*;
+
+    #[test]
+    fn test_check_image() {
+        let check = |config: &InstallConfig, filename: &str, length: u64, sector_size: u32| {
+            let source = ImageSource {
+                reader: Box::new(std::io::empty()),
+                length_hint: Some(length),
+                signature: None,
+                filename: filename.into(),
+                artifact_type: "disk".into(),
+                location: filename.into(),
+                sha256: None,
+                uncompressed_size: None,
+                partial: None,
+            };
+            let mut report = HardwareReport::default();
+            check_image(&mut report, config, &[], sector_size, &source, &[]).unwrap();
+            report.checks.iter().map(|c| c.status).collect::<Vec<_>>()
+        };
+        let mut config = InstallConfig {
+            dest_image_file: Some("disk.img".into()),
+            image_size: Some(ByteSize(8 << 30)),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            check(&config, "fcos-metal.x86_64.raw", 4 << 30, 512),
+            [Pass, Pass]
+        );
+        // compressed image size is unknown
+        assert_eq!(
+            check(&config, "fcos-metal.x86_64.raw.xz", 1 << 30, 512),
+            [Pass, Skip]
+        );
+        assert_eq!(
+            check(&config, "fcos-metal4k.x86_64.raw", 4 << 30, 512),
+            [Fail, Pass]
+        );
+        assert_eq!(
+            check(&config, "fcos-metal.x86_64.raw", 4 << 30, 520),
+            [Warn, Pass]
+        );
+        assert_eq!(check(&config, "custom.raw", 16 << 30, 512), [Skip, Fail]);
+        config.image_size = None;
+        assert_eq!(
+            check(&config, "fcos-metal.x86_64.raw", 4 << 30, 512),
+            [Pass, Skip]
+        );
+
+        let mut report = HardwareReport::default();
+        report.add("a", None, Warn, "w");
+        assert!(!report.has_failures());
+        report.finish().unwrap();
+        report.add("b", Some("/dev/vda"), Fail, "f");
+        assert!(report.has_failures());
+        report.finish().unwrap_err();
+        assert!(report.checks.is_empty());
+    }
+}
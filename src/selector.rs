@@ -0,0 +1,271 @@
+// Copyright 2024 Red Hat, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Selection of destination disks by their properties, so an installer
+//! config baked into boot media can target a class of hardware rather
+//! than a device name that varies between machines.
+//!
+//! A selector is either a device path starting with `/`, or a
+//! comma-separated list of terms that must all match:
+//!
+//! - `wwn:GLOB`, `serial:GLOB`, `model:GLOB`: match the property, with
+//!   shell-style wildcards.  WWNs are compared case-insensitively.
+//! - `min-size:SIZE`, `max-size:SIZE`: size limits, with an optional K,
+//!   M, G, or T suffix
+//! - `ssd`, `hdd`: non-rotational or rotational disks
+//! - `removable`, `fixed`: removable or non-removable disks
+//! - `smallest`, `largest`: pick one of several matching disks by size.
+//!   A filter can be appended with a dash, as in `smallest-ssd`.
+//!
+//! Without `smallest` or `largest`, the selector must match exactly one
+//! disk.
+
+use anyhow::{bail, Context, Error, Result};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::blockdev::DiskInfo;
+use crate::cmdline::ByteSize;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeviceSelector {
+    /// A device node
+    Path(String),
+    /// Disk properties, and the original selector string
+    Query(Vec<DeviceFilter>, Option<DevicePick>, String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeviceFilter {
+    Wwn(glob::Pattern),
+    Serial(glob::Pattern),
+    Model(glob::Pattern),
+    MinSize(u64),
+    MaxSize(u64),
+    Ssd(bool),
+    Removable(bool),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DevicePick {
+    Smallest,
+    Largest,
+}
+
+impl FromStr for DeviceSelector {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with('/') {
+            return Ok(Self::Path(s.into()));
+        }
+        if s.is_empty() {
+            bail!("empty device selector");
+        }
+        let mut filters = Vec::new();
+        let mut pick = None;
+        for term in s.split(',') {
+            let (term, filter) = match term.split_once('-') {
+                Some((p @ ("smallest" | "largest"), filter)) => (p, Some(filter)),
+                _ => (term, None),
+            };
+            match term {
+                "smallest" | "largest" => {
+                    if pick.is_some() {
+                        bail!(
+                            "device selector '{s}' specifies more than one of smallest and largest"
+                        );
+                    }
+                    pick = Some(if term == "smallest" {
+                        DevicePick::Smallest
+                    } else {
+                        DevicePick::Largest
+                    });
+                    if let Some(filter) = filter {
+                        filters.push(parse_filter(filter, s)?);
+                    }
+                }
+                _ => filters.push(parse_filter(term, s)?),
+            }
+        }
+        Ok(Self::Query(filters, pick, s.into()))
+    }
+}
+
+fn parse_filter(term: &str, selector: &str) -> Result<DeviceFilter> {
+    let pattern = |value: &str| {
+        glob::Pattern::new(value)
+            .with_context(|| format!("parsing pattern '{value}' in device selector '{selector}'"))
+    };
+    Ok(match term.split_once(':') {
+        Some(("wwn", value)) => DeviceFilter::Wwn(pattern(&value.to_lowercase())?),
+        Some(("serial", value)) => DeviceFilter::Serial(pattern(value)?),
+        Some(("model", value)) => DeviceFilter::Model(pattern(value)?),
+        Some(("min-size", value)) => DeviceFilter::MinSize(ByteSize::from_str(value)?.0),
+        Some(("max-size", value)) => DeviceFilter::MaxSize(ByteSize::from_str(value)?.0),
+        None if term == "ssd" => DeviceFilter::Ssd(true),
+        None if term == "hdd" => DeviceFilter::Ssd(false),
+        None if term == "removable" => DeviceFilter::Removable(true),
+        None if term == "fixed" => DeviceFilter::Removable(false),
+        _ => bail!(
+            "unknown term '{}' in device selector '{}'; device paths must start with '/'",
+            term,
+            selector
+        ),
+    })
+}
+
+impl fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Path(path) => write!(f, "{path}"),
+            Self::Query(_, _, spec) => write!(f, "{spec}"),
+        }
+    }
+}
+
+impl DeviceFilter {
+    fn matches(&self, disk: &DiskInfo) -> bool {
+        let glob = |pattern: &glob::Pattern, value: Option<&str>| {
+            value.is_some_and(|v| pattern.matches(v))
+        };
+        match self {
+            Self::Wwn(pattern) => glob(
+                pattern,
+                disk.wwn.as_deref().map(str::to_lowercase).as_deref(),
+            ),
+            Self::Serial(pattern) => glob(pattern, disk.serial.as_deref()),
+            Self::Model(pattern) => glob(pattern, disk.model.as_deref()),
+            Self::MinSize(size) => disk.size >= *size,
+            Self::MaxSize(size) => disk.size <= *size,
+            Self::Ssd(ssd) => disk.rotational != *ssd,
+            Self::Removable(removable) => disk.removable == *removable,
+        }
+    }
+}
+
+impl DeviceSelector {
+    /// Pick the disk matching the selector from a list of candidates.
+    /// Paths are returned as-is.
+    pub fn select(&self, disks: &[DiskInfo]) -> Result<String> {
+        let (filters, pick) = match self {
+            Self::Path(path) => return Ok(path.clone()),
+            Self::Query(filters, pick, _) => (filters, pick),
+        };
+        let matches: Vec<&DiskInfo> = disks
+            .iter()
+            .filter(|d| filters.iter().all(|f| f.matches(d)))
+            .collect();
+        let disk = match (pick, matches.as_slice()) {
+            (_, []) => bail!("no disk matches device selector '{}'", self),
+            (None, [disk]) => disk,
+            (None, _) => bail!(
+                "device selector '{}' matches multiple disks ({}); add a filter, \"smallest\", or \"largest\"",
+                self,
+                matches
+                    .iter()
+                    .map(|d| d.path.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            // ties go to the first disk listed
+            (Some(DevicePick::Smallest), _) => matches
+                .iter()
+                .min_by_key(|d| d.size)
+                .expect("matches is non-empty"),
+            (Some(DevicePick::Largest), _) => matches
+                .iter()
+                .rev()
+                .max_by_key(|d| d.size)
+                .expect("matches is non-empty"),
+        };
+        Ok(disk.path.clone())
+    }
+
+    pub fn is_path(&self) -> bool {
+        matches!(self, Self::Path(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disk(path: &str, size: u64, model: &str, rotational: bool, removable: bool) -> DiskInfo {
+        DiskInfo {
+            path: path.into(),
+            size,
+            model: Some(model.into()),
+            serial: Some(format!("SN-{}", &path[5..])),
+            wwn: Some(format!("0x5000C500{}", &path[5..])),
+            removable,
+            rotational,
+        }
+    }
+
+    #[test]
+    fn test_device_selector() {
+        let disks = vec![
+            disk("/dev/sda", 2 << 40, "ST2000DM008", true, false),
+            disk("/dev/sdb", 500 << 30, "Samsung SSD 870", false, false),
+            disk("/dev/sdc", 250 << 30, "Crucial SSD", false, false),
+            disk("/dev/sdd", 16 << 30, "Flash Disk", false, true),
+            disk("/dev/sde", 250 << 30, "Crucial SSD", false, false),
+        ];
+        let select = |s: &str| DeviceSelector::from_str(s).unwrap().select(&disks);
+
+        assert_eq!(select("/dev/sdz").unwrap(), "/dev/sdz");
+        assert_eq!(select("wwn:0x5000c500sdb").unwrap(), "/dev/sdb");
+        assert_eq!(select("serial:SN-sdd").unwrap(), "/dev/sdd");
+        assert_eq!(select("model:Samsung*").unwrap(), "/dev/sdb");
+        assert_eq!(select("hdd").unwrap(), "/dev/sda");
+        assert_eq!(select("removable").unwrap(), "/dev/sdd");
+        assert_eq!(select("smallest-ssd").unwrap(), "/dev/sdd");
+        assert_eq!(select("smallest-ssd,fixed").unwrap(), "/dev/sdc");
+        assert_eq!(select("fixed,smallest,ssd").unwrap(), "/dev/sdc");
+        assert_eq!(select("largest").unwrap(), "/dev/sda");
+        assert_eq!(select("largest-ssd").unwrap(), "/dev/sdb");
+        assert_eq!(select("model:*SSD*,min-size:300G").unwrap(), "/dev/sdb");
+        assert_eq!(select("ssd,max-size:100G").unwrap(), "/dev/sdd");
+        // ties go to the first disk
+        assert_eq!(select("largest,model:Crucial*").unwrap(), "/dev/sdc");
+        assert!(select("model:*SSD*")
+            .unwrap_err()
+            .to_string()
+            .contains("/dev/sdb, /dev/sdc, /dev/sde"));
+        select("model:*NVMe*").unwrap_err();
+        select("smallest,hdd,removable").unwrap_err();
+
+        for bad in [
+            "",
+            "sda",
+            "dev/sda",
+            "ssd,",
+            "smallest,largest",
+            "smallest-nvme",
+            "model:[",
+            "min-size:lots",
+            "color:blue",
+        ] {
+            DeviceSelector::from_str(bad).unwrap_err();
+        }
+        assert_eq!(
+            DeviceSelector::from_str("smallest-ssd")
+                .unwrap()
+                .to_string(),
+            "smallest-ssd"
+        );
+        assert!(DeviceSelector::from_str("/dev/sda").unwrap().is_path());
+    }
+}
@@ -0,0 +1,219 @@
+// Copyright 2024 Red Hat, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cancellation of long-running operations on SIGINT and SIGTERM.
+//!
+//! By default those signals kill the process immediately, which can leave
+//! a half-written disk behind.  A command that needs to clean up calls
+//! `catch_cancel_signals()`, after which the first signal only records
+//! that cancellation was requested.  The copy loop polls for that through
+//! `CancelReader`, and fetch retries through `check_cancelled()`, and
+//! fail, so the command's usual error path runs.  Afterward,
+//! `reraise_cancel_signal()` kills the process with the original signal
+//! so the caller sees the expected exit status.  If the command is stuck
+//! somewhere that doesn't poll, such as a stalled network read, a second
+//! signal kills it immediately, unless the command has started cleaning
+//! up and called `CancelSignalGuard::hold()`.
+//!
+//! The previous handlers are restored when the `CancelSignalGuard` is
+//! dropped, so library callers keep their own signal handling.
+
+use anyhow::{Context, Result};
+use nix::sys::signal::{raise, sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+const CANCEL_SIGNALS: [Signal; 2] = [Signal::SIGINT, Signal::SIGTERM];
+
+/// How often sleep_cancellable() checks for cancellation.
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The cancellation signal received, or 0.
+static CANCEL_SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn handle_cancel_signal(signal: libc::c_int) {
+    // must be async-signal-safe
+    CANCEL_SIGNAL.store(signal, Ordering::SeqCst);
+}
+
+/// Record the first SIGINT or SIGTERM rather than dying on it.  The
+/// handler then resets itself, so a second signal kills the process.
+/// The previous handlers are restored when the returned guard is dropped;
+/// signals received in the meantime are still reported by
+/// `cancel_signal()`.
+pub fn catch_cancel_signals() -> Result<CancelSignalGuard> {
+    CANCEL_SIGNAL.store(0, Ordering::SeqCst);
+    let mut guard = CancelSignalGuard {
+        previous: Vec::new(),
+    };
+    for signal in CANCEL_SIGNALS {
+        let previous = set_cancel_handler(signal, SaFlags::SA_RESETHAND)?;
+        guard.previous.push((signal, previous));
+    }
+    Ok(guard)
+}
+
+fn set_cancel_handler(signal: Signal, flags: SaFlags) -> Result<SigAction> {
+    let action = SigAction::new(
+        SigHandler::Handler(handle_cancel_signal),
+        SaFlags::SA_RESTART | flags,
+        SigSet::empty(),
+    );
+    unsafe { sigaction(signal, &action) }.with_context(|| format!("setting {signal} handler"))
+}
+
+/// Keeps SIGINT and SIGTERM from killing the process until dropped.
+#[must_use]
+pub struct CancelSignalGuard {
+    previous: Vec<(Signal, SigAction)>,
+}
+
+impl CancelSignalGuard {
+    /// Keep recording SIGINT and SIGTERM instead of letting a second
+    /// signal kill the process, so cleanup can't be interrupted.
+    pub fn hold(&self) -> Result<()> {
+        for (signal, _) in &self.previous {
+            set_cancel_handler(*signal, SaFlags::empty())?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for CancelSignalGuard {
+    fn drop(&mut self) {
+        for (signal, previous) in &self.previous {
+            if let Err(e) = unsafe { sigaction(*signal, previous) } {
+                eprintln!("Couldn't restore {signal} handler: {e}");
+            }
+        }
+    }
+}
+
+/// The signal that requested cancellation, if any.
+pub fn cancel_signal() -> Option<Signal> {
+    match CANCEL_SIGNAL.load(Ordering::SeqCst) {
+        0 => None,
+        signal => Signal::try_from(signal).ok(),
+    }
+}
+
+/// Fail if cancellation has been requested.
+pub fn check_cancelled() -> Result<()> {
+    match cancel_signal() {
+        Some(signal) => Err(Cancelled(signal).into()),
+        None => Ok(()),
+    }
+}
+
+/// Sleep for the specified duration, failing early if cancellation is
+/// requested in the meantime.
+pub fn sleep_cancellable(duration: Duration) -> Result<()> {
+    let deadline = Instant::now() + duration;
+    loop {
+        check_cancelled()?;
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(());
+        }
+        sleep((deadline - now).min(CANCEL_POLL_INTERVAL));
+    }
+}
+
+/// If cancellation was requested, die from the same signal.  Call after
+/// cleaning up and reporting the error.
+pub fn reraise_cancel_signal() {
+    if let Some(signal) = cancel_signal() {
+        // best effort; if either fails we'll exit with an error anyway
+        let action = SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty());
+        let _ = unsafe { sigaction(signal, &action) };
+        let _ = raise(signal);
+    }
+}
+
+#[derive(Debug)]
+pub struct Cancelled(Signal);
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "interrupted by {}", self.0)
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Reader that fails once cancellation has been requested.
+pub struct CancelReader<R: Read> {
+    source: R,
+}
+
+impl<R: Read> CancelReader<R> {
+    pub fn new(source: R) -> Self {
+        Self { source }
+    }
+}
+
+impl<R: Read> Read for CancelReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(signal) = cancel_signal() {
+            // not ErrorKind::Interrupted, which would be retried
+            return Err(io::Error::other(Cancelled(signal)));
+        }
+        self.source.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn current_handler(signal: Signal) -> SigHandler {
+        // sigaction() can only query by replacing, so put it back
+        let ignore = SigAction::new(SigHandler::SigIgn, SaFlags::empty(), SigSet::empty());
+        let current = unsafe { sigaction(signal, &ignore) }.unwrap();
+        unsafe { sigaction(signal, &current) }.unwrap();
+        current.handler()
+    }
+
+    #[test]
+    fn guard_restores_previous_handlers() {
+        extern "C" fn previous_handler(_: libc::c_int) {}
+        let previous = SigAction::new(
+            SigHandler::Handler(previous_handler),
+            SaFlags::empty(),
+            SigSet::empty(),
+        );
+        let orig = unsafe { sigaction(Signal::SIGTERM, &previous) }.unwrap();
+
+        let guard = catch_cancel_signals().unwrap();
+        assert_eq!(
+            current_handler(Signal::SIGTERM),
+            SigHandler::Handler(handle_cancel_signal)
+        );
+        guard.hold().unwrap();
+        assert_eq!(
+            current_handler(Signal::SIGTERM),
+            SigHandler::Handler(handle_cancel_signal)
+        );
+        drop(guard);
+        assert_eq!(
+            current_handler(Signal::SIGTERM),
+            SigHandler::Handler(previous_handler)
+        );
+
+        unsafe { sigaction(Signal::SIGTERM, &orig) }.unwrap();
+    }
+}
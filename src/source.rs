@@ -13,19 +13,33 @@
 // limitations under the License.
 
 use anyhow::{anyhow, bail, Context, Result};
-use reqwest::{blocking, StatusCode, Url};
-use serde::Deserialize;
-use std::collections::HashMap;
+use openssl::x509::X509;
+use reqwest::header::{
+    ACCEPT_RANGES, CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE, RETRY_AFTER,
+};
+use reqwest::{blocking, Certificate, Proxy, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
 use std::fmt::{Display, Formatter};
-use std::fs::OpenOptions;
-use std::io::{Read, Seek, SeekFrom};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::num::{NonZeroU32, NonZeroU64};
 use std::path::{Path, PathBuf};
-use std::thread::sleep;
-use std::time::Duration;
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use crate::cache::Cache;
 use crate::cmdline::*;
+use crate::download::{format_duration, ChunkedReader};
+use crate::io::{
+    verify_detached, xz_uncompressed_size, RateLimitReader, RateLimiter, TeeReader, VerifyKeys,
+};
 use crate::osmet::*;
-use crate::util::set_die_on_sigpipe;
+use crate::signal::{check_cancelled, sleep_cancellable};
+use crate::status;
+use crate::util::{cmd_output, set_die_on_sigpipe};
 
 /// Completion timeout for HTTP requests (4 hours).
 const HTTP_COMPLETION_TIMEOUT: Duration = Duration::from_secs(4 * 60 * 60);
@@ -36,6 +50,22 @@ const DEFAULT_STREAM_BASE_URL: &str = "https://builds.coreos.fedoraproject.org/s
 /// Directory in which we look for osmet files.
 const OSMET_FILES_DIR: &str = "/run/coreos-installer/osmet";
 
+/// Directory in which container images are pulled if $TMPDIR isn't set.
+/// Unlike /tmp, it's usually on disk.
+const DEFAULT_CONTAINER_TMPDIR: &str = "/var/tmp";
+
+/// containers-transports(5) prefixes that skopeo accepts in an image
+/// reference.  References without one are assumed to be registry images.
+const CONTAINER_TRANSPORTS: &[&str] = &[
+    "containers-storage:",
+    "dir:",
+    "docker://",
+    "docker-archive:",
+    "docker-daemon:",
+    "oci:",
+    "oci-archive:",
+];
+
 pub trait ImageLocation: Display {
     // Obtain image lengths and signatures and start fetching the images
     fn sources(&self) -> Result<Vec<ImageSource>>;
@@ -67,7 +97,10 @@ pub struct UrlLocation {
     image_url: Url,
     sig_url: Url,
     artifact_type: String,
-    retries: FetchRetries,
+    retries: RetryPolicy,
+    partial_dir: Option<PathBuf>,
+    download_threads: Option<NonZeroU32>,
+    size_lookup: bool,
 }
 
 // Remote image source specified by Fedora CoreOS stream metadata
@@ -79,7 +112,32 @@ pub struct StreamLocation {
     architecture: String,
     platform: String,
     format: String,
-    retries: FetchRetries,
+    retries: RetryPolicy,
+    partial_dir: Option<PathBuf>,
+    download_threads: Option<NonZeroU32>,
+    signature_keys: Option<VerifyKeys>,
+    metadata_retries: Option<RetryPolicy>,
+    cache: Option<Cache>,
+    size_lookup: bool,
+}
+
+// Disk image stored as the single layer of a container image
+#[derive(Debug)]
+pub struct ContainerLocation {
+    image_ref: String,
+    policy: Option<String>,
+    tmpdir: Option<PathBuf>,
+}
+
+/// An artifact listed in stream metadata, for mirroring.
+#[derive(Debug, Clone)]
+pub struct StreamArtifact {
+    pub platform: String,
+    pub format: String,
+    pub artifact_type: String,
+    pub location: Url,
+    pub signature: Url,
+    pub sha256: Option<String>,
 }
 
 pub struct ImageSource {
@@ -88,6 +146,28 @@ pub struct ImageSource {
     pub signature: Option<Vec<u8>>,
     pub filename: String,
     pub artifact_type: String,
+    /// Path, URL, or container reference the image was read from
+    pub location: String,
+    /// Expected SHA-256 digest of the image, if known
+    pub sha256: Option<String>,
+    /// Size of the image after decompression, if known
+    pub uncompressed_size: Option<u64>,
+    /// `.partial` file that receives a copy of the image as it's read
+    pub partial: Option<PathBuf>,
+}
+
+impl ImageSource {
+    /// Size of the image as written to disk, if known.  The length of a
+    /// compressed image says nothing about its decompressed size.
+    pub fn image_size(&self) -> Option<u64> {
+        self.uncompressed_size.or_else(|| {
+            self.length_hint.filter(|_| {
+                ![".gz", ".xz", ".zst"]
+                    .iter()
+                    .any(|s| self.filename.ends_with(s))
+            })
+        })
+    }
 }
 
 impl FileLocation {
@@ -121,6 +201,11 @@ impl ImageLocation for FileLocation {
         let length = out
             .seek(SeekFrom::End(0))
             .context("seeking source image file")?;
+        let uncompressed_size = self
+            .image_path
+            .ends_with(".xz")
+            .then(|| read_xz_size(&mut out, length).ok())
+            .flatten();
         out.rewind().context("seeking source image file")?;
 
         // load signature file if present
@@ -148,26 +233,129 @@ impl ImageLocation for FileLocation {
             signature,
             filename,
             artifact_type: "disk".to_string(),
+            location: self.image_path.clone(),
+            sha256: None,
+            uncompressed_size,
+            partial: None,
         }])
     }
 }
 
 impl UrlLocation {
-    pub fn new(url: &Url, retries: FetchRetries) -> Self {
+    pub fn new(url: &Url, retries: RetryPolicy) -> Self {
         let mut sig_url = url.clone();
         sig_url.set_path(&format!("{}.sig", sig_url.path()));
         Self::new_full(url, &sig_url, "disk", retries)
     }
 
-    fn new_full(url: &Url, sig_url: &Url, artifact_type: &str, retries: FetchRetries) -> Self {
+    fn new_full(url: &Url, sig_url: &Url, artifact_type: &str, retries: RetryPolicy) -> Self {
         Self {
             image_url: url.clone(),
             sig_url: sig_url.clone(),
             artifact_type: artifact_type.to_string(),
             retries,
+            partial_dir: None,
+            download_threads: None,
+            size_lookup: false,
         }
     }
 
+    /// Save downloaded data to a `.partial` file in the specified
+    /// directory, and resume from an existing `.partial` file left behind
+    /// by an earlier interrupted download.  The caller should delete the
+    /// file, or rename it into place, after the download succeeds.
+    pub fn with_partial_dir(mut self, dir: &Path) -> Self {
+        self.partial_dir = Some(dir.to_path_buf());
+        self
+    }
+
+    /// Fetch the image in the specified number of parallel ranged requests,
+    /// if the server supports them.
+    pub fn with_download_threads(mut self, threads: Option<NonZeroU32>) -> Self {
+        self.download_threads = threads;
+        self
+    }
+
+    /// Read the uncompressed size of an xz image from the index at the
+    /// end of the image.  This costs extra range requests, so only
+    /// callers that check the size should ask for it.
+    pub fn with_size_lookup(mut self, lookup: bool) -> Self {
+        self.size_lookup = lookup;
+        self
+    }
+
+    /// Return a reader for the body of an image response whose body
+    /// starts at the specified offset into the image.  If parallel
+    /// fetching was requested and the server supports it, drop the
+    /// response and fetch the rest of the image in chunks.  The reader
+    /// honors the rate limit set by set_http_options().
+    fn body_reader(
+        &self,
+        client: blocking::Client,
+        resp: blocking::Response,
+        offset: u64,
+    ) -> Box<dyn Read> {
+        if let (Some(threads), Some(length)) = (
+            self.download_threads.filter(|t| t.get() > 1),
+            resp.content_length(),
+        ) {
+            let ranges = resp
+                .headers()
+                .get(ACCEPT_RANGES)
+                .is_some_and(|v| v.as_bytes() == b"bytes");
+            if ranges {
+                let url = resp.url().clone();
+                let validator = strong_etag(&resp);
+                drop(resp);
+                return rate_limited(Box::new(ChunkedReader::new(
+                    client,
+                    &url,
+                    offset..offset + length,
+                    threads,
+                    validator,
+                    self.retries,
+                )));
+            }
+            status!("Server doesn't support range requests; fetching image in one stream");
+        }
+        rate_limited(Box::new(ResumableReader::new(
+            client,
+            resp,
+            offset,
+            self.retries,
+        )))
+    }
+
+    /// Fetch the index at the end of an xz image with range requests,
+    /// and return the uncompressed size of the image.
+    fn fetch_xz_size(
+        &self,
+        client: blocking::Client,
+        url: &Url,
+        length: u64,
+        validator: Option<&str>,
+    ) -> Result<u64> {
+        xz_uncompressed_size(length, |range| {
+            let resp = http_get_range(
+                client.clone(),
+                url,
+                range.start,
+                Some(range.end),
+                validator,
+                self.retries,
+            )?;
+            if resp.status() != StatusCode::PARTIAL_CONTENT {
+                bail!("server didn't return a partial response");
+            }
+            check_content_range(&resp, range.start)?;
+            let mut buf = Vec::new();
+            resp.take(range.end - range.start)
+                .read_to_end(&mut buf)
+                .context("reading xz index")?;
+            Ok(buf)
+        })
+    }
+
     /// Fetch signature content from URL.
     fn fetch_signature(&self) -> Result<Vec<u8>> {
         let client = new_http_client()?;
@@ -179,6 +367,149 @@ impl UrlLocation {
             .context("reading signature content")?;
         Ok(sig_bytes)
     }
+
+    /// Return a reader for the image which first replays the contents of
+    /// the partial file, if any, and then continues with the rest of the
+    /// image from the server, appending it to the partial file.  Also
+    /// return the total image length, if known.
+    ///
+    /// The partial file is only resumed if the resource still matches the
+    /// validator saved alongside it, which is sent with If-Range.
+    fn open_partial(
+        &self,
+        client: blocking::Client,
+        mut resp: blocking::Response,
+        path: &Path,
+    ) -> Result<(Box<dyn Read>, Option<u64>)> {
+        let validator_path = partial_validator_path(path);
+        let mut partial = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .with_context(|| format!("opening {}", path.display()))?;
+        let offset = partial
+            .seek(SeekFrom::End(0))
+            .with_context(|| format!("seeking {}", path.display()))?;
+        let saved_validator = match fs::read_to_string(&validator_path) {
+            Ok(validator) => Some(validator.trim().to_string()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => {
+                return Err(e).with_context(|| format!("reading {}", validator_path.display()))
+            }
+        };
+        if offset > 0 {
+            let Some(validator) = saved_validator else {
+                eprintln!(
+                    "Can't tell whether {} is still current; starting over",
+                    path.display()
+                );
+                partial
+                    .set_len(0)
+                    .with_context(|| format!("truncating {}", path.display()))?;
+                return self.start_partial(client, resp, partial, &validator_path);
+            };
+            let url = resp.url().clone();
+            drop(resp);
+            let range_resp = http_get_range(
+                client.clone(),
+                &url,
+                offset,
+                None,
+                Some(&validator),
+                self.retries,
+            )
+            .context("resuming image fetch")?;
+            match range_resp.status() {
+                StatusCode::PARTIAL_CONTENT => {
+                    check_content_range(&range_resp, offset)?;
+                    eprintln!("Resuming download of {} at byte {}", path.display(), offset);
+                    partial
+                        .rewind()
+                        .with_context(|| format!("seeking {}", path.display()))?;
+                    let saved = partial
+                        .try_clone()
+                        .with_context(|| format!("cloning {}", path.display()))?;
+                    let length = range_resp.content_length().map(|l| l + offset);
+                    let rest = self.body_reader(client, range_resp, offset);
+                    return Ok((
+                        Box::new(saved.take(offset).chain(TeeReader::new(rest, partial))),
+                        length,
+                    ));
+                }
+                // the partial file is already complete, so it must have
+                // failed verification; start over
+                StatusCode::RANGE_NOT_SATISFIABLE => {
+                    eprintln!("Discarding complete but unusable {}", path.display());
+                    resp = http_get(client.clone(), &url, self.retries)
+                        .context("fetching image URL")?;
+                }
+                // the server doesn't support Range, or the image changed
+                // since the partial download; this is the whole image
+                StatusCode::OK => {
+                    eprintln!("Server can't resume download; starting over");
+                    resp = range_resp;
+                }
+                s => bail!("image fetch failed: {}", s),
+            }
+            partial
+                .set_len(0)
+                .with_context(|| format!("truncating {}", path.display()))?;
+        }
+        self.start_partial(client, resp, partial, &validator_path)
+    }
+
+    /// Return a reader for the image which saves it to an empty partial
+    /// file, and record the validator of the response.
+    fn start_partial(
+        &self,
+        client: blocking::Client,
+        resp: blocking::Response,
+        partial: File,
+        validator_path: &Path,
+    ) -> Result<(Box<dyn Read>, Option<u64>)> {
+        // remember which version of the image we're saving
+        match response_validator(&resp) {
+            Some(validator) => fs::write(validator_path, validator)
+                .with_context(|| format!("writing {}", validator_path.display()))?,
+            None => remove_if_exists(validator_path)?,
+        }
+        let length = resp.content_length();
+        let reader = self.body_reader(client, resp, 0);
+        Ok((Box::new(TeeReader::new(reader, partial)), length))
+    }
+}
+
+/// Path to the file holding the partial contents of an interrupted
+/// download.
+pub fn partial_download_path(dir: &Path, filename: &str) -> PathBuf {
+    dir.join(format!("{filename}.partial"))
+}
+
+/// Path to the file recording the ETag or Last-Modified time of the
+/// resource being saved to a partial file.
+fn partial_validator_path(partial: &Path) -> PathBuf {
+    let mut path = partial.as_os_str().to_owned();
+    path.push(".validator");
+    path.into()
+}
+
+/// Delete a partial download and its validator.  Failures aren't fatal.
+pub fn remove_partial_download(partial: &Path) {
+    for path in [partial.to_path_buf(), partial_validator_path(partial)] {
+        if let Err(e) = remove_if_exists(&path) {
+            eprintln!("{e:#}");
+        }
+    }
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("deleting {}", path.display()))
+        }
+        _ => Ok(()),
+    }
 }
 
 impl Display for UrlLocation {
@@ -200,12 +531,13 @@ impl ImageLocation for UrlLocation {
 
         // start fetch, get length
         let client = new_http_client()?;
-        let resp = http_get(client, &self.image_url, self.retries).context("fetching image URL")?;
+        let resp = http_get(client.clone(), &self.image_url, self.retries)
+            .context("fetching image URL")?;
         match resp.status() {
             StatusCode::OK => (),
             s => bail!("image fetch failed: {}", s),
         };
-        let length_hint = resp.content_length();
+        let mut length_hint = resp.content_length();
         // ignores the Content-Disposition filename
         let filename = resp
             .url()
@@ -214,13 +546,40 @@ impl ImageLocation for UrlLocation {
             .next_back()
             .context("walking image URL")?
             .to_string();
+        // failure only means that the size is unknown
+        let uncompressed_size = match length_hint {
+            Some(length) if self.size_lookup && filename.ends_with(".xz") => {
+                let url = resp.url().clone();
+                let validator = strong_etag(&resp);
+                self.fetch_xz_size(client.clone(), &url, length, validator.as_deref())
+                    .ok()
+            }
+            _ => None,
+        };
+
+        let partial = self
+            .partial_dir
+            .as_ref()
+            .map(|dir| partial_download_path(dir, &filename));
+        let reader: Box<dyn Read> = match &partial {
+            Some(path) => {
+                let (reader, length) = self.open_partial(client, resp, path)?;
+                length_hint = length;
+                reader
+            }
+            None => self.body_reader(client, resp, 0),
+        };
 
         Ok(vec![ImageSource {
-            reader: Box::new(resp),
+            reader,
             length_hint,
             signature,
             filename,
             artifact_type: self.artifact_type.clone(),
+            location: self.image_url.to_string(),
+            sha256: None,
+            uncompressed_size,
+            partial,
         }])
     }
 }
@@ -232,7 +591,7 @@ impl StreamLocation {
         platform: &str,
         format: &str,
         base_url: Option<&Url>,
-        retries: FetchRetries,
+        retries: RetryPolicy,
     ) -> Result<Self> {
         Ok(Self {
             stream_base_url: base_url.cloned(),
@@ -242,8 +601,207 @@ impl StreamLocation {
             platform: platform.to_string(),
             format: format.to_string(),
             retries,
+            partial_dir: None,
+            download_threads: None,
+            signature_keys: None,
+            metadata_retries: None,
+            cache: None,
+            size_lookup: false,
         })
     }
+
+    /// Retry stream metadata fetches according to `retries` rather than
+    /// the retry count for artifacts, if specified.
+    pub fn with_metadata_retries(mut self, retries: Option<RetryPolicy>) -> Self {
+        self.metadata_retries = retries;
+        self
+    }
+
+    /// Require the stream metadata to have a valid detached signature
+    /// (`<stream>.json.sig`) from the specified keys.
+    pub fn with_signature_keys(mut self, keys: Option<VerifyKeys>) -> Self {
+        self.signature_keys = keys;
+        self
+    }
+
+    /// Save downloaded artifacts to `.partial` files in the specified
+    /// directory.  See UrlLocation::with_partial_dir().
+    pub fn with_partial_dir(mut self, dir: &Path) -> Self {
+        self.partial_dir = Some(dir.to_path_buf());
+        self
+    }
+
+    /// Fetch each artifact in the specified number of parallel ranged
+    /// requests, if the server supports them.
+    pub fn with_download_threads(mut self, threads: Option<NonZeroU32>) -> Self {
+        self.download_threads = threads;
+        self
+    }
+
+    /// Cache stream metadata in the specified cache, and use cached
+    /// artifacts instead of downloading them when their digests match
+    /// the metadata.
+    pub fn with_cache(mut self, cache: Option<Cache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Read the uncompressed sizes of xz artifacts.  See
+    /// UrlLocation::with_size_lookup().
+    pub fn with_size_lookup(mut self, lookup: bool) -> Self {
+        self.size_lookup = lookup;
+        self
+    }
+
+    pub fn stream_url(&self) -> &Url {
+        &self.stream_url
+    }
+
+    /// Fetch the stream metadata and list every artifact for the
+    /// architecture, optionally only for the specified platforms.  Returns
+    /// the raw metadata along with the artifacts.
+    pub fn all_artifacts(&self, platforms: &[String]) -> Result<(Vec<u8>, Vec<StreamArtifact>)> {
+        let data = self.fetch_stream_data()?;
+        let stream: Stream = serde_json::from_slice(&data).context("decoding stream metadata")?;
+        let arch = stream
+            .architectures
+            .get(&self.architecture)
+            .with_context(|| {
+                format!(
+                    "couldn't find architecture {} in stream metadata",
+                    self.architecture
+                )
+            })?;
+        for platform in platforms {
+            if !arch.artifacts.contains_key(platform) {
+                bail!("couldn't find platform {} in stream metadata", platform);
+            }
+        }
+
+        let mut artifacts = Vec::new();
+        for (platform_name, platform) in arch.artifacts.iter() {
+            if !platforms.is_empty() && !platforms.contains(platform_name) {
+                continue;
+            }
+            for (format_name, format) in platform.formats.iter() {
+                for (artifact_type, artifact) in format.iter() {
+                    artifacts.push(StreamArtifact {
+                        platform: platform_name.clone(),
+                        format: format_name.clone(),
+                        artifact_type: artifact_type.clone(),
+                        location: Url::parse(&artifact.location)
+                            .context("parsing artifact URL from stream metadata")?,
+                        signature: Url::parse(&artifact.signature)
+                            .context("parsing signature URL from stream metadata")?,
+                        sha256: artifact.sha256.clone(),
+                    });
+                }
+            }
+        }
+        artifacts.sort_by(|a, b| {
+            (&a.platform, &a.format, &a.artifact_type).cmp(&(
+                &b.platform,
+                &b.format,
+                &b.artifact_type,
+            ))
+        });
+        Ok((data, artifacts))
+    }
+
+    /// Return a location for downloading one artifact from all_artifacts(),
+    /// with this location's settings.
+    pub fn artifact_location(&self, artifact: &StreamArtifact) -> UrlLocation {
+        let mut location = UrlLocation::new_full(
+            &artifact.location,
+            &artifact.signature,
+            &artifact.artifact_type,
+            self.retries,
+        );
+        if let Some(dir) = &self.partial_dir {
+            location = location.with_partial_dir(dir);
+        }
+        location.with_download_threads(self.download_threads)
+    }
+
+    /// Fetch the stream metadata, saving it to the cache.  If the server
+    /// can't be reached, fall back to the cached copy, unless the metadata
+    /// must be signed; the cache doesn't keep the signature.
+    fn fetch_stream_data(&self) -> Result<Vec<u8>> {
+        let client = new_http_client()?;
+        let result = fetch_stream_data(
+            client,
+            &self.stream_url,
+            self.metadata_retries.unwrap_or(self.retries),
+            self.signature_keys.clone(),
+        );
+        let Some(cache) = &self.cache else {
+            return result;
+        };
+        match result {
+            Ok(data) => {
+                if let Err(e) = cache.insert_stream(self.stream_url.as_str(), &data) {
+                    eprintln!("Couldn't cache stream metadata: {e:#}");
+                }
+                Ok(data)
+            }
+            Err(err) if self.signature_keys.is_none() && is_transport_error(&err) => {
+                match cache.get_stream(self.stream_url.as_str()) {
+                    Ok(Some((data, age))) => {
+                        eprintln!(
+                            "{err:#}\nWarning: using stream metadata cached {} ago; it may not list the latest release",
+                            format_duration(age)
+                        );
+                        Ok(data)
+                    }
+                    _ => Err(err),
+                }
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Return a source reading the artifact from the cache, if present
+    /// and intact.
+    fn cached_source(
+        &self,
+        url: &Url,
+        artifact_type: &str,
+        artifact: &Artifact,
+    ) -> Result<Option<ImageSource>> {
+        let (Some(cache), Some(sha256)) = (&self.cache, &artifact.sha256) else {
+            return Ok(None);
+        };
+        let Some((mut file, signature)) = cache.get_image(sha256)? else {
+            return Ok(None);
+        };
+        let length = file
+            .seek(SeekFrom::End(0))
+            .context("seeking cached image")?;
+        let uncompressed_size = url
+            .path()
+            .ends_with(".xz")
+            .then(|| read_xz_size(&mut file, length).ok())
+            .flatten();
+        file.rewind().context("seeking cached image")?;
+        let filename = url
+            .path_segments()
+            .context("splitting image URL")?
+            .next_back()
+            .context("walking image URL")?
+            .to_string();
+        eprintln!("Using cached {filename}");
+        Ok(Some(ImageSource {
+            reader: Box::new(file),
+            length_hint: Some(length),
+            signature,
+            filename,
+            artifact_type: artifact_type.to_string(),
+            location: url.to_string(),
+            sha256: Some(sha256.clone()),
+            uncompressed_size,
+            partial: None,
+        }))
+    }
 }
 
 impl Display for StreamLocation {
@@ -267,8 +825,8 @@ impl Display for StreamLocation {
 impl ImageLocation for StreamLocation {
     fn sources(&self) -> Result<Vec<ImageSource>> {
         // fetch and parse stream metadata
-        let client = new_http_client()?;
-        let stream = fetch_stream(client, &self.stream_url, self.retries)?;
+        let data = self.fetch_stream_data()?;
+        let stream: Stream = serde_json::from_slice(&data).context("decoding stream metadata")?;
 
         // descend it
         let artifacts = stream
@@ -292,9 +850,22 @@ impl ImageLocation for StreamLocation {
                 .context("parsing artifact URL from stream metadata")?;
             let signature_url = Url::parse(&artifact.signature)
                 .context("parsing signature URL from stream metadata")?;
-            let mut artifact_sources =
-                UrlLocation::new_full(&artifact_url, &signature_url, artifact_type, self.retries)
-                    .sources()?;
+            if let Some(source) = self.cached_source(&artifact_url, artifact_type, artifact)? {
+                sources.push(source);
+                continue;
+            }
+            let mut location =
+                UrlLocation::new_full(&artifact_url, &signature_url, artifact_type, self.retries);
+            if let Some(dir) = &self.partial_dir {
+                location = location.with_partial_dir(dir);
+            }
+            location = location
+                .with_download_threads(self.download_threads)
+                .with_size_lookup(self.size_lookup);
+            let mut artifact_sources = location.sources()?;
+            for source in artifact_sources.iter_mut() {
+                source.sha256 = artifact.sha256.clone();
+            }
             sources.append(&mut artifact_sources);
         }
         sources.sort_by_key(|k| k.artifact_type.to_string());
@@ -336,7 +907,8 @@ impl Display for OsmetLocation {
 
 impl ImageLocation for OsmetLocation {
     fn sources(&self) -> Result<Vec<ImageSource>> {
-        let unpacker = OsmetUnpacker::new_from_sysroot(Path::new(&self.osmet_path))?;
+        let unpacker =
+            OsmetUnpacker::new_from_sysroot(Path::new(&self.osmet_path), self.sector_size)?;
 
         let filename = {
             let stem = self.osmet_path.file_stem().with_context(|| {
@@ -363,6 +935,10 @@ impl ImageLocation for OsmetLocation {
             signature: None,
             filename,
             artifact_type: "disk".to_string(),
+            location: self.osmet_path.display().to_string(),
+            sha256: None,
+            uncompressed_size: None,
+            partial: None,
         }])
     }
 
@@ -373,61 +949,237 @@ impl ImageLocation for OsmetLocation {
     }
 }
 
+impl ContainerLocation {
+    pub fn new(image_ref: &str, policy: Option<&str>) -> Self {
+        let image_ref = if CONTAINER_TRANSPORTS
+            .iter()
+            .any(|transport| image_ref.starts_with(transport))
+        {
+            image_ref.to_string()
+        } else {
+            format!("docker://{image_ref}")
+        };
+        Self {
+            image_ref,
+            policy: policy.map(|p| p.to_string()),
+            tmpdir: None,
+        }
+    }
+
+    /// Pull the image into a temporary directory under `dir`, rather than
+    /// under $TMPDIR or DEFAULT_CONTAINER_TMPDIR.
+    pub fn with_tmpdir(mut self, dir: Option<&str>) -> Self {
+        self.tmpdir = dir.map(PathBuf::from);
+        self
+    }
+
+    /// Whether the reference names a registry image by tag rather than by
+    /// digest, so its contents could change between runs.
+    fn is_unpinned(&self) -> bool {
+        self.image_ref.starts_with("docker://") && !self.image_ref.contains('@')
+    }
+}
+
+impl Display for ContainerLocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> ::std::fmt::Result {
+        write!(f, "Pulling image from {}", self.image_ref)?;
+        if let Some(policy) = &self.policy {
+            write!(f, "\nVerifying image with signature policy {policy}")?;
+        }
+        Ok(())
+    }
+}
+
+impl ImageLocation for ContainerLocation {
+    fn sources(&self) -> Result<Vec<ImageSource>> {
+        if self.is_unpinned() {
+            status!("Warning: image reference is not pinned to a digest");
+        }
+
+        // Pull into a directory so we can find the layer blob.  skopeo
+        // checks the manifest and blob digests, and enforces the signature
+        // policy, while copying.  skopeo can't stream a single blob, so
+        // the whole image is stored; keep it out of /tmp, which may be
+        // in RAM.
+        let parent = self.tmpdir.clone().unwrap_or_else(|| {
+            env::var_os("TMPDIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| DEFAULT_CONTAINER_TMPDIR.into())
+        });
+        let dir = tempfile::Builder::new()
+            .prefix("coreos-installer-")
+            .tempdir_in(&parent)
+            .with_context(|| format!("creating temporary directory in {}", parent.display()))?;
+        let mut cmd = Command::new("skopeo");
+        cmd.arg("copy");
+        if let Some(policy) = &self.policy {
+            cmd.arg("--policy").arg(policy);
+        }
+        cmd.arg(&self.image_ref)
+            .arg(format!("dir:{}", dir.path().display()));
+        cmd_output(&mut cmd).context("pulling container image")?;
+        let (blob, layer) = open_disk_layer(dir.path())?;
+
+        // repository name without tag or digest
+        let filename = self
+            .image_ref
+            .rsplit('/')
+            .next()
+            .and_then(|name| name.split(['@', ':']).next())
+            .unwrap_or_default()
+            .to_string();
+        Ok(vec![ImageSource {
+            reader: Box::new(blob),
+            length_hint: Some(layer.size),
+            signature: None,
+            filename,
+            artifact_type: "disk".to_string(),
+            location: self.image_ref.clone(),
+            sha256: None,
+            uncompressed_size: None,
+            partial: None,
+        }])
+    }
+
+    // Container images are verified against the containers signature
+    // policy rather than with a detached GPG signature.
+    fn require_signature(&self) -> bool {
+        false
+    }
+}
+
 /// Subcommand to list objects available in stream metadata.
 pub fn list_stream(config: ListStreamConfig) -> Result<()> {
-    #[derive(PartialEq, Eq, PartialOrd, Ord)]
-    struct Row<'a> {
-        architecture: &'a str,
-        platform: &'a str,
-        format: &'a str,
-    }
+    set_http_options(
+        config.http_proxy.as_ref(),
+        config.https_proxy.as_ref(),
+        &config.cacert,
+        None,
+        None,
+    )?;
 
     // fetch stream metadata
     let client = new_http_client()?;
     let stream_url = build_stream_url(&config.stream, config.stream_base_url.as_ref())?;
-    let stream = fetch_stream(client, &stream_url, FetchRetries::None)?;
-
-    // walk formats
-    let mut rows: Vec<Row> = Vec::new();
-    for (architecture_name, architecture) in stream.architectures.iter() {
-        for (platform_name, platform) in architecture.artifacts.iter() {
-            for format_name in platform.formats.keys() {
-                rows.push(Row {
+    let stream = fetch_stream(client, &stream_url, RetryPolicy::default(), None)?;
+
+    let entries = list_stream_entries(
+        &stream,
+        config.architecture.as_deref(),
+        config.platform.as_deref(),
+    );
+    if entries.is_empty() {
+        bail!("no matching formats found in stream metadata");
+    }
+
+    // report results
+    set_die_on_sigpipe()?;
+    match config.format {
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&entries).context("serializing stream listing")?
+        ),
+        OutputFormat::Text if config.artifacts => {
+            let mut rows = vec![vec![
+                "Architecture",
+                "Platform",
+                "Format",
+                "Type",
+                "SHA256",
+                "URL",
+            ]];
+            for entry in &entries {
+                for (artifact_type, artifact) in &entry.artifacts {
+                    rows.push(vec![
+                        entry.architecture,
+                        entry.platform,
+                        entry.format,
+                        *artifact_type,
+                        artifact.sha256.as_deref().unwrap_or("-"),
+                        artifact.location.as_str(),
+                    ]);
+                }
+            }
+            print_table(&rows);
+        }
+        OutputFormat::Text => {
+            let mut rows = vec![vec!["Architecture", "Platform", "Format"]];
+            for entry in &entries {
+                rows.push(vec![entry.architecture, entry.platform, entry.format]);
+            }
+            print_table(&rows);
+        }
+    }
+    Ok(())
+}
+
+/// A format of a platform in stream metadata, with its artifacts.
+#[derive(Debug, Serialize)]
+struct StreamListEntry<'a> {
+    architecture: &'a str,
+    platform: &'a str,
+    format: &'a str,
+    artifacts: BTreeMap<&'a str, &'a Artifact>,
+}
+
+/// Walk the formats in stream metadata, optionally filtering by
+/// architecture and platform, and return them in sorted order.
+fn list_stream_entries<'a>(
+    stream: &'a Stream,
+    architecture: Option<&str>,
+    platform: Option<&str>,
+) -> Vec<StreamListEntry<'a>> {
+    let mut entries = Vec::new();
+    for (architecture_name, arch) in stream.architectures.iter() {
+        if architecture.is_some_and(|a| a != architecture_name) {
+            continue;
+        }
+        for (platform_name, plat) in arch.artifacts.iter() {
+            if platform.is_some_and(|p| p != platform_name) {
+                continue;
+            }
+            for (format_name, artifacts) in plat.formats.iter() {
+                entries.push(StreamListEntry {
                     architecture: architecture_name,
                     platform: platform_name,
                     format: format_name,
+                    artifacts: artifacts.iter().map(|(k, v)| (k.as_str(), v)).collect(),
                 });
             }
         }
     }
-    rows.sort();
+    entries.sort_by_key(|e| (e.architecture, e.platform, e.format));
+    entries
+}
 
-    // add header row
-    rows.insert(
-        0,
-        Row {
-            architecture: "Architecture",
-            platform: "Platform",
-            format: "Format",
-        },
-    );
+/// Print rows as columns separated by two spaces.  The first row is the
+/// header.  The last column isn't padded.
+pub(crate) fn print_table(rows: &[Vec<&str>]) {
+    print!("{}", format_table(rows));
+}
 
-    // calculate field widths
-    let mut widths: [usize; 2] = [0; 2];
-    for row in &rows {
-        widths[0] = widths[0].max(row.architecture.len());
-        widths[1] = widths[1].max(row.platform.len());
+/// Like `print_table()`, but return the text.
+pub(crate) fn format_table(rows: &[Vec<&str>]) -> String {
+    let mut table = String::new();
+    let mut widths = vec![0; rows[0].len()];
+    for row in rows {
+        for (width, field) in widths.iter_mut().zip(row) {
+            *width = (*width).max(field.len());
+        }
     }
-
-    // report results
-    set_die_on_sigpipe()?;
-    for row in &rows {
-        println!(
-            "{:3$}  {:4$}  {}",
-            row.architecture, row.platform, row.format, widths[0], widths[1]
-        );
+    for row in rows {
+        let mut line = String::new();
+        for (i, field) in row.iter().enumerate() {
+            if i == row.len() - 1 {
+                line.push_str(field);
+            } else {
+                line.push_str(&format!("{:1$}  ", field, widths[i]));
+            }
+        }
+        table.push_str(&line);
+        table.push('\n');
     }
-    Ok(())
+    table
 }
 
 /// Generate a stream URL from a stream name and base URL, or the default
@@ -439,26 +1191,190 @@ fn build_stream_url(stream: &str, base_url: Option<&Url>) -> Result<Url> {
         .context("building stream URL")
 }
 
-/// Fetch and parse stream metadata.
-fn fetch_stream(client: blocking::Client, url: &Url, retries: FetchRetries) -> Result<Stream> {
+/// Fetch and parse stream metadata.  If keys are specified, require a
+/// valid detached signature alongside the metadata.
+fn fetch_stream(
+    client: blocking::Client,
+    url: &Url,
+    retries: RetryPolicy,
+    keys: Option<VerifyKeys>,
+) -> Result<Stream> {
+    let data = fetch_stream_data(client, url, retries, keys)?;
+    let stream: Stream = serde_json::from_slice(&data).context("decoding stream metadata")?;
+    Ok(stream)
+}
+
+/// Fetch stream metadata without parsing it, checking its signature if
+/// keys are specified.
+fn fetch_stream_data(
+    client: blocking::Client,
+    url: &Url,
+    retries: RetryPolicy,
+    keys: Option<VerifyKeys>,
+) -> Result<Vec<u8>> {
     // fetch stream metadata
-    let resp = http_get(client, url, retries).context("fetching stream metadata")?;
+    let mut resp = http_get(client.clone(), url, retries).context("fetching stream metadata")?;
     match resp.status() {
         StatusCode::OK => (),
         s => bail!("stream metadata fetch from {} failed: {}", url, s),
     };
+    let mut data = Vec::new();
+    resp.read_to_end(&mut data)
+        .context("reading stream metadata")?;
 
-    // parse it
-    let stream: Stream = serde_json::from_reader(resp).context("decoding stream metadata")?;
-    Ok(stream)
+    // verify it
+    if let Some(keys) = keys {
+        let sig_url = Url::parse(&format!("{url}.sig")).context("building signature URL")?;
+        let mut signature = Vec::new();
+        http_get(client, &sig_url, retries)
+            .context("fetching stream metadata signature")?
+            .error_for_status()
+            .with_context(|| format!("fetching stream metadata signature from {sig_url}"))?
+            .read_to_end(&mut signature)
+            .context("reading stream metadata signature")?;
+        verify_detached(&data, &signature, keys).context("verifying stream metadata")?;
+    }
+
+    Ok(data)
+}
+
+/// Default cap on the delay between fetch retries; matches curl.
+const DEFAULT_MAX_RETRY_DELAY: Duration = Duration::from_secs(10 * 60);
+
+/// How many times to retry a failed fetch, and the cap on the delay
+/// between attempts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub count: FetchRetries,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Retry `count` times, waiting at most `max_delay` seconds between
+    /// attempts, or the default cap if not specified.
+    pub fn new(count: FetchRetries, max_delay: Option<NonZeroU32>) -> Self {
+        Self {
+            count,
+            max_delay: max_delay
+                .map(|secs| Duration::from_secs(secs.get().into()))
+                .unwrap_or(DEFAULT_MAX_RETRY_DELAY),
+        }
+    }
+
+    /// The same policy with a different retry count.
+    pub fn with_count(self, count: FetchRetries) -> Self {
+        Self { count, ..self }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(FetchRetries::default(), None)
+    }
+}
+
+/// Proxies and additional trusted certificates for new HTTP clients, and
+/// the bandwidth limit for image fetches.
+#[derive(Debug)]
+struct HttpOptions {
+    proxies: Vec<Proxy>,
+    certificates: Vec<Certificate>,
+    rate_limit: Option<RateLimiter>,
+}
+
+impl HttpOptions {
+    const fn new() -> Self {
+        Self {
+            proxies: Vec::new(),
+            certificates: Vec::new(),
+            rate_limit: None,
+        }
+    }
+}
+
+/// Set by set_http_options().
+static HTTP_OPTIONS: Mutex<HttpOptions> = Mutex::new(HttpOptions::new());
+
+/// Configure proxies and additional trusted CA certificates for HTTP
+/// clients created afterward by new_http_client().  By default, the
+/// http_proxy, https_proxy, and no_proxy environment variables are
+/// honored; specifying a proxy here overrides all of them.  Image fetches
+/// started afterward share a limit of limit_rate bytes per second.
+pub fn set_http_options(
+    http_proxy: Option<&Url>,
+    https_proxy: Option<&Url>,
+    cacerts: &[String],
+    limit_rate: Option<ByteSize>,
+) -> Result<()> {
+    let options = load_http_options(http_proxy, https_proxy, cacerts, limit_rate)?;
+    *HTTP_OPTIONS.lock().expect("locking HTTP options") = options;
+    Ok(())
+}
+
+fn load_http_options(
+    http_proxy: Option<&Url>,
+    https_proxy: Option<&Url>,
+    cacerts: &[String],
+    limit_rate: Option<ByteSize>,
+) -> Result<HttpOptions> {
+    let mut options = HttpOptions::new();
+    if let Some(rate) = limit_rate {
+        let rate = NonZeroU64::new(rate.0).context("--limit-rate must be greater than zero")?;
+        options.rate_limit = Some(RateLimiter::new(rate));
+    }
+    if let Some(url) = http_proxy {
+        options
+            .proxies
+            .push(Proxy::http(url.as_str()).with_context(|| format!("setting HTTP proxy {url}"))?);
+    }
+    if let Some(url) = https_proxy {
+        options.proxies.push(
+            Proxy::https(url.as_str()).with_context(|| format!("setting HTTPS proxy {url}"))?,
+        );
+    }
+    for path in cacerts {
+        let data = std::fs::read(path).with_context(|| format!("reading {path}"))?;
+        let certs = X509::stack_from_pem(&data)
+            .with_context(|| format!("parsing certificates from {path}"))?;
+        if certs.is_empty() {
+            bail!("no PEM certificates found in {path}");
+        }
+        for cert in certs {
+            let pem = cert.to_pem().context("serializing certificate")?;
+            options.certificates.push(
+                Certificate::from_pem(&pem)
+                    .with_context(|| format!("loading certificate from {path}"))?,
+            );
+        }
+    }
+    Ok(options)
+}
+
+/// Wrap a reader of fetched data to honor the rate limit set by
+/// set_http_options(), if any.
+fn rate_limited(reader: Box<dyn Read>) -> Box<dyn Read> {
+    let limiter = HTTP_OPTIONS
+        .lock()
+        .expect("locking HTTP options")
+        .rate_limit
+        .clone();
+    match limiter {
+        Some(limiter) => Box::new(RateLimitReader::new(reader, limiter)),
+        None => reader,
+    }
 }
 
 /// Customize and build a new HTTP client.
 pub fn new_http_client() -> Result<blocking::Client> {
-    blocking::ClientBuilder::new()
-        .timeout(HTTP_COMPLETION_TIMEOUT)
-        .build()
-        .context("building HTTP client")
+    let mut builder = blocking::ClientBuilder::new().timeout(HTTP_COMPLETION_TIMEOUT);
+    let options = HTTP_OPTIONS.lock().expect("locking HTTP options");
+    for proxy in &options.proxies {
+        builder = builder.proxy(proxy.clone());
+    }
+    for cert in &options.certificates {
+        builder = builder.add_root_certificate(cert.clone());
+    }
+    builder.build().context("building HTTP client")
 }
 
 /// Wrapper around Client::get() with error handling based on HTTP return code and optionally basic
@@ -466,27 +1382,72 @@ pub fn new_http_client() -> Result<blocking::Client> {
 pub fn http_get(
     client: blocking::Client,
     url: &Url,
-    retries: FetchRetries,
+    retries: RetryPolicy,
+) -> Result<blocking::Response> {
+    http_get_inner(client, url, None, retries)
+}
+
+/// Like http_get(), but request the contents of the URL starting at the
+/// specified offset and ending before `end`, or at the end of the
+/// resource if `end` is None.  If the server ignores the Range header, or
+/// if `validator` is specified and the resource no longer matches it, the
+/// response will be 200 OK with the full contents rather than 206 Partial
+/// Content.  416 Range Not Satisfiable is returned rather than treated as
+/// an error.
+pub fn http_get_range(
+    client: blocking::Client,
+    url: &Url,
+    offset: u64,
+    end: Option<u64>,
+    validator: Option<&str>,
+    retries: RetryPolicy,
+) -> Result<blocking::Response> {
+    let range = match end {
+        Some(end) => format!("bytes={}-{}", offset, end - 1),
+        None => format!("bytes={offset}-"),
+    };
+    http_get_inner(client, url, Some((range, validator)), retries)
+}
+
+fn http_get_inner(
+    client: blocking::Client,
+    url: &Url,
+    range: Option<(String, Option<&str>)>,
+    retries: RetryPolicy,
 ) -> Result<blocking::Response> {
     // this matches `curl --retry` semantics -- see list in `curl(1)`
     const RETRY_STATUS_CODES: [u16; 6] = [408, 429, 500, 502, 503, 504];
 
-    let mut delay = 1;
-    let (infinite, mut tries) = match retries {
+    let max_delay = retries.max_delay;
+    let mut delay = Duration::from_secs(1).min(max_delay);
+    let (infinite, mut tries) = match retries.count {
         FetchRetries::Infinite => (true, 0),
         FetchRetries::Finite(n) => (false, n.get() + 1),
         FetchRetries::None => (false, 1),
     };
 
     loop {
-        let err: anyhow::Error = match client.get(url.clone()).send() {
+        check_cancelled()?;
+        let mut req = client.get(url.clone());
+        if let Some((range, validator)) = &range {
+            req = req.header(RANGE, range);
+            if let Some(validator) = validator {
+                req = req.header(IF_RANGE, validator);
+            }
+        }
+        let mut retry_after = None;
+        let err: anyhow::Error = match req.send() {
             Err(err) => err.into(),
             Ok(resp) => match resp.status().as_u16() {
-                code if RETRY_STATUS_CODES.contains(&code) => anyhow!(
-                    "HTTP {} {}",
-                    code,
-                    resp.status().canonical_reason().unwrap_or("")
-                ),
+                416 if range.is_some() => return Ok(resp),
+                code if RETRY_STATUS_CODES.contains(&code) => {
+                    retry_after = parse_retry_after(&resp);
+                    anyhow!(
+                        "HTTP {} {}",
+                        code,
+                        resp.status().canonical_reason().unwrap_or("")
+                    )
+                }
                 _ => {
                     return resp
                         .error_for_status()
@@ -502,10 +1463,179 @@ pub fn http_get(
             }
         }
 
+        // honor the server's Retry-After if it sent one; otherwise back off
+        // exponentially, with jitter so that many clients failing at once
+        // don't retry in lockstep
+        let wait = match retry_after {
+            Some(wait) => wait.min(max_delay),
+            None => jittered(delay),
+        };
         eprintln!("Error fetching '{url}': {err}");
-        eprintln!("Sleeping {delay}s and retrying...");
-        sleep(Duration::from_secs(delay));
-        delay = std::cmp::min(delay * 2, 10 * 60); // cap to 10 mins; matches curl
+        eprintln!("Sleeping {:.1}s and retrying...", wait.as_secs_f64());
+        sleep_cancellable(wait)?;
+        delay = (delay * 2).min(max_delay);
+    }
+}
+
+/// Whether a fetch failed because the server couldn't be reached, rather
+/// than because of its response or our checks of it.
+fn is_transport_error(err: &anyhow::Error) -> bool {
+    err.chain().any(|e| {
+        e.downcast_ref::<reqwest::Error>()
+            .is_some_and(|e| e.is_connect() || e.is_timeout())
+    })
+}
+
+/// Parse the Retry-After header of a response, if it specifies a delay in
+/// seconds.  HTTP dates aren't supported.
+fn parse_retry_after(resp: &blocking::Response) -> Option<Duration> {
+    resp.headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Pick a delay between half of `delay` and `delay`.  The jitter only
+/// needs to differ between clients, so the clock is random enough.
+fn jittered(delay: Duration) -> Duration {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    delay.mul_f64(0.5 + f64::from(nanos) / 2e9)
+}
+
+/// Reader for the body of an HTTP response.  If the connection fails
+/// partway through, reissue the request with a Range header and continue
+/// where we left off.
+pub struct ResumableReader {
+    client: blocking::Client,
+    resp: blocking::Response,
+    url: Url,
+    retries: RetryPolicy,
+    validator: Option<String>,
+    position: u64,
+    length: Option<u64>,
+}
+
+impl ResumableReader {
+    /// Wrap a response whose body starts at the specified offset into the
+    /// resource.
+    pub fn new(
+        client: blocking::Client,
+        resp: blocking::Response,
+        offset: u64,
+        retries: RetryPolicy,
+    ) -> Self {
+        let validator = strong_etag(&resp);
+        Self {
+            client,
+            url: resp.url().clone(),
+            length: resp.content_length().map(|l| l + offset),
+            resp,
+            retries,
+            validator,
+            position: offset,
+        }
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        let resp = http_get_range(
+            self.client.clone(),
+            &self.url,
+            self.position,
+            None,
+            self.validator.as_deref(),
+            self.retries,
+        )?;
+        if resp.status() != StatusCode::PARTIAL_CONTENT {
+            bail!(
+                "server can't resume fetch of '{}': {}",
+                self.url,
+                resp.status()
+            );
+        }
+        check_content_range(&resp, self.position)?;
+        self.resp = resp;
+        Ok(())
+    }
+}
+
+/// Return the strong ETag of a response, or failing that its
+/// Last-Modified time, for checking with If-Range that a later request
+/// gets the same version of the resource.
+fn response_validator(resp: &blocking::Response) -> Option<String> {
+    strong_etag(resp).or_else(|| {
+        resp.headers()
+            .get(LAST_MODIFIED)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from)
+    })
+}
+
+/// Return the ETag of a response if it can be used with If-Range.  Only
+/// strong ETags are allowed.
+pub(crate) fn strong_etag(resp: &blocking::Response) -> Option<String> {
+    resp.headers()
+        .get(ETAG)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.starts_with("W/"))
+        .map(String::from)
+}
+
+/// Read the index at the end of a local xz file and return the
+/// uncompressed size of the image.  Leaves the file offset unspecified.
+fn read_xz_size(file: &mut File, length: u64) -> Result<u64> {
+    xz_uncompressed_size(length, |range| {
+        let mut buf = vec![0; (range.end - range.start) as usize];
+        file.seek(SeekFrom::Start(range.start))
+            .context("seeking xz index")?;
+        file.read_exact(&mut buf).context("reading xz index")?;
+        Ok(buf)
+    })
+}
+
+/// Make sure a 206 Partial Content response starts where we asked it to.
+pub(crate) fn check_content_range(resp: &blocking::Response, offset: u64) -> Result<()> {
+    let expected = format!("bytes {offset}-");
+    match resp.headers().get(CONTENT_RANGE).map(|v| v.to_str()) {
+        Some(Ok(range)) if range.starts_with(&expected) => Ok(()),
+        range => bail!("unexpected Content-Range in resumed fetch: {:?}", range),
+    }
+}
+
+impl Read for ResumableReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut resumed = false;
+        loop {
+            let err = match self.resp.read(buf) {
+                Ok(0) if !buf.is_empty() && self.length.is_some_and(|l| self.position < l) => {
+                    io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "connection closed before end of response",
+                    )
+                }
+                Ok(count) => {
+                    self.position += count as u64;
+                    return Ok(count);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => return Err(e),
+                Err(e) => e,
+            };
+            // give up if we can't make progress after resuming
+            if resumed {
+                return Err(err);
+            }
+            eprintln!("Error reading '{}': {}", self.url, err);
+            eprintln!("Resuming at byte {}...", self.position);
+            self.resume()
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{e:#}")))?;
+            resumed = true;
+        }
     }
 }
 
@@ -524,18 +1654,412 @@ struct Platform {
     formats: HashMap<String, HashMap<String, Artifact>>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 struct Artifact {
     location: String,
     signature: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+}
+
+/// OCI or Docker schema 2 image manifest
+#[derive(Debug, Deserialize)]
+struct ContainerManifest {
+    layers: Vec<ContainerLayer>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+struct ContainerLayer {
+    media_type: String,
+    digest: String,
+    size: u64,
+}
+
+/// Find the disk image layer of a container image pulled into `dir` with
+/// the `dir:` transport, and open its blob.  The blob stays readable after
+/// the directory is cleaned up.
+fn open_disk_layer(dir: &Path) -> Result<(File, ContainerLayer)> {
+    let manifest: ContainerManifest = serde_json::from_reader(
+        OpenOptions::new()
+            .read(true)
+            .open(dir.join("manifest.json"))
+            .context("opening container image manifest")?,
+    )
+    .context("parsing container image manifest")?;
+    let layer = match <[ContainerLayer; 1]>::try_from(manifest.layers) {
+        Ok([layer]) => layer,
+        Err(layers) => bail!(
+            "expected container image with one layer, found {}",
+            layers.len()
+        ),
+    };
+    if layer.media_type.contains(".tar") {
+        bail!(
+            "container image layer has type {}; expected a disk image, not a filesystem layer",
+            layer.media_type
+        );
+    }
+    let hex = match layer.digest.split_once(':') {
+        Some((_, hex)) if !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()) => hex,
+        _ => bail!("invalid layer digest {}", layer.digest),
+    };
+    let blob = OpenOptions::new()
+        .read(true)
+        .open(dir.join(hex))
+        .context("opening container image layer")?;
+    Ok((blob, layer))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::*;
+    use std::io::Write;
+    use std::sync::atomic::{AtomicBool, Ordering};
 
     #[test]
     fn test_new_http_client() {
         let _ = new_http_client().unwrap();
     }
+
+    #[test]
+    fn test_load_http_options() {
+        let url = Url::parse("http://proxy.example.com:3128").unwrap();
+        let options = load_http_options(Some(&url), Some(&url), &[], None).unwrap();
+        assert_eq!(options.proxies.len(), 2);
+        assert!(options.certificates.is_empty());
+        assert!(options.rate_limit.is_none());
+        let options = load_http_options(None, None, &[], Some(ByteSize(1 << 20))).unwrap();
+        assert!(options.rate_limit.is_some());
+
+        // missing file
+        load_http_options(None, None, &["/nonexistent".into()], None).unwrap_err();
+        // no certificates
+        let mut f = tempfile::NamedTempFile::new().unwrap();
+        f.write_all(b"not a certificate\n").unwrap();
+        load_http_options(None, None, &[f.path().to_str().unwrap().into()], None).unwrap_err();
+        // zero rate
+        load_http_options(None, None, &[], Some(ByteSize(0))).unwrap_err();
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.count, FetchRetries::None);
+        assert_eq!(policy.max_delay, DEFAULT_MAX_RETRY_DELAY);
+        let policy = RetryPolicy::new(FetchRetries::Infinite, NonZeroU32::new(30));
+        assert_eq!(policy.max_delay, Duration::from_secs(30));
+        let policy = policy.with_count(FetchRetries::None);
+        assert_eq!(policy.count, FetchRetries::None);
+        assert_eq!(policy.max_delay, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_jittered() {
+        let delay = Duration::from_secs(8);
+        for _ in 0..100 {
+            let wait = jittered(delay);
+            assert!(wait >= delay / 2 && wait <= delay, "{wait:?}");
+        }
+    }
+
+    #[test]
+    fn test_list_stream_entries() {
+        let stream: Stream = serde_json::from_str(
+            r#"{
+                "architectures": {
+                    "x86_64": {"artifacts": {
+                        "metal": {"formats": {
+                            "raw.xz": {"disk": {"location": "https://example.com/a", "signature": "https://example.com/a.sig", "sha256": "aa"}},
+                            "iso": {"disk": {"location": "https://example.com/b", "signature": "https://example.com/b.sig"}}
+                        }},
+                        "qemu": {"formats": {
+                            "qcow2.xz": {"disk": {"location": "https://example.com/c", "signature": "https://example.com/c.sig"}}
+                        }}
+                    }},
+                    "aarch64": {"artifacts": {
+                        "metal": {"formats": {
+                            "raw.xz": {"disk": {"location": "https://example.com/d", "signature": "https://example.com/d.sig"}}
+                        }}
+                    }}
+                }
+            }"#,
+        )
+        .unwrap();
+        let names = |entries: Vec<StreamListEntry>| {
+            entries
+                .iter()
+                .map(|e| format!("{} {} {}", e.architecture, e.platform, e.format))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            names(list_stream_entries(&stream, None, None)),
+            vec![
+                "aarch64 metal raw.xz",
+                "x86_64 metal iso",
+                "x86_64 metal raw.xz",
+                "x86_64 qemu qcow2.xz"
+            ]
+        );
+        assert_eq!(
+            names(list_stream_entries(&stream, Some("x86_64"), Some("metal"))),
+            vec!["x86_64 metal iso", "x86_64 metal raw.xz"]
+        );
+        assert!(list_stream_entries(&stream, Some("s390x"), None).is_empty());
+
+        let entries = list_stream_entries(&stream, None, Some("metal"));
+        assert_eq!(
+            serde_json::to_value(&entries[2]).unwrap(),
+            serde_json::json!({
+                "architecture": "x86_64",
+                "platform": "metal",
+                "format": "raw.xz",
+                "artifacts": {
+                    "disk": {
+                        "location": "https://example.com/a",
+                        "signature": "https://example.com/a.sig",
+                        "sha256": "aa"
+                    }
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_container_location() {
+        let loc = ContainerLocation::new("quay.io/fedora/fedora-coreos:stable", None);
+        assert_eq!(
+            loc.image_ref,
+            "docker://quay.io/fedora/fedora-coreos:stable"
+        );
+        assert!(loc.is_unpinned());
+        let loc = ContainerLocation::new("quay.io/fedora/fedora-coreos@sha256:0123", None);
+        assert_eq!(
+            loc.image_ref,
+            "docker://quay.io/fedora/fedora-coreos@sha256:0123"
+        );
+        assert!(!loc.is_unpinned());
+        let loc = ContainerLocation::new("oci-archive:/tmp/image.ociarchive", None);
+        assert_eq!(loc.image_ref, "oci-archive:/tmp/image.ociarchive");
+        assert!(!loc.is_unpinned());
+        assert_eq!(loc.tmpdir, None);
+        let loc = loc.with_tmpdir(Some("/mnt/scratch"));
+        assert_eq!(loc.tmpdir.as_deref(), Some(Path::new("/mnt/scratch")));
+    }
+
+    /// Set up a directory like the one `skopeo copy` writes for the `dir:`
+    /// transport, using a manifest from fixtures/container.
+    fn container_dir(manifest: &[u8]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("manifest.json"), manifest).unwrap();
+        fs::write(
+            dir.path().join("version"),
+            "Directory Transport Version: 1.1\n",
+        )
+        .unwrap();
+        for blob in ["disk image\n", "other layer\n", "config\n"] {
+            let digest = hex::encode(openssl::sha::sha256(blob.as_bytes()));
+            fs::write(dir.path().join(digest), blob).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_open_disk_layer() {
+        for manifest in [
+            &include_bytes!("../fixtures/container/oci-manifest.json")[..],
+            &include_bytes!("../fixtures/container/docker-manifest.json")[..],
+        ] {
+            let dir = container_dir(manifest);
+            let (mut blob, layer) = open_disk_layer(dir.path()).unwrap();
+            assert_eq!(
+                layer.digest,
+                "sha256:c8885b679757df10e37e1e4100ee519611f492dea2734540cbb8fe2f45a39833"
+            );
+            assert_eq!(layer.size, 11);
+            // still readable after the directory is removed
+            drop(dir);
+            let mut data = String::new();
+            blob.read_to_string(&mut data).unwrap();
+            assert_eq!(data, "disk image\n");
+        }
+        let (_, layer) = open_disk_layer(
+            container_dir(include_bytes!("../fixtures/container/oci-manifest.json")).path(),
+        )
+        .unwrap();
+        assert_eq!(layer.media_type, "application/vnd.coreos.disk.raw.xz");
+
+        let err = open_disk_layer(
+            container_dir(include_bytes!("../fixtures/container/multi-layer.json")).path(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("found 2"), "{err:#}");
+        let err = open_disk_layer(
+            container_dir(include_bytes!(
+                "../fixtures/container/filesystem-layer.json"
+            ))
+            .path(),
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("not a filesystem layer"),
+            "{err:#}"
+        );
+
+        // missing blob
+        let dir = container_dir(include_bytes!("../fixtures/container/oci-manifest.json"));
+        fs::remove_file(
+            dir.path()
+                .join("c8885b679757df10e37e1e4100ee519611f492dea2734540cbb8fe2f45a39833"),
+        )
+        .unwrap();
+        open_disk_layer(dir.path()).unwrap_err();
+        // digests that aren't plain hex aren't used as paths
+        for digest in ["sha256:../manifest.json", "sha256:", "c8885b67"] {
+            let manifest = serde_json::json!({
+                "layers": [{"mediaType": "application/octet-stream", "digest": digest, "size": 1}]
+            });
+            let dir = container_dir(manifest.to_string().as_bytes());
+            let err = open_disk_layer(dir.path()).unwrap_err();
+            assert!(err.to_string().contains("invalid layer digest"), "{err:#}");
+        }
+        // unparseable manifest
+        open_disk_layer(container_dir(b"{}").path()).unwrap_err();
+    }
+
+    fn test_image() -> Vec<u8> {
+        (0..200_000u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// Read the image at `url`, saving it to a partial file in `dir`.
+    fn read_with_partial(url: &Url, dir: &Path) -> Vec<u8> {
+        let mut sources = UrlLocation::new(url, RetryPolicy::new(FetchRetries::None, None))
+            .with_partial_dir(dir)
+            .sources()
+            .unwrap();
+        let mut data = Vec::new();
+        sources[0].reader.read_to_end(&mut data).unwrap();
+        data
+    }
+
+    /// Requests for the image, excluding the signature
+    fn image_requests(server: &HttpServer) -> Vec<HttpRequest> {
+        server
+            .requests()
+            .into_iter()
+            .filter(|r| r.path == "/image.raw")
+            .collect()
+    }
+
+    #[test]
+    fn test_partial_resume() {
+        let image = test_image();
+        let server = HttpServer::file(&image, "\"v1\"");
+        let dir = tempfile::tempdir().unwrap();
+        let partial = partial_download_path(dir.path(), "image.raw");
+        std::fs::write(&partial, &image[..1000]).unwrap();
+        std::fs::write(partial_validator_path(&partial), "\"v1\"\n").unwrap();
+
+        assert_eq!(
+            read_with_partial(&server.url("/image.raw"), dir.path()),
+            image
+        );
+        assert_eq!(std::fs::read(&partial).unwrap(), image);
+        let requests = image_requests(&server);
+        let resume = requests.last().unwrap();
+        assert_eq!(resume.header("range"), Some("bytes=1000-"));
+        assert_eq!(resume.header("if-range"), Some("\"v1\""));
+    }
+
+    #[test]
+    fn test_partial_changed_image() {
+        let image = test_image();
+        let server = HttpServer::file(&image, "\"v2\"");
+        let dir = tempfile::tempdir().unwrap();
+        let partial = partial_download_path(dir.path(), "image.raw");
+        std::fs::write(&partial, [0xff; 1000]).unwrap();
+        std::fs::write(partial_validator_path(&partial), "\"v1\"").unwrap();
+
+        // If-Range fails, so the server sends the whole image
+        assert_eq!(
+            read_with_partial(&server.url("/image.raw"), dir.path()),
+            image
+        );
+        assert_eq!(std::fs::read(&partial).unwrap(), image);
+        assert_eq!(
+            std::fs::read_to_string(partial_validator_path(&partial)).unwrap(),
+            "\"v2\""
+        );
+    }
+
+    #[test]
+    fn test_partial_without_validator() {
+        let image = test_image();
+        let server = HttpServer::file(&image, "\"v1\"");
+        let dir = tempfile::tempdir().unwrap();
+        let partial = partial_download_path(dir.path(), "image.raw");
+        std::fs::write(&partial, [0xff; 1000]).unwrap();
+
+        // can't tell where the partial data came from, so start over
+        assert_eq!(
+            read_with_partial(&server.url("/image.raw"), dir.path()),
+            image
+        );
+        assert_eq!(std::fs::read(&partial).unwrap(), image);
+        assert!(image_requests(&server)
+            .iter()
+            .all(|r| r.header("range").is_none()));
+    }
+
+    #[test]
+    fn test_partial_range_ignored() {
+        let image = test_image();
+        let server = {
+            let image = image.clone();
+            HttpServer::new(move |_| HttpResponse::new("200 OK", &image).header("ETag", "\"v1\""))
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let partial = partial_download_path(dir.path(), "image.raw");
+        std::fs::write(&partial, &image[..1000]).unwrap();
+        std::fs::write(partial_validator_path(&partial), "\"v1\"").unwrap();
+
+        assert_eq!(
+            read_with_partial(&server.url("/image.raw"), dir.path()),
+            image
+        );
+        assert_eq!(std::fs::read(&partial).unwrap(), image);
+    }
+
+    #[test]
+    fn test_resumable_reader() {
+        let image = test_image();
+        let first = AtomicBool::new(true);
+        let server = {
+            let image = image.clone();
+            HttpServer::new(move |req| {
+                let mut resp = ranged_response(req, &image, "\"v1\"");
+                if req.path == "/image.raw" && first.swap(false, Ordering::SeqCst) {
+                    // drop the connection partway through
+                    resp.truncate = Some(5000);
+                }
+                resp
+            })
+        };
+
+        let mut sources = UrlLocation::new(
+            &server.url("/image.raw"),
+            RetryPolicy::new(FetchRetries::None, None),
+        )
+        .sources()
+        .unwrap();
+        let mut data = Vec::new();
+        sources[0].reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, image);
+        let requests = image_requests(&server);
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].header("range"), Some("bytes=5000-"));
+        assert_eq!(requests[1].header("if-range"), Some("\"v1\""));
+    }
 }
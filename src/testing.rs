@@ -0,0 +1,209 @@
+// Copyright 2024 Red Hat, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fixtures shared between the unit tests of several modules.
+
+use reqwest::Url;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// A request received by an `HttpServer`.
+#[derive(Clone, Debug)]
+pub struct HttpRequest {
+    pub path: String,
+    /// Header names are lowercased
+    pub headers: HashMap<String, String>,
+}
+
+impl HttpRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+}
+
+/// A response for an `HttpServer` to send.
+#[derive(Clone, Debug)]
+pub struct HttpResponse {
+    pub status: &'static str,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Close the connection after sending this many bytes of the body,
+    /// though Content-Length covers all of it
+    pub truncate: Option<usize>,
+}
+
+impl HttpResponse {
+    pub fn new(status: &'static str, body: &[u8]) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: body.to_vec(),
+            truncate: None,
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+}
+
+type Handler = dyn Fn(&HttpRequest) -> HttpResponse + Send + Sync;
+
+/// A minimal HTTP/1.1 server on localhost, handling one request per
+/// connection.  Stopped when dropped.
+pub struct HttpServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    requests: Arc<Mutex<Vec<HttpRequest>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl HttpServer {
+    pub fn new(handler: impl Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler: Arc<Handler> = Arc::new(handler);
+        let thread = {
+            let stop = stop.clone();
+            let requests = requests.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let Ok(stream) = stream else {
+                        continue;
+                    };
+                    let (handler, requests) = (handler.clone(), requests.clone());
+                    // clients may hold one connection open while making
+                    // another request
+                    thread::spawn(move || handle(stream, &*handler, &requests));
+                }
+            })
+        };
+        Self {
+            addr,
+            stop,
+            requests,
+            thread: Some(thread),
+        }
+    }
+
+    /// Serve `data` at any path, honoring Range and If-Range like a
+    /// static file server.
+    pub fn file(data: &[u8], etag: &str) -> Self {
+        let (data, etag) = (data.to_vec(), etag.to_string());
+        Self::new(move |req| ranged_response(req, &data, &etag))
+    }
+
+    pub fn url(&self, path: &str) -> Url {
+        Url::parse(&format!("http://{}{}", self.addr, path)).unwrap()
+    }
+
+    /// The requests received so far, in order of arrival.
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for HttpServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // wake up the accept loop
+        let _ = TcpStream::connect(self.addr);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Respond to a request for `data` the way a static file server would.
+pub fn ranged_response(req: &HttpRequest, data: &[u8], etag: &str) -> HttpResponse {
+    let range = req
+        .header("range")
+        .filter(|_| req.header("if-range").map_or(true, |v| v == etag))
+        .and_then(|r| r.strip_prefix("bytes="))
+        .and_then(|r| r.split_once('-'));
+    match range {
+        Some((start, end)) => {
+            let start: usize = start.parse().unwrap();
+            let end = match end {
+                "" => data.len(),
+                end => (end.parse::<usize>().unwrap() + 1).min(data.len()),
+            };
+            if start >= data.len() {
+                return HttpResponse::new("416 Range Not Satisfiable", b"")
+                    .header("Content-Range", &format!("bytes */{}", data.len()));
+            }
+            HttpResponse::new("206 Partial Content", &data[start..end])
+                .header("ETag", etag)
+                .header("Accept-Ranges", "bytes")
+                .header(
+                    "Content-Range",
+                    &format!("bytes {}-{}/{}", start, end - 1, data.len()),
+                )
+        }
+        None => HttpResponse::new("200 OK", data)
+            .header("ETag", etag)
+            .header("Accept-Ranges", "bytes"),
+    }
+}
+
+fn handle(stream: TcpStream, handler: &Handler, requests: &Mutex<Vec<HttpRequest>>) {
+    let mut reader = BufReader::new(&stream);
+    let mut line = String::new();
+    if reader.read_line(&mut line).unwrap_or(0) == 0 {
+        return;
+    }
+    let path = line.split_ascii_whitespace().nth(1).unwrap_or("/").into();
+    let mut headers = HashMap::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return;
+        }
+        match line.trim_end().split_once(':') {
+            Some((name, value)) => {
+                headers.insert(name.to_ascii_lowercase(), value.trim().to_string());
+            }
+            None => break,
+        }
+    }
+    let req = HttpRequest { path, headers };
+    requests.lock().unwrap().push(req.clone());
+
+    let resp = handler(&req);
+    let mut out = &stream;
+    let mut head = format!(
+        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        resp.status,
+        resp.body.len()
+    );
+    for (name, value) in &resp.headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str("\r\n");
+    let body = &resp.body[..resp.truncate.unwrap_or(resp.body.len())];
+    // the client may hang up early
+    let _ = out.write_all(head.as_bytes());
+    let _ = out.write_all(body);
+    let _ = out.flush();
+}
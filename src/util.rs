@@ -14,6 +14,32 @@
 
 use anyhow::{bail, Context, Result};
 use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by --quiet to suppress informational messages.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Suppress informational messages printed with status!().
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// Whether informational messages are being suppressed.
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Prints an informational message to stderr, like eprintln!(), unless
+/// messages have been suppressed with set_quiet().  Errors should still be
+/// reported with eprintln!().
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !$crate::util::is_quiet() {
+            eprintln!($($arg)*);
+        }
+    }
+}
 
 /// Runs the provided command. The first macro argument is the executable, and following arguments
 /// are passed to the command. Returns a Result<()> describing whether the command failed. Errors
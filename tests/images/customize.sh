@@ -29,6 +29,11 @@ grepq() {
     grep "$@" > /dev/null
 }
 
+unwrap_data_url() {
+    # Decode the gzipped data URLs that customizations embed in Ignition
+    sed 's/^data:;base64,//' | base64 -d | zcat
+}
+
 # kargs we need on every boot
 kargs_common=(
     # make sure log output is readable
@@ -65,6 +70,8 @@ opts_common=(
     --network-keyfile "${fixtures}/installer-test.nmconnection"
     --network-nmstate "${fixtures}/installer-test-nmstate-json.json"
     --network-nmstate "${fixtures}/installer-test-nmstate-yaml.yaml"
+    # Tested implicitly
+    --live-file "${fixtures}/pre-install-1:/usr/local/share/live-file-test"
 )
 
 # options that do initiate an install
@@ -203,6 +210,8 @@ check_dest() {
 }
 
 # Check equivalence of ISO outputs
+# pin the timestamp in the customization manifest
+export SOURCE_DATE_EPOCH=1700000000
 coreos-installer iso customize src-iso -o iso \
     "${opts_common[@]}" "${opts_install[@]}"
 expected=$(digest iso)
@@ -215,6 +224,41 @@ found=$(coreos-installer iso customize src-iso -o - \
     "${opts_common[@]}" "${opts_install[@]}" | digest)
 [ "${expected}" = "${found}" ]
 
+# Check customization manifest
+coreos-installer iso info --format json iso > info.json
+jq -e '.customizations.timestamp == 1700000000' info.json
+jq -e '.customizations.options|index("--dest-device=/dev/vda")' info.json
+jq -e '.customizations.inputs[]|select(.option == "live-file")|.dest == "/usr/local/share/live-file-test"' info.json
+jq -e --arg sum "$(digest "${fixtures}/dest.ign")" \
+    '.customizations.inputs[]|select(.option == "dest-ignition")|select(.sha256 == $sum)|.path == "dest.ign"' info.json
+coreos-installer iso info iso | grepq "^Customizations:"
+coreos-installer iso reset iso -o reset-iso
+coreos-installer iso info --format json reset-iso | jq -e 'has("customizations")|not'
+rm info.json reset-iso
+coreos-installer iso customize src-iso -o iso2 --live-karg-append foo \
+    --source-date-epoch 1234
+coreos-installer iso info --format json iso2 | jq -e '.customizations.timestamp == 1234'
+rm iso2
+
+# Check added ISO files
+coreos-installer iso customize src-iso -o iso \
+    --iso-file "${fixtures}/live.ign:IMAGES/LIVE.IGN"
+coreos-installer iso extract file iso IMAGES/LIVE.IGN -o - |
+    cmp - "${fixtures}/live.ign"
+expected=$(digest iso)
+found=$(coreos-installer iso customize src-iso -o - \
+    --iso-file "${fixtures}/live.ign:IMAGES/LIVE.IGN" | digest)
+[ "${expected}" = "${found}" ]
+# forcing replaces the file rather than adding a second copy
+coreos-installer iso customize -f iso -o iso2 \
+    --iso-file "${fixtures}/live.ign:IMAGES/LIVE.IGN"
+[ "${expected}" = "$(digest iso2)" ]
+coreos-installer iso reset iso -o reset-iso
+(coreos-installer iso extract file reset-iso IMAGES/LIVE.IGN -o - 2>&1 ||:) |
+    grepq "no record"
+cmp reset-iso src-iso
+rm iso iso2 reset-iso
+
 # Check ISO error conditions
 (coreos-installer iso customize src-iso -o iso \
     "${opts_common[@]}" "${opts_install[@]}" 2>&1 ||:) |
@@ -250,6 +294,16 @@ xz -dc "${rootdir}/fixtures/iso/synthetic.iso.xz" > old.iso
     grepq "Unrecognized CoreOS ISO image"
 # no-op
 coreos-installer iso customize src-iso -o iso
+rm iso
+
+# Check initrd compression
+coreos-installer iso customize src-iso -o iso \
+    --initrd-compression zstd:3 --live-ignition "${fixtures}/live.ign"
+coreos-installer iso ignition show iso | grepq applied-live-ign
+rm iso
+(coreos-installer iso customize src-iso -o iso \
+    --initrd-compression lz4 --live-ignition "${fixtures}/live.ign" 2>&1 ||:) |
+    grepq "unknown compression"
 
 # Check PXE initrd concatenation
 pxe_customize "${opts_common[@]}" "${opts_install[@]}"
@@ -260,6 +314,14 @@ cmp -n "${orig_size}" src-initrd initrd
 [ "$(dd if=initrd skip="$((orig_size + 1))" bs=1 count=4 status=none)" = "7zXZ" ]
 rm initrd
 
+# Check PXE initrd written alongside the ISO
+coreos-installer iso customize src-iso -o iso --pxe-output initrd \
+    "${opts_common[@]}" "${opts_install[@]}"
+coreos-installer pxe customize src-initrd -o initrd2 \
+    "${opts_common[@]}" "${opts_install[@]}"
+[ "$(digest initrd)" = "$(digest initrd2)" ]
+rm iso initrd initrd2
+
 # Check equivalence of PXE outputs
 coreos-installer pxe customize src-initrd -o initrd \
     "${opts_common[@]}" "${opts_install[@]}"
@@ -267,6 +329,13 @@ expected=$(digest initrd)
 found=$(coreos-installer pxe customize src-initrd -o - \
     "${opts_common[@]}" "${opts_install[@]}" | digest)
 [ "${expected}" = "${found}" ]
+# separate output is the appended part of the combined output
+coreos-installer pxe customize src-initrd --separate-output custom \
+    "${opts_common[@]}" "${opts_install[@]}"
+[ "$(cat src-initrd custom | digest)" = "${expected}" ]
+rm custom
+(coreos-installer pxe customize src-initrd --separate-output custom 2>&1 ||:) |
+    grepq "no customizations specified"
 
 # Check PXE error conditions
 # don't re-test feature flags here, since the comprehensive tests would fail
@@ -277,12 +346,66 @@ found=$(coreos-installer pxe customize src-initrd -o - \
 (coreos-installer pxe customize initrd -o initrd2 \
     "${opts_common[@]}" "${opts_install[@]}" 2>&1 ||:) |
     grepq "already customized"
-rm initrd
+# layering keeps the existing customizations as a prefix
+coreos-installer pxe customize initrd -o initrd2 --merge \
+    --dest-karg-append merged-karg
+cmp -n "$(stat -c %s initrd)" initrd initrd2
+# the existing live config is merged first so the new one takes precedence,
+# and the new installer config sorts after the existing ones
+coreos-installer pxe ignition unwrap initrd > base.ign
+coreos-installer pxe ignition unwrap initrd2 > merged.ign
+jq -e '.ignition.config.merge|length == 2' merged.ign
+jq -r '.ignition.config.merge[0].source' merged.ign | unwrap_data_url > merged-0.ign
+jq -r '.ignition.config.merge[1].source' merged.ign | unwrap_data_url > merged-1.ign
+cmp base.ign merged-0.ign
+installer_serials() {
+    jq -r '.storage.files[].path' "$1" |
+        sed -n 's,^/etc/coreos/installer\.d/\([0-9]*\)-.*,\1,p'
+}
+base_last=$(installer_serials base.ign | sort -n | tail -n1)
+new_first=$(installer_serials merged-1.ign | sort -n | head -n1)
+[ "$((10#${new_first}))" -gt "$((10#${base_last}))" ]
+jq -r '.storage.files[]|select(.path|startswith("/etc/coreos/installer.d/"))|.contents.source' merged-1.ign |
+    unwrap_data_url | grepq merged-karg
+rm initrd initrd2 base.ign merged.ign merged-0.ign merged-1.ign
 coreos-installer pxe ignition wrap -i /dev/null > empty-initrd
 (coreos-installer pxe customize empty-initrd -o initrd 2>&1 ||:) |
     grepq "not a CoreOS live initramfs image"
 # no-op
 coreos-installer pxe customize src-initrd -o initrd
+rm initrd
+
+# Check iPXE script
+coreos-installer pxe customize src-initrd --separate-output initrd \
+    --live-ignition "${fixtures}/live.ign" \
+    --live-karg-append live-karg \
+    --ipxe-script ipxe-script \
+    --ipxe-kernel-url http://example.com/kernel \
+    --ipxe-initrd-url http://example.com/initrd \
+    --ipxe-custom-initrd-url http://example.com/custom \
+    --ipxe-rootfs-url http://example.com/rootfs
+grepq "^kernel http://example.com/kernel initrd=main initrd=custom coreos.live.rootfs_url=http://example.com/rootfs ignition.firstboot ignition.platform.id=metal live-karg$" ipxe-script
+grepq "^initrd --name main http://example.com/initrd$" ipxe-script
+grepq "^initrd --name custom http://example.com/custom$" ipxe-script
+rm initrd ipxe-script
+# the script needs a separate customization initramfs
+(coreos-installer pxe customize src-initrd -o initrd \
+    --ipxe-script ipxe-script \
+    --ipxe-kernel-url http://example.com/kernel \
+    --ipxe-initrd-url http://example.com/initrd \
+    --ipxe-custom-initrd-url http://example.com/custom \
+    --ipxe-rootfs-url http://example.com/rootfs 2>&1 ||:) |
+    grepq "required"
+[ ! -e initrd ]
+
+# Check PXE kargs
+expected=$(coreos-installer iso kargs show -d src-iso | tr ' ' '\n' |
+    grep -v '^coreos\.liveiso=' | paste -sd ' ')
+[ "$(coreos-installer pxe kargs show src-iso)" = "${expected}" ]
+kargs=$(coreos-installer pxe kargs modify src-iso \
+    --rootfs-url http://example.com/rootfs -a pxe-karg -d ignition.firstboot)
+echo "${kargs}" | grepq "coreos.live.rootfs_url=http://example.com/rootfs pxe-karg$"
+(echo "${kargs}" | grepq -e ignition.firstboot -e coreos.liveiso=) && exit 1
 
 # Check arg restrictions
 (iso_customize \
@@ -318,6 +441,19 @@ coreos-installer pxe customize src-initrd -o initrd
 (iso_customize \
     --installer-config "${fixtures}/installer-test.nmconnection" 2>&1 ||:) |
     grepq "parsing installer config"
+(iso_customize \
+    --live-unit "${fixtures}/pre-install-1" 2>&1 ||:) |
+    grepq "valid unit type suffix"
+printf '[Service]\nExecStart=/usr/bin/true\n' > no-install.service
+(iso_customize \
+    --dest-unit no-install.service 2>&1 ||:) |
+    grepq "no \[Install\] section"
+
+# Test live units by reading them back out of the ISO
+printf '[Service]\nExecStart=/usr/bin/true\n[Install]\nWantedBy=multi-user.target\n' > live-test.service
+iso_customize \
+    --live-unit live-test.service
+coreos-installer iso ignition show iso | grepq live-test.service
 
 # Test live kargs by reading them back out of the ISO
 coreos-installer iso kargs show src-iso | grepq ignition.platform.id=metal
@@ -333,6 +469,21 @@ iso_customize \
     --live-karg-delete ignition.platform.id=metal
 ! coreos-installer iso kargs show iso | grepq ignition.platform.id
 
+# Test multipath by reading the unit and installer config out of the ISO
+iso_customize \
+    --dest-device /dev/mapper/mpatha \
+    --dest-multipath
+coreos-installer iso ignition show iso > live.json
+jq -e '.systemd.units[]|select(.name == "coreos-installer-multipath.service")|.enabled' live.json
+jq -r '.storage.files[]|select(.path|startswith("/etc/coreos/installer.d/"))|.contents.source' live.json |
+    unwrap_data_url > installer.yaml
+grepq "rd.multipath=default" installer.yaml
+grepq "root=/dev/disk/by-label/dm-mpath-root" installer.yaml
+grepq "/dev/mapper/mpatha" installer.yaml
+rm live.json installer.yaml
+(iso_customize --dest-multipath 2>&1 ||:) |
+    grepq "required arguments were not provided"
+
 # Runtime tests
 echo "=== ISO without install ==="
 iso_customize "${opts_common[@]}"
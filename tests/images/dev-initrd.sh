@@ -53,6 +53,12 @@ coreos-installer dev show initrd compressed.img 'gzip/*' > out
 files | grep gzip | diff - out
 coreos-installer dev show initrd - 'gzip*' 'xz*' < compressed.img > out
 files | grep -E 'gzip|xz' | diff - out
+coreos-installer dev show initrd --format json compressed.img > out.json
+jq -r '.[]|select(.type == "file")|.path' out.json | diff <(files) -
+jq -e '.[]|select(.path == "gzip/hello")|.size == 6 and .sha256 == "3b09aeb6f5f5336beb205d7f720371bc927cd46c21922e334d47ba264acb5ba4"' out.json
+jq -e '.[]|select(.path == "uncompressed-1")|.type == "directory" and .mode == "0775"' out.json
+coreos-installer dev show initrd --format json compressed.img 'xz/*' > out.json
+jq -e 'length == 2' out.json
 
 # dev extract initrd
 coreos-installer dev extract initrd compressed.img
@@ -72,6 +78,10 @@ check d gzip xz
 [ -e d/uncompressed-1 ] && exit 1
 [ -e d/uncompressed-2 ] && exit 1
 rm -r d
+coreos-installer dev extract initrd -C d --list-json compressed.img 'zstd/*' > out.json
+check d zstd
+coreos-installer dev show initrd --format json compressed.img 'zstd/*' | diff - out.json
+rm -r d
 (coreos-installer dev extract initrd \
     "${fixtures}/initrd/traversal-absolute.img" 2>&1 ||:) | grepq traversal
 (coreos-installer dev extract initrd \
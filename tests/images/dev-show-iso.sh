@@ -31,6 +31,10 @@ jq -e '.header.descriptors[]|select(.type == "primary")|.system_id|contains("LIN
 jq -e '.header.descriptors[]|select(.type == "primary")|.volume_id|contains("fedora-coreos")' inspect.json
 jq -e '.header.descriptors[]|select(.type == "boot")|.boot_system_id|contains("EL TORITO")' inspect.json
 
+# check the El Torito boot catalog
+jq -e '.boot_catalog.entries|length > 0' inspect.json
+jq -e '.boot_catalog.entries[0].bootable' inspect.json
+
 # check that it found some various files and directories at various depths
 jq -e '.records|index("EFI") >= 0' inspect.json
 jq -e '.records|index("IMAGES/PXEBOOT") >= 0' inspect.json
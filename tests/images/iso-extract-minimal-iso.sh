@@ -95,6 +95,15 @@ if [ "${hash}" != "${modified_hash}" ]; then
     fatal "Streamed hash with rootfs URL doesn't match copied hash: ${hash} vs. ${modified_hash}"
 fi
 
+# Check --verify-signatures
+rm out
+coreos-installer iso extract minimal-iso --verify-signatures "${iso}" out 2>&1 |
+    grepq "EFI boot files"
+hash=$(digest out)
+if [ "${hash}" != "${miniso_hash}" ]; then
+    fatal "Output hash with signature verification doesn't match copied hash: ${hash} vs. ${miniso_hash}"
+fi
+
 # Output already exists
 rm out
 (coreos-installer iso extract minimal-iso "${iso}" out --output-rootfs rootfs 2>&1 ||:) | grepq "File exists"
@@ -46,5 +46,22 @@ compare_digests "${base}-initrd.img" ${builddir}/*-initramfs.*.img
 # shellcheck disable=SC2086
 compare_digests "${base}-rootfs.img" ${builddir}/*-rootfs.*.img
 
+# check extracting individual files
+mkdir files
+coreos-installer iso extract file "${iso}" IMAGES/PXEBOOT/VMLINUZ -o files
+compare_digests "${base}-vmlinuz" files/VMLINUZ
+coreos-installer iso extract file "${iso}" IMAGES/PXEBOOT/INITRD.IMG -o - > initrd
+compare_digests "${base}-initrd.img" initrd
+
+# check PXE kargs
+mkdir kargs
+coreos-installer iso extract pxe "${iso}" -o kargs --karg-append foo=bar \
+    --karg-delete ignition.firstboot
+grep -q "foo=bar" "kargs/${base}.kargs"
+if grep -q -e "coreos.liveiso=" -e "ignition.firstboot" "kargs/${base}.kargs"; then
+    fatal "unexpected kargs in ${base}.kargs"
+fi
+grep -q "ignition.platform.id=metal" "kargs/${base}.kargs"
+
 # Done
 echo "Success."
@@ -60,6 +60,36 @@ length=$(coreos-installer dev show iso --ignition "${iso}" | jq -r .length)
 if [ "${config}" != "$(dd if=${iso} skip=${offset} count=${length} bs=1 status=none | xzcat | cpio -i --to-stdout --quiet)" ]; then
     fatal "Failed to manually round-trip Ignition config"
 fi
+coreos-installer iso verify "${iso}" | grepq "Ignition config: OK"
+
+# Test pretty-printing and validation
+valid_config='{"ignition": {"version": "3.3.0"}}'
+coreos-installer iso ignition embed -i <(echo "${valid_config}") "${iso}" -f -o valid.iso
+coreos-installer iso ignition show --pretty valid.iso | grepq '^  "ignition": {$'
+coreos-installer iso ignition show --pretty valid.iso | jq -e '.ignition.version == "3.3.0"' >/dev/null
+coreos-installer iso ignition show --validate valid.iso 2>&1 >/dev/null | grepq "spec version 3.3.0"
+coreos-installer iso ignition embed -i <(echo '{"ignition": {}}') "${iso}" -f -o invalid.iso
+if coreos-installer iso ignition show --validate invalid.iso >/dev/null 2>&1; then
+    fatal "Validated invalid Ignition config"
+fi
+
+# Test editing
+VISUAL= EDITOR="sed -i s/3.3.0/3.4.0/" coreos-installer iso ignition edit valid.iso
+coreos-installer iso ignition show valid.iso | jq -e '.ignition.version == "3.4.0"' >/dev/null
+VISUAL= EDITOR=true coreos-installer iso ignition edit valid.iso 2>&1 | grepq "Ignition config unchanged"
+hash=$(digest valid.iso)
+if echo n | VISUAL= EDITOR="sed -i s/3.4.0/9.9.9/" coreos-installer iso ignition edit valid.iso 2>/dev/null; then
+    fatal "Accepted invalid edited Ignition config"
+fi
+if [ "$(digest valid.iso)" != "${hash}" ]; then
+    fatal "ISO modified after invalid edit"
+fi
+rm valid.iso invalid.iso
+
+# Test merging multiple configs
+rm "${out_iso}"
+coreos-installer iso ignition embed -i <(echo "${config}") -i <(echo "${config}") "${iso}" -o "${out_iso}"
+coreos-installer iso ignition show "${out_iso}" | jq -e '.ignition.config.merge | length == 2' >/dev/null
 
 # Test forcing
 (coreos-installer iso ignition embed -i <(echo "${config}") "${iso}" 2>&1 ||:) | grepq "already has an embedded Ignition config"
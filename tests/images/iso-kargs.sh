@@ -89,6 +89,19 @@ embed_size=$(coreos-installer dev show iso --kargs "${iso}" | jq .length)
 embed_default_kargs_size=$(coreos-installer iso kargs show --default "${iso}" | wc -c)
 embed_usable_size=$((${embed_size} - ${embed_default_kargs_size} - 1))
 
+# Check the JSON report of the available space; it includes the space
+# separating an appended karg from the defaults.
+coreos-installer iso kargs show --format json "${iso}" > kargs.json
+jq -e ".\"max-length\" == $((embed_size - 1))" kargs.json
+jq -e ".remaining == $((embed_usable_size + 1))" kargs.json
+jq -e '.architecture != null' kargs.json
+jq -e '."embed-area".kargs|length > 0' kargs.json
+jq -e --arg d "$(coreos-installer iso kargs show -d "${iso}")" '.default == $d' kargs.json
+if coreos-installer iso kargs show -d --format json "${iso}" > /dev/null 2>&1; then
+    echo "--default accepted with JSON output"
+    exit 1
+fi
+
 long_karg=$(printf '%*s' $((embed_usable_size)) | tr ' ' "k")
 coreos-installer iso kargs modify -a "${long_karg}" "${iso}"
 coreos-installer iso kargs show "${iso}" | grepq " ${long_karg}\$"
@@ -141,6 +154,56 @@ else
         fatal "Failed to manually round-trip kargs"
     fi
 fi
+coreos-installer iso kargs reset "${iso}"
+
+# Test changing the volume ID.
+orig_volume_id=$(coreos-installer iso info --format json "${iso}" | jq -r '."volume-id"')
+rm "${out_iso}"
+coreos-installer iso set-label relabeled "${iso}" -o "${out_iso}"
+coreos-installer iso info --format json "${out_iso}" | jq -e '."volume-id" == "relabeled"'
+if coreos-installer iso kargs show --default "${iso}" | grepq "coreos.liveiso=${orig_volume_id}"; then
+    coreos-installer iso kargs show "${out_iso}" | grepq "coreos.liveiso=relabeled"
+    coreos-installer iso kargs show --default "${out_iso}" | grepq "coreos.liveiso=relabeled"
+fi
+stdout_hash=$(coreos-installer iso customize --volume-id relabeled -o - "${iso}" | digest)
+hash=$(digest "${out_iso}")
+if [ "${stdout_hash}" != "${hash}" ]; then
+    fatal "Streamed hash doesn't match relabeled hash: ${stdout_hash} vs ${hash}"
+fi
+# kargs.json may be reformatted, so compare contents rather than hashes
+coreos-installer iso set-label "${orig_volume_id}" "${out_iso}"
+coreos-installer iso info --format json "${out_iso}" | jq -e ".\"volume-id\" == \"${orig_volume_id}\""
+if [ "$(coreos-installer iso kargs show "${out_iso}")" != "$(coreos-installer iso kargs show "${iso}")" ]; then
+    fatal "Kargs not restored after relabeling"
+fi
+coreos-installer iso verify "${out_iso}"
+if coreos-installer iso set-label "bad label" "${iso}" 2>err.txt; then
+    fatal "Was able to set invalid volume ID"
+fi
+grepq 'may only contain' err.txt
+
+# Test selective reset.
+config='{"ignition": {"version": "3.3.0"}}'
+coreos-installer iso kargs modify -a foobar=val "${iso}"
+echo "${config}" | coreos-installer iso ignition embed "${iso}"
+coreos-installer iso reset --ignition "${iso}"
+(coreos-installer iso ignition show "${iso}" 2>&1 ||:) | grepq "No embedded Ignition config"
+coreos-installer iso kargs show "${iso}" | grepq foobar=val
+echo "${config}" | coreos-installer iso ignition embed "${iso}"
+coreos-installer iso reset --kargs "${iso}"
+coreos-installer iso ignition show "${iso}" > /dev/null
+if coreos-installer iso kargs show "${iso}" | grepq foobar; then
+    fatal "Unexpected foobar karg after reset --kargs"
+fi
+coreos-installer iso kargs modify -a foobar=val "${iso}"
+coreos-installer iso reset --keep-kargs "${iso}"
+(coreos-installer iso ignition show "${iso}" 2>&1 ||:) | grepq "No embedded Ignition config"
+coreos-installer iso kargs show "${iso}" | grepq foobar=val
+coreos-installer iso reset "${iso}"
+hash=$(digest "${iso}")
+if [ "${orig_hash}" != "${hash}" ]; then
+    fatal "Hash doesn't match original hash after reset: ${hash} vs ${orig_hash}"
+fi
 
 # Done
 echo "Success."